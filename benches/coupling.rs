@@ -0,0 +1,17 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use polyglot_code_scanner::benchmarks::{coupling_buckets, SyntheticTree};
+
+fn coupling_bucket_building(c: &mut Criterion) {
+    let mut group = c.benchmark_group("coupling buckets");
+    group.sample_size(10);
+    for files in [100, 1000] {
+        let tree = SyntheticTree::new(files, 2000);
+        group.bench_with_input(BenchmarkId::from_parameter(files), &tree, |b, tree| {
+            b.iter(|| coupling_buckets(black_box(tree)).unwrap());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, coupling_bucket_building);
+criterion_main!(benches);
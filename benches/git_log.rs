@@ -0,0 +1,29 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use polyglot_code_scanner::benchmarks::git_log_file_changes;
+use polyglot_code_scanner::{generate_bench_fixture, BenchFixtureConfig};
+use test_shared::unzip_test_sample;
+
+fn git_log_parsing(c: &mut Criterion) {
+    let workdir = tempfile::tempdir().unwrap();
+    let sample = unzip_test_sample("git_sample", workdir.path()).unwrap();
+    let fixture = workdir.path().join("fixture");
+    generate_bench_fixture(
+        &fixture,
+        BenchFixtureConfig {
+            files: 200,
+            commits: 200,
+            ..BenchFixtureConfig::default()
+        },
+    )
+    .unwrap();
+
+    c.bench_function("git log of git_sample", |b| {
+        b.iter(|| git_log_file_changes(black_box(&sample)).unwrap());
+    });
+    c.bench_function("git log of 200 commit fixture", |b| {
+        b.iter(|| git_log_file_changes(black_box(&fixture)).unwrap());
+    });
+}
+
+criterion_group!(benches, git_log_parsing);
+criterion_main!(benches);
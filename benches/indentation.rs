@@ -0,0 +1,15 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use polyglot_code_scanner::benchmarks::indentation;
+use std::path::Path;
+
+fn indentation_parsing(c: &mut Criterion) {
+    // a large, real rust file - more representative than synthetic source
+    let file = Path::new("src/coupling.rs");
+
+    c.bench_function("indentation of coupling.rs", |b| {
+        b.iter(|| indentation(black_box(file)).unwrap());
+    });
+}
+
+criterion_group!(benches, indentation_parsing);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use polyglot_code_scanner::benchmarks::walk_paths;
+use polyglot_code_scanner::{generate_bench_fixture, BenchFixtureConfig};
+use test_shared::unzip_test_sample;
+
+fn walking(c: &mut Criterion) {
+    let workdir = tempfile::tempdir().unwrap();
+    let sample = unzip_test_sample("git_sample", workdir.path()).unwrap();
+    let fixture = workdir.path().join("fixture");
+    generate_bench_fixture(
+        &fixture,
+        BenchFixtureConfig {
+            commits: 1,
+            ..BenchFixtureConfig::default()
+        },
+    )
+    .unwrap();
+
+    c.bench_function("walk git_sample", |b| {
+        b.iter(|| walk_paths(black_box(&sample)).unwrap());
+    });
+    c.bench_function("walk 1000 file fixture", |b| {
+        b.iter(|| walk_paths(black_box(&fixture)).unwrap());
+    });
+}
+
+criterion_group!(benches, walking);
+criterion_main!(benches);
@@ -0,0 +1,250 @@
+#![warn(clippy::all)]
+//! Architecture information declared in the repository itself - a `.polyglot_annotations.toml`
+//! file in any directory attaches a component name, owning team, or deprecation status to that
+//! directory, and optionally to paths below it:
+//! ```toml
+//! component = "billing"
+//! owner = "payments-team"
+//!
+//! [paths."legacy/"]
+//! deprecated = true
+//! deprecation_note = "replaced by billing-v2"
+//!
+//! [paths."src/invoice.rs"]
+//! owner = "invoicing-team"
+//! ```
+//! Paths are relative to the annotations file's directory. Annotations are only attached to the
+//! nodes they are declared for - they aren't copied down the tree, so consumers should look up
+//! the tree for the nearest annotated directory. Where a path is annotated by a parent and by
+//! its own file, the nearest file wins for each value it sets.
+
+use anyhow::{Context, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::{Diagnostic, DiagnosticCategory};
+use crate::flare::FlareTreeNode;
+use crate::polyglot_data::IndicatorMetadata;
+use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+
+pub const ANNOTATIONS_FILE: &str = ".polyglot_annotations.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component: Option<String>,
+    /// the team or person who owns the code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deprecated: bool,
+    /// why the code is deprecated, or what replaces it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecation_note: Option<String>,
+}
+
+impl Annotations {
+    /// values set in `nearer` replace these
+    fn merge(&mut self, nearer: Annotations) {
+        if nearer.component.is_some() {
+            self.component = nearer.component;
+        }
+        if nearer.owner.is_some() {
+            self.owner = nearer.owner;
+        }
+        self.deprecated |= nearer.deprecated;
+        if nearer.deprecation_note.is_some() {
+            self.deprecation_note = nearer.deprecation_note;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+struct AnnotationsFile {
+    /// for the directory containing the file
+    #[serde(flatten)]
+    annotations: Annotations,
+    /// for paths relative to the directory
+    #[serde(default)]
+    paths: BTreeMap<String, Annotations>,
+}
+
+impl AnnotationsFile {
+    fn read(file: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(file)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AnnotationsCalculator {
+    /// annotations from parent directories' files, for paths not visited yet
+    pending: HashMap<PathBuf, Annotations>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl AnnotationsCalculator {
+    /// annotations declared for a directory by its own file, after noting those for paths below it
+    fn read_directory(&mut self, dir: &Path) -> Option<Annotations> {
+        let file = dir.join(ANNOTATIONS_FILE);
+        if !file.is_file() {
+            return None;
+        }
+        match AnnotationsFile::read(&file)
+            .with_context(|| format!("reading annotations from {:?}", file))
+        {
+            Ok(AnnotationsFile { annotations, paths }) => {
+                for (relative, path_annotations) in paths {
+                    self.pending
+                        .entry(dir.join(relative))
+                        .or_default()
+                        .merge(path_annotations);
+                }
+                Some(annotations)
+            }
+            Err(e) => {
+                self.diagnostics.push(Diagnostic::new(
+                    DiagnosticCategory::InvalidAnnotations,
+                    Some(&file),
+                    format!("{:#}", e),
+                ));
+                None
+            }
+        }
+    }
+}
+
+impl ToxicityIndicatorCalculator for AnnotationsCalculator {
+    fn name(&self) -> String {
+        "annotations".to_string()
+    }
+
+    fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        let mut annotations = self.pending.remove(path);
+        if path.is_dir() {
+            if let Some(own) = self.read_directory(path) {
+                annotations
+                    .get_or_insert_with(Annotations::default)
+                    .merge(own);
+            }
+        }
+        if let Some(annotations) = annotations {
+            node.indicators_mut().annotations = Some(annotations);
+        }
+        Ok(())
+    }
+
+    fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// includes annotated paths that were never visited - typos, or ignored files
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut missing: Vec<&PathBuf> = self.pending.keys().collect();
+        missing.sort();
+        self.diagnostics
+            .iter()
+            .cloned()
+            .chain(missing.into_iter().map(|path| {
+                Diagnostic::new(
+                    DiagnosticCategory::InvalidAnnotations,
+                    Some(path),
+                    "Annotated path was not scanned",
+                )
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn annotations_are_attached_to_declared_paths() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("billing/legacy"))?;
+        fs::write(root.join("billing/invoice.rs"), "fn main() {}\n")?;
+        fs::write(
+            root.join("billing").join(ANNOTATIONS_FILE),
+            r#"
+component = "billing"
+owner = "payments"
+
+[paths."legacy/"]
+deprecated = true
+deprecation_note = "use billing-v2"
+
+[paths."invoice.rs"]
+owner = "invoicing"
+
+[paths."missing.rs"]
+owner = "nobody"
+"#,
+        )?;
+        fs::write(
+            root.join("billing/legacy").join(ANNOTATIONS_FILE),
+            "owner = \"legacy-team\"\n",
+        )?;
+
+        let mut calculator = AnnotationsCalculator::default();
+        let mut annotations_of = |path: PathBuf| {
+            let mut node = FlareTreeNode::new(path.file_name().unwrap(), path.is_file());
+            calculator.visit_node(&mut node, &path).unwrap();
+            node.indicators().annotations.clone()
+        };
+        assert_eq!(annotations_of(root.to_owned()), None);
+        assert_eq!(
+            annotations_of(root.join("billing")),
+            Some(Annotations {
+                component: Some("billing".to_owned()),
+                owner: Some("payments".to_owned()),
+                ..Annotations::default()
+            })
+        );
+        assert_eq!(
+            annotations_of(root.join("billing/invoice.rs")),
+            Some(Annotations {
+                owner: Some("invoicing".to_owned()),
+                ..Annotations::default()
+            })
+        );
+        assert_eq!(
+            annotations_of(root.join("billing/legacy")),
+            Some(Annotations {
+                owner: Some("legacy-team".to_owned()),
+                deprecated: true,
+                deprecation_note: Some("use billing-v2".to_owned()),
+                ..Annotations::default()
+            })
+        );
+
+        let diagnostics = calculator.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, Some(root.join("billing/missing.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_files_are_diagnostics() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join(ANNOTATIONS_FILE),
+            "deprecated = \"maybe\"\n",
+        )?;
+
+        let mut calculator = AnnotationsCalculator::default();
+        let mut node = FlareTreeNode::dir("root");
+        calculator.visit_node(&mut node, dir.path())?;
+        assert_eq!(node.indicators().annotations, None);
+        assert_eq!(
+            calculator.diagnostics()[0].category,
+            DiagnosticCategory::InvalidAnnotations
+        );
+        Ok(())
+    }
+}
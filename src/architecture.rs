@@ -0,0 +1,294 @@
+#![warn(clippy::all)]
+//! Architecture "components" - groups of files declared in a JSON file, so coupling can be
+//! summarised between components rather than files, and coupling that crosses declared
+//! component boundaries can be reported as a violation.
+//!
+//! The file format is:
+//! ```json
+//! {
+//!   "components": [
+//!     { "name": "core", "paths": ["src/core/"], "may_couple_with": ["util"] },
+//!     { "name": "util", "paths": ["src/util/", "src/helpers.rs"] }
+//!   ]
+//! }
+//! ```
+//! Paths are gitignore-style globs relative to the scanned directory; a file belongs to the
+//! first component with a matching path. Coupling is allowed within a component, or between
+//! components where either lists the other in `may_couple_with`.
+
+use anyhow::{Context, Error};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::flare::FlareTreeNode;
+use crate::polyglot_data::PolyglotData;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentConfig {
+    pub name: String,
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub may_couple_with: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchitectureConfig {
+    pub components: Vec<ComponentConfig>,
+}
+
+impl ArchitectureConfig {
+    pub fn from_file(file: &Path) -> Result<Self, Error> {
+        let reader = BufReader::new(
+            File::open(file).with_context(|| format!("opening architecture file {:?}", file))?,
+        );
+        serde_json::from_reader(reader)
+            .with_context(|| format!("parsing architecture file {:?}", file))
+    }
+}
+
+/// Total coupling from files in one component to files in another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentCoupling {
+    pub from: String,
+    pub to: String,
+    pub count: u64,
+}
+
+/// Coupling between two files that crosses a component boundary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoundaryViolation {
+    pub from_file: String,
+    pub from: String,
+    pub to_file: String,
+    pub to: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchitectureMetadata {
+    pub components: Vec<ComponentConfig>,
+    pub coupling: Vec<ComponentCoupling>,
+    pub violations: Vec<BoundaryViolation>,
+}
+
+struct ComponentMatcher {
+    components: Vec<(ComponentConfig, Gitignore)>,
+}
+
+impl ComponentMatcher {
+    fn new(config: &ArchitectureConfig) -> Result<Self, Error> {
+        let components = config
+            .components
+            .iter()
+            .map(|component| {
+                let mut builder = GitignoreBuilder::new(".");
+                for path in &component.paths {
+                    builder.add_line(None, path).with_context(|| {
+                        format!("invalid path {} for component {}", path, component.name)
+                    })?;
+                }
+                Ok((component.clone(), builder.build()?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(ComponentMatcher { components })
+    }
+
+    fn component_for(&self, file: &Path) -> Option<&ComponentConfig> {
+        self.components
+            .iter()
+            .find(|(_, matcher)| matcher.matched_path_or_any_parents(file, false).is_ignore())
+            .map(|(component, _)| component)
+    }
+}
+
+fn may_couple(from: &ComponentConfig, to: &ComponentConfig) -> bool {
+    from.name == to.name
+        || from.may_couple_with.contains(&to.name)
+        || to.may_couple_with.contains(&from.name)
+}
+
+fn accumulate_coupling(
+    node: &FlareTreeNode,
+    path: &Path,
+    matcher: &ComponentMatcher,
+    totals: &mut BTreeMap<(String, String), u64>,
+    violations: &mut BTreeMap<(PathBuf, PathBuf), BoundaryViolation>,
+) {
+    if let Some(coupling) = &node.indicators().coupling {
+        if let Some(from) = matcher.component_for(path) {
+            for bucket in &coupling.buckets {
+                for (other_file, count) in &bucket.coupled_files {
+                    let other_path = other_file.to_path_buf();
+                    if let Some(to) = matcher.component_for(&other_path) {
+                        *totals
+                            .entry((from.name.clone(), to.name.clone()))
+                            .or_insert(0) += count;
+                        if !may_couple(from, to) {
+                            violations
+                                .entry((path.to_owned(), other_path.clone()))
+                                .or_insert_with(|| BoundaryViolation {
+                                    from_file: path.to_string_lossy().to_string(),
+                                    from: from.name.clone(),
+                                    to_file: other_path.to_string_lossy().to_string(),
+                                    to: to.name.clone(),
+                                    count: 0,
+                                })
+                                .count += count;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    for child in node.get_children() {
+        accumulate_coupling(child, &path.join(child.name()), matcher, totals, violations);
+    }
+}
+
+/// summarise file coupling by component - must be run after coupling has been gathered
+pub fn gather_architecture_coupling(
+    polyglot_data: &mut PolyglotData,
+    config: &ArchitectureConfig,
+) -> Result<(), Error> {
+    let matcher = ComponentMatcher::new(config)?;
+    let mut totals = BTreeMap::new();
+    let mut violations = BTreeMap::new();
+    for child in polyglot_data.tree().get_children() {
+        accumulate_coupling(
+            child,
+            Path::new(child.name()),
+            &matcher,
+            &mut totals,
+            &mut violations,
+        );
+    }
+    if !violations.is_empty() {
+        warn!(
+            "Found {} couplings crossing component boundaries",
+            violations.len()
+        );
+    }
+    polyglot_data.metadata().architecture = Some(ArchitectureMetadata {
+        components: config.components.clone(),
+        coupling: totals
+            .into_iter()
+            .map(|((from, to), count)| ComponentCoupling { from, to, count })
+            .collect(),
+        violations: violations.into_values().collect(),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coupling::{PathVec, SerializableCouplingBucketData, SerializableCouplingData};
+    use crate::FeatureFlags;
+    use pretty_assertions::assert_eq;
+    use std::rc::Rc;
+
+    fn component(name: &str, paths: &[&str], may_couple_with: &[&str]) -> ComponentConfig {
+        ComponentConfig {
+            name: name.to_owned(),
+            paths: paths.iter().map(|p| (*p).to_owned()).collect(),
+            may_couple_with: may_couple_with.iter().map(|p| (*p).to_owned()).collect(),
+        }
+    }
+
+    fn coupled_file(name: &str, coupled_files: &[(&str, u64)]) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().coupling = Some(SerializableCouplingData {
+            buckets: vec![SerializableCouplingBucketData {
+                bucket_start: 0,
+                bucket_end: 1000,
+                activity_bursts: 10,
+                coupled_files: coupled_files
+                    .iter()
+                    .map(|(path, count)| (Rc::new(PathVec::from(*path)), *count))
+                    .collect(),
+                p_values: Vec::new(),
+            }],
+        });
+        file
+    }
+
+    #[test]
+    fn files_are_matched_to_the_first_matching_component() {
+        let config = ArchitectureConfig {
+            components: vec![
+                component("ui", &["src/ui/", "*.css"], &[]),
+                component("core", &["src/"], &[]),
+            ],
+        };
+        let matcher = ComponentMatcher::new(&config).unwrap();
+        let name_of = |path: &str| {
+            matcher
+                .component_for(Path::new(path))
+                .map(|c| c.name.clone())
+        };
+        assert_eq!(name_of("src/ui/button.js"), Some("ui".to_owned()));
+        assert_eq!(name_of("assets/main.css"), Some("ui".to_owned()));
+        assert_eq!(name_of("src/model/user.js"), Some("core".to_owned()));
+        assert_eq!(name_of("README.md"), None);
+    }
+
+    #[test]
+    fn coupling_is_summarised_by_component_with_violations() {
+        let config = ArchitectureConfig {
+            components: vec![
+                component("ui", &["ui/"], &["api"]),
+                component("api", &["api/"], &[]),
+                component("db", &["db/"], &[]),
+            ],
+        };
+        let mut ui = FlareTreeNode::dir("ui");
+        ui.append_child(coupled_file(
+            "a.js",
+            &[("api/b.rs", 2), ("db/c.sql", 3), ("other.txt", 5)],
+        ));
+        let mut api = FlareTreeNode::dir("api");
+        api.append_child(coupled_file("b.rs", &[("ui/a.js", 1)]));
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(ui);
+        root.append_child(api);
+        let mut data = PolyglotData::new("test", Some("id"), root, FeatureFlags::default());
+
+        gather_architecture_coupling(&mut data, &config).unwrap();
+
+        let metadata = data.metadata().architecture.clone().unwrap();
+        assert_eq!(
+            metadata.coupling,
+            vec![
+                ComponentCoupling {
+                    from: "api".to_owned(),
+                    to: "ui".to_owned(),
+                    count: 1
+                },
+                ComponentCoupling {
+                    from: "ui".to_owned(),
+                    to: "api".to_owned(),
+                    count: 2
+                },
+                ComponentCoupling {
+                    from: "ui".to_owned(),
+                    to: "db".to_owned(),
+                    count: 3
+                },
+            ]
+        );
+        assert_eq!(
+            metadata.violations,
+            vec![BoundaryViolation {
+                from_file: "ui/a.js".to_owned(),
+                from: "ui".to_owned(),
+                to_file: "db/c.sql".to_owned(),
+                to: "db".to_owned(),
+                count: 3
+            }]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+#![warn(clippy::all)]
+//! Notable files and directories, listed in metadata so simple consumers can show highlights
+//! without walking the tree:
+//! - the largest files, by lines of code
+//! - the oldest untouched files, by the time of their last git change
+//! - the files with the most authors, by git user count
+//! - the deepest directories, by how many directories are above them
+//!
+//! Lists are empty if what they need wasn't scanned. As with reports, vendored files and
+//! `<other>` nodes standing in for aggregated files are left out. Ties are in path order, so
+//! the lists are stable between scans.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AreasOfInterestConfig {
+    /// how many nodes are listed in each area
+    pub top: usize,
+}
+
+impl Default for AreasOfInterestConfig {
+    fn default() -> Self {
+        AreasOfInterestConfig { top: 10 }
+    }
+}
+
+/// a node, and the value that makes it notable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotableNode {
+    /// relative to the tree root
+    pub path: PathBuf,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AreasOfInterest {
+    /// files with the most lines of code - the value is lines of code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub largest_files: Vec<NotableNode>,
+    /// files changed longest ago - the value is the last change, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub oldest_untouched_files: Vec<NotableNode>,
+    /// files changed by the most people - the value is the number of git users
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub most_authors: Vec<NotableNode>,
+    /// the most deeply nested directories - the value is the depth, 1 for top-level directories
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deepest_directories: Vec<NotableNode>,
+}
+
+#[derive(Debug, Default)]
+struct Candidates {
+    largest_files: Vec<NotableNode>,
+    oldest_untouched_files: Vec<NotableNode>,
+    most_authors: Vec<NotableNode>,
+    deepest_directories: Vec<NotableNode>,
+}
+
+fn gather_candidates(node: &FlareTreeNode, path: &Path, depth: u64, candidates: &mut Candidates) {
+    for child in node.get_children() {
+        let indicators = child.indicators();
+        if indicators.vendored || indicators.aggregated.is_some() {
+            continue;
+        }
+        let child_path = path.join(child.name());
+        let notable = |value| NotableNode {
+            path: child_path.clone(),
+            value,
+        };
+        if !child.is_file() {
+            candidates.deepest_directories.push(notable(depth + 1));
+            gather_candidates(child, &child_path, depth + 1, candidates);
+            continue;
+        }
+        if let Some(loc) = indicators.loc.as_ref().filter(|loc| loc.code > 0) {
+            candidates.largest_files.push(notable(loc.code as u64));
+        }
+        if let Some(GitNodeData::File { data }) = &indicators.git {
+            candidates
+                .oldest_untouched_files
+                .push(notable(data.last_update));
+            if data.user_count > 0 {
+                candidates
+                    .most_authors
+                    .push(notable(data.user_count as u64));
+            }
+        }
+    }
+}
+
+/// the top nodes, highest values first unless `lowest_first`, then by path
+fn top(mut nodes: Vec<NotableNode>, top: usize, lowest_first: bool) -> Vec<NotableNode> {
+    nodes.sort_by(|a, b| {
+        let by_value = if lowest_first {
+            a.value.cmp(&b.value)
+        } else {
+            b.value.cmp(&a.value)
+        };
+        by_value.then_with(|| a.path.cmp(&b.path))
+    });
+    nodes.truncate(top);
+    nodes
+}
+
+/// finds the notable nodes in the tree - paths should be final, so call this after any path
+/// prefix is added
+#[must_use]
+pub fn find_areas_of_interest(
+    tree: &FlareTreeNode,
+    config: AreasOfInterestConfig,
+) -> AreasOfInterest {
+    info!("Finding areas of interest");
+    let mut candidates = Candidates::default();
+    gather_candidates(tree, Path::new(""), 0, &mut candidates);
+    AreasOfInterest {
+        largest_files: top(candidates.largest_files, config.top, false),
+        oldest_untouched_files: top(candidates.oldest_untouched_files, config.top, true),
+        most_authors: top(candidates.most_authors, config.top, false),
+        deepest_directories: top(candidates.deepest_directories, config.top, false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::GitData;
+    use crate::loc::LanguageLocData;
+    use pretty_assertions::assert_eq;
+
+    fn file(name: &str, code: usize, last_update: u64, user_count: usize) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: "Rust".to_owned(),
+            binary: false,
+            blanks: 0,
+            code,
+            comments: 0,
+            lines: code,
+            bytes: 100,
+        });
+        let mut git = GitData::fake_with_activity(Vec::new());
+        git.last_update = last_update;
+        git.user_count = user_count;
+        file.indicators_mut().git = Some(GitNodeData::File { data: git });
+        file
+    }
+
+    fn notable(path: &str, value: u64) -> NotableNode {
+        NotableNode {
+            path: PathBuf::from(path),
+            value,
+        }
+    }
+
+    #[test]
+    fn top_nodes_are_listed_for_each_area() {
+        let mut root = FlareTreeNode::dir("<root>");
+        let mut src = FlareTreeNode::dir("src");
+        let mut deep = FlareTreeNode::dir("deep");
+        deep.append_child(file("a.rs", 100, 3000, 1));
+        src.append_child(deep);
+        src.append_child(file("b.rs", 300, 1000, 4));
+        let mut vendor = FlareTreeNode::dir("vendor");
+        vendor.indicators_mut().vendored = true;
+        vendor.append_child(file("huge.rs", 10000, 10, 9));
+        root.append_child(src);
+        root.append_child(vendor);
+        root.append_child(file("c.rs", 100, 2000, 4));
+
+        let areas = find_areas_of_interest(&root, AreasOfInterestConfig { top: 2 });
+        assert_eq!(
+            areas,
+            AreasOfInterest {
+                largest_files: vec![notable("src/b.rs", 300), notable("c.rs", 100)],
+                oldest_untouched_files: vec![notable("src/b.rs", 1000), notable("c.rs", 2000)],
+                most_authors: vec![notable("c.rs", 4), notable("src/b.rs", 4)],
+                deepest_directories: vec![notable("src/deep", 2), notable("src", 1)],
+            }
+        );
+    }
+}
@@ -0,0 +1,129 @@
+#![warn(clippy::all)]
+//! Writing output files atomically - so a scan that crashes or is killed part way through never
+//! leaves a truncated data file for downstream tools to choke on.
+//!
+//! Output is written to a temporary file alongside the real one, with `.tmp` appended to its
+//! name, and renamed into place once it is complete. Renames within a directory replace the old
+//! file in one step, so readers see either the previous output or the new one.
+
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// a file that only appears at its path once `commit` is called
+#[derive(Debug)]
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    /// None once committed
+    out: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let out = BufWriter::new(File::create(&temp)?);
+        Ok(AtomicFile {
+            path: path.to_owned(),
+            temp,
+            out: Some(out),
+        })
+    }
+
+    /// flushes everything written, and moves the file into place
+    pub fn commit(mut self) -> Result<(), Error> {
+        if let Some(out) = self.out.take() {
+            let file = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+            fs::rename(&self.temp, &self.path)?;
+        }
+        Ok(())
+    }
+
+    fn out(&mut self) -> io::Result<&mut BufWriter<File>> {
+        self.out
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "output already committed"))
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out()?.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    /// output that was never committed is incomplete, so is thrown away
+    fn drop(&mut self) {
+        if self.out.take().is_some() {
+            if let Err(e) = fs::remove_file(&self.temp) {
+                warn!("Can't remove incomplete output {:?}: {}", self.temp, e);
+            }
+        }
+    }
+}
+
+/// the path with a UTC timestamp added to the file name, before any extension - so
+/// `scans/project.json` becomes `scans/project-20221121T093000Z.json`
+#[must_use]
+pub fn with_timestamp_suffix(path: &Path, time: DateTime<Utc>) -> PathBuf {
+    let mut name: OsString = path.file_stem().unwrap_or_default().to_owned();
+    name.push(time.format("-%Y%m%dT%H%M%SZ").to_string());
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn output_only_appears_when_committed() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        fs::write(&path, "old").unwrap();
+
+        let mut out = AtomicFile::create(&path).unwrap();
+        out.write_all(b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        out.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!dir.path().join("data.json.tmp").exists());
+
+        let mut out = AtomicFile::create(&path).unwrap();
+        out.write_all(b"abandoned").unwrap();
+        drop(out);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!dir.path().join("data.json.tmp").exists());
+    }
+
+    #[test]
+    fn timestamps_go_before_the_extension() {
+        // 2022-11-21 09:30:00 UTC
+        let time = DateTime::from_utc(NaiveDateTime::from_timestamp(1_669_023_000, 0), Utc);
+        assert_eq!(
+            with_timestamp_suffix(Path::new("scans/project.json"), time),
+            PathBuf::from("scans/project-20221121T093000Z.json")
+        );
+        assert_eq!(
+            with_timestamp_suffix(Path::new("project"), time),
+            PathBuf::from("project-20221121T093000Z")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+#![warn(clippy::all)]
+//! Generating synthetic repositories for benchmarking and profiling.
+//!
+//! The fixture is a git repository of nested directories of indented source files, with a
+//! history of commits each changing a few files. It is deterministic for the same settings, so
+//! timings can be compared between runs and machines - there is no randomness beyond a fixed
+//! pseudo-random sequence.
+
+use anyhow::{Context, Error};
+use git2::{IndexAddOption, Repository, Signature, Time};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// the start of the fixture's history - 2020-01-01
+const START_TIME: i64 = 1_577_836_800;
+
+const AUTHORS: &[&str] = &["alice", "bob", "carol", "dave", "erin"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchFixtureConfig {
+    pub files: usize,
+    pub commits: usize,
+    /// files per directory - directories nest until every file has a place
+    pub files_per_directory: usize,
+    /// how many files each commit changes
+    pub files_per_commit: usize,
+}
+
+impl Default for BenchFixtureConfig {
+    fn default() -> Self {
+        BenchFixtureConfig {
+            files: 1000,
+            commits: 500,
+            files_per_directory: 20,
+            files_per_commit: 5,
+        }
+    }
+}
+
+/// a fixed pseudo-random sequence - an LCG, good enough for picking files
+struct Sequence(u64);
+
+impl Sequence {
+    fn next(&mut self, below: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((self.0 >> 33) % below as u64) as usize
+    }
+}
+
+/// "d3/d1/file_42.rs" style paths, nesting a level for each `files_per_directory` power
+fn fixture_path(index: usize, files_per_directory: usize) -> PathBuf {
+    let mut path = PathBuf::new();
+    let mut directory = index / files_per_directory;
+    while directory > 0 {
+        path.push(format!("d{}", directory % files_per_directory));
+        directory /= files_per_directory;
+    }
+    path.push(format!("file_{}.rs", index));
+    path
+}
+
+/// source with some nesting, so indentation and nesting calculators have work to do
+fn fixture_source(index: usize, revision: usize) -> String {
+    let mut source = format!("// file {} revision {}\n", index, revision);
+    for function in 0..=(index % 7) {
+        source.push_str(&format!("fn function_{}(value: u64) -> u64 {{\n", function));
+        for depth in 1..=(function % 4) + 1 {
+            source.push_str(&format!(
+                "{}if value > {} {{\n",
+                "    ".repeat(depth),
+                depth
+            ));
+        }
+        for depth in (1..=(function % 4) + 1).rev() {
+            source.push_str(&format!("{}}}\n", "    ".repeat(depth)));
+        }
+        source.push_str(&format!("    value + {}\n}}\n\n", revision));
+    }
+    source
+}
+
+fn write_file(root: &Path, relative: &Path, contents: &str) -> Result<(), Error> {
+    let path = root.join(relative);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, contents).with_context(|| format!("writing {:?}", path))
+}
+
+fn commit_all(
+    repository: &Repository,
+    author: &str,
+    time: i64,
+    message: &str,
+) -> Result<(), Error> {
+    let mut index = repository.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repository.find_tree(index.write_tree()?)?;
+    let signature = Signature::new(
+        author,
+        &format!("{}@example.com", author),
+        &Time::new(time, 0),
+    )?;
+    let parent = match repository.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+    let parents: Vec<&git2::Commit<'_>> = parent.iter().collect();
+    repository.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    Ok(())
+}
+
+/// writes a fixture repository into `root`, which must not already have files in it
+pub fn generate_bench_fixture(root: &Path, config: BenchFixtureConfig) -> Result<(), Error> {
+    if root.exists() && fs::read_dir(root)?.next().is_some() {
+        bail!(
+            "{:?} is not empty - fixtures must be generated in a new directory",
+            root
+        );
+    }
+    if config.files == 0 || config.files_per_directory < 2 {
+        bail!("Fixtures need at least one file, and at least two files per directory");
+    }
+    fs::create_dir_all(root)?;
+    let repository = Repository::init(root)?;
+    let paths: Vec<PathBuf> = (0..config.files)
+        .map(|index| fixture_path(index, config.files_per_directory))
+        .collect();
+    for (index, path) in paths.iter().enumerate() {
+        write_file(root, path, &fixture_source(index, 0))?;
+    }
+    commit_all(&repository, AUTHORS[0], START_TIME, "Initial commit")?;
+
+    let mut sequence = Sequence(config.files as u64 ^ config.commits as u64);
+    let mut time = START_TIME;
+    for revision in 1..config.commits {
+        // a few hours apart, sometimes a few days - so coupling sees separate bursts
+        time += 3600 * (1 + sequence.next(48)) as i64;
+        for _ in 0..config.files_per_commit {
+            let index = sequence.next(config.files);
+            write_file(root, &paths[index], &fixture_source(index, revision))?;
+        }
+        let author = AUTHORS[sequence.next(AUTHORS.len())];
+        commit_all(
+            &repository,
+            author,
+            time,
+            &format!("Change {} files", config.files_per_commit),
+        )?;
+    }
+    info!(
+        "Generated {} files with {} commits in {:?}",
+        config.files, config.commits, root
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn paths_nest_by_directory_size() {
+        assert_eq!(fixture_path(3, 10), PathBuf::from("file_3.rs"));
+        assert_eq!(fixture_path(42, 10), PathBuf::from("d4/file_42.rs"));
+        assert_eq!(fixture_path(123, 10), PathBuf::from("d2/d1/file_123.rs"));
+    }
+
+    #[test]
+    fn fixtures_have_files_and_history() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("fixture");
+        let config = BenchFixtureConfig {
+            files: 30,
+            commits: 10,
+            files_per_directory: 10,
+            files_per_commit: 3,
+        };
+        generate_bench_fixture(&root, config)?;
+
+        assert!(root.join("d2/file_25.rs").is_file());
+        let repository = Repository::open(&root)?;
+        let mut revwalk = repository.revwalk()?;
+        revwalk.push_head()?;
+        assert_eq!(revwalk.count(), 10);
+        assert!(generate_bench_fixture(&root, config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+#![warn(clippy::all)]
+//! Thin wrappers around the scanner's hot paths, for the criterion benchmarks in `benches/`.
+//!
+//! Only built with the `bench` feature - these aren't a stable API, they exist so benchmarks can
+//! time one phase at a time without a whole scan around it.
+
+use anyhow::Error;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::coupling::{coupled_file_count, CouplingConfig};
+use crate::file_walker::{directory_paths, WalkOptions};
+use crate::flare::FlareTreeNode;
+use crate::git::{GitActivity, GitData, GitNodeData};
+use crate::git_logger::{CommitChange, GitLog, GitLogConfig};
+use crate::indentation::{parse_file, IndentationConfig};
+
+/// walks a directory the way a scan does, returning how many paths were found
+pub fn walk_paths(root: &Path) -> Result<usize, Error> {
+    let (paths, _) = directory_paths(root, &WalkOptions::default())?;
+    Ok(paths.filter(Result::is_ok).count())
+}
+
+/// measures a single file's indentation, returning the indentation sum
+pub fn indentation(file: &Path) -> Result<Option<u64>, Error> {
+    Ok(parse_file(file, &IndentationConfig::default())?.map(|data| data.sum))
+}
+
+/// parses the whole git log of the repository containing `root`, returning how many file
+/// changes it had
+pub fn git_log_file_changes(root: &Path) -> Result<usize, Error> {
+    let git_log = GitLog::new(root, GitLogConfig::default())?;
+    let mut changes = 0;
+    for entry in git_log.iterator()? {
+        changes += entry?.file_changes().len();
+    }
+    Ok(changes)
+}
+
+/// a tree with git activity but no files on disk, for benchmarking coupling
+pub struct SyntheticTree(FlareTreeNode);
+
+impl SyntheticTree {
+    /// `files` files spread over directories of 20, each changed in a deterministic pattern
+    /// across `commits` commits an hour apart
+    #[must_use]
+    pub fn new(files: usize, commits: usize) -> Self {
+        let mut root = FlareTreeNode::dir("root");
+        let mut dir = FlareTreeNode::dir("d0");
+        for file in 0..files {
+            if file > 0 && file % 20 == 0 {
+                root.append_child(dir);
+                dir = FlareTreeNode::dir(format!("d{}", file / 20));
+            }
+            let activity = (0..commits)
+                .filter(|commit| (commit * 7 + file * 3) % 11 < 2)
+                .map(|commit| GitActivity {
+                    author_time: 1_577_836_800 + commit as u64 * 3600,
+                    commit_time: 1_577_836_800 + commit as u64 * 3600,
+                    users: BTreeSet::new(),
+                    change: CommitChange::Modify,
+                    lines_added: 1,
+                    lines_deleted: 1,
+                    bulk: false,
+                    parent_count: 1,
+                    tickets: Vec::new(),
+                })
+                .collect();
+            let mut node = FlareTreeNode::file(format!("file_{}.rs", file));
+            node.indicators_mut().git = Some(GitNodeData::File {
+                data: GitData::fake_with_activity(activity),
+            });
+            dir.append_child(node);
+        }
+        root.append_child(dir);
+        SyntheticTree(root)
+    }
+}
+
+/// builds coupling buckets for a tree with the default coupling settings, returning how many
+/// files are coupled
+pub fn coupling_buckets(tree: &SyntheticTree) -> Result<usize, Error> {
+    let config = CouplingConfig::new(91, 10, 0.8, 60 * 60, 60 * 60, 3, None);
+    coupled_file_count(&tree.0, config)
+}
@@ -0,0 +1,339 @@
+#![warn(clippy::all)]
+//! What binary files are - images, archives, executables and so on - from their leading "magic"
+//! bytes rather than their names, with image dimensions where the header has them, and whether
+//! git LFS tracks them.
+//!
+//! Only the first few kilobytes of each file are read. JPEG dimensions are only found if the
+//! frame header is in that range, which it usually is unless there is a big embedded thumbnail.
+//!
+//! LFS pointer files - what is checked out when the LFS content hasn't been fetched - are
+//! recognised too, so they can be reported with the size they point to rather than as tiny text
+//! files.
+
+use anyhow::Error;
+use git2::{AttrCheckFlags, Repository};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// how much of each file is read
+const HEADER_BYTES: u64 = 64 * 1024;
+
+/// the LFS spec says pointer files must be smaller than this
+const MAX_LFS_POINTER_BYTES: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryKind {
+    Image,
+    Archive,
+    Executable,
+    Document,
+    Audio,
+    Video,
+    Font,
+    Database,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinaryData {
+    pub kind: BinaryKind,
+    /// e.g. "png" or "zip" - None if the kind is unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// image width and height in pixels, if the format has them in its header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// tracked by git LFS, according to git attributes
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
+}
+
+/// magic bytes at an offset, and what files starting with them are
+const SIGNATURES: &[(usize, &[u8], BinaryKind, &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", BinaryKind::Image, "png"),
+    (0, b"GIF87a", BinaryKind::Image, "gif"),
+    (0, b"GIF89a", BinaryKind::Image, "gif"),
+    (0, b"\xff\xd8\xff", BinaryKind::Image, "jpeg"),
+    (0, b"BM", BinaryKind::Image, "bmp"),
+    (8, b"WEBP", BinaryKind::Image, "webp"),
+    (0, b"\x00\x00\x01\x00", BinaryKind::Image, "ico"),
+    (8, b"WAVE", BinaryKind::Audio, "wav"),
+    (0, b"ID3", BinaryKind::Audio, "mp3"),
+    (0, b"OggS", BinaryKind::Audio, "ogg"),
+    (0, b"fLaC", BinaryKind::Audio, "flac"),
+    (4, b"ftyp", BinaryKind::Video, "mp4"),
+    (0, b"%PDF", BinaryKind::Document, "pdf"),
+    (0, b"PK\x03\x04", BinaryKind::Archive, "zip"),
+    (0, b"\x1f\x8b", BinaryKind::Archive, "gzip"),
+    (0, b"BZh", BinaryKind::Archive, "bzip2"),
+    (0, b"\xfd7zXZ\x00", BinaryKind::Archive, "xz"),
+    (0, b"7z\xbc\xaf\x27\x1c", BinaryKind::Archive, "7z"),
+    (257, b"ustar", BinaryKind::Archive, "tar"),
+    (0, b"\x7fELF", BinaryKind::Executable, "elf"),
+    (0, b"\xcf\xfa\xed\xfe", BinaryKind::Executable, "mach-o"),
+    (0, b"\xce\xfa\xed\xfe", BinaryKind::Executable, "mach-o"),
+    (0, b"MZ", BinaryKind::Executable, "pe"),
+    (0, b"\x00asm", BinaryKind::Executable, "wasm"),
+    // fat mach-o binaries share this with java classes, which are more likely in source trees
+    (0, b"\xca\xfe\xba\xbe", BinaryKind::Executable, "java-class"),
+    (0, b"wOFF", BinaryKind::Font, "woff"),
+    (0, b"wOF2", BinaryKind::Font, "woff2"),
+    (0, b"OTTO", BinaryKind::Font, "otf"),
+    (0, b"\x00\x01\x00\x00\x00", BinaryKind::Font, "ttf"),
+    (0, b"SQLite format 3\x00", BinaryKind::Database, "sqlite"),
+];
+
+fn u16_be(header: &[u8], offset: usize) -> Option<u32> {
+    let bytes = header.get(offset..offset + 2)?;
+    Some(u32::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+}
+
+fn u16_le(header: &[u8], offset: usize) -> Option<u32> {
+    let bytes = header.get(offset..offset + 2)?;
+    Some(u32::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+}
+
+fn u32_be(header: &[u8], offset: usize) -> Option<u32> {
+    let bytes = header.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn i32_le(header: &[u8], offset: usize) -> Option<u32> {
+    let bytes = header.get(offset..offset + 4)?;
+    Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).unsigned_abs())
+}
+
+/// walks JPEG segments to the first frame header, which has the dimensions
+fn jpeg_dimensions(header: &[u8]) -> Option<(u32, u32)> {
+    let mut offset = 2;
+    loop {
+        if *header.get(offset)? != 0xff {
+            return None;
+        }
+        let marker = *header.get(offset + 1)?;
+        // SOF0 to SOF15, except DHT, JPG and DAC which share the range
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            return Some((u16_be(header, offset + 7)?, u16_be(header, offset + 5)?));
+        }
+        offset += 2 + u16_be(header, offset + 2)? as usize;
+    }
+}
+
+fn image_dimensions(format: &str, header: &[u8]) -> Option<(u32, u32)> {
+    match format {
+        "png" => Some((u32_be(header, 16)?, u32_be(header, 20)?)),
+        "gif" => Some((u16_le(header, 6)?, u16_le(header, 8)?)),
+        "bmp" => Some((i32_le(header, 18)?, i32_le(header, 22)?)),
+        "jpeg" => jpeg_dimensions(header),
+        _ => None,
+    }
+}
+
+/// the kind and format from a file's leading bytes
+fn identify(header: &[u8]) -> (BinaryKind, Option<&'static str>) {
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _, _)| header.get(*offset..offset + magic.len()) == Some(*magic))
+        .map_or((BinaryKind::Unknown, None), |(_, _, kind, format)| {
+            (*kind, Some(*format))
+        })
+}
+
+/// true if git attributes say the file is stored with git LFS - false outside git repositories
+fn lfs_tracked(file: &Path) -> bool {
+    let repository = match Repository::discover(file.parent().unwrap_or(file)) {
+        Ok(repository) => repository,
+        Err(_) => return false,
+    };
+    let (workdir, file) = match (
+        repository.workdir().map(Path::canonicalize),
+        file.canonicalize(),
+    ) {
+        (Some(Ok(workdir)), Ok(file)) => (workdir, file),
+        _ => return false,
+    };
+    match file.strip_prefix(&workdir) {
+        Ok(relative) => matches!(
+            repository.get_attr(relative, "filter", AttrCheckFlags::FILE_THEN_INDEX),
+            Ok(Some("lfs"))
+        ),
+        Err(_) => false,
+    }
+}
+
+/// the declared size of the real content, if this is an LFS pointer file - see
+/// <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>
+fn parse_lfs_pointer(content: &[u8]) -> Option<u64> {
+    let content = std::str::from_utf8(content).ok()?;
+    let mut lines = content.lines();
+    let version = lines.next()?.strip_prefix("version ")?;
+    if !version.starts_with("https://git-lfs.github.com/spec/")
+        && !version.starts_with("https://hawser.github.com/spec/")
+    {
+        return None;
+    }
+    let mut has_oid = false;
+    let mut size = None;
+    for line in lines {
+        match line.split_once(' ')? {
+            ("oid", oid) => has_oid = oid.contains(':'),
+            ("size", value) => size = Some(value.parse().ok()?),
+            _ => (),
+        }
+    }
+    if has_oid {
+        size
+    } else {
+        None
+    }
+}
+
+/// the declared size of the real content, if the file is an LFS pointer rather than the content
+pub fn lfs_pointer_size(file: &Path) -> Result<Option<u64>, Error> {
+    if file.metadata()?.len() >= MAX_LFS_POINTER_BYTES {
+        return Ok(None);
+    }
+    let mut content = Vec::new();
+    File::open(file)?
+        .take(MAX_LFS_POINTER_BYTES)
+        .read_to_end(&mut content)?;
+    Ok(parse_lfs_pointer(&content))
+}
+
+/// an LFS pointer says nothing about what the content is, only that it is in LFS
+fn lfs_pointer() -> BinaryData {
+    BinaryData {
+        kind: BinaryKind::Unknown,
+        format: None,
+        width: None,
+        height: None,
+        lfs: true,
+    }
+}
+
+fn describe_header(header: &[u8]) -> BinaryData {
+    let (kind, format) = identify(header);
+    let dimensions = match (kind, format) {
+        (BinaryKind::Image, Some(format)) => image_dimensions(format, header),
+        _ => None,
+    };
+    BinaryData {
+        kind,
+        format: format.map(str::to_owned),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        lfs: false,
+    }
+}
+
+/// what a binary file is, from its first few kilobytes
+pub fn describe(file: &Path) -> Result<BinaryData, Error> {
+    if lfs_pointer_size(file)?.is_some() {
+        return Ok(lfs_pointer());
+    }
+    let mut header = Vec::new();
+    File::open(file)?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)?;
+    Ok(BinaryData {
+        lfs: lfs_tracked(file),
+        ..describe_header(&header)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn image(format: &str, width: u32, height: u32) -> BinaryData {
+        BinaryData {
+            kind: BinaryKind::Image,
+            format: Some(format.to_owned()),
+            width: Some(width),
+            height: Some(height),
+            lfs: false,
+        }
+    }
+
+    #[test]
+    fn image_formats_and_dimensions_are_found() {
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend(640_u32.to_be_bytes());
+        png.extend(480_u32.to_be_bytes());
+        assert_eq!(describe_header(&png), image("png", 640, 480));
+
+        let gif = b"GIF89a\x20\x03\x58\x02rest";
+        assert_eq!(describe_header(gif), image("gif", 800, 600));
+
+        // SOI, an APP0 segment, then a baseline frame header
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04ab\xff\xc0\x00\x11\x08\x00\x78\x00\xa0\x03";
+        assert_eq!(describe_header(jpeg), image("jpeg", 160, 120));
+    }
+
+    #[test]
+    fn other_kinds_are_found_by_magic_bytes() {
+        let zip = describe_header(b"PK\x03\x04rest of the zip");
+        assert_eq!(zip.kind, BinaryKind::Archive);
+        assert_eq!(zip.format.as_deref(), Some("zip"));
+        assert_eq!(zip.width, None);
+
+        assert_eq!(
+            describe_header(b"\x7fELF\x02\x01").kind,
+            BinaryKind::Executable
+        );
+        assert_eq!(
+            describe_header(b"\x00\x00\x00\x18ftypmp42").kind,
+            BinaryKind::Video
+        );
+        assert_eq!(
+            describe_header(b"\x00\x01\x02\x03"),
+            BinaryData {
+                kind: BinaryKind::Unknown,
+                format: None,
+                width: None,
+                height: None,
+                lfs: false
+            }
+        );
+    }
+
+    #[test]
+    fn lfs_pointers_are_parsed() {
+        let pointer = b"version https://git-lfs.github.com/spec/v1\n\
+            oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+            size 12345\n";
+        assert_eq!(parse_lfs_pointer(pointer), Some(12345));
+
+        assert_eq!(
+            parse_lfs_pointer(b"version https://git-lfs.github.com/spec/v1\nsize 12345\n"),
+            None
+        );
+        assert_eq!(
+            parse_lfs_pointer(b"version 1.2.3\noid sha256:abc\nsize 1\n"),
+            None
+        );
+        assert_eq!(parse_lfs_pointer(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn lfs_files_are_found_from_git_attributes() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        Repository::init(dir.path())?;
+        std::fs::write(
+            dir.path().join(".gitattributes"),
+            "*.psd filter=lfs diff=lfs merge=lfs -text\n",
+        )?;
+        std::fs::write(dir.path().join("design.psd"), b"8BPS\x00\x01")?;
+        std::fs::write(dir.path().join("logo.gif"), b"GIF89a")?;
+
+        assert!(describe(&dir.path().join("design.psd"))?.lfs);
+        assert!(!describe(&dir.path().join("logo.gif"))?.lfs);
+        Ok(())
+    }
+}
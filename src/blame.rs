@@ -0,0 +1,231 @@
+#![warn(clippy::all)]
+//! Line ownership from `git blame` - who last changed each line that is in the file now.
+//!
+//! This is much more accurate than counting commits, but much slower, so it is opt-in, limited
+//! to smaller files, and can be sampled. It is run by the git calculator, so user ids are the
+//! same as in git data. Only committed lines are blamed - uncommitted changes are ignored.
+
+use crate::git_logger::User;
+use crate::git_user_dictionary::GitUserDictionary;
+use anyhow::Error;
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameConfig {
+    /// larger files aren't blamed
+    pub max_file_bytes: u64,
+    /// stop blaming after this many files
+    pub max_files: Option<usize>,
+    /// only blame every nth file that is small enough, in scan order
+    pub sample_every: usize,
+}
+
+impl Default for BlameConfig {
+    fn default() -> Self {
+        BlameConfig {
+            max_file_bytes: 256 * 1024,
+            max_files: None,
+            sample_every: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineOwnership {
+    pub user: usize,
+    pub lines: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameData {
+    /// total committed lines
+    pub lines: u64,
+    /// most lines first, with ties in user id order
+    pub owners: Vec<LineOwnership>,
+}
+
+/// blames files, keeping repositories open and counting files for limits and sampling
+pub struct Blamer {
+    config: BlameConfig,
+    repositories: HashMap<PathBuf, Repository>,
+    small_files: usize,
+    blamed_files: usize,
+}
+
+impl fmt::Debug for Blamer {
+    // git2 repositories aren't Debug
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blamer")
+            .field("config", &self.config)
+            .field(
+                "repositories",
+                &self.repositories.keys().collect::<Vec<_>>(),
+            )
+            .field("small_files", &self.small_files)
+            .field("blamed_files", &self.blamed_files)
+            .finish()
+    }
+}
+
+impl Blamer {
+    pub fn new(config: BlameConfig) -> Self {
+        Blamer {
+            config,
+            repositories: HashMap::new(),
+            small_files: 0,
+            blamed_files: 0,
+        }
+    }
+
+    /// should this file be blamed, given the size limit, file limit, and sampling?
+    fn select(&mut self, file: &Path) -> Result<bool, Error> {
+        if fs::metadata(file)?.len() > self.config.max_file_bytes {
+            return Ok(false);
+        }
+        if let Some(max_files) = self.config.max_files {
+            if self.blamed_files >= max_files {
+                return Ok(false);
+            }
+        }
+        let sampled = self.small_files % self.config.sample_every.max(1) == 0;
+        self.small_files += 1;
+        if sampled {
+            self.blamed_files += 1;
+        }
+        Ok(sampled)
+    }
+
+    /// line ownership for a file in the repository at `workdir`, and (author time, lines) pairs
+    /// for line ages - None if the file wasn't selected
+    pub fn blame(
+        &mut self,
+        file: &Path,
+        workdir: &Path,
+        dictionary: &mut GitUserDictionary,
+    ) -> Result<Option<(BlameData, Vec<(u64, u64)>)>, Error> {
+        if !self.select(file)? {
+            return Ok(None);
+        }
+        let repository = match self.repositories.entry(workdir.to_owned()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(Repository::open(workdir)?),
+        };
+        let canonical_file = file.canonicalize()?;
+        let relative_file = canonical_file.strip_prefix(workdir)?;
+        let blame = repository.blame_file(relative_file, None)?;
+        let mut lines_by_user: HashMap<usize, u64> = HashMap::new();
+        let mut line_times = Vec::new();
+        for hunk in blame.iter() {
+            let signature = hunk.final_signature();
+            let user = dictionary.register(&User::new(signature.name(), signature.email()));
+            let lines = hunk.lines_in_hunk() as u64;
+            *lines_by_user.entry(user).or_default() += lines;
+            line_times.push((signature.when().seconds() as u64, lines));
+        }
+        Ok(Some((blame_data(lines_by_user), line_times)))
+    }
+}
+
+impl BlameData {
+    /// change all user ids, e.g. when merging user dictionaries - see `GitData::remap_users`
+    pub fn remap_users(&mut self, new_ids: &[usize]) -> Result<(), Error> {
+        let mut lines_by_user: HashMap<usize, u64> = HashMap::new();
+        for owner in &self.owners {
+            let user = new_ids
+                .get(owner.user)
+                .copied()
+                .ok_or_else(|| anyhow!("Unknown user id {}", owner.user))?;
+            *lines_by_user.entry(user).or_default() += owner.lines;
+        }
+        *self = blame_data(lines_by_user);
+        Ok(())
+    }
+}
+
+fn blame_data(lines_by_user: HashMap<usize, u64>) -> BlameData {
+    let mut owners: Vec<LineOwnership> = lines_by_user
+        .into_iter()
+        .map(|(user, lines)| LineOwnership { user, lines })
+        .collect();
+    owners.sort_by(|a, b| b.lines.cmp(&a.lines).then(a.user.cmp(&b.user)));
+    BlameData {
+        lines: owners.iter().map(|owner| owner.lines).sum(),
+        owners,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+    use test_shared::unzip_test_sample;
+
+    #[test]
+    fn owners_are_sorted_by_lines() {
+        let data = blame_data(HashMap::from([(2, 5), (0, 1), (1, 5)]));
+        assert_eq!(
+            data,
+            BlameData {
+                lines: 11,
+                owners: vec![
+                    LineOwnership { user: 1, lines: 5 },
+                    LineOwnership { user: 2, lines: 5 },
+                    LineOwnership { user: 0, lines: 1 },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn remapped_owners_are_combined() -> Result<(), Error> {
+        let mut data = blame_data(HashMap::from([(0, 2), (1, 3), (2, 4)]));
+        data.remap_users(&[5, 6, 5])?;
+        assert_eq!(
+            data,
+            BlameData {
+                lines: 9,
+                owners: vec![
+                    LineOwnership { user: 5, lines: 6 },
+                    LineOwnership { user: 6, lines: 3 },
+                ]
+            }
+        );
+        assert!(data.remap_users(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn files_can_be_blamed_with_sampling() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        let workdir = git_root.canonicalize()?;
+        let file = git_root.join("simple/parent.clj");
+        let mut dictionary = GitUserDictionary::default();
+        let mut blamer = Blamer::new(BlameConfig {
+            sample_every: 2,
+            ..BlameConfig::default()
+        });
+
+        let (data, line_times) = blamer.blame(&file, &workdir, &mut dictionary)?.unwrap();
+        assert_eq!(
+            data.lines,
+            fs::read_to_string(&file)?.lines().count() as u64
+        );
+        assert!(!data.owners.is_empty());
+        assert_eq!(
+            line_times.iter().map(|(_, lines)| lines).sum::<u64>(),
+            data.lines
+        );
+        assert_eq!(blamer.blame(&file, &workdir, &mut dictionary)?, None);
+        assert!(blamer.blame(&file, &workdir, &mut dictionary)?.is_some());
+
+        Ok(())
+    }
+}
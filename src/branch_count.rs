@@ -0,0 +1,131 @@
+#![warn(clippy::all)]
+//! A cheap complexity estimate - counts branching keywords and boolean operators in code lines,
+//! similar to the "complexity" count from [scc](https://github.com/boyter/scc).
+//!
+//! This is deliberately language-agnostic, the same keywords are counted for every language, so
+//! it is only useful for comparing files in similar languages. Comments are excluded (via tokei)
+//! but strings are not. Files in unknown languages are not counted.
+
+use crate::code_line_data::{should_stream, ParseLimits};
+use crate::diagnostics::Diagnostic;
+use crate::flare::FlareTreeNode;
+use crate::polyglot_data::IndicatorMetadata;
+
+use super::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use anyhow::{Context, Error};
+use std::path::{Path, PathBuf};
+use tokei::{Config, LanguageType};
+
+const BRANCH_KEYWORDS: &[&str] = &[
+    "if", "elif", "elsif", "for", "foreach", "while", "until", "unless", "case", "when", "catch",
+    "except", "rescue",
+];
+
+const BRANCH_OPERATORS: &[&str] = &["&&", "||"];
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+fn line_branches(line: &[u8]) -> u64 {
+    let keywords = line
+        .split(|c| !is_identifier_byte(*c))
+        .filter(|word| BRANCH_KEYWORDS.iter().any(|k| k.as_bytes() == *word))
+        .count();
+    let operators: usize = BRANCH_OPERATORS
+        .iter()
+        .map(|op| {
+            line.windows(op.len())
+                .filter(|window| *window == op.as_bytes())
+                .count()
+        })
+        .sum();
+    (keywords + operators) as u64
+}
+
+fn parse_file(filename: &Path, limits: &ParseLimits) -> Result<Option<u64>, Error> {
+    let config = Config::default();
+    let language = match LanguageType::from_path(filename, &config) {
+        Some(language) => language,
+        None => return Ok(None),
+    };
+    if should_stream(filename, limits) {
+        debug!("Not counting branches for large file {:?}", filename);
+        return Ok(None);
+    }
+    let report = language
+        .parse(PathBuf::from(filename), &config)
+        .map_err(|(error, _pathbuf)| error)?;
+    Ok(Some(
+        report
+            .stats
+            .code_lines
+            .iter()
+            .map(|line| line_branches(limits.line(line)))
+            .sum(),
+    ))
+}
+
+#[derive(Debug, Default)]
+pub struct BranchCountCalculator {
+    limits: ParseLimits,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl BranchCountCalculator {
+    pub fn new(limits: ParseLimits) -> Self {
+        BranchCountCalculator {
+            limits,
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl ToxicityIndicatorCalculator for BranchCountCalculator {
+    fn name(&self) -> String {
+        "branch_count".to_string()
+    }
+
+    fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        if path.is_file() {
+            match parse_file(path, &self.limits)
+                .with_context(|| format!("counting branches for {:?}", path))
+            {
+                Ok(branch_count) => node.indicators_mut().branch_count = branch_count,
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, &self.name(), &e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keywords_and_operators_are_counted() {
+        assert_eq!(line_branches(b"if (a && b || c) {"), 3);
+        assert_eq!(line_branches(b"} else if (x) { for_each(y); }"), 1);
+        assert_eq!(line_branches(b"while(true) switch (x) { case 1: }"), 2);
+        assert_eq!(line_branches(b"let iffy = notif;"), 0);
+    }
+
+    #[test]
+    fn unknown_languages_are_not_counted() {
+        let count = parse_file(Path::new("./tests/data/languages/foo.unknown"), None).unwrap();
+        assert_eq!(count, None);
+        let count = parse_file(Path::new("./tests/data/simple/parent.clj"), None).unwrap();
+        assert_eq!(count, Some(0));
+    }
+}
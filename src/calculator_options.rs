@@ -0,0 +1,133 @@
+#![warn(clippy::all)]
+//! Setting calculator options by name, e.g. `indentation.tab_width=2`, so every calculator config
+//! can be changed from the command line without a flag for each option.
+//!
+//! Options are the fields of a calculator's config, as named in its serialized form - the config
+//! is serialized to JSON, the option is set, and it is deserialized again. Values are parsed as
+//! JSON if they can be, so `2`, `true` and `["#", "//"]` work as expected, and anything else is
+//! a string. Nested fields and map entries are set with more dots, e.g.
+//! `indentation.language_tab_widths.Makefile=8`.
+
+use anyhow::{Context, Error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// a value parsed as JSON, or a plain string if it isn't valid JSON
+fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))
+}
+
+/// true for values that a config might leave out when serializing
+fn is_empty_value(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::Array(values) => values.is_empty(),
+        Value::Object(values) => values.is_empty(),
+        _ => false,
+    }
+}
+
+/// sets a dot-separated option in a config - fails if the option doesn't exist, or the value
+/// is the wrong type
+pub fn set_option<T: Serialize + DeserializeOwned>(
+    config: &mut T,
+    option: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let parts: Vec<&str> = option.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        bail!("Invalid option name '{}'", option);
+    }
+    let value = parse_value(value);
+    let mut json = serde_json::to_value(&*config)?;
+    let mut target = &mut json;
+    for part in &parts {
+        let object = target
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Option '{}' has no field '{}'", option, part))?;
+        // fields with default values may have been left out
+        target = object
+            .entry((*part).to_owned())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    *target = value.clone();
+    let updated: T = serde_json::from_value(json)
+        .with_context(|| format!("Invalid value {} for option '{}'", value, option))?;
+
+    // unknown fields are ignored when deserializing, so check the value was really used
+    let check = serde_json::to_value(&updated)?;
+    let stored = parts
+        .iter()
+        .try_fold(&check, |json, part| json.get(part))
+        .cloned()
+        .unwrap_or(Value::Null);
+    if stored != value && !(is_empty_value(&value) && is_empty_value(&stored)) {
+        bail!("Unknown option '{}'", option);
+    }
+    *config = updated;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        named_toxicity_indicator_calculator, IndentationConfig, ScannerConfig, CALCULATORS,
+    };
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn options_are_set_by_name() -> Result<(), Error> {
+        let mut config = IndentationConfig::default();
+        set_option(&mut config, "tab_width", "2")?;
+        set_option(&mut config, "language_tab_widths.Makefile", "8")?;
+        set_option(&mut config, "comment_prefixes", r##"["#"]"##)?;
+        set_option(&mut config, "histogram", "true")?;
+        set_option(&mut config, "max_lines", "null")?;
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(config.language_tab_widths.get("Makefile"), Some(&8));
+        assert_eq!(config.comment_prefixes, vec!["#".to_owned()]);
+        assert!(config.histogram);
+        assert_eq!(config.max_lines, None);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_options_and_bad_values_are_errors() {
+        let mut config = IndentationConfig::default();
+        assert!(set_option(&mut config, "tab_wdth", "2").is_err());
+        assert!(set_option(&mut config, "tab_width", "wide").is_err());
+        assert!(set_option(&mut config, "tab_width.inner", "2").is_err());
+        assert!(set_option(&mut config, "", "2").is_err());
+        assert_eq!(config, IndentationConfig::default());
+    }
+
+    #[test]
+    fn every_registered_calculator_can_be_built() {
+        let config = ScannerConfig::default("test");
+        for name in CALCULATORS {
+            if *name == "syntax" && !cfg!(feature = "tree-sitter") {
+                continue;
+            }
+            assert!(
+                named_toxicity_indicator_calculator(name, &config).is_some(),
+                "no calculator for {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn scanner_options_are_found_by_calculator() -> Result<(), Error> {
+        let mut config = ScannerConfig::default("test");
+        config.set_calculator_option("nesting.tab_width", "8")?;
+        config.set_calculator_option("license.lines", "5")?;
+        assert_eq!(config.indentation.tab_width, 8);
+        assert_eq!(config.license_lines, 5);
+        assert!(config.set_calculator_option("git.years", "2").is_err());
+        assert!(config.set_calculator_option("nonsense.x", "2").is_err());
+        assert!(config.set_calculator_option("tab_width", "2").is_err());
+        Ok(())
+    }
+}
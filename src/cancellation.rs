@@ -0,0 +1,104 @@
+#![warn(clippy::all)]
+//! Cancelling a scan from another thread - for applications embedding the scanner.
+//!
+//! The token is checked between files, while loading git logs, and between scan phases, so a
+//! cancelled scan stops soon but not instantly. A cancelled scan fails with a `ScanCancelled`
+//! error, which holds any diagnostics gathered before it stopped.
+
+use crate::diagnostics::Diagnostic;
+use anyhow::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// cheap to clone - all clones share the same state, so one can be kept to cancel a running scan
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// fails with an empty `ScanCancelled` if cancelled - callers can add diagnostics with `cancelled_with`
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(ScanCancelled::default().into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// the error returned from a cancelled scan - use `Error::downcast_ref` to tell it from other errors
+#[derive(Debug, Default)]
+pub struct ScanCancelled {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ScanCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Scan cancelled, with {} diagnostics",
+            self.diagnostics.len()
+        )
+    }
+}
+
+impl std::error::Error for ScanCancelled {}
+
+pub(crate) fn is_cancellation(error: &Error) -> bool {
+    error.downcast_ref::<ScanCancelled>().is_some()
+}
+
+/// a cancellation error holding these diagnostics, as well as any it already had
+pub(crate) fn cancelled_with(error: &Error, diagnostics: Vec<Diagnostic>) -> Error {
+    let mut all = diagnostics;
+    if let Some(cancelled) = error.downcast_ref::<ScanCancelled>() {
+        all.extend(cancelled.diagnostics.iter().cloned());
+    }
+    ScanCancelled { diagnostics: all }.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostics::DiagnosticCategory;
+    use anyhow::Context;
+
+    #[test]
+    fn clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        assert!(clone.check().is_ok());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert!(is_cancellation(&clone.check().unwrap_err()));
+    }
+
+    #[test]
+    fn cancellation_is_found_through_context() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let error = token.check().context("loading git log").unwrap_err();
+        assert!(is_cancellation(&error));
+        let diagnostic = Diagnostic::new(DiagnosticCategory::SkippedPath, None, "skipped");
+        let error = cancelled_with(&error, vec![diagnostic.clone()]);
+        assert_eq!(
+            error.downcast_ref::<ScanCancelled>().unwrap().diagnostics,
+            vec![diagnostic]
+        );
+    }
+}
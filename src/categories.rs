@@ -0,0 +1,295 @@
+#![warn(clippy::all)]
+//! File categories - build config, CI pipelines, infrastructure as code, docs, tests, assets
+//! and source - so visualisations can be filtered to just source code, or just tests.
+//!
+//! Categories come from rules of gitignore-style globs, relative to the scanned directory. The
+//! first matching rule wins, and custom rules are checked before the built-in ones. Files no rule
+//! matches are "source" if they are in a language tokei knows, otherwise they have no category.
+
+use crate::flare::FlareTreeNode;
+use crate::polyglot_data::IndicatorMetadata;
+use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use anyhow::{Context, Error};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+use tokei::{Config, LanguageType};
+
+/// the category for files in known languages that no rule matches
+pub const SOURCE_CATEGORY: &str = "source";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CategoryRule {
+    pub category: String,
+    pub pattern: String,
+}
+
+/// Finds the category of a file from its path
+#[derive(Debug, Clone)]
+pub struct FileCategorizer {
+    rules: Vec<CategoryRule>,
+    matchers: Vec<Gitignore>,
+}
+
+impl FileCategorizer {
+    pub fn new(rules: Vec<CategoryRule>) -> Result<Self, Error> {
+        let matchers = rules
+            .iter()
+            .map(|rule| {
+                let mut builder = GitignoreBuilder::new(".");
+                builder
+                    .add_line(None, &rule.pattern)
+                    .with_context(|| format!("invalid pattern for category {}", rule.category))?;
+                Ok(builder.build()?)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(FileCategorizer { rules, matchers })
+    }
+
+    /// custom rules, then the defaults
+    pub fn with_custom_rules(custom_rules: Vec<CategoryRule>) -> Result<Self, Error> {
+        FileCategorizer::new(
+            custom_rules
+                .into_iter()
+                .chain(FileCategorizer::default_rules())
+                .collect(),
+        )
+    }
+
+    /// common build, CI, infrastructure, docs, test and asset files
+    #[must_use]
+    pub fn default_rules() -> Vec<CategoryRule> {
+        let categories: &[(&str, &[&str])] = &[
+            (
+                "ci",
+                &[
+                    ".github/workflows/",
+                    ".gitlab-ci.yml",
+                    ".circleci/",
+                    ".travis.yml",
+                    "Jenkinsfile",
+                    "azure-pipelines.yml",
+                    "bitbucket-pipelines.yml",
+                    ".buildkite/",
+                ],
+            ),
+            (
+                "infrastructure",
+                &[
+                    "*.tf",
+                    "*.tfvars",
+                    "*.hcl",
+                    "Dockerfile",
+                    "*.dockerfile",
+                    "docker-compose*.yml",
+                    "docker-compose*.yaml",
+                    "k8s/",
+                    "kubernetes/",
+                    "helm/",
+                    "charts/",
+                    "ansible/",
+                ],
+            ),
+            (
+                "build",
+                &[
+                    "Cargo.toml",
+                    "Cargo.lock",
+                    "package.json",
+                    "package-lock.json",
+                    "yarn.lock",
+                    "pnpm-lock.yaml",
+                    "pom.xml",
+                    "*.gradle",
+                    "*.gradle.kts",
+                    "Makefile",
+                    "CMakeLists.txt",
+                    "setup.py",
+                    "setup.cfg",
+                    "pyproject.toml",
+                    "requirements*.txt",
+                    "go.mod",
+                    "go.sum",
+                    "Gemfile",
+                    "Gemfile.lock",
+                    "*.csproj",
+                    "*.sln",
+                ],
+            ),
+            (
+                "test",
+                &[
+                    "test/",
+                    "tests/",
+                    "spec/",
+                    "__tests__/",
+                    "*_test.*",
+                    "*.test.*",
+                    "*_spec.*",
+                    "*.spec.*",
+                    "test_*.py",
+                    "*Test.java",
+                ],
+            ),
+            (
+                "docs",
+                &[
+                    "docs/",
+                    "doc/",
+                    "*.md",
+                    "*.markdown",
+                    "*.rst",
+                    "*.adoc",
+                    "LICENSE*",
+                ],
+            ),
+            (
+                "asset",
+                &[
+                    "*.png", "*.jpg", "*.jpeg", "*.gif", "*.svg", "*.ico", "*.webp", "*.woff",
+                    "*.woff2", "*.ttf", "*.eot", "*.mp3", "*.mp4", "*.wav", "*.pdf",
+                ],
+            ),
+        ];
+        categories
+            .iter()
+            .flat_map(|(category, patterns)| {
+                patterns.iter().map(|pattern| CategoryRule {
+                    category: (*category).to_owned(),
+                    pattern: (*pattern).to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn rules(&self) -> &[CategoryRule] {
+        &self.rules
+    }
+
+    /// the category of the first matching rule, if any
+    fn rule_category(&self, relative_path: &Path) -> Option<&str> {
+        self.rules
+            .iter()
+            .zip(&self.matchers)
+            .find(|(_, matcher)| {
+                matcher
+                    .matched_path_or_any_parents(relative_path, false)
+                    .is_ignore()
+            })
+            .map(|(rule, _)| rule.category.as_str())
+    }
+
+    /// the category for a file - `relative_path` is relative to the scanned directory, `file`
+    /// is where it is on disk, as languages may need the file contents
+    #[must_use]
+    pub fn category_for(&self, relative_path: &Path, file: &Path) -> Option<String> {
+        match self.rule_category(relative_path) {
+            Some(category) => Some(category.to_owned()),
+            None => LanguageType::from_path(file, &Config::default())
+                .map(|_| SOURCE_CATEGORY.to_owned()),
+        }
+    }
+}
+
+impl Default for FileCategorizer {
+    fn default() -> Self {
+        FileCategorizer::new(FileCategorizer::default_rules())
+            .expect("invalid default category rules")
+    }
+}
+
+impl Serialize for FileCategorizer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.rules.serialize(serializer)
+    }
+}
+
+#[derive(Debug)]
+pub struct CategoryCalculator {
+    categorizer: FileCategorizer,
+    /// the root of the scan - the first node visited
+    root: Option<PathBuf>,
+}
+
+impl CategoryCalculator {
+    pub fn new(categorizer: FileCategorizer) -> Self {
+        CategoryCalculator {
+            categorizer,
+            root: None,
+        }
+    }
+}
+
+impl ToxicityIndicatorCalculator for CategoryCalculator {
+    fn name(&self) -> String {
+        "categories".to_string()
+    }
+
+    fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        let root = self.root.get_or_insert_with(|| path.to_owned());
+        if path.is_file() {
+            // a single file scan has the file as its root
+            let relative_path = match path.strip_prefix(root) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => Path::new(path.file_name().unwrap_or_default()),
+            };
+            node.indicators_mut().category = self.categorizer.category_for(relative_path, path);
+        }
+        Ok(())
+    }
+
+    fn apply_metadata(&self, metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        metadata.category_rules = Some(self.categorizer.rules().to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn files_are_categorised_by_path() {
+        let categorizer = FileCategorizer::default();
+        let category_of = |path: &str| categorizer.category_for(Path::new(path), Path::new(path));
+        assert_eq!(
+            category_of(".github/workflows/build.yml"),
+            Some("ci".into())
+        );
+        assert_eq!(category_of("deploy/main.tf"), Some("infrastructure".into()));
+        assert_eq!(category_of("Cargo.toml"), Some("build".into()));
+        assert_eq!(category_of("tests/integration.rs"), Some("test".into()));
+        assert_eq!(category_of("src/parser.test.ts"), Some("test".into()));
+        assert_eq!(category_of("README.md"), Some("docs".into()));
+        assert_eq!(category_of("web/logo.png"), Some("asset".into()));
+        assert_eq!(category_of("src/main.rs"), Some("source".into()));
+        assert_eq!(category_of("data/blob.xyz"), None);
+    }
+
+    #[test]
+    fn custom_rules_are_checked_first() -> Result<(), Error> {
+        let categorizer = FileCategorizer::with_custom_rules(vec![
+            CategoryRule {
+                category: "generated".to_owned(),
+                pattern: "src/generated/".to_owned(),
+            },
+            CategoryRule {
+                category: "source".to_owned(),
+                pattern: "src/docs/".to_owned(),
+            },
+        ])?;
+        let category_of = |path: &str| categorizer.category_for(Path::new(path), Path::new(path));
+        assert_eq!(
+            category_of("src/generated/api.rs"),
+            Some("generated".into())
+        );
+        assert_eq!(category_of("src/docs/render.rs"), Some("source".into()));
+        assert_eq!(category_of("docs/index.md"), Some("docs".into()));
+        Ok(())
+    }
+}
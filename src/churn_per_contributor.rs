@@ -0,0 +1,203 @@
+#![warn(clippy::all)]
+//! Churn normalised by team size - per directory, changes per contributor-month, so areas with
+//! big teams aren't hotspots just because of headcount.
+//!
+//! The window is the last `window_days` of the scan, up to the latest change in the scan, split
+//! into 30-day months counting back from the latest change. Each contributor active in a
+//! directory in a month is one contributor-month, so two people working for three months, or
+//! six people for one month, are six contributor-months either way.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::contributor_churn::latest_change;
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const SECONDS_PER_MONTH: u64 = 30 * SECONDS_PER_DAY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChurnPerContributorConfig {
+    /// how many days before the latest change are counted
+    pub window_days: u64,
+}
+
+impl Default for ChurnPerContributorConfig {
+    fn default() -> Self {
+        ChurnPerContributorConfig { window_days: 180 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChurnPerContributorData {
+    /// commits in the window
+    pub commits: u64,
+    /// lines added and deleted in the window
+    pub lines_changed: u64,
+    /// contributors active in each month of the window, summed over the months
+    pub contributor_months: u64,
+    pub commits_per_contributor_month: f64,
+    pub lines_per_contributor_month: f64,
+}
+
+impl ChurnPerContributorData {
+    fn new(commits: u64, lines_changed: u64, contributor_months: u64) -> Self {
+        let per_contributor_month = |value: u64| value as f64 / contributor_months as f64;
+        ChurnPerContributorData {
+            commits,
+            lines_changed,
+            contributor_months,
+            commits_per_contributor_month: per_contributor_month(commits),
+            lines_per_contributor_month: per_contributor_month(lines_changed),
+        }
+    }
+}
+
+/// changes in the window under a node
+#[derive(Debug, Default)]
+struct WindowChanges {
+    commits: u64,
+    lines_changed: u64,
+    /// (months before the latest change, user dictionary id)
+    active: HashSet<(u64, usize)>,
+}
+
+/// sets churn per contributor on each directory, returning the changes under the node
+fn apply_churn_per_contributor(
+    node: &mut FlareTreeNode,
+    window_start: u64,
+    latest: u64,
+) -> WindowChanges {
+    let mut changes = WindowChanges::default();
+    if let Some(GitNodeData::File { data }) = &node.indicators().git {
+        for details in data
+            .details
+            .iter()
+            .filter(|details| details.commit_day >= window_start)
+        {
+            changes.commits += details.commits;
+            changes.lines_changed += details.lines_added + details.lines_deleted;
+            let month = latest.saturating_sub(details.commit_day) / SECONDS_PER_MONTH;
+            changes
+                .active
+                .extend(details.users.iter().map(|user| (month, *user)));
+        }
+    }
+    if node.is_file() {
+        return changes;
+    }
+    for child in node.get_children_mut() {
+        let child_changes = apply_churn_per_contributor(child, window_start, latest);
+        changes.commits += child_changes.commits;
+        changes.lines_changed += child_changes.lines_changed;
+        changes.active.extend(child_changes.active);
+    }
+    if !changes.active.is_empty() {
+        node.indicators_mut().churn_per_contributor = Some(ChurnPerContributorData::new(
+            changes.commits,
+            changes.lines_changed,
+            changes.active.len() as u64,
+        ));
+    }
+    changes
+}
+
+/// adds churn per contributor-month to every directory with changes in the window
+pub fn calculate_churn_per_contributor(
+    tree: &mut FlareTreeNode,
+    config: &ChurnPerContributorConfig,
+) {
+    info!("Calculating churn per contributor");
+    let latest = latest_change(tree);
+    let window_start = latest.saturating_sub(config.window_days * SECONDS_PER_DAY);
+    apply_churn_per_contributor(tree, window_start, latest);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::{GitData, GitDetails};
+    use pretty_assertions::assert_eq;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+    const LATEST: u64 = 1000 * DAY;
+
+    /// a file changed by each user on the given day, adding 10 lines each time
+    fn git_file(name: &str, changes: &[(usize, u64)]) -> FlareTreeNode {
+        let mut data = GitData::fake_with_activity(Vec::new());
+        data.last_update = changes.iter().map(|(_, day)| *day).max().unwrap_or(0);
+        data.details = changes
+            .iter()
+            .map(|(user, day)| GitDetails {
+                commit_day: *day,
+                users: [*user].into_iter().collect(),
+                commits: 1,
+                lines_added: 10,
+                lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
+            })
+            .collect();
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file
+    }
+
+    #[test]
+    fn churn_is_divided_by_contributor_months() {
+        let mut root = FlareTreeNode::dir("root");
+        let mut big_team = FlareTreeNode::dir("big_team");
+        // four people, each committing twice in the same month
+        for user in 0..4 {
+            big_team.append_child(git_file(
+                &format!("{}.rs", user),
+                &[(user, LATEST), (user, LATEST - DAY)],
+            ));
+        }
+        root.append_child(big_team);
+        let mut solo = FlareTreeNode::dir("solo");
+        // one person committing in two months, and once before the window
+        solo.append_child(git_file(
+            "solo.rs",
+            &[
+                (4, LATEST - 10 * DAY),
+                (4, LATEST - 40 * DAY),
+                (4, LATEST - 41 * DAY),
+                (4, LATEST - 400 * DAY),
+            ],
+        ));
+        root.append_child(solo);
+        let mut old = FlareTreeNode::dir("old");
+        old.append_child(git_file("old.rs", &[(5, LATEST - 500 * DAY)]));
+        root.append_child(old);
+
+        calculate_churn_per_contributor(&mut root, &ChurnPerContributorConfig::default());
+
+        let churn_of = |name: &str| {
+            root.get_children()
+                .iter()
+                .find(|child| child.name() == name)
+                .and_then(|child| child.indicators().churn_per_contributor.clone())
+        };
+        assert_eq!(
+            churn_of("big_team"),
+            Some(ChurnPerContributorData::new(8, 80, 4))
+        );
+        assert_eq!(
+            churn_of("solo"),
+            Some(ChurnPerContributorData {
+                commits: 3,
+                lines_changed: 30,
+                contributor_months: 2,
+                commits_per_contributor_month: 1.5,
+                lines_per_contributor_month: 15.0,
+            })
+        );
+        assert_eq!(churn_of("old"), None);
+        assert_eq!(
+            root.indicators().churn_per_contributor,
+            Some(ChurnPerContributorData::new(11, 110, 6))
+        );
+    }
+}
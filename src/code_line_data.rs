@@ -1,9 +1,15 @@
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
 
-use anyhow::Error;
+use anyhow::{Context, Error};
 use encoding_rs_io::DecodeReaderBytesBuilder;
 use tokei::CodeStats;
 
+use crate::comment_filter::CommentFilter;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CodeLineData {
     pub spaces: u32,
@@ -41,44 +47,240 @@ impl CodeLineData {
     }
 }
 
+/// Files bigger than this are streamed line by line when a line cap is set, rather than
+/// parsed by tokei, which loads the whole file into memory
+pub const LARGE_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Limits that stop pathological files - huge files, or generated and minified files with
+/// enormous lines - from stalling a scan. Files over the limits are streamed as text instead of
+/// being parsed by language, and only the start of each over-long line is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// only process this many lines per file - if set, files over `LARGE_FILE_BYTES` are streamed
+    pub max_lines: Option<usize>,
+    /// only measure this many bytes of each line - files with longer lines are streamed
+    pub max_line_bytes: usize,
+    /// files bigger than this are always streamed, even without a line cap
+    pub max_parse_bytes: u64,
+}
+
+pub const DEFAULT_MAX_LINE_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_MAX_PARSE_BYTES: u64 = 256 * 1024 * 1024;
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_lines: None,
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            max_parse_bytes: DEFAULT_MAX_PARSE_BYTES,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// the start of a line, up to `max_line_bytes`
+    pub fn line<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        &line[..line.len().min(self.max_line_bytes)]
+    }
+}
+
+/// true if a file should be streamed rather than parsed - if it is too big, or has a line longer
+/// than the limit. Without a line cap only files over `max_parse_bytes` are too big.
+pub fn should_stream(path: &Path, limits: &ParseLimits) -> bool {
+    let size = match path.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return false,
+    };
+    if size > limits.max_parse_bytes || (limits.max_lines.is_some() && size > LARGE_FILE_BYTES) {
+        return true;
+    }
+    // a file can't have a long line if it isn't long itself
+    if size > limits.max_line_bytes as u64 {
+        match has_long_line(path, limits.max_line_bytes) {
+            Ok(true) => {
+                debug!(
+                    "Streaming {:?} as it has lines over {} bytes",
+                    path, limits.max_line_bytes
+                );
+                return true;
+            }
+            Ok(false) => {}
+            // parsing will report the error
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+/// true if any line is over `max_line_bytes` - stops at the first one, without storing lines
+fn has_long_line(path: &Path, max_line_bytes: usize) -> Result<bool, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line_length = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(false);
+        }
+        let consumed = buffer.len();
+        for c in buffer {
+            if *c == b'\n' {
+                line_length = 0;
+            } else {
+                line_length += 1;
+                if line_length > max_line_bytes {
+                    return Ok(true);
+                }
+            }
+        }
+        reader.consume(consumed);
+    }
+}
+
+/// reads a line into `line`, keeping only the first `max_bytes` bytes - the rest of the line is
+/// skipped, so a single huge line can't use huge amounts of memory.
+/// Returns the bytes read including any skipped, 0 at the end of the file, as for `read_until`
+fn read_capped_line(
+    reader: &mut impl BufRead,
+    line: &mut Vec<u8>,
+    max_bytes: usize,
+) -> Result<usize, Error> {
+    let mut read = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(read);
+        }
+        let (chunk, done) = match buffer.iter().position(|c| *c == b'\n') {
+            Some(end) => (&buffer[..=end], true),
+            None => (buffer, false),
+        };
+        let room = max_bytes.saturating_sub(line.len());
+        line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if done && chunk.len() > room {
+            // keep the newline, so callers can tell the line was complete
+            line.push(b'\n');
+        }
+        let consumed = chunk.len();
+        reader.consume(consumed);
+        read += consumed;
+        if done {
+            return Ok(read);
+        }
+    }
+}
+
+/// counts (total, blank) lines in a file without loading it all into memory
+pub fn count_lines(path: &Path, limits: &ParseLimits) -> Result<(usize, usize), Error> {
+    let f = File::open(path).with_context(|| format!("error opening file {:?}", path))?;
+    let mut reader = BufReader::new(DecodeReaderBytesBuilder::new().build(f));
+    let mut lines = 0;
+    let mut blanks = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = read_capped_line(&mut reader, &mut line, limits.max_line_bytes)?;
+        if read == 0 {
+            return Ok((lines, blanks));
+        }
+        lines += 1;
+        // an over-long line is never blank - whitespace that long is a generated file
+        if read == line.len() && line.iter().all(u8::is_ascii_whitespace) {
+            blanks += 1;
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CodeLines {
     pub lines: Vec<CodeLineData>,
+    /// true if there were more than the maximum lines, and the rest were ignored
+    pub truncated: bool,
 }
 
 impl CodeLines {
-    pub fn from_stats(stats: &CodeStats) -> Self {
+    pub fn from_stats(stats: &CodeStats, limits: &ParseLimits) -> Self {
+        let max_lines = limits.max_lines.unwrap_or(usize::MAX);
         CodeLines {
             lines: stats
                 .code_lines
                 .iter()
-                .map(|line| CodeLineData::new(line))
+                .take(max_lines)
+                .map(|line| CodeLineData::new(limits.line(line)))
                 .collect(),
+            truncated: stats.code_lines.len() > max_lines,
         }
     }
-    pub fn new(path: &PathBuf) -> Result<Self, Error> {
-        let text: Vec<Vec<u8>> = {
-            let f = match File::open(path) {
-                Ok(f) => f,
-                Err(e) => return Err(anyhow!("error opening file {:?} - {}", &path, e)),
-            };
-            let mut s = Vec::new();
-            let mut reader = DecodeReaderBytesBuilder::new().build(f);
-            reader.read_to_end(&mut s)?;
-
-            s.split(|b| *b == b'\n').map(Vec::from).collect()
+    /// reads a file a line at a time, so huge files only need memory for `max_lines` line
+    /// summaries, and huge lines only need `max_line_bytes`
+    pub fn new(path: &PathBuf, limits: &ParseLimits) -> Result<Self, Error> {
+        Self::read(path, limits, None)
+    }
+
+    /// reads a file as for `new`, skipping lines that look like comments - for languages tokei
+    /// can't separate comments from code for. Skipped lines don't count towards `max_lines`
+    pub fn without_comments(
+        path: &PathBuf,
+        limits: &ParseLimits,
+        comments: &CommentFilter,
+    ) -> Result<Self, Error> {
+        Self::read(path, limits, Some(comments))
+    }
+
+    fn read(
+        path: &PathBuf,
+        limits: &ParseLimits,
+        comments: Option<&CommentFilter>,
+    ) -> Result<Self, Error> {
+        let is_comment = |line: &[u8]| comments.map_or(false, |filter| filter.is_comment(line));
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(anyhow!("error opening file {:?} - {}", &path, e)),
         };
-        Ok(CodeLines {
-            lines: text.iter().map(|line| CodeLineData::new(line)).collect(),
-        })
+        let mut reader = BufReader::new(DecodeReaderBytesBuilder::new().build(f));
+        let max_lines = limits.max_lines.unwrap_or(usize::MAX);
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let bytes = read_capped_line(&mut reader, &mut line, limits.max_line_bytes)?;
+            if lines.len() >= max_lines {
+                return Ok(CodeLines {
+                    lines,
+                    truncated: bytes > 0,
+                });
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                if !is_comment(&line) {
+                    lines.push(CodeLineData::new(&line));
+                }
+            } else {
+                // last line, with no trailing newline - possibly empty
+                if !is_comment(&line) {
+                    lines.push(CodeLineData::new(&line));
+                }
+                return Ok(CodeLines {
+                    lines,
+                    truncated: false,
+                });
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
     use tokei::{Config, LanguageType};
 
+    fn limited_to(max_lines: Option<usize>) -> ParseLimits {
+        ParseLimits {
+            max_lines,
+            ..ParseLimits::default()
+        }
+    }
+
     #[test]
     pub fn can_process_tabs_and_spaces() {
         let data = CodeLineData::new(" \t \t foo".as_bytes());
@@ -105,6 +307,20 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn files_are_streamed_up_to_max_lines() {
+        let path = PathBuf::from("./tests/data/languages/pfunit_test.pf");
+        let all = CodeLines::new(&path, &ParseLimits::default()).unwrap();
+        assert!(!all.truncated);
+
+        let capped = CodeLines::new(&path, &limited_to(Some(3))).unwrap();
+        assert!(capped.truncated);
+        assert_eq!(capped.lines, all.lines[0..3].to_vec());
+
+        let exact = CodeLines::new(&path, &limited_to(Some(all.lines.len()))).unwrap();
+        assert!(!exact.truncated);
+    }
+
     #[test]
     pub fn can_parse_source_code() {
         let code = r#"function foo☃() {
@@ -129,7 +345,7 @@ foo();"#;
         //     .collect();
         // eprintln!("Code lines: {:?}", printable_lines);
 
-        let result: CodeLines = CodeLines::from_stats(&stats);
+        let result: CodeLines = CodeLines::from_stats(&stats, &ParseLimits::default());
 
         let mut expected = vec![
             CodeLineData {
@@ -170,4 +386,44 @@ foo();"#;
         actual.sort_by(|a, b| a.text.partial_cmp(&b.text).unwrap());
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    pub fn only_the_start_of_long_lines_is_kept() {
+        let mut reader = "short\nmuch longer line\nend".as_bytes();
+        let mut line = Vec::new();
+        assert_eq!(read_capped_line(&mut reader, &mut line, 8).unwrap(), 6);
+        assert_eq!(line, b"short\n");
+        line.clear();
+        assert_eq!(read_capped_line(&mut reader, &mut line, 8).unwrap(), 17);
+        assert_eq!(line, b"much lon\n");
+        line.clear();
+        assert_eq!(read_capped_line(&mut reader, &mut line, 8).unwrap(), 3);
+        assert_eq!(line, b"end");
+        line.clear();
+        assert_eq!(read_capped_line(&mut reader, &mut line, 8).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn files_with_huge_lines_are_streamed_with_capped_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "  fn foo() {{").unwrap();
+        // invalid utf-8 and an unterminated comment on a huge line
+        file.write_all(b"\t/* \xff\xfe").unwrap();
+        file.write_all(&[b'x'; 5000]).unwrap();
+        writeln!(file).unwrap();
+        write!(file, "}}").unwrap();
+        let limits = ParseLimits {
+            max_line_bytes: 100,
+            ..ParseLimits::default()
+        };
+        let path = file.path().to_path_buf();
+        assert!(should_stream(&path, &limits));
+        assert!(!should_stream(&path, &ParseLimits::default()));
+
+        let code_lines = CodeLines::new(&path, &limits).unwrap();
+        assert_eq!(code_lines.lines.len(), 3);
+        assert_eq!(code_lines.lines[1].tabs, 1);
+        assert!(code_lines.lines[1].text <= 100);
+        assert_eq!(count_lines(&path, &limits).unwrap(), (3, 0));
+    }
 }
@@ -0,0 +1,70 @@
+#![warn(clippy::all)]
+//! Guessing which lines are comments, for files in languages tokei doesn't know.
+//!
+//! tokei only separates code from comments for languages it can parse - other text files are
+//! read a line at a time, and every line would count as code, including license headers and
+//! other big comment blocks that skew indentation. Instead, any line starting with one of a set
+//! of common comment prefixes, after indentation, is treated as a comment.
+//!
+//! This is a heuristic - block comments with lines that don't start with a prefix are still
+//! counted, and code that starts with a prefix character (such as `*` for a dereference) isn't.
+
+/// prefixes used for comments in most languages - `*` covers the middle and end of C-style
+/// block comments
+pub const DEFAULT_COMMENT_PREFIXES: [&str; 7] = ["#", "//", "/*", "*", "--", ";", "<!--"];
+
+#[must_use]
+pub fn default_comment_prefixes() -> Vec<String> {
+    DEFAULT_COMMENT_PREFIXES
+        .iter()
+        .map(|prefix| (*prefix).to_owned())
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommentFilter {
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl CommentFilter {
+    /// empty prefixes are ignored, as they would match every line
+    #[must_use]
+    pub fn new(prefixes: &[String]) -> Self {
+        CommentFilter {
+            prefixes: prefixes
+                .iter()
+                .filter(|prefix| !prefix.is_empty())
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// true if the line looks like a comment - it starts with a prefix, ignoring indentation
+    #[must_use]
+    pub fn is_comment(&self, line: &[u8]) -> bool {
+        let start = line
+            .iter()
+            .position(|c| *c != b' ' && *c != b'\t')
+            .unwrap_or(line.len());
+        let text = &line[start..];
+        self.prefixes.iter().any(|prefix| text.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines_starting_with_a_prefix_are_comments() {
+        let filter = CommentFilter::new(&default_comment_prefixes());
+        assert!(filter.is_comment(b"# Licensed under the Apache License"));
+        assert!(filter.is_comment(b"\t  // indented comment"));
+        assert!(filter.is_comment(b" * middle of a block"));
+        assert!(!filter.is_comment(b"  value = 1 # trailing comment"));
+        assert!(!filter.is_comment(b""));
+
+        let nothing = CommentFilter::new(&[String::new()]);
+        assert!(!nothing.is_comment(b"# not filtered"));
+    }
+}
@@ -0,0 +1,163 @@
+#![warn(clippy::all)]
+//! Commit sizes per file - how many lines each commit changed in the file, to tell files changed
+//! by frequent small tweaks from files hit by occasional big edits.
+//!
+//! A commit's size is the lines it added and deleted in the file, not in the whole commit.
+//! Giant commits change more than `giant_commit_lines` lines of the file - the share of all
+//! changed lines that came from giant commits shows how much of a file's churn is bulk edits.
+
+use serde::{Deserialize, Serialize};
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitSizeConfig {
+    /// commits changing more lines of a file than this are giant
+    pub giant_commit_lines: u64,
+}
+
+impl Default for CommitSizeConfig {
+    fn default() -> Self {
+        CommitSizeConfig {
+            giant_commit_lines: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitSizeData {
+    pub commits: u64,
+    /// the median lines changed per commit
+    pub median_lines: u64,
+    /// the mean lines changed per commit
+    pub mean_lines: f64,
+    /// commits changing more than the giant commit lines
+    pub giant_commits: u64,
+    /// the share of changed lines that were in giant commits
+    pub giant_lines_share: f64,
+}
+
+impl CommitSizeData {
+    /// None if there are no commits
+    fn new(mut sizes: Vec<u64>, config: &CommitSizeConfig) -> Option<Self> {
+        if sizes.is_empty() {
+            return None;
+        }
+        sizes.sort_unstable();
+        let total: u64 = sizes.iter().sum();
+        let giant: Vec<u64> = sizes
+            .iter()
+            .copied()
+            .filter(|size| *size > config.giant_commit_lines)
+            .collect();
+        let giant_total: u64 = giant.iter().sum();
+        Some(CommitSizeData {
+            commits: sizes.len() as u64,
+            // the nearest-rank median, so it is always a real commit size
+            median_lines: sizes[(sizes.len() - 1) / 2],
+            mean_lines: total as f64 / sizes.len() as f64,
+            giant_commits: giant.len() as u64,
+            giant_lines_share: if total == 0 {
+                0.0
+            } else {
+                giant_total as f64 / total as f64
+            },
+        })
+    }
+}
+
+fn apply_commit_sizes(node: &mut FlareTreeNode, config: &CommitSizeConfig) {
+    let sizes: Option<Vec<u64>> = match &node.indicators().git {
+        Some(GitNodeData::File { data }) => Some(
+            data.activity
+                .iter()
+                .map(|activity| activity.lines_added + activity.lines_deleted)
+                .collect(),
+        ),
+        _ => None,
+    };
+    if let Some(sizes) = sizes {
+        node.indicators_mut().commit_sizes = CommitSizeData::new(sizes, config);
+    }
+    for child in node.get_children_mut() {
+        apply_commit_sizes(child, config);
+    }
+}
+
+/// adds commit sizes to every file with git activity
+pub fn calculate_commit_sizes(tree: &mut FlareTreeNode, config: &CommitSizeConfig) {
+    info!("Calculating commit sizes");
+    apply_commit_sizes(tree, config);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::{GitActivity, GitData};
+    use crate::git_logger::CommitChange;
+    use pretty_assertions::assert_eq;
+
+    /// a file changed by commits of each size
+    fn git_file(name: &str, sizes: &[u64]) -> FlareTreeNode {
+        let activity = sizes
+            .iter()
+            .enumerate()
+            .map(|(time, size)| GitActivity {
+                author_time: time as u64,
+                commit_time: time as u64,
+                users: [0].into_iter().collect(),
+                change: CommitChange::Modify,
+                lines_added: *size,
+                lines_deleted: 0,
+                bulk: false,
+                parent_count: 1,
+                tickets: Vec::new(),
+            })
+            .collect();
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File {
+            data: GitData::fake_with_activity(activity),
+        });
+        file
+    }
+
+    #[test]
+    fn commit_sizes_are_summarised_per_file() {
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(git_file("tweaked.rs", &[1, 3, 2, 2]));
+        root.append_child(git_file("reformatted.rs", &[2, 4, 1000, 2]));
+        root.append_child(git_file("empty.rs", &[]));
+
+        calculate_commit_sizes(&mut root, &CommitSizeConfig::default());
+
+        let sizes_of = |name: &str| {
+            root.get_children()
+                .iter()
+                .find(|child| child.name() == name)
+                .and_then(|child| child.indicators().commit_sizes.clone())
+        };
+        assert_eq!(
+            sizes_of("tweaked.rs"),
+            Some(CommitSizeData {
+                commits: 4,
+                median_lines: 2,
+                mean_lines: 2.0,
+                giant_commits: 0,
+                giant_lines_share: 0.0,
+            })
+        );
+        assert_eq!(
+            sizes_of("reformatted.rs"),
+            Some(CommitSizeData {
+                commits: 4,
+                median_lines: 2,
+                mean_lines: 252.0,
+                giant_commits: 1,
+                giant_lines_share: 1000.0 / 1008.0,
+            })
+        );
+        assert_eq!(sizes_of("empty.rs"), None);
+        assert_eq!(root.indicators().commit_sizes, None);
+    }
+}
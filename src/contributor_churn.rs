@@ -0,0 +1,191 @@
+#![warn(clippy::all)]
+//! "People churn" - per directory, how many contributors are new and how many have departed,
+//! to show onboarding and attrition hotspots.
+//!
+//! The churn window is the last `window_days` of the scan, up to the latest change in the scan:
+//! * new contributors made their first commit to the directory within the window
+//! * departed contributors committed to the directory before the window, but not within it
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContributorChurnConfig {
+    /// how many days before the latest change count as recent
+    pub window_days: u64,
+}
+
+impl Default for ContributorChurnConfig {
+    fn default() -> Self {
+        ContributorChurnConfig { window_days: 180 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContributorChurnData {
+    /// everyone who committed to the directory in the scanned history
+    pub contributors: u64,
+    pub new_contributors: u64,
+    pub departed_contributors: u64,
+}
+
+/// first and last commit days for each user, by dictionary id
+type ActiveRanges = HashMap<usize, (u64, u64)>;
+
+/// the time of the latest change to any file under the node
+pub(crate) fn latest_change(node: &FlareTreeNode) -> u64 {
+    let own = match &node.indicators().git {
+        Some(GitNodeData::File { data }) => data.last_update,
+        _ => 0,
+    };
+    node.get_children()
+        .iter()
+        .map(latest_change)
+        .fold(own, u64::max)
+}
+
+fn merge_ranges(into: &mut ActiveRanges, from: ActiveRanges) {
+    for (user, (first, last)) in from {
+        let range = into.entry(user).or_insert((first, last));
+        range.0 = range.0.min(first);
+        range.1 = range.1.max(last);
+    }
+}
+
+/// sets churn data on each directory, returning the active ranges under the node
+fn apply_churn(node: &mut FlareTreeNode, window_start: u64) -> ActiveRanges {
+    let mut ranges = ActiveRanges::new();
+    if let Some(GitNodeData::File { data }) = &node.indicators().git {
+        for details in &data.details {
+            for user in &details.users {
+                let range = ranges
+                    .entry(*user)
+                    .or_insert((details.commit_day, details.commit_day));
+                range.0 = range.0.min(details.commit_day);
+                range.1 = range.1.max(details.commit_day);
+            }
+        }
+    }
+    if node.is_file() {
+        return ranges;
+    }
+    for child in node.get_children_mut() {
+        let child_ranges = apply_churn(child, window_start);
+        merge_ranges(&mut ranges, child_ranges);
+    }
+    if !ranges.is_empty() {
+        let new_contributors = ranges
+            .values()
+            .filter(|(first, _)| *first >= window_start)
+            .count();
+        let departed_contributors = ranges
+            .values()
+            .filter(|(_, last)| *last < window_start)
+            .count();
+        node.indicators_mut().contributor_churn = Some(ContributorChurnData {
+            contributors: ranges.len() as u64,
+            new_contributors: new_contributors as u64,
+            departed_contributors: departed_contributors as u64,
+        });
+    }
+    ranges
+}
+
+/// adds contributor churn data to every directory with git details
+pub fn calculate_contributor_churn(tree: &mut FlareTreeNode, config: &ContributorChurnConfig) {
+    info!("Calculating contributor churn");
+    let window_start = latest_change(tree).saturating_sub(config.window_days * SECONDS_PER_DAY);
+    apply_churn(tree, window_start);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::{GitData, GitDetails};
+    use pretty_assertions::assert_eq;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+    const LATEST: u64 = 1000 * DAY;
+
+    /// a file changed by each user on the given day
+    fn git_file(name: &str, changes: &[(usize, u64)]) -> FlareTreeNode {
+        let mut data = GitData::fake_with_activity(Vec::new());
+        data.last_update = changes.iter().map(|(_, day)| *day).max().unwrap_or(0);
+        data.details = changes
+            .iter()
+            .map(|(user, day)| GitDetails {
+                commit_day: *day,
+                users: [*user].into_iter().collect(),
+                commits: 1,
+                lines_added: 1,
+                lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
+            })
+            .collect();
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file
+    }
+
+    #[test]
+    fn directories_count_new_and_departed_contributors() {
+        let mut root = FlareTreeNode::dir("root");
+        let mut src = FlareTreeNode::dir("src");
+        // user 0 has been here all along, user 1 left, user 2 is new
+        src.append_child(git_file(
+            "a.rs",
+            &[(0, LATEST - 500 * DAY), (1, LATEST - 400 * DAY)],
+        ));
+        src.append_child(git_file("b.rs", &[(0, LATEST), (2, LATEST - 10 * DAY)]));
+        root.append_child(src);
+        let mut docs = FlareTreeNode::dir("docs");
+        // user 1 has left src, but not docs
+        docs.append_child(git_file(
+            "readme.md",
+            &[(1, LATEST - 400 * DAY), (1, LATEST)],
+        ));
+        docs.append_child(FlareTreeNode::file("untracked.md"));
+        root.append_child(docs);
+        root.append_child(FlareTreeNode::dir("empty"));
+
+        calculate_contributor_churn(&mut root, &ContributorChurnConfig::default());
+
+        let churn_of = |name: &str| {
+            root.get_children()
+                .iter()
+                .find(|child| child.name() == name)
+                .and_then(|child| child.indicators().contributor_churn.clone())
+        };
+        assert_eq!(
+            churn_of("src"),
+            Some(ContributorChurnData {
+                contributors: 3,
+                new_contributors: 1,
+                departed_contributors: 1
+            })
+        );
+        assert_eq!(
+            churn_of("docs"),
+            Some(ContributorChurnData {
+                contributors: 1,
+                new_contributors: 0,
+                departed_contributors: 0
+            })
+        );
+        assert_eq!(churn_of("empty"), None);
+        assert_eq!(
+            root.indicators().contributor_churn,
+            Some(ContributorChurnData {
+                contributors: 3,
+                new_contributors: 1,
+                departed_contributors: 0
+            })
+        );
+    }
+}
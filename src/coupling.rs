@@ -1,14 +1,21 @@
+use crate::cancellation::CancellationToken;
+use crate::diagnostics::{Diagnostic, DiagnosticCategory};
 use crate::flare::FlareTreeNode;
 use crate::git::GitNodeData;
+use crate::path_interner::{PathId, PathInterner};
 use crate::polyglot_data::PolyglotData;
+use crate::progress::Progress;
 use anyhow::Error;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressStyle;
 use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use std::collections::{BTreeMap, BTreeSet};
-use std::path::{Component, PathBuf};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::{
     collections::{HashMap, HashSet},
     ffi::OsString,
@@ -27,7 +34,7 @@ impl PathVec {
             components: Vec::new(),
         }
     }
-    fn to_path_buf(&self) -> PathBuf {
+    pub(crate) fn to_path_buf(&self) -> PathBuf {
         self.components.iter().collect()
     }
     fn push<T>(&mut self, path: T)
@@ -47,6 +54,26 @@ impl Serialize for PathVec {
     }
 }
 
+impl<'de> Deserialize<'de> for PathVec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let path = PathBuf::from(String::deserialize(deserializer)?);
+        // From<> panics on anything but normal components
+        if path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(serde::de::Error::custom(format!(
+                "Unsupported coupling path {:?}",
+                path
+            )));
+        }
+        Ok(PathVec::from(path))
+    }
+}
+
 impl<P> From<P> for PathVec
 where
     P: Into<PathBuf>,
@@ -72,30 +99,45 @@ where
 /// * by timestamp, in a `BTreeMap` so it's easy to access ranges
 /// * by filename, with a `BTreeSet` of timestamps so again we can get ranges out easily
 struct FileChangeTimestamps {
+    paths: PathInterner<PathVec>,
     /// all files changed by timestamp - must actually have lines changed!
-    timestamps: BTreeMap<u64, HashSet<Rc<PathVec>>>,
-    file_changes: HashMap<Rc<PathVec>, BTreeSet<u64>>,
+    timestamps: BTreeMap<u64, HashSet<PathId>>,
+    file_changes: HashMap<PathId, BTreeSet<u64>>,
+    /// only if commits had ticket ids - for each file, the first time it changed for each ticket
+    ticket_changes: HashMap<PathId, BTreeMap<String, u64>>,
+    /// all files changed for each ticket
+    ticket_files: HashMap<String, HashSet<PathId>>,
 }
 
 impl FileChangeTimestamps {
     pub fn new(root: &FlareTreeNode) -> Result<Self, Error> {
-        let mut timestamps: BTreeMap<u64, HashSet<Rc<PathVec>>> = BTreeMap::new();
-        let mut file_changes: HashMap<Rc<PathVec>, BTreeSet<u64>> = HashMap::new();
-        FileChangeTimestamps::accumulate_files(
-            &mut timestamps,
-            &mut file_changes,
-            root,
-            &Rc::from(PathVec::new()),
-        )?;
-        Ok(FileChangeTimestamps {
-            timestamps,
-            file_changes,
-        })
+        let mut timestamps = FileChangeTimestamps {
+            paths: PathInterner::default(),
+            timestamps: BTreeMap::new(),
+            file_changes: HashMap::new(),
+            ticket_changes: HashMap::new(),
+            ticket_files: HashMap::new(),
+        };
+        timestamps.accumulate_files(root, &Rc::from(PathVec::new()))?;
+        Ok(timestamps)
     }
 
     fn is_empty(&self) -> bool {
         self.timestamps.is_empty()
     }
+    /// how much work coupling a file takes - roughly proportional to its changes, so a much
+    /// better measure of progress than counting files, as a few files change far more than most
+    fn work_for(&self, file: PathId, by_ticket: bool) -> u64 {
+        if by_ticket {
+            self.ticket_changes
+                .get(&file)
+                .map_or(0, |tickets| tickets.len() as u64)
+        } else {
+            self.file_changes
+                .get(&file)
+                .map_or(0, |changes| changes.len() as u64)
+        }
+    }
     fn earliest(&self) -> Option<&u64> {
         self.timestamps.range(..).next().map(|x| x.0)
     }
@@ -103,26 +145,47 @@ impl FileChangeTimestamps {
         self.timestamps.range(..).next_back().map(|x| x.0)
     }
 
-    fn accumulate_files(
-        timestamps: &mut BTreeMap<u64, HashSet<Rc<PathVec>>>,
-        file_changes: &mut HashMap<Rc<PathVec>, BTreeSet<u64>>,
-        node: &FlareTreeNode,
-        path: &Rc<PathVec>,
-    ) -> Result<(), Error> {
+    /// records a change to a file at a time
+    fn add_change(&mut self, path: &Rc<PathVec>, commit_time: u64) {
+        let id = self.paths.intern(path);
+        self.timestamps
+            .entry(commit_time)
+            .or_insert_with(HashSet::new)
+            .insert(id);
+        self.file_changes
+            .entry(id)
+            .or_insert_with(BTreeSet::new)
+            .insert(commit_time);
+    }
+
+    /// records a change to a file for a ticket
+    fn add_ticket_change(&mut self, path: &Rc<PathVec>, ticket: &str, commit_time: u64) {
+        let id = self.paths.intern(path);
+        let first_change = self
+            .ticket_changes
+            .entry(id)
+            .or_default()
+            .entry(ticket.to_owned())
+            .or_insert(commit_time);
+        *first_change = (*first_change).min(commit_time);
+        self.ticket_files
+            .entry(ticket.to_owned())
+            .or_default()
+            .insert(id);
+    }
+
+    fn accumulate_files(&mut self, node: &FlareTreeNode, path: &Rc<PathVec>) -> Result<(), Error> {
         let lines = node.indicators().loc.as_ref().map_or(0, |loc| loc.code);
 
         if lines > 0 {
             if let Some(GitNodeData::File { data }) = &node.indicators().git {
                 for activity in &data.activity {
-                    if activity.lines_deleted > 0 || activity.lines_added > 0 {
-                        let timestamp_entry = timestamps
-                            .entry(activity.commit_time)
-                            .or_insert_with(HashSet::new);
-                        (*timestamp_entry).insert(path.clone());
-                        let file_entry = file_changes
-                            .entry(path.clone())
-                            .or_insert_with(BTreeSet::new);
-                        (*file_entry).insert(activity.commit_time);
+                    // bulk commits couple everything with everything, so are just noise
+                    if !activity.bulk && (activity.lines_deleted > 0 || activity.lines_added > 0) {
+                        self.add_change(path, activity.commit_time);
+                        for ticket in &activity.tickets {
+                            self.add_ticket_change(path, ticket, activity.commit_time);
+                        }
                     }
                 }
             }
@@ -131,12 +194,7 @@ impl FileChangeTimestamps {
         for child in node.get_children() {
             let mut child_path = (**path).clone();
             child_path.push(child.name());
-            FileChangeTimestamps::accumulate_files(
-                timestamps,
-                file_changes,
-                child,
-                &Rc::new(child_path),
-            )?;
+            self.accumulate_files(child, &Rc::new(child_path))?;
         }
         Ok(())
     }
@@ -195,22 +253,51 @@ impl ActivityBurst {
 
 /// The basic coupling data - for each file in some time period, how often did it change and how often did
 /// another file change at roughly the same time
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 struct Coupling {
-    name: Rc<PathVec>,
+    name: PathId,
     activity_bursts: u64,
-    coupled_files: HashMap<Rc<PathVec>, u64>,
+    coupled_files: HashMap<PathId, u64>,
+    /// only if significance is tested - how likely the coupling is coincidental
+    p_values: HashMap<PathId, f64>,
+}
+
+/// the probability of `successes` or more in `trials` independent trials each with
+/// probability `p` - the upper tail of the binomial distribution
+fn binomial_upper_tail(trials: u64, successes: u64, p: f64) -> f64 {
+    if successes == 0 || p >= 1.0 {
+        return 1.0;
+    }
+    if successes > trials || p <= 0.0 {
+        return 0.0;
+    }
+    let (ln_p, ln_q) = (p.ln(), (-p).ln_1p());
+    // sums in log space, as the terms can be too small for f64
+    let mut ln_term = (0..successes)
+        .map(|i| ((trials - i) as f64 / (i + 1) as f64).ln())
+        .sum::<f64>()
+        + successes as f64 * ln_p
+        + (trials - successes) as f64 * ln_q;
+    let mut tail = 0.0;
+    for k in successes..=trials {
+        tail += ln_term.exp();
+        if k < trials {
+            ln_term += ((trials - k) as f64 / (k + 1) as f64).ln() + ln_p - ln_q;
+        }
+    }
+    tail.min(1.0)
 }
 
 impl Coupling {
-    fn new(name: Rc<PathVec>) -> Self {
+    fn new(name: PathId) -> Self {
         Coupling {
             name,
             activity_bursts: 0,
             coupled_files: HashMap::new(),
+            p_values: HashMap::new(),
         }
     }
-    fn add_file(&mut self, file: Rc<PathVec>) {
+    fn add_file(&mut self, file: PathId) {
         if file != self.name {
             let count = self.coupled_files.entry(file).or_insert(0);
             *count += 1;
@@ -218,7 +305,7 @@ impl Coupling {
     }
     fn add_files<T>(&mut self, files: T)
     where
-        T: IntoIterator<Item = Rc<PathVec>>,
+        T: IntoIterator<Item = PathId>,
     {
         for file in files {
             self.add_file(file);
@@ -229,7 +316,7 @@ impl Coupling {
     fn filter_by_ratio(&self, min_coupling_ratio: f64) -> Coupling {
         let bursts = self.activity_bursts as f64;
         Coupling {
-            name: self.name.clone(),
+            name: self.name,
             activity_bursts: self.activity_bursts,
             coupled_files: self
                 .coupled_files
@@ -237,7 +324,15 @@ impl Coupling {
                 .filter(|(_file, other_bursts)| {
                     **other_bursts as f64 / bursts >= min_coupling_ratio
                 })
-                .map(|(file, other_bursts)| (file.clone(), *other_bursts))
+                .map(|(file, other_bursts)| (*file, *other_bursts))
+                .collect(),
+            p_values: self
+                .p_values
+                .iter()
+                .filter(|(file, _p_value)| {
+                    self.coupled_files[file] as f64 / bursts >= min_coupling_ratio
+                })
+                .map(|(file, p_value)| (*file, *p_value))
                 .collect(),
         }
     }
@@ -247,7 +342,7 @@ impl Coupling {
 struct CouplingBucket {
     bucket_start: u64,
     bucket_size: u64,
-    couplings: HashMap<Rc<PathVec>, Coupling>,
+    couplings: HashMap<PathId, Coupling>,
 }
 
 impl CouplingBucket {
@@ -259,17 +354,47 @@ impl CouplingBucket {
         }
     }
 
-    fn add_files<T>(&mut self, from: Rc<PathVec>, to: T)
+    fn add_files<T>(&mut self, from: PathId, to: T)
     where
-        T: IntoIterator<Item = Rc<PathVec>>,
+        T: IntoIterator<Item = PathId>,
     {
         let stats = self
             .couplings
-            .entry(from.clone())
+            .entry(from)
             .or_insert_with(|| Coupling::new(from));
         (*stats).add_files(to);
     }
 
+    /// remove coupling that could easily be coincidence, keeping p-values for the rest.
+    /// If the other file's bursts were spread at random over the bucket, each burst of this file
+    /// would overlap one with a probability based on `window` - the coupling time before and
+    /// after a burst. The p-value is the chance of at least the observed overlaps.
+    fn filter_by_significance(&mut self, max_p_value: f64, window: u64) {
+        let bursts: HashMap<PathId, u64> = self
+            .couplings
+            .iter()
+            .map(|(file, stats)| (*file, stats.activity_bursts))
+            .collect();
+        let window_share = (window as f64 / self.bucket_size as f64).min(1.0);
+        for stats in self.couplings.values_mut() {
+            let mut p_values = HashMap::new();
+            let activity_bursts = stats.activity_bursts;
+            stats.coupled_files.retain(|file, count| {
+                // a file changed at the bucket edge might only have bursts in the next bucket
+                let other_bursts = bursts.get(file).copied().unwrap_or(1).max(1);
+                let overlap_chance = 1.0 - (1.0 - window_share).powf(other_bursts as f64);
+                let p_value = binomial_upper_tail(activity_bursts, *count, overlap_chance);
+                if p_value <= max_p_value {
+                    p_values.insert(*file, p_value);
+                    true
+                } else {
+                    false
+                }
+            });
+            stats.p_values = p_values;
+        }
+    }
+
     /// filter the bucket to remove noise
     /// `min_source_days` is the minimum number of days a file should have existed for it to be included
     /// `min_coupling_ratio` is the overall ratio of dest days / source days for the destination to be included.
@@ -283,16 +408,193 @@ impl CouplingBucket {
     }
 }
 
+/// how often to save a checkpoint - saving a big one takes a while itself
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How a coupling run is controlled, as opposed to what it calculates
+#[derive(Debug, Clone, Default)]
+pub struct CouplingRun {
+    /// checked between files, as coupling a huge repository can take hours
+    pub cancellation: CancellationToken,
+    /// saves progress here every few minutes and when cancelled, and resumes from it if it
+    /// matches - named coupling configurations get their own file, with the name appended
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl CouplingRun {
+    fn checkpoint_for(&self, name: Option<&str>) -> Option<PathBuf> {
+        let checkpoint = self.checkpoint.as_ref()?;
+        Some(match name {
+            Some(name) => {
+                let mut named = checkpoint.as_os_str().to_owned();
+                named.push(".");
+                named.push(name);
+                PathBuf::from(named)
+            }
+            None => checkpoint.clone(),
+        })
+    }
+}
+
+/// a file's coupling in a bucket, by path as ids aren't stable between runs
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointCoupling {
+    file: Rc<PathVec>,
+    activity_bursts: u64,
+    coupled_files: Vec<(Rc<PathVec>, u64)>,
+}
+
+/// Unfiltered coupling for the files done so far, so an interrupted run can carry on where it
+/// stopped. It is only used if the config, buckets and amount of work all match - otherwise
+/// the history has changed, and coupling starts again.
+#[derive(Debug, Serialize, Deserialize)]
+struct CouplingCheckpoint {
+    config: CouplingConfig,
+    buckets: BucketingConfig,
+    work: u64,
+    done: Vec<Rc<PathVec>>,
+    couplings: Vec<Vec<CheckpointCoupling>>,
+}
+
+impl CouplingCheckpoint {
+    fn new(
+        config: CouplingConfig,
+        bucketing_config: BucketingConfig,
+        work: u64,
+        paths: &PathInterner<PathVec>,
+        done: &HashSet<PathId>,
+        buckets: &[CouplingBucket],
+    ) -> Self {
+        CouplingCheckpoint {
+            config,
+            buckets: bucketing_config,
+            work,
+            done: done.iter().map(|file| paths.path(*file).clone()).collect(),
+            couplings: buckets
+                .iter()
+                .map(|bucket| {
+                    bucket
+                        .couplings
+                        .values()
+                        .map(|stats| CheckpointCoupling {
+                            file: paths.path(stats.name).clone(),
+                            activity_bursts: stats.activity_bursts,
+                            coupled_files: stats
+                                .coupled_files
+                                .iter()
+                                .map(|(file, count)| (paths.path(*file).clone(), *count))
+                                .collect(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// a checkpoint that can't be read is logged and ignored - it only saves time
+    fn load(path: &Path) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+        let loaded = File::open(path)
+            .map_err(Error::from)
+            .and_then(|file| Ok(serde_json::from_reader(BufReader::new(file))?));
+        match loaded {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                warn!("Ignoring unreadable coupling checkpoint {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        // written alongside then renamed, so a run killed while saving keeps the last checkpoint
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut out = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut out, self)?;
+        out.flush()?;
+        drop(out);
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// the files already done, with their coupling added to `buckets` - or None if this
+    /// checkpoint is from a different history
+    fn restore(
+        self,
+        config: CouplingConfig,
+        bucketing_config: BucketingConfig,
+        work: u64,
+        paths: &PathInterner<PathVec>,
+        buckets: &mut [CouplingBucket],
+    ) -> Option<HashSet<PathId>> {
+        if self.config != config
+            || self.buckets != bucketing_config
+            || self.work != work
+            || self.couplings.len() != buckets.len()
+        {
+            return None;
+        }
+        let done = self
+            .done
+            .iter()
+            .map(|file| paths.id(file))
+            .collect::<Option<HashSet<PathId>>>()?;
+        let mut restored = Vec::with_capacity(buckets.len());
+        for checkpoint_bucket in self.couplings {
+            let mut couplings = HashMap::new();
+            for checkpoint_coupling in checkpoint_bucket {
+                let name = paths.id(&checkpoint_coupling.file)?;
+                let mut coupling = Coupling::new(name);
+                coupling.activity_bursts = checkpoint_coupling.activity_bursts;
+                for (file, count) in checkpoint_coupling.coupled_files {
+                    coupling.coupled_files.insert(paths.id(&file)?, count);
+                }
+                couplings.insert(name, coupling);
+            }
+            restored.push(couplings);
+        }
+        for (bucket, couplings) in buckets.iter_mut().zip(restored) {
+            bucket.couplings = couplings;
+        }
+        Some(done)
+    }
+}
+
 struct CouplingBuckets {
+    paths: PathInterner<PathVec>,
     buckets: Vec<CouplingBucket>,
 }
 
 impl CouplingBuckets {
+    #[cfg(test)]
     fn new(
         config: CouplingConfig,
-        file_change_timestamps: &FileChangeTimestamps,
+        file_change_timestamps: FileChangeTimestamps,
         bucketing_config: BucketingConfig,
     ) -> Self {
+        CouplingBuckets::build(
+            config,
+            file_change_timestamps,
+            bucketing_config,
+            &CancellationToken::new(),
+            None,
+        )
+        .unwrap()
+    }
+
+    /// builds and filters coupling buckets - if `checkpoint` is set, progress is saved to it as
+    /// files are done, and the build resumes from it if it matches
+    fn build(
+        config: CouplingConfig,
+        file_change_timestamps: FileChangeTimestamps,
+        bucketing_config: BucketingConfig,
+        cancellation: &CancellationToken,
+        checkpoint: Option<&Path>,
+    ) -> Result<Self, Error> {
         let bucket_size = bucketing_config.bucket_size;
         let mut buckets: Vec<CouplingBucket> = (0..bucketing_config.bucket_count)
             .map(|bucket| {
@@ -300,20 +602,96 @@ impl CouplingBuckets {
                 CouplingBucket::new(bucket_start, bucket_size)
             })
             .collect();
-        let bar = ProgressBar::new(file_change_timestamps.file_changes.len() as u64);
-        bar.set_style(
+        let paths = &file_change_timestamps.paths;
+        let work_for = |file: PathId| file_change_timestamps.work_for(file, config.by_ticket);
+        let total_work: u64 = file_change_timestamps
+            .file_changes
+            .keys()
+            .map(|file| work_for(*file))
+            .sum();
+        let mut done: HashSet<PathId> = checkpoint
+            .and_then(|path| {
+                let resumed = CouplingCheckpoint::load(path)?.restore(
+                    config,
+                    bucketing_config,
+                    total_work,
+                    paths,
+                    &mut buckets,
+                );
+                match &resumed {
+                    Some(done) => info!(
+                        "Resuming coupling from {:?}, with {} files already done",
+                        path,
+                        done.len()
+                    ),
+                    None => warn!(
+                        "Ignoring coupling checkpoint {:?}, as it is from a different history or config",
+                        path
+                    ),
+                }
+                resumed
+            })
+            .unwrap_or_default();
+        let save_checkpoint = |done: &HashSet<PathId>, buckets: &[CouplingBucket]| match checkpoint
+        {
+            Some(path) => {
+                CouplingCheckpoint::new(config, bucketing_config, total_work, paths, done, buckets)
+                    .save(path)
+            }
+            None => Ok(()),
+        };
+
+        let bar = Progress::bar(
+            "coupling",
+            total_work,
             ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-                .expect("Invalid template in CouplingBuckets::new!")
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {percent:>3}% eta {eta} {msg}")
+                .expect("Invalid template in CouplingBuckets::build!")
                 .progress_chars("##-"),
         );
+        bar.inc(done.iter().map(|file| work_for(*file)).sum());
+        let file_count = file_change_timestamps.file_changes.len();
+        let mut last_saved = Instant::now();
         for (file, timestamps) in &file_change_timestamps.file_changes {
-            bar.inc(1);
+            if done.contains(file) {
+                continue;
+            }
+            if let Err(e) = cancellation.check() {
+                save_checkpoint(&done, &buckets)?;
+                return Err(e);
+            }
+            if checkpoint.is_some() && last_saved.elapsed() >= CHECKPOINT_INTERVAL {
+                save_checkpoint(&done, &buckets)?;
+                last_saved = Instant::now();
+            }
+            done.insert(*file);
+            bar.inc(work_for(*file));
+            bar.set_message(format!("{}/{} files", done.len(), file_count));
+            if config.by_ticket {
+                // each ticket counts as a burst, coupled to everything else changed for the ticket
+                let tickets = file_change_timestamps.ticket_changes.get(file);
+                for (ticket, first_change) in tickets.into_iter().flatten() {
+                    let bucket_number = bucketing_config.bucket_for(*first_change).unwrap();
+                    let coupled_files = file_change_timestamps.ticket_files[ticket]
+                        .iter()
+                        .filter(|&dest_file| {
+                            filter_file(
+                                config.min_distance,
+                                config.max_common_roots,
+                                paths.path(*file),
+                                paths.path(*dest_file),
+                            )
+                        })
+                        .copied();
+                    buckets[bucket_number].add_files(*file, coupled_files);
+                }
+                continue;
+            }
             for burst in ActivityBurst::from_events(timestamps, config.min_activity_gap) {
                 let window_start = burst.start - config.coupling_time_distance;
                 let window_end = burst.end + config.coupling_time_distance;
                 let bucket_number = bucketing_config.bucket_for(burst.start).unwrap();
-                let mut unique_files: HashSet<Rc<PathVec>> = HashSet::new();
+                let mut unique_files: HashSet<PathId> = HashSet::new();
                 for (_coupled_time, coupled_files) in file_change_timestamps
                     .timestamps
                     .range(window_start..window_end)
@@ -325,53 +703,149 @@ impl CouplingBuckets {
                                 filter_file(
                                     config.min_distance,
                                     config.max_common_roots,
-                                    file,
-                                    dest_file,
+                                    paths.path(*file),
+                                    paths.path(*dest_file),
                                 )
                             })
-                            .cloned(),
+                            .copied(),
                     );
                 }
-                buckets[bucket_number].add_files(file.clone(), unique_files);
+                buckets[bucket_number].add_files(*file, unique_files);
             }
         }
         bar.finish();
+        if let Some(path) = checkpoint.filter(|path| path.exists()) {
+            // finished, so there is nothing to resume
+            fs::remove_file(path)?;
+        }
         info!("Gathering coupling stats - filtering buckets");
 
+        let filtering = Progress::bar(
+            "coupling filtering",
+            buckets
+                .iter()
+                .map(|bucket| bucket.couplings.len() as u64)
+                .sum(),
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} eta {eta}")
+                .expect("Invalid template in CouplingBuckets::build!")
+                .progress_chars("##-"),
+        );
         for bucket in &mut buckets {
+            let bucket_files = bucket.couplings.len() as u64;
+            if let Some(max_p_value) = config.max_p_value {
+                // needs burst counts for every file, so must happen before other filtering
+                bucket.filter_by_significance(max_p_value, config.coupling_time_distance * 2);
+            }
             bucket.filter_by(config.min_bursts, config.min_coupling_ratio);
+            filtering.inc(bucket_files);
         }
-        CouplingBuckets { buckets }
+        filtering.finish();
+        Ok(CouplingBuckets {
+            paths: file_change_timestamps.paths,
+            buckets,
+        })
     }
 
-    fn all_files(&self) -> HashSet<Rc<PathVec>> {
+    fn summaries(&self) -> Vec<CouplingBucketSummary> {
         self.buckets
             .iter()
-            .flat_map(|coupling_bucket| coupling_bucket.couplings.keys().cloned())
+            .filter(|coupling_bucket| !coupling_bucket.couplings.is_empty())
+            .map(|coupling_bucket| {
+                let mut edges: Vec<CouplingEdge> = coupling_bucket
+                    .couplings
+                    .values()
+                    .flat_map(|stats| {
+                        stats
+                            .coupled_files
+                            .iter()
+                            .map(|(file, count)| CouplingEdge {
+                                source: self.paths.path(stats.name).clone(),
+                                target: self.paths.path(*file).clone(),
+                                count: *count,
+                                ratio: *count as f64 / stats.activity_bursts as f64,
+                            })
+                    })
+                    .collect();
+                let mut ratio_distribution = vec![0; RATIO_BANDS];
+                for edge in &edges {
+                    let band = ((edge.ratio * RATIO_BANDS as f64) as usize).min(RATIO_BANDS - 1);
+                    ratio_distribution[band] += 1;
+                }
+                let edge_count = edges.len() as u64;
+                edges.sort_by(|edge1, edge2| {
+                    edge2
+                        .ratio
+                        .partial_cmp(&edge1.ratio)
+                        .unwrap()
+                        .then(edge2.count.cmp(&edge1.count))
+                        .then_with(|| edge1.source.partial_cmp(&edge2.source).unwrap())
+                        .then_with(|| edge1.target.partial_cmp(&edge2.target).unwrap())
+                });
+                edges.truncate(SUMMARY_EDGES);
+                CouplingBucketSummary {
+                    bucket_start: coupling_bucket.bucket_start,
+                    bucket_end: coupling_bucket.bucket_start + coupling_bucket.bucket_size - 1,
+                    files: coupling_bucket.couplings.len() as u64,
+                    edges: edge_count,
+                    ratio_distribution,
+                    strongest_edges: edges,
+                }
+            })
+            .collect()
+    }
+
+    fn all_files(&self) -> HashSet<&Rc<PathVec>> {
+        self.buckets
+            .iter()
+            .flat_map(|coupling_bucket| coupling_bucket.couplings.keys())
+            .map(|id| self.paths.path(*id))
             .collect()
     }
 
     fn file_coupling_data(&self, file: &Rc<PathVec>) -> SerializableCouplingData {
+        let id = match self.paths.id(file) {
+            Some(id) => id,
+            None => return SerializableCouplingData::new(Vec::new()),
+        };
         SerializableCouplingData::new(
             self.buckets
                 .iter()
-                .filter(|coupling_bucket| coupling_bucket.couplings.contains_key(&file.clone()))
-                .map(|coupling_bucket| {
-                    let stats = coupling_bucket.couplings.get(&file.clone()).unwrap();
+                .filter_map(|coupling_bucket| {
+                    coupling_bucket
+                        .couplings
+                        .get(&id)
+                        .map(|stats| (coupling_bucket, stats))
+                })
+                .map(|(coupling_bucket, stats)| {
                     let activity_bursts = stats.activity_bursts;
                     let mut coupled_files: Vec<_> = stats
                         .coupled_files
                         .iter()
-                        .map(|(file, count)| (file.clone(), *count))
+                        .map(|(file, count)| {
+                            (
+                                self.paths.path(*file).clone(),
+                                *count,
+                                stats.p_values.get(file).copied(),
+                            )
+                        })
                         .collect();
-                    coupled_files.sort_by(|(path1, _count1), (path2, _count2)| {
+                    coupled_files.sort_by(|(path1, _count1, _p1), (path2, _count2, _p2)| {
                         path1.partial_cmp(path2).unwrap()
                     });
+                    let p_values = coupled_files
+                        .iter()
+                        .filter_map(|(_path, _count, p_value)| *p_value)
+                        .collect();
                     SerializableCouplingBucketData {
                         bucket_start: coupling_bucket.bucket_start,
                         bucket_end: coupling_bucket.bucket_start + coupling_bucket.bucket_size - 1,
                         activity_bursts,
-                        coupled_files,
+                        coupled_files: coupled_files
+                            .into_iter()
+                            .map(|(path, count, _p_value)| (path, count))
+                            .collect(),
+                        p_values,
                     }
                 })
                 .collect(),
@@ -380,16 +854,19 @@ impl CouplingBuckets {
 }
 
 /// Individual bucket to save in the Json tree
-#[derive(Debug, PartialEq, Eq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct SerializableCouplingBucketData {
     pub bucket_start: u64,
     pub bucket_end: u64,
     pub activity_bursts: u64,
     pub coupled_files: Vec<(Rc<PathVec>, u64)>,
+    /// only if significance is tested - the p-value of each of `coupled_files`, in the same order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub p_values: Vec<f64>,
 }
 
 /// Data to save in the Json tree for a file
-#[derive(Debug, PartialEq, Eq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct SerializableCouplingData {
     pub buckets: Vec<SerializableCouplingBucketData>,
 }
@@ -400,7 +877,7 @@ impl SerializableCouplingData {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct CouplingConfig {
     // number of days in a bucket
     bucket_days: u64,
@@ -418,12 +895,53 @@ pub struct CouplingConfig {
     /// eg if 0, they must have different top-level folders.
     /// This is combined with min_distance (and maybe I'll ditch one?)
     max_common_roots: Option<usize>,
+    /// if set, only keep coupling this unlikely to be coincidence - see `filter_by_significance`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_p_value: Option<f64>,
+    /// couple files changed for the same ticket, however far apart in time, instead of files
+    /// changed at around the same time - activity bursts are then tickets
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    by_ticket: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CouplingMetadata {
     pub buckets: BucketingConfig,
     pub config: CouplingConfig,
+    /// overall stats for each bucket with coupling, for checking the config is sensible
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub summary: Vec<CouplingBucketSummary>,
+}
+
+/// how many of the strongest edges are kept in each bucket summary
+const SUMMARY_EDGES: usize = 10;
+
+/// how many bands the coupling ratio distribution is split into
+const RATIO_BANDS: usize = 10;
+
+/// one file coupled to another in a bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CouplingEdge {
+    pub source: Rc<PathVec>,
+    pub target: Rc<PathVec>,
+    /// how many of the source's activity bursts the target changed in
+    pub count: u64,
+    /// `count` divided by the source's activity bursts
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CouplingBucketSummary {
+    pub bucket_start: u64,
+    pub bucket_end: u64,
+    /// files with enough activity bursts to measure coupling from
+    pub files: u64,
+    /// coupled pairs after filtering - A to B and B to A are separate edges
+    pub edges: u64,
+    /// edges by coupling ratio, in tenths - the first counts ratios under 0.1, the last 0.9 to 1.0
+    pub ratio_distribution: Vec<u64>,
+    /// the highest ratio edges, highest first
+    pub strongest_edges: Vec<CouplingEdge>,
 }
 
 impl CouplingConfig {
@@ -445,6 +963,29 @@ impl CouplingConfig {
             coupling_time_distance,
             min_distance,
             max_common_roots,
+            max_p_value: None,
+            by_ticket: false,
+        }
+    }
+    /// the same config, coupling by ticket ids instead of by time
+    #[must_use]
+    pub fn with_ticket_coupling(self, by_ticket: bool) -> Self {
+        CouplingConfig { by_ticket, ..self }
+    }
+    /// the same config, only keeping statistically significant coupling
+    #[must_use]
+    pub fn with_max_p_value(self, max_p_value: Option<f64>) -> Self {
+        CouplingConfig {
+            max_p_value,
+            ..self
+        }
+    }
+    /// the same config with a different bucket size - for named coupling configs
+    #[must_use]
+    pub fn with_bucket_days(self, bucket_days: u64) -> Self {
+        CouplingConfig {
+            bucket_days,
+            ..self
         }
     }
     #[must_use]
@@ -461,7 +1002,8 @@ impl CouplingConfig {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Serialized by hand, but the field names match so Deserialize can be derived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub struct BucketingConfig {
     bucket_size: u64,
     bucket_count: u64,
@@ -577,6 +1119,8 @@ fn filter_file(
 fn file_changes_to_coupling_buckets(
     tree: &FlareTreeNode,
     config: CouplingConfig,
+    cancellation: &CancellationToken,
+    checkpoint: Option<&Path>,
 ) -> Result<Option<(BucketingConfig, CouplingBuckets)>, Error> {
     info!("Gathering coupling stats - collecting timestamps");
 
@@ -586,6 +1130,10 @@ fn file_changes_to_coupling_buckets(
         warn!("No timestamps found, no coupling data processed");
         return Ok(None);
     }
+    if config.by_ticket && timestamps.ticket_files.is_empty() {
+        warn!("No ticket ids found in git activity, no coupling data processed");
+        return Ok(None);
+    }
 
     info!(
         "Collected {} timestamps, touching {} files",
@@ -600,16 +1148,45 @@ fn file_changes_to_coupling_buckets(
 
     let bucketing_config = BucketingConfig::new(config, *earliest, *latest);
 
-    let filtered_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+    let filtered_buckets = CouplingBuckets::build(
+        config,
+        timestamps,
+        bucketing_config,
+        cancellation,
+        checkpoint,
+    )?;
     Ok(Some((bucketing_config, filtered_buckets)))
 }
 
+/// builds coupling buckets without applying them to the tree, returning how many files are
+/// coupled - the expensive part of `gather_coupling`, for benchmarks
+#[cfg(feature = "bench")]
+pub(crate) fn coupled_file_count(
+    tree: &FlareTreeNode,
+    config: CouplingConfig,
+) -> Result<usize, Error> {
+    Ok(
+        file_changes_to_coupling_buckets(tree, config, &CancellationToken::new(), None)?
+            .map_or(0, |(_, buckets)| buckets.all_files().len()),
+    )
+}
+
+/// gather coupling stats for the whole tree
+/// If `name` is specified, results are stored under that name in `named_coupling`, so the same
+/// scan can hold coupling data for several configurations.
 pub fn gather_coupling(
     polyglot_data: &mut PolyglotData,
     config: CouplingConfig,
+    name: Option<&str>,
+    run: &CouplingRun,
 ) -> Result<(), Error> {
     info!("Gathering coupling stats - accumulating timestamps");
-    let bucket_info = file_changes_to_coupling_buckets(polyglot_data.tree(), config)?;
+    let bucket_info = file_changes_to_coupling_buckets(
+        polyglot_data.tree(),
+        config,
+        &run.cancellation,
+        run.checkpoint_for(name).as_deref(),
+    )?;
 
     let (bucketing_config, filtered_buckets) = match bucket_info {
         Some(result) => result,
@@ -625,18 +1202,39 @@ pub fn gather_coupling(
             .tree_mut()
             .get_in_mut(&mut file_buf.components())
         {
-            let coupling_data = filtered_buckets.file_coupling_data(&file);
-            tree_node.indicators_mut().coupling = Some(coupling_data);
+            let coupling_data = filtered_buckets.file_coupling_data(file);
+            match name {
+                Some(name) => {
+                    tree_node
+                        .indicators_mut()
+                        .named_coupling
+                        .insert(name.to_owned(), coupling_data);
+                }
+                None => tree_node.indicators_mut().coupling = Some(coupling_data),
+            }
         } else {
-            // TODO: return an error
-            error!("Can't find {:?} in tree!", &file);
+            polyglot_data.add_diagnostic(Diagnostic::new(
+                DiagnosticCategory::UnmatchedHistory,
+                Some(&file_buf),
+                "File has coupling data but is not in the scanned tree",
+            ));
         };
     }
 
-    polyglot_data.metadata().coupling = Some(CouplingMetadata {
+    let coupling_metadata = CouplingMetadata {
         buckets: bucketing_config,
         config,
-    });
+        summary: filtered_buckets.summaries(),
+    };
+    match name {
+        Some(name) => {
+            polyglot_data
+                .metadata()
+                .named_coupling
+                .insert(name.to_owned(), coupling_metadata);
+        }
+        None => polyglot_data.metadata().coupling = Some(coupling_metadata),
+    }
 
     info!("Gathering coupling stats - done");
     Ok(())
@@ -648,6 +1246,7 @@ mod test {
         git::{GitActivity, GitData, GitNodeData},
         git_logger::CommitChange,
         loc::LanguageLocData,
+        FeatureFlags,
     };
 
     use super::*;
@@ -677,6 +1276,8 @@ mod test {
             coupling_time_distance: 60 * 60,
             min_distance: 0,
             max_common_roots: None,
+            max_p_value: None,
+            by_ticket: false,
         }
     }
 
@@ -693,6 +1294,9 @@ mod test {
             change: CommitChange::Add,
             lines_added: 1,
             lines_deleted: 0,
+            bulk: false,
+            parent_count: 1,
+            tickets: Vec::new(),
         }
     }
 
@@ -768,8 +1372,19 @@ mod test {
         expected_file_changes.insert(root_file_1, [DAY1, DAY21].iter().copied().collect());
         expected_file_changes.insert(child_file_1, [DAY21, DAY22].iter().copied().collect());
 
-        assert_eq!(expected_timestamps, stats.timestamps);
-        assert_eq!(expected_file_changes, stats.file_changes);
+        let path = |id: &PathId| stats.paths.path(*id).clone();
+        let timestamps: BTreeMap<u64, HashSet<Rc<PathVec>>> = stats
+            .timestamps
+            .iter()
+            .map(|(timestamp, ids)| (*timestamp, ids.iter().map(path).collect()))
+            .collect();
+        let file_changes: HashMap<Rc<PathVec>, BTreeSet<u64>> = stats
+            .file_changes
+            .iter()
+            .map(|(id, timestamps)| (path(id), timestamps.clone()))
+            .collect();
+        assert_eq!(expected_timestamps, timestamps);
+        assert_eq!(expected_file_changes, file_changes);
     }
 
     #[test]
@@ -880,29 +1495,19 @@ mod test {
     }
 
     fn make_test_timestamps(data: &[(u64, Vec<&str>)]) -> FileChangeTimestamps {
-        let timestamps: BTreeMap<u64, HashSet<Rc<PathVec>>> = data
-            .iter()
-            .map(|(day, namelist)| {
-                let paths: HashSet<Rc<PathVec>> = namelist
-                    .iter()
-                    .map(|name| Rc::from(PathVec::from(name)))
-                    .collect();
-                (*day, paths)
-            })
-            .collect();
-        let mut file_changes: HashMap<Rc<PathVec>, BTreeSet<u64>> = HashMap::new();
-        for (timestamp, files) in timestamps.clone() {
+        let mut timestamps = FileChangeTimestamps {
+            paths: PathInterner::default(),
+            timestamps: BTreeMap::new(),
+            file_changes: HashMap::new(),
+            ticket_changes: HashMap::new(),
+            ticket_files: HashMap::new(),
+        };
+        for (timestamp, files) in data {
             for file in files {
-                let fs_entry = file_changes
-                    .entry(file.clone())
-                    .or_insert_with(BTreeSet::new);
-                (*fs_entry).insert(timestamp);
+                timestamps.add_change(&rc_pb(file), *timestamp);
             }
         }
-        FileChangeTimestamps {
-            timestamps,
-            file_changes,
-        }
+        timestamps
     }
 
     fn rc_pb(name: &str) -> Rc<PathVec> {
@@ -918,7 +1523,7 @@ mod test {
         let config = simple_coupling_config();
         let bucketing_config = BucketingConfig::new(config, DAY1, DAY1);
 
-        let coupling_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
 
         assert_eq!(coupling_buckets.buckets.len(), 1);
         let first_bucket = coupling_buckets.buckets.get(0).unwrap();
@@ -926,25 +1531,29 @@ mod test {
         assert_eq!(first_bucket.bucket_start, DAY1 - (20 * DAY_SIZE) + 1);
         assert_eq!(first_bucket.bucket_size, 20 * DAY_SIZE);
 
-        let mut expected_stats: HashMap<Rc<PathVec>, Coupling> = HashMap::new();
-        let mut foo_coupling: HashMap<Rc<PathVec>, u64> = HashMap::new();
-        foo_coupling.insert(rc_pb("foo"), 1);
-        let mut bar_coupling: HashMap<Rc<PathVec>, u64> = HashMap::new();
-        bar_coupling.insert(rc_pb("bar"), 1);
+        let foo = coupling_buckets.paths.id(&rc_pb("foo")).unwrap();
+        let bar = coupling_buckets.paths.id(&rc_pb("bar")).unwrap();
+        let mut expected_stats: HashMap<PathId, Coupling> = HashMap::new();
+        let mut foo_coupling: HashMap<PathId, u64> = HashMap::new();
+        foo_coupling.insert(foo, 1);
+        let mut bar_coupling: HashMap<PathId, u64> = HashMap::new();
+        bar_coupling.insert(bar, 1);
         expected_stats.insert(
-            rc_pb("foo"),
+            foo,
             Coupling {
-                name: rc_pb("foo"),
+                name: foo,
                 activity_bursts: 1,
                 coupled_files: bar_coupling,
+                p_values: HashMap::new(),
             },
         );
         expected_stats.insert(
-            rc_pb("bar"),
+            bar,
             Coupling {
-                name: rc_pb("bar"),
+                name: bar,
                 activity_bursts: 1,
                 coupled_files: foo_coupling,
+                p_values: HashMap::new(),
             },
         );
 
@@ -968,7 +1577,7 @@ mod test {
         let config = simple_coupling_config();
         let bucketing_config = BucketingConfig::new(config, DAY1, DAY22 + 500);
 
-        let coupling_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
 
         // there should be 2 buckets (as each one is 20 days long)
         assert_eq!(coupling_buckets.buckets.len(), 2);
@@ -977,22 +1586,19 @@ mod test {
 
         // first bucket should have file_stats for foo, bar and baz
         //  easier to dig out specific test cases than build the whole structure for equality testing
-        let foo_stats = first_bucket.couplings.get(&rc_pb("foo")).unwrap();
-        assert_eq!(foo_stats.name, rc_pb("foo")); // redundant!
+        let id = |name: &str| coupling_buckets.paths.id(&rc_pb(name)).unwrap();
+        let foo_stats = first_bucket.couplings.get(&id("foo")).unwrap();
+        assert_eq!(foo_stats.name, id("foo")); // redundant!
         assert_eq!(foo_stats.activity_bursts, 1); // actually activity bursts not commits - and there is only one
-        let foo_coupling: HashMap<Rc<PathVec>, u64> = [(rc_pb("bar"), 1), (rc_pb("baz"), 1)]
-            .iter()
-            .cloned()
-            .collect();
+        let foo_coupling: HashMap<PathId, u64> =
+            [(id("bar"), 1), (id("baz"), 1)].iter().copied().collect();
         assert_eq!(foo_stats.coupled_files, foo_coupling);
 
         // second bucket, foo has two bursts, one coupled with baz, one with bat
-        let foo_stats_b2 = second_bucket.couplings.get(&rc_pb("foo")).unwrap();
+        let foo_stats_b2 = second_bucket.couplings.get(&id("foo")).unwrap();
         assert_eq!(foo_stats_b2.activity_bursts, 2);
-        let foo_coupling_b2: HashMap<Rc<PathVec>, u64> = [(rc_pb("baz"), 1), (rc_pb("bat"), 2)]
-            .iter()
-            .cloned()
-            .collect();
+        let foo_coupling_b2: HashMap<PathId, u64> =
+            [(id("baz"), 1), (id("bat"), 2)].iter().copied().collect();
         assert_eq!(foo_stats_b2.coupled_files, foo_coupling_b2);
     }
 
@@ -1013,7 +1619,7 @@ mod test {
         let config = simple_coupling_config();
         let bucketing_config = BucketingConfig::new(config, DAY1, DAY22 + 500);
 
-        let coupling_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
 
         let foo_coupling = coupling_buckets.file_coupling_data(&rc_pb("foo"));
 
@@ -1051,6 +1657,8 @@ mod test {
             coupling_time_distance: 60 * 60,
             min_distance: 0,
             max_common_roots: None,
+            max_p_value: None,
+            by_ticket: false,
         };
         // test times should check these:
         // foo -> bar is in as it's 100%
@@ -1066,7 +1674,7 @@ mod test {
         ]);
         let bucketing_config = BucketingConfig::new(config, DAY1, DAY29);
 
-        let coupling_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
 
         let foo_coupling = coupling_buckets.file_coupling_data(&rc_pb("foo"));
         assert_eq!(foo_coupling.buckets.len(), 1);
@@ -1102,6 +1710,8 @@ mod test {
             coupling_time_distance: 60 * 60,
             min_distance: 2,
             max_common_roots: Some(1),
+            max_p_value: None,
+            by_ticket: false,
         };
         // filtering here means:
         //  siblings are not included
@@ -1115,7 +1725,7 @@ mod test {
         ]);
         let bucketing_config = BucketingConfig::new(config, DAY1, DAY29);
 
-        let coupling_buckets = CouplingBuckets::new(config, &timestamps, bucketing_config);
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
 
         let day1_coupling = coupling_buckets.file_coupling_data(&rc_pb("foo/bar.c"));
         assert_eq!(day1_coupling.buckets.len(), 1);
@@ -1137,6 +1747,77 @@ mod test {
         assert_eq!(day4_coupling.coupled_files, vec![(rc_pb("bar/foo.c"), 1)]);
     }
 
+    #[test]
+    fn buckets_are_summarised_with_the_strongest_edges_first() {
+        let config = simple_coupling_config();
+        let timestamps = make_test_timestamps(&[
+            (DAY1, vec!["foo", "bar"]),
+            (DAY2, vec!["foo"]),
+            (DAY3, vec!["foo", "bar"]),
+        ]);
+        let bucketing_config = BucketingConfig::new(config, DAY1, DAY3);
+
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
+        let summaries = coupling_buckets.summaries();
+
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.edges, 2);
+        assert_eq!(
+            summary.ratio_distribution,
+            vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 1]
+        );
+        assert_eq!(
+            summary.strongest_edges,
+            vec![
+                CouplingEdge {
+                    source: rc_pb("bar"),
+                    target: rc_pb("foo"),
+                    count: 2,
+                    ratio: 1.0
+                },
+                CouplingEdge {
+                    source: rc_pb("foo"),
+                    target: rc_pb("bar"),
+                    count: 2,
+                    ratio: 2.0 / 3.0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn files_changed_for_the_same_ticket_are_coupled_however_far_apart() {
+        let config = simple_coupling_config().with_ticket_coupling(true);
+        let mut timestamps = make_test_timestamps(&[
+            (DAY1, vec!["foo", "bar"]),
+            (DAY4, vec!["baz"]),
+            (DAY4 + 60, vec!["bat"]),
+        ]);
+        timestamps.add_ticket_change(&rc_pb("foo"), "ABC-1", DAY1);
+        timestamps.add_ticket_change(&rc_pb("baz"), "ABC-1", DAY4);
+        timestamps.add_ticket_change(&rc_pb("bat"), "ABC-2", DAY4 + 60);
+        let bucketing_config = BucketingConfig::new(config, DAY1, DAY4 + 60);
+
+        let coupling_buckets = CouplingBuckets::new(config, timestamps, bucketing_config);
+
+        let foo_coupling = coupling_buckets.file_coupling_data(&rc_pb("foo"));
+        assert_eq!(foo_coupling.buckets.len(), 1);
+        assert_eq!(foo_coupling.buckets[0].activity_bursts, 1);
+        // not bar, changed at the same time without a ticket, but baz from the same ticket
+        assert_eq!(
+            foo_coupling.buckets[0].coupled_files,
+            vec![(rc_pb("baz"), 1)]
+        );
+        let bat_coupling = coupling_buckets.file_coupling_data(&rc_pb("bat"));
+        assert!(bat_coupling.buckets[0].coupled_files.is_empty());
+        assert!(coupling_buckets
+            .file_coupling_data(&rc_pb("bar"))
+            .buckets
+            .is_empty());
+    }
+
     #[test]
     fn common_roots_calculates_common_parts_of_paths() {
         assert_eq!(common_roots(&"foo".into(), &"bar".into()), 0);
@@ -1185,4 +1866,134 @@ mod test {
             Some(3)
         );
     }
+
+    #[test]
+    fn binomial_tails_are_calculated() {
+        assert_eq!(binomial_upper_tail(2, 0, 0.5), 1.0);
+        assert!((binomial_upper_tail(2, 1, 0.5) - 0.75).abs() < 1e-12);
+        assert!((binomial_upper_tail(10, 10, 0.5) - 1.0 / 1024.0).abs() < 1e-12);
+        assert_eq!(binomial_upper_tail(3, 4, 0.5), 0.0);
+        // 1e-100 - tiny, but not zero
+        assert!(binomial_upper_tail(100, 100, 0.1) > 0.0);
+    }
+
+    #[test]
+    fn coincidental_coupling_is_not_significant() {
+        let (source, rare, busy) = (PathId(0), PathId(1), PathId(2));
+        let mut bucket = CouplingBucket::new(0, 20 * DAY_SIZE);
+        for burst in 0..10 {
+            // "rare" changes only with source, "busy" changes all the time and once with source
+            let mut coupled = vec![rare];
+            if burst == 0 {
+                coupled.push(busy);
+            }
+            bucket.add_files(source, coupled);
+            bucket.add_files(rare, vec![source]);
+        }
+        for _ in 0..100 {
+            bucket.add_files(busy, vec![]);
+        }
+
+        bucket.filter_by_significance(0.05, 2 * 60 * 60);
+
+        let stats = &bucket.couplings[&source];
+        assert_eq!(stats.coupled_files.keys().collect::<Vec<_>>(), vec![&rare]);
+        assert!(stats.p_values[&rare] < 1e-10);
+        assert!(!stats.p_values.contains_key(&busy));
+    }
+
+    #[test]
+    fn named_coupling_is_stored_by_name_with_its_own_buckets() {
+        let mut root = FlareTreeNode::dir("root");
+        for name in ["a.txt", "b.txt"] {
+            let mut file = FlareTreeNode::file(name);
+            file.indicators_mut().git = Some(fake_git_node_data(&[DAY1, DAY21]));
+            file.indicators_mut().loc = Some(fake_loc_data(10));
+            root.append_child(file);
+        }
+        let mut data = PolyglotData::new("test", None, root, FeatureFlags::default());
+        let config = simple_coupling_config();
+        let run = CouplingRun {
+            cancellation: CancellationToken::new(),
+            checkpoint: None,
+        };
+        gather_coupling(&mut data, config, None, &run).unwrap();
+        gather_coupling(&mut data, config.with_bucket_days(40), Some("long"), &run).unwrap();
+
+        let a = &data.tree().get_children()[0];
+        assert_eq!(a.indicators().coupling.as_ref().unwrap().buckets.len(), 2);
+        let long = &a.indicators().named_coupling["long"];
+        assert_eq!(long.buckets.len(), 1);
+        assert_eq!(long.buckets[0].activity_bursts, 2);
+        assert_eq!(long.buckets[0].coupled_files, vec![(rc_pb("b.txt"), 2)]);
+    }
+
+    #[test]
+    fn interrupted_coupling_resumes_from_a_checkpoint() {
+        let config = simple_coupling_config();
+        let changes = [
+            (DAY1, vec!["foo", "bar"]),
+            (DAY2, vec!["foo", "bar", "baz"]),
+        ];
+        let bucketing_config = BucketingConfig::new(config, DAY1, DAY2);
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = dir.path().join("coupling.checkpoint");
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let error = CouplingBuckets::build(
+            config,
+            make_test_timestamps(&changes),
+            bucketing_config,
+            &cancelled,
+            Some(&checkpoint),
+        )
+        .err()
+        .unwrap();
+        assert!(crate::cancellation::is_cancellation(&error));
+        assert!(checkpoint.exists());
+
+        // as if "foo" was done before the run was interrupted - with a burst count that can only
+        // have come from the checkpoint
+        let full = CouplingBuckets::new(config, make_test_timestamps(&changes), bucketing_config);
+        let foo = full.paths.id(&rc_pb("foo")).unwrap();
+        let mut partial = full.buckets.clone();
+        for bucket in &mut partial {
+            bucket.couplings.retain(|file, _| *file == foo);
+            for stats in bucket.couplings.values_mut() {
+                stats.activity_bursts = 99;
+            }
+        }
+        // 2 changes each to foo and bar, 1 to baz
+        let work = 5;
+        CouplingCheckpoint::new(
+            config,
+            bucketing_config,
+            work,
+            &full.paths,
+            &HashSet::from([foo]),
+            &partial,
+        )
+        .save(&checkpoint)
+        .unwrap();
+
+        let resumed = CouplingBuckets::build(
+            config,
+            make_test_timestamps(&changes),
+            bucketing_config,
+            &CancellationToken::new(),
+            Some(&checkpoint),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resumed.file_coupling_data(&rc_pb("foo")).buckets[0].activity_bursts,
+            99
+        );
+        assert_eq!(
+            resumed.file_coupling_data(&rc_pb("bar")),
+            full.file_coupling_data(&rc_pb("bar"))
+        );
+        assert!(!checkpoint.exists());
+    }
 }
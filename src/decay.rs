@@ -0,0 +1,252 @@
+#![warn(clippy::all)]
+//! "Code decay" - a single sortable score per file for risky old code, combining:
+//! * age - time since the file last changed, relative to the latest change in the scan
+//! * churn - lines added and deleted over the scanned history, relative to the busiest file
+//! * attrition - the proportion of the file's contributors who are no longer active anywhere in the scan
+//!
+//! Each part is between 0 and 1, and the score is their weighted average.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::flare::FlareTreeNode;
+use crate::git::{GitData, GitNodeData};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DecayConfig {
+    pub age_weight: f64,
+    pub churn_weight: f64,
+    pub attrition_weight: f64,
+    /// files this many days old or older have the maximum age score
+    pub max_age_days: u64,
+    /// users with no changes in this many days are counted as having left
+    pub inactive_days: u64,
+}
+
+impl Default for DecayConfig {
+    fn default() -> Self {
+        DecayConfig {
+            age_weight: 1.0,
+            churn_weight: 1.0,
+            attrition_weight: 1.0,
+            max_age_days: 730,
+            inactive_days: 365,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecayData {
+    pub score: f64,
+    pub age: f64,
+    pub churn: f64,
+    pub attrition: f64,
+}
+
+/// tree-wide values needed to normalise per-file values
+#[derive(Debug, Default)]
+struct DecayContext {
+    latest_change: u64,
+    max_churn: u64,
+    last_active_by_user: HashMap<usize, u64>,
+}
+
+fn churn(data: &GitData) -> u64 {
+    data.details
+        .iter()
+        .map(|details| details.lines_added + details.lines_deleted)
+        .sum()
+}
+
+fn gather_context(node: &FlareTreeNode, context: &mut DecayContext) {
+    if let Some(GitNodeData::File { data }) = &node.indicators().git {
+        context.latest_change = context.latest_change.max(data.last_update);
+        context.max_churn = context.max_churn.max(churn(data));
+        for details in &data.details {
+            for user in &details.users {
+                let last_active = context.last_active_by_user.entry(*user).or_insert(0);
+                *last_active = (*last_active).max(details.commit_day);
+            }
+        }
+    }
+    for child in node.get_children() {
+        gather_context(child, context);
+    }
+}
+
+fn file_decay(data: &GitData, context: &DecayContext, config: &DecayConfig) -> DecayData {
+    let age = if config.max_age_days == 0 {
+        1.0
+    } else {
+        let age_days = context.latest_change.saturating_sub(data.last_update) / SECONDS_PER_DAY;
+        (age_days as f64 / config.max_age_days as f64).min(1.0)
+    };
+    let churn = if context.max_churn == 0 {
+        0.0
+    } else {
+        churn(data) as f64 / context.max_churn as f64
+    };
+    let inactive_since = context
+        .latest_change
+        .saturating_sub(config.inactive_days * SECONDS_PER_DAY);
+    let attrition = if data.users.is_empty() {
+        0.0
+    } else {
+        let inactive = data
+            .users
+            .iter()
+            .filter(|user| match context.last_active_by_user.get(user) {
+                Some(last_active) => *last_active < inactive_since,
+                None => true,
+            })
+            .count();
+        inactive as f64 / data.users.len() as f64
+    };
+    let total_weight = config.age_weight + config.churn_weight + config.attrition_weight;
+    let score = if total_weight > 0.0 {
+        (age * config.age_weight
+            + churn * config.churn_weight
+            + attrition * config.attrition_weight)
+            / total_weight
+    } else {
+        0.0
+    };
+    DecayData {
+        score,
+        age,
+        churn,
+        attrition,
+    }
+}
+
+fn apply_decay(node: &mut FlareTreeNode, context: &DecayContext, config: &DecayConfig) {
+    let decay = match &node.indicators().git {
+        Some(GitNodeData::File { data }) => Some(file_decay(data, context, config)),
+        _ => None,
+    };
+    if decay.is_some() {
+        node.indicators_mut().decay = decay;
+    }
+    for child in node.get_children_mut() {
+        apply_decay(child, context, config);
+    }
+}
+
+/// adds decay data to every file with git data
+pub fn calculate_decay(tree: &mut FlareTreeNode, config: &DecayConfig) {
+    info!("Calculating code decay");
+    let mut context = DecayContext::default();
+    gather_context(tree, &mut context);
+    apply_decay(tree, &context, config);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::GitDetails;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeSet;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+    const LATEST: u64 = 1000 * DAY;
+
+    fn git_file(name: &str, last_update: u64, lines: u64, users: &[usize]) -> FlareTreeNode {
+        let mut data = GitData::fake_with_activity(Vec::new());
+        data.last_update = last_update;
+        data.users = users.to_vec();
+        data.details = vec![GitDetails {
+            commit_day: last_update,
+            users: users.iter().copied().collect::<BTreeSet<usize>>(),
+            commits: 1,
+            lines_added: lines,
+            lines_deleted: 0,
+            bulk_commits: 0,
+            merge_commits: 0,
+        }];
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file
+    }
+
+    fn decay_of(tree: &FlareTreeNode, name: &str) -> DecayData {
+        tree.get_children()
+            .iter()
+            .find(|child| child.name() == name)
+            .and_then(|child| child.indicators().decay.clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn old_churned_code_by_departed_users_decays_most() {
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(git_file("new.rs", LATEST, 10, &[0]));
+        root.append_child(git_file("old.rs", LATEST - 730 * DAY, 40, &[1]));
+        root.append_child(git_file("middling.rs", LATEST - 365 * DAY, 20, &[0]));
+
+        calculate_decay(&mut root, &DecayConfig::default());
+
+        assert_eq!(
+            decay_of(&root, "new.rs"),
+            DecayData {
+                score: 0.25 / 3.0,
+                age: 0.0,
+                churn: 0.25,
+                attrition: 0.0
+            }
+        );
+        assert_eq!(
+            decay_of(&root, "old.rs"),
+            DecayData {
+                score: 1.0,
+                age: 1.0,
+                churn: 1.0,
+                attrition: 1.0
+            }
+        );
+        assert_eq!(
+            decay_of(&root, "middling.rs"),
+            DecayData {
+                score: 1.0 / 3.0,
+                age: 0.5,
+                churn: 0.5,
+                attrition: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn weights_change_the_score() {
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(git_file("new.rs", LATEST, 10, &[0]));
+        root.append_child(git_file("old.rs", LATEST - 365 * DAY, 10, &[0]));
+        let config = DecayConfig {
+            age_weight: 1.0,
+            churn_weight: 0.0,
+            attrition_weight: 0.0,
+            ..DecayConfig::default()
+        };
+
+        calculate_decay(&mut root, &config);
+
+        assert_eq!(
+            decay_of(&root, "old.rs"),
+            DecayData {
+                score: 0.5,
+                age: 0.5,
+                churn: 1.0,
+                attrition: 0.0
+            }
+        );
+        assert_eq!(
+            decay_of(&root, "new.rs"),
+            DecayData {
+                score: 0.0,
+                age: 0.0,
+                churn: 1.0,
+                attrition: 0.0
+            }
+        );
+    }
+}
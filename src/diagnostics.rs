@@ -0,0 +1,118 @@
+#![warn(clippy::all)]
+//! Non-fatal problems found while scanning - these are logged, and also saved in the output
+//! so automated tools can tell when a scan is incomplete.
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticCategory {
+    /// a file couldn't be read - it will have no data for some indicators
+    UnreadableFile,
+    /// a file's contents couldn't be decoded as text
+    UndecodableFile,
+    /// a path was neither a file nor a directory, or couldn't be walked
+    SkippedPath,
+    /// a symbolic link wasn't followed, as it leads somewhere already scanned
+    SkippedLink,
+    /// git is enabled, but files were found outside any git repository
+    MissingGit,
+    /// a shallow clone - git history is incomplete
+    ShallowRepository,
+    /// git history or coupling was found for a file that isn't in the scanned tree
+    UnmatchedHistory,
+    /// the scan was estimated to be over the memory limit, so some data was dropped
+    MemoryLimit,
+    /// a hosting API call failed, e.g. fetching pull requests - those indicators are missing
+    RemoteApi,
+    /// a `.polyglot_annotations.toml` file couldn't be read, or annotates a path that wasn't scanned
+    InvalidAnnotations,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub category: DiagnosticCategory,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// builds a diagnostic, and logs it as a warning
+    pub fn new(
+        category: DiagnosticCategory,
+        path: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Self {
+        let diagnostic = Diagnostic {
+            category,
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        };
+        warn!(
+            "{:?} - {:?}: {}",
+            diagnostic.category, diagnostic.path, diagnostic.message
+        );
+        diagnostic
+    }
+
+    /// a problem reading a file - undecodable if caused by an `InvalidData` io error
+    pub fn file_error(path: &Path, calculator: &str, error: &Error) -> Self {
+        let undecodable = error.chain().any(|cause| {
+            matches!(cause.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::InvalidData)
+        });
+        let category = if undecodable {
+            DiagnosticCategory::UndecodableFile
+        } else {
+            DiagnosticCategory::UnreadableFile
+        };
+        Diagnostic::new(
+            category,
+            Some(path),
+            format!("{} failed: {:#}", calculator, error),
+        )
+    }
+
+    /// paths are easier to read relative to the scan root
+    #[must_use]
+    pub fn relative_to(mut self, root: &Path) -> Self {
+        if let Some(path) = &self.path {
+            if let Ok(relative) = path.strip_prefix(root) {
+                self.path = Some(relative.to_path_buf());
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn invalid_data_errors_are_undecodable() {
+        let error = Error::from(io::Error::new(io::ErrorKind::InvalidData, "bad utf-8"));
+        let diagnostic = Diagnostic::file_error(Path::new("foo.txt"), "loc", &error);
+        assert_eq!(diagnostic.category, DiagnosticCategory::UndecodableFile);
+
+        let error = Error::from(io::Error::new(io::ErrorKind::PermissionDenied, "nope"))
+            .context("reading foo.txt");
+        let diagnostic = Diagnostic::file_error(Path::new("foo.txt"), "loc", &error);
+        assert_eq!(diagnostic.category, DiagnosticCategory::UnreadableFile);
+    }
+
+    #[test]
+    fn paths_can_be_made_relative() {
+        let diagnostic = Diagnostic::new(
+            DiagnosticCategory::SkippedPath,
+            Some(Path::new("./root/dir/file")),
+            "test",
+        )
+        .relative_to(Path::new("./root"));
+        assert_eq!(diagnostic.path, Some(PathBuf::from("dir/file")));
+    }
+}
@@ -0,0 +1,239 @@
+#![warn(clippy::all)]
+//! Directory-only trees, for architecture-level views of huge systems.
+//!
+//! File nodes are removed, and each directory gets totals for everything under it - summed lines
+//! of code, an `aggregated` count of the files and directories replaced, and a
+//! `directory_summary` of indentation and git activity. With a depth limit, deeper directories
+//! are removed too, and counted in their ancestor at the limit.
+//!
+//! Totals include subdirectories, so leaf directories hold the values for sizing a treemap, and
+//! any directory can be compared with its siblings. File-level data like coupling is dropped
+//! with the files, though coupling metadata is kept.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use crate::loc::LanguageLocData;
+use crate::node_limit::AggregatedData;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryTreeConfig {
+    /// directories deeper than this are removed, and counted in their ancestor - the root's
+    /// children are depth 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth_limit: Option<usize>,
+}
+
+/// totals for a directory in a directory-only tree, for indicators that can be summed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectorySummary {
+    /// the sum of the indentation sums of files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indentation_sum: Option<u64>,
+    /// the latest git change to any file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_update: Option<u64>,
+    /// distinct git users who changed any file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_count: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    aggregated: AggregatedData,
+    loc: Option<LanguageLocData>,
+    /// code lines by language, to name the summed loc after the main language
+    language_code: BTreeMap<String, usize>,
+    indentation_sum: Option<u64>,
+    last_update: Option<u64>,
+    users: Option<BTreeSet<usize>>,
+}
+
+impl Totals {
+    fn add_file(&mut self, file: &FlareTreeNode) {
+        let indicators = file.indicators();
+        match indicators.aggregated {
+            Some(aggregated) => {
+                self.aggregated.files += aggregated.files;
+                self.aggregated.directories += aggregated.directories;
+            }
+            None => self.aggregated.files += 1,
+        }
+        if let Some(loc) = &indicators.loc {
+            self.add_loc(loc);
+        }
+        if let Some(indentation) = &indicators.indentation {
+            *self.indentation_sum.get_or_insert(0) += indentation.sum;
+        }
+        if let Some(GitNodeData::File { data }) = &indicators.git {
+            self.last_update = self.last_update.max(Some(data.last_update));
+            self.users
+                .get_or_insert_with(BTreeSet::new)
+                .extend(data.users.iter().copied());
+        }
+    }
+
+    fn add_loc(&mut self, loc: &LanguageLocData) {
+        *self.language_code.entry(loc.language.clone()).or_default() += loc.code;
+        self.sum_loc(loc);
+    }
+
+    fn sum_loc(&mut self, loc: &LanguageLocData) {
+        let total = self.loc.get_or_insert_with(|| LanguageLocData {
+            language: String::new(),
+            binary: false,
+            blanks: 0,
+            code: 0,
+            comments: 0,
+            lines: 0,
+            bytes: 0,
+        });
+        total.blanks += loc.blanks;
+        total.code += loc.code;
+        total.comments += loc.comments;
+        total.lines += loc.lines;
+        total.bytes += loc.bytes;
+    }
+
+    fn add_directory(&mut self, other: Totals) {
+        self.aggregated.files += other.aggregated.files;
+        self.aggregated.directories += other.aggregated.directories + 1;
+        if let Some(loc) = &other.loc {
+            self.sum_loc(loc);
+        }
+        for (language, code) in other.language_code {
+            *self.language_code.entry(language).or_default() += code;
+        }
+        if let Some(sum) = other.indentation_sum {
+            *self.indentation_sum.get_or_insert(0) += sum;
+        }
+        self.last_update = self.last_update.max(other.last_update);
+        if let Some(users) = other.users {
+            self.users.get_or_insert_with(BTreeSet::new).extend(users);
+        }
+    }
+
+    fn apply(&self, directory: &mut FlareTreeNode) {
+        let indicators = directory.indicators_mut();
+        indicators.aggregated = Some(self.aggregated);
+        indicators.loc = self.loc.clone().map(|loc| LanguageLocData {
+            language: self
+                .language_code
+                .iter()
+                .max_by(|(a_name, a_code), (b_name, b_code)| {
+                    a_code.cmp(b_code).then(b_name.cmp(a_name))
+                })
+                .map(|(name, _)| name.clone())
+                .unwrap_or_default(),
+            ..loc
+        });
+        let summary = DirectorySummary {
+            indentation_sum: self.indentation_sum,
+            last_update: self.last_update,
+            user_count: self.users.as_ref().map(BTreeSet::len),
+        };
+        indicators.directory_summary = Some(summary).filter(|s| *s != DirectorySummary::default());
+    }
+}
+
+fn collapse(directory: &mut FlareTreeNode, depth: usize, config: DirectoryTreeConfig) -> Totals {
+    let mut totals = Totals::default();
+    for mut child in std::mem::take(directory.get_children_mut()) {
+        if child.is_file() {
+            totals.add_file(&child);
+        } else {
+            let child_totals = collapse(&mut child, depth + 1, config);
+            totals.add_directory(child_totals);
+            if config.depth_limit.map_or(true, |limit| depth < limit) {
+                directory.append_child(child);
+            }
+        }
+    }
+    totals.apply(directory);
+    totals
+}
+
+/// removes files, and directories past the depth limit, adding their totals to directories
+pub fn apply_directory_tree(root: &mut FlareTreeNode, config: DirectoryTreeConfig) {
+    collapse(root, 0, config);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flare::ROOT_NAME;
+    use pretty_assertions::assert_eq;
+
+    fn file_with_lines(name: &str, language: &str, lines: usize) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: language.to_owned(),
+            binary: false,
+            blanks: 0,
+            code: lines,
+            comments: 0,
+            lines,
+            bytes: lines as u64 * 10,
+        });
+        file
+    }
+
+    /// root/a.rs, root/deep/{x.rs, y.clj, deeper/z.clj}
+    fn test_tree() -> FlareTreeNode {
+        let mut deeper = FlareTreeNode::dir("deeper");
+        deeper.append_child(file_with_lines("z.clj", "Clojure", 5));
+        let mut deep = FlareTreeNode::dir("deep");
+        deep.append_child(file_with_lines("x.rs", "Rust", 100));
+        deep.append_child(file_with_lines("y.clj", "Clojure", 50));
+        deep.append_child(deeper);
+        let mut root = FlareTreeNode::dir(ROOT_NAME);
+        root.append_child(file_with_lines("a.rs", "Rust", 1));
+        root.append_child(deep);
+        root
+    }
+
+    #[test]
+    fn files_are_replaced_by_directory_totals() {
+        let mut tree = test_tree();
+        apply_directory_tree(&mut tree, DirectoryTreeConfig::default());
+
+        assert_eq!(tree.get_children().len(), 1);
+        let deep = &tree.get_children()[0];
+        assert_eq!(deep.get_children().len(), 1);
+        let deep_loc = deep.indicators().loc.as_ref().unwrap();
+        assert_eq!(deep_loc.code, 155);
+        assert_eq!(deep_loc.language, "Rust");
+        assert_eq!(
+            deep.indicators().aggregated,
+            Some(AggregatedData {
+                files: 3,
+                directories: 1
+            })
+        );
+        assert_eq!(tree.indicators().loc.as_ref().unwrap().code, 156);
+        assert_eq!(
+            tree.indicators().aggregated,
+            Some(AggregatedData {
+                files: 4,
+                directories: 2
+            })
+        );
+    }
+
+    #[test]
+    fn directories_past_the_depth_limit_are_counted_in_their_ancestor() {
+        let mut tree = test_tree();
+        apply_directory_tree(
+            &mut tree,
+            DirectoryTreeConfig {
+                depth_limit: Some(1),
+            },
+        );
+
+        let deep = &tree.get_children()[0];
+        assert!(deep.get_children().is_empty());
+        assert_eq!(deep.indicators().loc.as_ref().unwrap().code, 155);
+    }
+}
@@ -0,0 +1,197 @@
+#![warn(clippy::all)]
+//! Writing only selected indicator fields, to shrink output for analyses that need a few values.
+//!
+//! Fields are indicator names, optionally followed by field names within the indicator, e.g.
+//! `loc.code` or `git.details.commits` - a bare indicator name like `indentation` keeps the
+//! whole indicator. Fields inside lists, like each of git's `details`, are selected from every
+//! list item. Metadata is kept as is, so explorers can still make sense of what is left.
+//!
+//! Like older formats, selection works on the whole file as JSON, so the tree isn't written a
+//! subtree at a time.
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// the fields to keep, as given e.g. `["loc.code", "git.age_in_days"]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FieldSelection(Vec<String>);
+
+/// selected fields as a tree, so nested fields can be matched a level at a time
+#[derive(Debug, PartialEq)]
+enum Selected {
+    All,
+    Fields(BTreeMap<String, Selected>),
+}
+
+impl Selected {
+    fn insert<'a>(&mut self, name: &str, mut rest: impl Iterator<Item = &'a str>) {
+        let fields = match self {
+            // keeping the whole value wins over keeping some of it
+            Selected::All => return,
+            Selected::Fields(fields) => fields,
+        };
+        match rest.next() {
+            None => {
+                fields.insert(name.to_owned(), Selected::All);
+            }
+            Some(next) => fields
+                .entry(name.to_owned())
+                .or_insert_with(|| Selected::Fields(BTreeMap::new()))
+                .insert(next, rest),
+        }
+    }
+
+    fn retain(&self, value: &mut Value) {
+        match (self, value) {
+            (Selected::All, _) => {}
+            (Selected::Fields(fields), Value::Object(object)) => retain_object(fields, object),
+            (selected, Value::Array(values)) => {
+                for value in values {
+                    selected.retain(value);
+                }
+            }
+            // a plain value where fields were expected - there's nothing to keep
+            (Selected::Fields(_), value) => *value = Value::Null,
+        }
+    }
+}
+
+fn retain_object(fields: &BTreeMap<String, Selected>, object: &mut Map<String, Value>) {
+    object.retain(|key, _| fields.contains_key(key));
+    for (key, value) in object.iter_mut() {
+        fields[key].retain(value);
+    }
+    object.retain(|_, value| match value {
+        Value::Null => false,
+        Value::Object(object) => !object.is_empty(),
+        _ => true,
+    });
+}
+
+impl FieldSelection {
+    /// parses a comma-separated list like "loc.code,git.age_in_days"
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let fields: Vec<String> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        if fields.is_empty() {
+            bail!("No fields selected");
+        }
+        if let Some(field) = fields
+            .iter()
+            .find(|field| field.split('.').any(str::is_empty))
+        {
+            bail!(
+                "Invalid field '{}' - should be INDICATOR or INDICATOR.FIELD e.g. loc.code",
+                field
+            );
+        }
+        Ok(FieldSelection(fields))
+    }
+
+    fn selected(&self) -> Selected {
+        let mut selected = Selected::Fields(BTreeMap::new());
+        for field in &self.0 {
+            let mut path = field.split('.');
+            if let Some(name) = path.next() {
+                selected.insert(name, path);
+            }
+        }
+        selected
+    }
+}
+
+fn select_in_node(node: &mut Value, selected: &Selected) {
+    if let Some(node) = node.as_object_mut() {
+        if let Some(data) = node.get_mut("data") {
+            selected.retain(data);
+            if data.as_object().map_or(true, Map::is_empty) {
+                node.remove("data");
+            }
+        }
+        if let Some(Value::Array(children)) = node.get_mut("children") {
+            for child in children {
+                select_in_node(child, selected);
+            }
+        }
+    }
+}
+
+/// keeps only the selected fields in every tree node of a serialized data file - nodes left
+/// with no data have no `data` at all, as if they had no indicators
+pub fn select_fields(data: &mut Value, selection: &FieldSelection) {
+    let selected = selection.selected();
+    if let Some(tree) = data.get_mut("tree") {
+        select_in_node(tree, &selected);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn fields_must_name_an_indicator() {
+        assert!(FieldSelection::parse("loc.code, git.age_in_days").is_ok());
+        assert!(FieldSelection::parse(" , ").is_err());
+        assert!(FieldSelection::parse("loc.").is_err());
+        assert!(FieldSelection::parse(".code").is_err());
+    }
+
+    #[test]
+    fn only_selected_fields_are_kept() {
+        let mut data = json!({
+            "name": "test",
+            "tree": {
+                "name": "<root>",
+                "data": {"git": {"head": "abc"}},
+                "children": [{
+                    "name": "a.rs",
+                    "data": {
+                        "loc": {"code": 10, "blanks": 2},
+                        "git": {
+                            "age_in_days": 3,
+                            "details": [{"commit_day": 1, "commits": 2}]
+                        },
+                        "indentation": {"p99": 4, "sum": 40},
+                        "nesting": {"maximum": 2}
+                    }
+                }]
+            },
+            "metadata": {"git": {"users": []}}
+        });
+        let selection = FieldSelection::parse(
+            "loc.code,git.age_in_days,git.details.commits,indentation,indentation.sum",
+        )
+        .unwrap();
+
+        select_fields(&mut data, &selection);
+
+        assert_eq!(
+            data,
+            json!({
+                "name": "test",
+                "tree": {
+                    "name": "<root>",
+                    "children": [{
+                        "name": "a.rs",
+                        "data": {
+                            "loc": {"code": 10},
+                            "git": {"age_in_days": 3, "details": [{"commits": 2}]},
+                            "indentation": {"p99": 4, "sum": 40}
+                        }
+                    }]
+                },
+                "metadata": {"git": {"users": []}}
+            })
+        );
+    }
+}
@@ -2,43 +2,62 @@ use std::{fs, path::Path};
 
 use anyhow::Error;
 use filetime::FileTime;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    flare::FlareTreeNode, polyglot_data::IndicatorMetadata,
+    diagnostics::Diagnostic, flare::FlareTreeNode, polyglot_data::IndicatorMetadata,
     toxicity_indicator_calculator::ToxicityIndicatorCalculator,
 };
 
 /// File creation and modification times, in seconds since unix epoch
 /// using the filetime crate so Windows times are converted to unix times!
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
 pub struct FileStats {
     created: i64,
     modified: i64,
+    /// file size in bytes - only recorded when loc data, which has sizes, isn't gathered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
 }
 
 impl FileStats {
-    fn new(path: &Path) -> Result<Self, Error> {
+    fn new(path: &Path, with_size: bool) -> Result<Self, Error> {
         let metadata = fs::metadata(path)?;
         let ctime = FileTime::from_creation_time(&metadata);
         let mtime = FileTime::from_last_modification_time(&metadata);
+        let bytes = (with_size && metadata.is_file()).then(|| metadata.len());
         match (ctime, mtime) {
             (Some(ctime), mtime) => Ok(FileStats {
                 created: ctime.unix_seconds(),
                 modified: mtime.unix_seconds(),
+                bytes,
             }),
             (None, mtime) => {
                 warn!("File has no ctime - using mtime");
                 Ok(FileStats {
                     created: mtime.unix_seconds(),
                     modified: mtime.unix_seconds(),
+                    bytes,
                 })
             }
         }
     }
 }
-#[derive(Debug)]
-pub struct FileStatsCalculator {}
+#[derive(Debug, Default)]
+pub struct FileStatsCalculator {
+    /// record file sizes as well as times
+    with_size: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FileStatsCalculator {
+    pub fn new(with_size: bool) -> Self {
+        FileStatsCalculator {
+            with_size,
+            diagnostics: Vec::new(),
+        }
+    }
+}
 
 impl ToxicityIndicatorCalculator for FileStatsCalculator {
     fn name(&self) -> String {
@@ -46,8 +65,12 @@ impl ToxicityIndicatorCalculator for FileStatsCalculator {
     }
 
     fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
-        let file_stats = FileStats::new(path)?;
-        node.indicators_mut().file_stats = Some(file_stats);
+        match FileStats::new(path, self.with_size) {
+            Ok(file_stats) => node.indicators_mut().file_stats = Some(file_stats),
+            Err(e) => self
+                .diagnostics
+                .push(Diagnostic::file_error(path, &self.name(), &e)),
+        }
 
         Ok(())
     }
@@ -55,6 +78,10 @@ impl ToxicityIndicatorCalculator for FileStatsCalculator {
     fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
         Ok(())
     }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
 }
 
 #[cfg(test)]
@@ -62,6 +89,7 @@ mod test {
     use std::time::SystemTime;
 
     use super::*;
+    use std::io::Write;
     use std::time::UNIX_EPOCH;
     use tempfile::{NamedTempFile, TempDir};
 
@@ -69,7 +97,7 @@ mod test {
     fn can_get_stats_for_a_file() -> Result<(), Error> {
         let newfile = NamedTempFile::new()?;
 
-        let stats = FileStats::new(newfile.path())?;
+        let stats = FileStats::new(newfile.path(), false)?;
         let now: i64 = SystemTime::now()
             .duration_since(UNIX_EPOCH)?
             .as_secs()
@@ -77,6 +105,17 @@ mod test {
 
         assert!(stats.created > now - 1 && stats.created < now + 1);
         assert!(stats.modified > now - 1 && stats.modified < now + 1);
+        assert_eq!(stats.bytes, None);
+
+        Ok(())
+    }
+    #[test]
+    fn can_get_sizes_for_a_file() -> Result<(), Error> {
+        let mut newfile = NamedTempFile::new()?;
+        newfile.write_all(b"twelve bytes")?;
+
+        let stats = FileStats::new(newfile.path(), true)?;
+        assert_eq!(stats.bytes, Some(12));
 
         Ok(())
     }
@@ -84,7 +123,7 @@ mod test {
     fn can_get_stats_for_a_dir() -> Result<(), Error> {
         let newdir = TempDir::new()?;
 
-        let stats = FileStats::new(newdir.path())?;
+        let stats = FileStats::new(newdir.path(), true)?;
         let now: i64 = SystemTime::now()
             .duration_since(UNIX_EPOCH)?
             .as_secs()
@@ -92,6 +131,8 @@ mod test {
 
         assert!(stats.created > now - 1 && stats.created < now + 1);
         assert!(stats.modified > now - 1 && stats.modified < now + 1);
+        // directories have no size of their own
+        assert_eq!(stats.bytes, None);
 
         Ok(())
     }
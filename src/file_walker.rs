@@ -1,100 +1,560 @@
 #![warn(clippy::all)]
 
-use crate::{polyglot_data::PolyglotData, FeatureFlags};
+use crate::{
+    cancellation::{cancelled_with, is_cancellation, CancellationToken},
+    diagnostics::{Diagnostic, DiagnosticCategory},
+    polyglot_data::PolyglotData,
+    progress::Progress,
+    time_budget,
+    timings::Profiler,
+    vendored::{VendoredConfig, VendoredMode},
+    FeatureFlags,
+};
 
 use super::flare;
 use super::flare::FlareTreeNode;
 use super::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
 use anyhow::{Context, Error};
-use ignore::{Walk, WalkBuilder};
+use ignore::WalkBuilder;
 #[allow(unused_imports)]
 use path_slash::PathExt;
-use std::{path::Path, time::Instant};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-fn apply_calculators_to_node(
+pub(crate) fn apply_calculators_to_node(
     node: &mut FlareTreeNode,
     path: &Path,
     toxicity_indicator_calculators: &mut [Box<dyn ToxicityIndicatorCalculator>],
+    profiler: &mut Profiler,
 ) -> Result<(), Error> {
+    let node_start = Instant::now();
     for tic in toxicity_indicator_calculators.iter_mut() {
+        let tic_start = Instant::now();
         tic.visit_node(node, path)
             .with_context(|| format!("applying calcluator {} to {:?}", tic.name(), path))?;
+        profiler.record_calculator(&tic.name(), tic_start.elapsed());
     }
+    profiler.record_node(path, node_start.elapsed());
     Ok(())
 }
 
+/// all diagnostics so far, walker and calculator, for a cancelled walk
+pub(crate) fn cancelled_walk(
+    error: &Error,
+    mut diagnostics: Vec<Diagnostic>,
+    toxicity_indicator_calculators: &[Box<dyn ToxicityIndicatorCalculator>],
+    root: &Path,
+) -> Error {
+    for tic in toxicity_indicator_calculators {
+        diagnostics.extend(
+            tic.diagnostics()
+                .into_iter()
+                .map(|diagnostic| diagnostic.relative_to(root)),
+        );
+    }
+    cancelled_with(error, diagnostics)
+}
+
 const LOG_INTERVAL_SECS: u64 = 60 * 5;
 
-fn walk_tree_walker(
-    walker: Walk,
+/// total size of the files under a directory, at any depth - gathered during the walk
+/// so clients can size directories without adding up every file
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DirSize {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl DirSize {
+    pub fn add(&mut self, other: DirSize) {
+        self.bytes += other.bytes;
+        self.files += other.files;
+    }
+}
+
+/// sizes are keyed by path relative to the root - file nodes might be renamed by calculators, but directories aren't
+fn apply_dir_sizes(node: &mut FlareTreeNode, path: &Path, dir_sizes: &HashMap<PathBuf, DirSize>) {
+    if let Some(size) = dir_sizes.get(path) {
+        node.indicators_mut().dir_size = Some(*size);
+    }
+    for child in node.get_children_mut() {
+        let child_path = path.join(child.name());
+        apply_dir_sizes(child, &child_path, dir_sizes);
+    }
+}
+
+/// builds the tree from a sequence of paths - parents must always be visited before their children
+fn walk_paths(
+    paths: impl Iterator<Item = Result<PathBuf, Diagnostic>>,
     prefix: &Path,
     name: &str,
     id: Option<&str>,
     toxicity_indicator_calculators: &mut [Box<dyn ToxicityIndicatorCalculator>],
     features: &FeatureFlags, // features just for JSON output
+    profiler: &mut Profiler,
+    cancellation: &CancellationToken,
 ) -> Result<PolyglotData, Error> {
     let mut tree = FlareTreeNode::new(flare::ROOT_NAME, false);
 
-    apply_calculators_to_node(&mut tree, prefix, toxicity_indicator_calculators)?;
+    if let Err(e) = cancellation.check().and_then(|_| {
+        apply_calculators_to_node(&mut tree, prefix, toxicity_indicator_calculators, profiler)
+    }) {
+        if is_cancellation(&e) {
+            return Err(cancelled_walk(
+                &e,
+                Vec::new(),
+                toxicity_indicator_calculators,
+                prefix,
+            ));
+        }
+        return Err(e);
+    }
 
     let mut last_log = Instant::now();
+    let mut diagnostics = Vec::new();
+    let mut dir_sizes: HashMap<PathBuf, DirSize> = HashMap::new();
+    // child indices from the root to each directory, so parents are found without searching
+    let mut dir_indices: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    dir_indices.insert(PathBuf::new(), Vec::new());
+    let progress = Progress::counter("walk");
     info!("Walking file tree");
 
-    for result in walker.map(|r| r.expect("File error!")).skip(1) {
-        let p = result.path();
+    for result in paths {
+        let p = match result {
+            Ok(p) => p,
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                continue;
+            }
+        };
+        let p = p.as_path();
         let relative = p.strip_prefix(prefix)?;
+        progress.inc(1);
         let elapsed_since_log = last_log.elapsed();
         if elapsed_since_log.as_secs() > LOG_INTERVAL_SECS {
             info!("Walking progress: {:?}", relative);
             last_log = Instant::now();
         }
 
-        let new_child = if p.is_dir() || p.is_file() {
-            let mut f = FlareTreeNode::new(p.file_name().unwrap(), p.is_file());
-            apply_calculators_to_node(&mut f, p, toxicity_indicator_calculators)?;
+        // one metadata call per entry - like `Path::is_file`, this follows symlinks
+        let metadata = fs::metadata(p).ok();
+        let is_file = metadata.as_ref().map_or(false, fs::Metadata::is_file);
+        let is_dir = metadata.as_ref().map_or(false, fs::Metadata::is_dir);
+
+        if is_file {
+            let file_size = DirSize {
+                bytes: metadata.as_ref().map_or(0, fs::Metadata::len),
+                files: 1,
+            };
+            for dir in relative.ancestors().skip(1) {
+                dir_sizes
+                    .entry(dir.to_path_buf())
+                    .or_default()
+                    .add(file_size);
+            }
+        }
+
+        let new_child = if is_dir || is_file {
+            let mut f = FlareTreeNode::new(p.file_name().unwrap(), is_file);
+            if let Err(e) = cancellation.check().and_then(|_| {
+                apply_calculators_to_node(&mut f, p, toxicity_indicator_calculators, profiler)
+            }) {
+                if is_cancellation(&e) {
+                    return Err(cancelled_walk(
+                        &e,
+                        diagnostics,
+                        toxicity_indicator_calculators,
+                        prefix,
+                    ));
+                }
+                return Err(e);
+            }
             Some(f)
         } else {
-            warn!("Not a file or dir: {:?} - skipping", p);
+            diagnostics.push(Diagnostic::new(
+                DiagnosticCategory::SkippedPath,
+                Some(relative),
+                "Not a file or directory - skipping",
+            ));
             None
         };
 
         if let Some(new_child) = new_child {
-            match relative.parent() {
-                Some(new_parent) => {
-                    let parent = tree
-                        .get_in_mut(&mut new_parent.components())
-                        .expect("no parent found!");
-                    parent.append_child(new_child);
-                }
-                None => {
-                    tree.append_child(new_child);
-                }
+            let mut indices = relative
+                .parent()
+                .and_then(|new_parent| dir_indices.get(new_parent))
+                .expect("no parent found!")
+                .clone();
+            let parent = tree.get_by_indices_mut(&indices).expect("no parent found!");
+            indices.push(parent.append_child(new_child));
+            if is_dir {
+                dir_indices.insert(relative.to_path_buf(), indices);
             }
         }
     }
+    progress.finish();
+    apply_dir_sizes(&mut tree, Path::new(""), &dir_sizes);
     info!("finished walking file tree");
-    Ok(PolyglotData::new(name, id, tree, features.clone()))
+    let mut data = PolyglotData::new(name, id, tree, features.clone());
+    for diagnostic in diagnostics {
+        data.add_diagnostic(diagnostic);
+    }
+    Ok(data)
+}
+
+/// options for walking a directory - by default, hidden and ignored files are skipped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkOptions {
+    pub follow_symlinks: bool,
+    /// include hidden files and directories - `.git` directories are always skipped
+    pub include_hidden: bool,
+    /// don't use `.gitignore`, `.ignore` and global git excludes - `.polyglot_code_scanner_ignore` files still apply
+    pub no_ignore: bool,
+    /// don't descend more than this many directories below the root
+    pub max_depth: Option<usize>,
+    /// extra files of ignore rules, in `.gitignore` syntax - these apply even with `no_ignore`
+    pub ignore_files: Vec<PathBuf>,
+    /// vendored dependency directories are skipped, if the mode is `Skip`
+    pub vendored: Option<VendoredConfig>,
+    /// only walk one in this many files, chosen by path - see `time_budget::is_sampled`
+    pub sample_one_in: Option<u64>,
+}
+
+/// the user's own ignore rules for every scan - `~/.config/polyglot_code_scanner/ignore`,
+/// or under `$XDG_CONFIG_HOME` if it is set.  None if there is no such file.
+#[must_use]
+pub fn global_ignore_file() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    let file = config_dir.join("polyglot_code_scanner").join("ignore");
+    if file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+/// the path a walk error is about, if any - errors may be wrapped with a depth or line number
+fn walk_error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// When following symlinks, links can form cycles, or lead back into the scanned directory so
+/// files are counted twice. This skips any link whose target is inside the root (it will be
+/// scanned anyway) or has already been reached by another link.
+///
+/// Targets are compared by canonical path rather than inode, which works on all platforms -
+/// hard links aren't followed "links" so are still scanned as separate files.
+#[derive(Debug)]
+pub(crate) struct LinkGuard {
+    root: PathBuf,
+    visited_targets: Mutex<HashSet<PathBuf>>,
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+impl LinkGuard {
+    fn new(root: &Path) -> Result<Self, Error> {
+        Ok(LinkGuard {
+            root: fs::canonicalize(root).with_context(|| format!("resolving {:?}", root))?,
+            visited_targets: Mutex::new(HashSet::new()),
+            diagnostics: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// should this entry be walked?  Only checks symlinks, other entries are always allowed.
+    fn allow(&self, path: &Path) -> bool {
+        // a broken link can't be canonicalized - let the walker report it
+        let target = match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(_) => return true,
+        };
+        let reason = if target.starts_with(&self.root) {
+            "Link target is inside the scanned directory - skipping"
+        } else if !self
+            .visited_targets
+            .lock()
+            .expect("poisoned link guard")
+            .insert(target.clone())
+        {
+            "Link target has already been scanned - skipping"
+        } else {
+            return true;
+        };
+        self.diagnostics
+            .lock()
+            .expect("poisoned link guard")
+            .push(Diagnostic::new(
+                DiagnosticCategory::SkippedLink,
+                Some(path),
+                format!("{} ({:?})", reason, target),
+            ));
+        false
+    }
+
+    fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.diagnostics.lock().expect("poisoned link guard"))
+    }
+
+    pub(crate) fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .lock()
+            .expect("poisoned link guard")
+            .clone()
+    }
+}
+
+/// paths under the root in tree order, not including the root - plus the guard used for
+/// symlinks, which holds diagnostics for links that were skipped
+pub(crate) type DirectoryPaths = (
+    Box<dyn Iterator<Item = Result<PathBuf, Diagnostic>>>,
+    Option<Arc<LinkGuard>>,
+);
+
+pub(crate) fn directory_paths(root: &Path, options: &WalkOptions) -> Result<DirectoryPaths, Error> {
+    directory_paths_within(root, options, None)
+}
+
+/// paths relative to the root that a walk should be limited to - the files, and every directory
+/// above them
+#[derive(Debug)]
+struct WalkLimit {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl WalkLimit {
+    fn new(files: &[PathBuf]) -> Self {
+        WalkLimit {
+            files: files.iter().cloned().collect(),
+            dirs: files
+                .iter()
+                .flat_map(|file| file.ancestors().skip(1))
+                .map(Path::to_path_buf)
+                .collect(),
+        }
+    }
+
+    fn allows(&self, relative: &Path) -> bool {
+        self.files.contains(relative) || self.dirs.contains(relative)
+    }
+}
+
+/// the listed files, relative to the root, that walking the directory would include - so ignore
+/// files, hidden files and skipped vendored directories apply to them.  Only the directories
+/// holding the files are read.
+pub(crate) fn walkable_files(
+    root: &Path,
+    files: &[PathBuf],
+    options: &WalkOptions,
+) -> Result<Vec<PathBuf>, Error> {
+    let limit = Arc::new(WalkLimit::new(files));
+    let (paths, _) = directory_paths_within(root, options, Some(limit.clone()))?;
+    Ok(paths
+        .filter_map(Result::ok)
+        .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .filter(|relative| limit.files.contains(relative))
+        .collect())
+}
+
+fn directory_paths_within(
+    root: &Path,
+    options: &WalkOptions,
+    limit: Option<Arc<WalkLimit>>,
+) -> Result<DirectoryPaths, Error> {
+    let link_guard = if options.follow_symlinks {
+        Some(Arc::new(LinkGuard::new(root)?))
+    } else {
+        None
+    };
+    let filter_guard = link_guard.clone();
+    let skip_vendored = options
+        .vendored
+        .clone()
+        .filter(|vendored| vendored.mode == VendoredMode::Skip);
+    let filter_root = root.to_owned();
+    let mut builder = WalkBuilder::new(root);
+    for ignore_file in &options.ignore_files {
+        if let Some(e) = builder.add_ignore(ignore_file) {
+            return Err(Error::from(e).context(format!("loading ignore file {:?}", ignore_file)));
+        }
+    }
+    let walker = builder
+        .add_custom_ignore_filename(".polyglot_code_scanner_ignore")
+        .follow_links(options.follow_symlinks)
+        .hidden(!options.include_hidden)
+        .ignore(!options.no_ignore)
+        .git_ignore(!options.no_ignore)
+        .git_global(!options.no_ignore)
+        .git_exclude(!options.no_ignore)
+        .max_depth(options.max_depth)
+        .filter_entry(move |entry| {
+            if entry.file_name() == ".git" {
+                return false;
+            }
+            if let Some(limit) = &limit {
+                match entry.path().strip_prefix(&filter_root) {
+                    Ok(relative) if limit.allows(relative) => {}
+                    _ => return false,
+                }
+            }
+            if let Some(vendored) = &skip_vendored {
+                if let Ok(relative) = entry.path().strip_prefix(&filter_root) {
+                    if vendored.is_vendored_dir(&filter_root, relative) {
+                        info!("Skipping vendored directory {:?}", relative);
+                        return false;
+                    }
+                }
+            }
+            match &filter_guard {
+                Some(guard) if entry.path_is_symlink() => guard.allow(entry.path()),
+                _ => true,
+            }
+        })
+        .sort_by_file_name(std::cmp::Ord::cmp)
+        .build();
+    let sample_root = root.to_owned();
+    let sample_one_in = options.sample_one_in;
+    let paths = walker
+        .skip(1)
+        .filter(move |result| match (result, sample_one_in) {
+            (Ok(entry), Some(one_in)) if entry.file_type().map_or(false, |t| t.is_file()) => {
+                let relative = entry
+                    .path()
+                    .strip_prefix(&sample_root)
+                    .unwrap_or(entry.path());
+                time_budget::is_sampled(relative, one_in)
+            }
+            _ => true,
+        })
+        .map(|result| {
+            result.map(ignore::DirEntry::into_path).map_err(|e| {
+                Diagnostic::new(
+                    DiagnosticCategory::SkippedPath,
+                    walk_error_path(&e),
+                    format!("Error walking directory: {}", e),
+                )
+            })
+        });
+    Ok((Box::new(paths), link_guard))
 }
 
 pub fn walk_directory(
     root: &Path,
     name: &str,
     id: Option<&str>,
-    follow_symlinks: bool,
+    options: &WalkOptions,
     toxicity_indicator_calculators: &mut [Box<dyn ToxicityIndicatorCalculator>],
     features: &FeatureFlags, // features just for JSON output
+    profiler: &mut Profiler,
+    cancellation: &CancellationToken,
 ) -> Result<PolyglotData, Error> {
-    walk_tree_walker(
-        WalkBuilder::new(root)
-            .add_custom_ignore_filename(".polyglot_code_scanner_ignore")
-            .follow_links(follow_symlinks)
-            .sort_by_file_name(std::cmp::Ord::cmp)
-            .build(),
+    let (paths, link_guard) = directory_paths(root, options)?;
+    let mut data = walk_paths(
+        paths,
         root,
         name,
         id,
         toxicity_indicator_calculators,
         features,
+        profiler,
+        cancellation,
+    )?;
+    if let Some(guard) = link_guard {
+        for diagnostic in guard.take_diagnostics() {
+            data.add_diagnostic(diagnostic.relative_to(root));
+        }
+    }
+    Ok(data)
+}
+
+/// converts a listed file into a simple relative path - None if it isn't under the root
+fn listed_path_relative_to(root: &Path, file: &Path) -> Option<PathBuf> {
+    let relative = if file.is_absolute() {
+        let canonical_root = root.canonicalize().ok()?;
+        file.strip_prefix(canonical_root).ok()?
+    } else {
+        file
+    };
+    let mut result = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(name) => result.push(name),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+/// All listed files and their parent directories, in tree order, plus problems with the list
+pub(crate) fn file_list_paths(root: &Path, files: &[PathBuf]) -> Vec<Result<PathBuf, Diagnostic>> {
+    let mut results = Vec::new();
+    let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+    for file in files {
+        match listed_path_relative_to(root, file) {
+            Some(relative) if root.join(&relative).exists() => {
+                paths.extend(
+                    relative
+                        .ancestors()
+                        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                        .map(Path::to_path_buf),
+                );
+            }
+            Some(_) => results.push(Err(Diagnostic::new(
+                DiagnosticCategory::SkippedPath,
+                Some(file),
+                "Listed file does not exist - skipping",
+            ))),
+            None => results.push(Err(Diagnostic::new(
+                DiagnosticCategory::SkippedPath,
+                Some(file),
+                "Listed file is not inside the scanned directory - skipping",
+            ))),
+        }
+    }
+    // BTreeSet orders paths by component, so parents are always before children
+    results.extend(paths.into_iter().map(|relative| Ok(root.join(relative))));
+    results
+}
+
+/// like `walk_directory` but only scans the listed files - paths are relative to the root.
+/// Ignore files are not checked, the list is assumed to be what was wanted.
+pub fn walk_file_list(
+    root: &Path,
+    files: &[PathBuf],
+    name: &str,
+    id: Option<&str>,
+    toxicity_indicator_calculators: &mut [Box<dyn ToxicityIndicatorCalculator>],
+    features: &FeatureFlags, // features just for JSON output
+    profiler: &mut Profiler,
+    cancellation: &CancellationToken,
+) -> Result<PolyglotData, Error> {
+    walk_paths(
+        file_list_paths(root, files).into_iter(),
+        root,
+        name,
+        id,
+        toxicity_indicator_calculators,
+        features,
+        profiler,
+        cancellation,
     )
 }
 
@@ -112,9 +572,11 @@ mod test {
             root,
             "test",
             Some("test-id"),
-            false,
+            &WalkOptions::default(),
             &mut Vec::new(),
             &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
         )
         .unwrap();
 
@@ -128,15 +590,332 @@ mod test {
             root,
             "test",
             Some("test-id"),
-            true,
+            &WalkOptions {
+                follow_symlinks: true,
+                ..WalkOptions::default()
+            },
             &mut Vec::new(),
             &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
         )
         .unwrap();
 
         assert_eq_json_file(&tree, "./tests/expected/simple_files.json");
     }
 
+    fn node_paths(node: &FlareTreeNode, prefix: &Path, paths: &mut Vec<String>) {
+        for child in node.get_children() {
+            let path = prefix.join(child.name());
+            paths.push(path.to_slash_lossy().to_string());
+            node_paths(child, &path, paths);
+        }
+    }
+
+    fn walked_paths(root: &Path, options: &WalkOptions) -> Vec<String> {
+        let data = walk_directory(
+            root,
+            "test",
+            None,
+            options,
+            &mut Vec::new(),
+            &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let mut paths = Vec::new();
+        node_paths(data.tree(), Path::new(""), &mut paths);
+        paths
+    }
+
+    #[test]
+    fn walk_options_control_hidden_ignored_and_deep_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("sub/deep")).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join(".hidden.txt"), "hidden").unwrap();
+        std::fs::write(root.join("a.log"), "ignored").unwrap();
+        std::fs::write(root.join("b.txt"), "normal").unwrap();
+        std::fs::write(root.join("sub/deep/c.txt"), "deep").unwrap();
+
+        assert_eq!(
+            walked_paths(root, &WalkOptions::default()),
+            vec!["b.txt", "sub", "sub/deep", "sub/deep/c.txt"]
+        );
+        assert_eq!(
+            walked_paths(
+                root,
+                &WalkOptions {
+                    include_hidden: true,
+                    ..WalkOptions::default()
+                }
+            ),
+            vec![
+                ".gitignore",
+                ".hidden.txt",
+                "b.txt",
+                "sub",
+                "sub/deep",
+                "sub/deep/c.txt"
+            ]
+        );
+        assert_eq!(
+            walked_paths(
+                root,
+                &WalkOptions {
+                    no_ignore: true,
+                    max_depth: Some(1),
+                    ..WalkOptions::default()
+                }
+            ),
+            vec!["a.log", "b.txt", "sub"]
+        );
+    }
+
+    #[test]
+    fn scanner_ignore_files_apply_at_any_level_and_from_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("sub/deep")).unwrap();
+        std::fs::write(root.join("sub/.polyglot_code_scanner_ignore"), "*.gen\n").unwrap();
+        std::fs::write(root.join("a.gen"), "kept").unwrap();
+        std::fs::write(root.join("b.tmp"), "extra rules").unwrap();
+        std::fs::write(root.join("sub/deep/c.gen"), "ignored").unwrap();
+        std::fs::write(root.join("sub/deep/d.txt"), "kept").unwrap();
+        let ignore_file = rules.path().join("ignore");
+        std::fs::write(&ignore_file, "*.tmp\n").unwrap();
+
+        assert_eq!(
+            walked_paths(
+                root,
+                &WalkOptions {
+                    no_ignore: true,
+                    ignore_files: vec![ignore_file],
+                    ..WalkOptions::default()
+                }
+            ),
+            vec!["a.gen", "sub", "sub/deep", "sub/deep/d.txt"]
+        );
+    }
+
+    #[test]
+    fn vendored_directories_can_be_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("web/node_modules/lib")).unwrap();
+        std::fs::create_dir_all(root.join("src/vendor")).unwrap();
+        std::fs::write(root.join("web/node_modules/lib/index.js"), "vendored").unwrap();
+        std::fs::write(root.join("web/app.js"), "kept").unwrap();
+        std::fs::write(root.join("src/vendor/ours.rs"), "kept").unwrap();
+        let vendored = VendoredConfig::new(VendoredMode::Skip, &[], &[PathBuf::from("src/vendor")]);
+
+        assert_eq!(
+            walked_paths(
+                root,
+                &WalkOptions {
+                    vendored: Some(vendored),
+                    ..WalkOptions::default()
+                }
+            ),
+            vec![
+                "src",
+                "src/vendor",
+                "src/vendor/ours.rs",
+                "web",
+                "web/app.js"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_ignore_files_are_an_error() {
+        let result = walk_directory(
+            Path::new("./tests/data/simple/"),
+            "test",
+            None,
+            &WalkOptions {
+                ignore_files: vec![PathBuf::from("./tests/data/no_such_ignore_file")],
+                ..WalkOptions::default()
+            },
+            &mut Vec::new(),
+            &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_into_the_root_or_to_visited_targets_are_skipped() {
+        use std::os::unix::fs::symlink;
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::fs::write(root.join("real/file.txt"), "real").unwrap();
+        std::fs::write(outside.path().join("shared.txt"), "shared").unwrap();
+        symlink(root.join("real"), root.join("back_in")).unwrap();
+        symlink(&root, root.join("real/cycle")).unwrap();
+        symlink(outside.path(), root.join("out_1")).unwrap();
+        symlink(outside.path(), root.join("out_2")).unwrap();
+
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..WalkOptions::default()
+        };
+        let data = walk_directory(
+            &root,
+            "test",
+            None,
+            &options,
+            &mut Vec::new(),
+            &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let mut paths = Vec::new();
+        node_paths(data.tree(), Path::new(""), &mut paths);
+        assert_eq!(
+            paths,
+            vec!["out_1", "out_1/shared.txt", "real", "real/file.txt"]
+        );
+        let mut skipped: Vec<PathBuf> = data
+            .diagnostics()
+            .iter()
+            .filter(|d| d.category == DiagnosticCategory::SkippedLink)
+            .filter_map(|d| d.path.clone())
+            .collect();
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            vec![
+                PathBuf::from("back_in"),
+                PathBuf::from("out_2"),
+                PathBuf::from("real/cycle")
+            ]
+        );
+    }
+
+    #[test]
+    fn scanning_a_file_list_builds_a_minimal_tree() {
+        let root = Path::new("./tests/data/simple/");
+        let files = vec![
+            PathBuf::from("./child/a.txt"),
+            PathBuf::from("missing.txt"),
+            PathBuf::from("../outside.txt"),
+        ];
+        let data = walk_file_list(
+            root,
+            &files,
+            "test",
+            Some("test-id"),
+            &mut Vec::new(),
+            &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        let value = serde_json::to_value(&data).unwrap();
+        assert_eq!(
+            value["tree"],
+            serde_json::json!({
+                "name": "<root>",
+                "children": [
+                    {
+                        "name": "child",
+                        "children": [{"name": "a.txt"}],
+                        "data": {"dir_size": {"bytes": 19, "files": 1}}
+                    }
+                ],
+                "data": {"dir_size": {"bytes": 19, "files": 1}}
+            })
+        );
+        assert_eq!(data.diagnostics().len(), 2);
+    }
+
+    #[derive(Debug)]
+    struct CancellingTIC {
+        cancellation: CancellationToken,
+    }
+
+    impl ToxicityIndicatorCalculator for CancellingTIC {
+        fn name(&self) -> String {
+            "cancelling".to_string()
+        }
+
+        fn visit_node(&mut self, _node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+            if path.is_file() {
+                self.cancellation.cancel();
+            }
+            Ok(())
+        }
+
+        fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn diagnostics(&self) -> Vec<Diagnostic> {
+            vec![Diagnostic::new(
+                DiagnosticCategory::SkippedPath,
+                None,
+                "cancelled",
+            )]
+        }
+    }
+
+    #[test]
+    fn walk_errors_keep_their_path() {
+        let error = ignore::Error::WithDepth {
+            depth: 2,
+            err: Box::new(ignore::Error::WithPath {
+                path: PathBuf::from("a/b"),
+                err: Box::new(ignore::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "denied",
+                ))),
+            }),
+        };
+        assert_eq!(walk_error_path(&error), Some(Path::new("a/b")));
+        let error = ignore::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, "oops"));
+        assert_eq!(walk_error_path(&error), None);
+    }
+
+    #[test]
+    fn cancelling_stops_the_walk_with_diagnostics() {
+        let root = Path::new("./tests/data/simple/");
+        let cancellation = CancellationToken::new();
+        let calculator = CancellingTIC {
+            cancellation: cancellation.clone(),
+        };
+        let calculators: &mut Vec<Box<dyn ToxicityIndicatorCalculator>> =
+            &mut vec![Box::new(calculator)];
+
+        let error = walk_directory(
+            root,
+            "test",
+            None,
+            &WalkOptions::default(),
+            calculators,
+            &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &cancellation,
+        )
+        .unwrap_err();
+
+        let cancelled = error
+            .downcast_ref::<crate::cancellation::ScanCancelled>()
+            .expect("not a cancellation error");
+        assert_eq!(cancelled.diagnostics.len(), 1);
+        assert_eq!(cancelled.diagnostics[0].message, "cancelled");
+    }
+
     #[derive(Debug)]
     struct FirstTIC {}
 
@@ -192,9 +971,11 @@ mod test {
             root,
             "test",
             Some("test-id"),
-            false,
+            &WalkOptions::default(),
             calculators,
             &FeatureFlags::default(),
+            &mut Profiler::new(),
+            &CancellationToken::new(),
         )
         .unwrap();
 
@@ -4,40 +4,184 @@
 //!
 //! As of version 1.0.0 (when I started versioning!) of the data format,
 //! the syntax differs from D3 flare files, but I haven't renamed the module (yet)
+//!
+//! Classic flare files can still be written, for tools that only understand those - see `OutputFormat::Flare`
 
+use anyhow::Error;
 use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{OsStr, OsString};
-
+use std::io;
+
+use crate::annotations::Annotations;
+use crate::binary::BinaryData;
+use crate::blame::BlameData;
+use crate::churn_per_contributor::ChurnPerContributorData;
+use crate::commit_sizes::CommitSizeData;
+use crate::contributor_churn::ContributorChurnData;
 use crate::coupling::SerializableCouplingData;
+use crate::decay::DecayData;
+use crate::directory_tree::DirectorySummary;
 use crate::file_stats::FileStats;
+use crate::file_walker::DirSize;
 use crate::git::GitNodeData;
+use crate::inactive::InactiveCodeData;
 use crate::indentation::IndentationData;
+use crate::license::LicenseData;
+use crate::line_age::LineAgeData;
 use crate::loc::LanguageLocData;
+use crate::nesting::NestingData;
+use crate::node_limit::AggregatedData;
+use crate::pairing::PairingData;
+use crate::pull_requests::PullRequestData;
+use crate::quadrant::Quadrant;
+use crate::secrets::SecretsData;
+use crate::syntax::SyntaxData;
+use crate::working_hours::WorkingHours;
 
 pub static ROOT_NAME: &str = "<root>";
 
-#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+/// the structure of the output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// the polyglot data file, with all indicators - see `polyglot_data`
+    #[default]
+    Polyglot,
+    /// a classic D3 flare file - just `name` and `children`, with lines of code as each file's `value`
+    Flare,
+    /// a plain text table of lines of code per language - see `loc_report`
+    LocReport,
+    /// a standalone HTML page with the polyglot data embedded in a minimal viewer - see `html_viewer`
+    Html,
+    /// a row of summary values per file - see `summary_output`
+    Csv,
+    /// totals per language in the Prometheus text format - see `summary_output`
+    Prometheus,
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct IndicatorData {
+    /// only for files in git repositories - stays the same across scans, see `GitFileHistory::stable_id_for`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stable_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub git: Option<GitNodeData>,
+    /// current line ownership, if blame was enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blame: Option<BlameData>,
+    /// how old current lines are, if line ages were requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_age: Option<LineAgeData>,
+    /// how much of the file was last changed by inactive contributors, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inactive_code: Option<InactiveCodeData>,
+    /// merged pull requests changing the file, if they were fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pull_requests: Option<PullRequestData>,
+    /// commits by hour and weekday in authors' timezones, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_hours: Option<WorkingHours>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub indentation: Option<IndentationData>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub loc: Option<LanguageLocData>,
+    /// what binary files are - only for binary files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary: Option<BinaryData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nesting: Option<NestingData>,
+    /// the license declared in the file header, if licenses were requested - only for text files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<LicenseData>,
+    /// possible secrets, if they were searched for and any were found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretsData>,
+    /// e.g. "source", "test" or "ci", if categories were requested - only for files a rule or language matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// component, owner and deprecation status declared in `.polyglot_annotations.toml` files, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+    /// function and class counts from a parse tree, if syntax was requested - only for known languages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syntax: Option<SyntaxData>,
+    /// branching keywords and operators, as a cheap complexity estimate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_count: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub coupling: Option<SerializableCouplingData>,
+    /// coupling for extra named coupling configs
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub named_coupling: BTreeMap<String, SerializableCouplingData>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file_stats: Option<FileStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decay: Option<DecayData>,
+    /// churn vs complexity, relative to the rest of the scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quadrant: Option<Quadrant>,
+    /// new and departed contributors, for directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributor_churn: Option<ContributorChurnData>,
+    /// recent changes per contributor-month, for directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churn_per_contributor: Option<ChurnPerContributorData>,
+    /// the share of commits with co-authors, for files and directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pairing: Option<PairingData>,
+    /// lines changed per commit, for files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_sizes: Option<CommitSizeData>,
+    /// only for directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_size: Option<DirSize>,
+    /// in a vendored dependency directory, if these are being detected
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub vendored: bool,
+    /// only for synthetic `<other>` files - what they replaced, to keep under the node limit - and
+    /// for directories in directory-only trees, the files and directories under them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregated: Option<AggregatedData>,
+    /// only for directories in directory-only trees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory_summary: Option<DirectorySummary>,
 }
 
 impl IndicatorData {
     fn is_empty(&self) -> bool {
-        self.git.is_none()
+        self.stable_id.is_none()
+            && self.git.is_none()
+            && self.blame.is_none()
+            && self.line_age.is_none()
+            && self.inactive_code.is_none()
+            && self.pull_requests.is_none()
+            && self.working_hours.is_none()
             && self.indentation.is_none()
             && self.loc.is_none()
+            && self.binary.is_none()
+            && self.nesting.is_none()
+            && self.license.is_none()
+            && self.secrets.is_none()
+            && self.category.is_none()
+            && self.annotations.is_none()
+            && self.syntax.is_none()
+            && self.branch_count.is_none()
             && self.coupling.is_none()
+            && self.named_coupling.is_empty()
             && self.file_stats.is_none()
+            && self.decay.is_none()
+            && self.quadrant.is_none()
+            && self.contributor_churn.is_none()
+            && self.churn_per_contributor.is_none()
+            && self.pairing.is_none()
+            && self.commit_sizes.is_none()
+            && self.dir_size.is_none()
+            && !self.vendored
+            && self.aggregated.is_none()
+            && self.directory_summary.is_none()
     }
 }
 
@@ -47,6 +191,42 @@ pub struct FlareTreeNode {
     is_file: bool,
     children: Vec<FlareTreeNode>,
     data: IndicatorData,
+    child_index: ChildIndex,
+}
+
+/// positions of children by name, so paths are found without searching each level - children can
+/// be changed or renamed through `get_children_mut`, so a position is checked before it is used,
+/// and children are searched if it's wrong
+#[derive(Debug, Clone, Default)]
+struct ChildIndex(HashMap<OsString, usize>);
+
+impl ChildIndex {
+    fn of(children: &[FlareTreeNode]) -> Self {
+        let mut index = ChildIndex::default();
+        for (ix, child) in children.iter().enumerate() {
+            index.add(&child.name, ix);
+        }
+        index
+    }
+
+    /// the first child with a name is the one found by searching, so later duplicates are ignored
+    fn add(&mut self, name: &OsStr, ix: usize) {
+        self.0.entry(name.to_owned()).or_insert(ix);
+    }
+
+    fn position(&self, children: &[FlareTreeNode], name: &OsStr) -> Option<usize> {
+        match self.0.get(name) {
+            Some(&ix) if children.get(ix).map_or(false, |child| child.name == name) => Some(ix),
+            _ => children.iter().position(|child| child.name == name),
+        }
+    }
+}
+
+/// the index is just a cache, so nodes with the same children are equal however they were built
+impl PartialEq for ChildIndex {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
 impl FlareTreeNode {
@@ -54,7 +234,10 @@ impl FlareTreeNode {
         &self.name
     }
 
-    #[cfg(test)]
+    pub fn is_file(&self) -> bool {
+        self.is_file
+    }
+
     pub fn set_name(&mut self, name: &OsStr) {
         self.name = name.to_owned();
     }
@@ -66,6 +249,7 @@ impl FlareTreeNode {
             children: Vec::new(),
 
             data: IndicatorData::default(),
+            child_index: ChildIndex::default(),
         }
     }
 
@@ -86,20 +270,32 @@ impl FlareTreeNode {
         &self.data
     }
 
-    pub fn append_child(&mut self, child: FlareTreeNode) {
+    /// returns the index of the new child, for `get_by_indices_mut`
+    pub fn append_child(&mut self, child: FlareTreeNode) -> usize {
         assert!(!self.is_file, "appending child to a directory: {:?}", self);
-        self.children.push(child); // TODO - return self?
+        let ix = self.children.len();
+        self.child_index.add(&child.name, ix);
+        self.children.push(child);
+        ix
+    }
+
+    /// gets a mutable tree entry by the index of the child at each level - like `get_in_mut`
+    /// but without hashing names, for callers that already know where each child is
+    pub fn get_by_indices_mut(&mut self, indices: &[usize]) -> Option<&mut FlareTreeNode> {
+        match indices.split_first() {
+            Some((first, rest)) => self.children.get_mut(*first)?.get_by_indices_mut(rest),
+            None => Some(self),
+        }
     }
 
     /// gets a tree entry by path, or None if something along the path doesn't exist
-    #[allow(dead_code)] // used in tests
     pub fn get_in(&self, path: &mut std::path::Components<'_>) -> Option<&FlareTreeNode> {
         match path.next() {
             Some(first_name) => {
                 let dir_name = first_name.as_os_str();
                 if !self.is_file {
-                    let first_match = self.children.iter().find(|c| dir_name == c.name)?;
-                    return first_match.get_in(path);
+                    let ix = self.child_index.position(&self.children, dir_name)?;
+                    return self.children[ix].get_in(path);
                 }
                 None
             }
@@ -116,8 +312,11 @@ impl FlareTreeNode {
             Some(first_name) => {
                 let dir_name = first_name.as_os_str();
                 if !self.is_file {
-                    let first_match = self.children.iter_mut().find(|c| dir_name == c.name)?;
-                    return first_match.get_in_mut(path);
+                    if self.child_index.0.len() != self.children.len() {
+                        self.child_index = ChildIndex::of(&self.children);
+                    }
+                    let ix = self.child_index.position(&self.children, dir_name)?;
+                    return self.children[ix].get_in_mut(path);
                 }
                 None
             }
@@ -131,8 +330,37 @@ impl FlareTreeNode {
 
     // used only for postprocessing - could refactor - move functionality here
     pub fn get_children_mut(&mut self) -> &mut Vec<FlareTreeNode> {
+        // children may be added, removed or moved, so the index is rebuilt when next needed
+        self.child_index = ChildIndex::default();
         &mut self.children
     }
+
+    /// writes a classic D3 flare tree, consuming the tree like `ConsumingNode` - files without
+    /// lines of code have a value of zero
+    pub fn write_classic_json<W: io::Write>(self, out: &mut W) -> Result<(), Error> {
+        let name = self
+            .name
+            .to_str()
+            .ok_or_else(|| anyhow!("name {:?} contains invalid UTF-8 characters", self.name))?;
+        out.write_all(b"{\"name\":")?;
+        serde_json::to_writer(&mut *out, name)?;
+        if self.is_file {
+            let value = self.data.loc.as_ref().map_or(0, |loc| loc.code);
+            out.write_all(b",\"value\":")?;
+            serde_json::to_writer(&mut *out, &value)?;
+        } else {
+            out.write_all(b",\"children\":[")?;
+            for (ix, child) in self.children.into_iter().enumerate() {
+                if ix > 0 {
+                    out.write_all(b",")?;
+                }
+                child.write_classic_json(out)?;
+            }
+            out.write_all(b"]")?;
+        }
+        out.write_all(b"}")?;
+        Ok(())
+    }
 }
 
 fn name_as_str<S: Serializer>(name: &OsStr) -> Result<&str, S::Error> {
@@ -141,22 +369,114 @@ fn name_as_str<S: Serializer>(name: &OsStr) -> Result<&str, S::Error> {
     })
 }
 
+fn serialize_node<S, C>(
+    serializer: S,
+    name: &OsStr,
+    data: &IndicatorData,
+    is_file: bool,
+    children: &C,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    C: Serialize + ?Sized,
+{
+    let mut state = serializer.serialize_struct("FlareTreeNode", 3)?;
+    let name = name_as_str::<S>(name)?;
+    state.serialize_field("name", &name)?;
+    if !data.is_empty() {
+        state.serialize_field("data", data)?;
+    }
+    if !is_file {
+        state.serialize_field("children", children)?;
+    }
+
+    state.end()
+}
+
 impl Serialize for FlareTreeNode {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("FlareTreeNode", 3)?;
-        let name = name_as_str::<S>(&self.name)?;
-        state.serialize_field("name", &name)?;
-        if !self.data.is_empty() {
-            state.serialize_field("data", &self.data)?;
-        }
-        if !self.is_file {
-            state.serialize_field("children", &self.children)?;
-        }
+        serialize_node(
+            serializer,
+            &self.name,
+            &self.data,
+            self.is_file,
+            &self.children,
+        )
+    }
+}
+
+/// Serializes the same as the node it holds, but takes the node out to do it, and each child
+/// the same way - so each subtree is freed as soon as it is written, and the whole tree isn't
+/// held until the end. It can only be serialized once.
+#[derive(Debug)]
+pub struct ConsumingNode(RefCell<Option<FlareTreeNode>>);
+
+impl ConsumingNode {
+    pub fn new(node: FlareTreeNode) -> Self {
+        ConsumingNode(RefCell::new(Some(node)))
+    }
+}
+
+struct ConsumingChildren(RefCell<Vec<FlareTreeNode>>);
+
+impl Serialize for ConsumingNode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let node = self
+            .0
+            .take()
+            .ok_or_else(|| serde::ser::Error::custom("node has already been serialized"))?;
+        serialize_node(
+            serializer,
+            &node.name,
+            &node.data,
+            node.is_file,
+            &ConsumingChildren(RefCell::new(node.children)),
+        )
+    }
+}
 
-        state.end()
+impl Serialize for ConsumingChildren {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.take().into_iter().map(ConsumingNode::new))
+    }
+}
+
+/// the serialized form of a node - files are the nodes with no children
+#[derive(Deserialize)]
+struct SerializedFlareTreeNode {
+    name: String,
+    #[serde(default)]
+    data: IndicatorData,
+    children: Option<Vec<FlareTreeNode>>,
+}
+
+impl<'de> Deserialize<'de> for FlareTreeNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let node = SerializedFlareTreeNode::deserialize(deserializer)?;
+        let child_index = node
+            .children
+            .as_deref()
+            .map(ChildIndex::of)
+            .unwrap_or_default();
+        Ok(FlareTreeNode {
+            name: node.name.into(),
+            is_file: node.children.is_none(),
+            children: node.children.unwrap_or_default(),
+            data: node.data,
+            child_index,
+        })
     }
 }
 
@@ -183,9 +503,11 @@ mod test {
                     is_file: true,
                     data: IndicatorData::default(),
                     children: Vec::new(),
+                    child_index: ChildIndex::default(),
                 }],
 
                 data: IndicatorData::default(),
+                child_index: ChildIndex::default(),
             }
         );
     }
@@ -220,6 +542,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn can_get_elements_by_index() {
+        let mut tree = build_test_tree();
+
+        let grandchild = tree.get_by_indices_mut(&[2, 1, 0]);
+        assert_eq!(
+            grandchild.expect("Grandchild not found!").name(),
+            "grandchild_file.txt"
+        );
+        assert_eq!(tree.get_by_indices_mut(&[]).unwrap().name(), "root");
+        assert!(tree.get_by_indices_mut(&[2, 7]).is_none());
+        assert!(tree.get_by_indices_mut(&[0, 0]).is_none());
+    }
+
     #[test]
     fn can_get_top_level_element_from_tree() {
         let tree = build_test_tree();
@@ -275,6 +611,37 @@ mod test {
         assert_eq!(new_kid.name(), "new_kid_on_the_block.txt");
     }
 
+    #[test]
+    fn can_get_renamed_and_moved_elements() {
+        let mut tree = build_test_tree();
+        tree.get_in_mut(&mut Path::new("child1/grandchild").components())
+            .unwrap()
+            .set_name(OsStr::new("renamed"));
+        assert!(tree
+            .get_in_mut(&mut Path::new("child1/grandchild").components())
+            .is_none());
+        assert_eq!(
+            tree.get_in_mut(&mut Path::new("child1/renamed/grandchild_file.txt").components())
+                .expect("renamed child not found!")
+                .name(),
+            "grandchild_file.txt"
+        );
+
+        tree.get_children_mut().reverse();
+        assert_eq!(
+            tree.get_in(&mut Path::new("root_file_2.txt").components())
+                .expect("moved child not found!")
+                .name(),
+            "root_file_2.txt"
+        );
+        assert_eq!(
+            tree.get_in_mut(&mut Path::new("child2/child2_file.txt").components())
+                .expect("moved child not found!")
+                .name(),
+            "child2_file.txt"
+        );
+    }
+
     #[test]
     fn can_serialize_directory_to_json() {
         let root = FlareTreeNode::dir("root");
@@ -345,4 +712,50 @@ mod test {
             }),
         );
     }
+
+    #[test]
+    fn consuming_nodes_serialize_like_nodes_once() {
+        let mut root = build_test_tree();
+        root.get_children_mut()[0].indicators_mut().vendored = true;
+        let expected = serde_json::to_value(&root).unwrap();
+
+        let consuming = ConsumingNode::new(root);
+        assert_eq!(serde_json::to_value(&consuming).unwrap(), expected);
+        assert!(serde_json::to_value(&consuming).is_err());
+    }
+
+    #[test]
+    fn classic_flare_has_loc_values() {
+        let mut root = FlareTreeNode::dir("root");
+        let mut code = FlareTreeNode::file("main.rs");
+        code.indicators_mut().loc = Some(LanguageLocData {
+            language: "Rust".to_owned(),
+            binary: false,
+            blanks: 1,
+            code: 12,
+            comments: 2,
+            lines: 15,
+            bytes: 300,
+        });
+        code.indicators_mut().vendored = true;
+        root.append_child(code);
+        root.append_child(FlareTreeNode::file("image.png"));
+        root.append_child(FlareTreeNode::dir("empty"));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        root.write_classic_json(&mut buffer).unwrap();
+
+        let written: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(
+            written,
+            json!({
+                "name": "root",
+                "children": [
+                    {"name": "main.rs", "value": 12},
+                    {"name": "image.png", "value": 0},
+                    {"name": "empty", "children": []}
+                ]
+            })
+        );
+    }
 }
@@ -0,0 +1,318 @@
+#![warn(clippy::all)]
+//! Older data file formats, for explorers that don't understand the current one.
+//!
+//! The current format is serialized as usual, then anything the older format didn't have is
+//! dropped. The 1.0 format is a list of the fields it had, rather than of what was added since,
+//! so new fields are dropped without needing changes here. The 1.1 format had far too many fields
+//! to list, so it is the other way around - new fields must be added to the 1.1 lists of fields
+//! added since.
+//!
+//! Downgrading works on the whole file as JSON, so downgraded files don't get the memory savings
+//! of writing the tree a subtree at a time.
+
+use serde_json::{Map, Value};
+
+/// the data file formats the scanner can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormatVersion {
+    /// the current `DATA_FILE_VERSION`
+    #[default]
+    Latest,
+    /// the format before git days since creation and last change were measured to the scan time
+    V1_1,
+    /// the format before metadata, diagnostics and most of the newer indicators
+    V1_0,
+}
+
+/// the version recorded in downgraded 1.1 files
+const V1_1_VERSION: &str = "1.1.0";
+
+/// git file fields added after 1.1
+const AFTER_V1_1_GIT_FILE: &[&str] = &["days_since_creation", "days_since_last_change"];
+/// scanner config metadata added after 1.1
+const AFTER_V1_1_CONFIG: &[&str] = &["scan_time", "secrets", "categories", "report"];
+
+/// the version recorded in downgraded 1.0 files - the last 1.0 format
+const V1_0_VERSION: &str = "1.0.4";
+
+const V1_0_TOP_LEVEL: &[&str] = &["version", "name", "id", "tree", "metadata", "features"];
+const V1_0_METADATA: &[&str] = &["git", "coupling"];
+const V1_0_GIT_METADATA: &[&str] = &["users"];
+const V1_0_FEATURES: &[&str] = &["git", "coupling", "git_details", "file_stats"];
+const V1_0_NODE: &[&str] = &["name", "data", "children"];
+const V1_0_INDICATORS: &[&str] = &["git", "indentation", "loc", "coupling", "file_stats"];
+const V1_0_GIT_FILE: &[&str] = &[
+    "last_update",
+    "age_in_days",
+    "creation_date",
+    "user_count",
+    "users",
+    "details",
+    "activity",
+];
+const V1_0_GIT_DIR: &[&str] = &["remote_url", "head"];
+const V1_0_GIT_DETAILS: &[&str] = &[
+    "commit_day",
+    "users",
+    "commits",
+    "lines_added",
+    "lines_deleted",
+];
+const V1_0_GIT_ACTIVITY: &[&str] = &[
+    "author_time",
+    "commit_time",
+    "users",
+    "change",
+    "lines_added",
+    "lines_deleted",
+];
+const V1_0_INDENTATION: &[&str] = &["lines", "minimum", "maximum", "median", "stddev", "sum"];
+
+fn retain(value: &mut Value, keys: &[&str]) -> Option<&mut Map<String, Value>> {
+    let object = value.as_object_mut()?;
+    object.retain(|key, _| keys.contains(&key.as_str()));
+    Some(object)
+}
+
+fn retain_field(object: &mut Map<String, Value>, field: &str, keys: &[&str]) {
+    if let Some(value) = object.get_mut(field) {
+        retain(value, keys);
+    }
+}
+
+fn retain_each(object: &mut Map<String, Value>, field: &str, keys: &[&str]) {
+    if let Some(Value::Array(values)) = object.get_mut(field) {
+        for value in values {
+            retain(value, keys);
+        }
+    }
+}
+
+fn remove(value: &mut Value, keys: &[&str]) {
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| !keys.contains(&key.as_str()));
+    }
+}
+
+fn downgrade_node_1_1(node: &mut Value) {
+    if let Some(git) = node.pointer_mut("/data/git") {
+        remove(git, AFTER_V1_1_GIT_FILE);
+    }
+    if let Some(Value::Array(children)) = node.get_mut("children") {
+        for child in children {
+            downgrade_node_1_1(child);
+        }
+    }
+}
+
+fn downgrade_1_1(data: &mut Value) {
+    if let Some(data) = data.as_object_mut() {
+        data.insert("version".to_owned(), Value::from(V1_1_VERSION));
+    }
+    if let Some(config) = data.pointer_mut("/metadata/scanner/config") {
+        remove(config, AFTER_V1_1_CONFIG);
+    }
+    if let Some(tree) = data.get_mut("tree") {
+        downgrade_node_1_1(tree);
+    }
+}
+
+fn downgrade_git_data(git: &mut Value) {
+    // files and directories share the `git` field - only files have a last update
+    let is_file = git.get("last_update").is_some();
+    if !is_file {
+        retain(git, V1_0_GIT_DIR);
+        return;
+    }
+    if let Some(git) = retain(git, V1_0_GIT_FILE) {
+        retain_each(git, "details", V1_0_GIT_DETAILS);
+        retain_each(git, "activity", V1_0_GIT_ACTIVITY);
+    }
+}
+
+fn downgrade_node_1_0(node: &mut Value) {
+    let node = match retain(node, V1_0_NODE) {
+        Some(node) => node,
+        None => return,
+    };
+    let mut empty = false;
+    if let Some(data) = node.get_mut("data") {
+        if let Some(indicators) = retain(data, V1_0_INDICATORS) {
+            if let Some(git) = indicators.get_mut("git") {
+                downgrade_git_data(git);
+            }
+            retain_field(indicators, "indentation", V1_0_INDENTATION);
+            empty = indicators.is_empty();
+        }
+    }
+    if empty {
+        // nodes had no data rather than empty data
+        node.remove("data");
+    }
+    if let Some(Value::Array(children)) = node.get_mut("children") {
+        for child in children {
+            downgrade_node_1_0(child);
+        }
+    }
+}
+
+fn downgrade_1_0(data: &mut Value) {
+    let data = match retain(data, V1_0_TOP_LEVEL) {
+        Some(data) => data,
+        None => return,
+    };
+    data.insert("version".to_owned(), Value::from(V1_0_VERSION));
+    if let Some(metadata) = data
+        .get_mut("metadata")
+        .and_then(|metadata| retain(metadata, V1_0_METADATA))
+    {
+        retain_field(metadata, "git", V1_0_GIT_METADATA);
+    }
+    retain_field(data, "features", V1_0_FEATURES);
+    if let Some(tree) = data.get_mut("tree") {
+        downgrade_node_1_0(tree);
+    }
+}
+
+/// converts a serialized current data file to an older format
+pub fn downgrade(data: &mut Value, version: FormatVersion) {
+    match version {
+        FormatVersion::Latest => {}
+        FormatVersion::V1_1 => downgrade_1_1(data),
+        FormatVersion::V1_0 => downgrade_1_0(data),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn newer_fields_are_dropped_for_1_0() {
+        let mut data = json!({
+            "version": "1.2.0",
+            "name": "test",
+            "id": "test-id",
+            "tree": {
+                "name": "<root>",
+                "data": {
+                    "git": {"remote_url": null, "head": "abc", "remotes": []},
+                    "dir_size": {"bytes": 10, "files": 1}
+                },
+                "children": [{
+                    "name": "a.rs",
+                    "data": {
+                        "stable_id": "x",
+                        "indentation": {
+                            "lines": 1, "minimum": 0, "maximum": 0, "median": 0,
+                            "stddev": 0.0, "sum": 0, "truncated": true
+                        },
+                        "git": {
+                            "last_update": 1, "age_in_days": 2, "creation_date": null,
+                            "days_since_last_change": 3,
+                            "user_count": 1, "users": [0], "last_author": 0,
+                            "details": [{
+                                "commit_day": 0, "users": [0], "commits": 1,
+                                "lines_added": 1, "lines_deleted": 0, "bulk_commits": 1
+                            }],
+                            "activity": []
+                        }
+                    }
+                }, {
+                    "name": "b.rs",
+                    "data": {"vendored": true}
+                }]
+            },
+            "metadata": {"git": {"users": [], "timeline": []}, "scanner": {}},
+            "features": {"git": true, "coupling": false, "git_details": true, "file_stats": false},
+            "diagnostics": []
+        });
+        downgrade(&mut data, FormatVersion::V1_0);
+        assert_eq!(
+            data,
+            json!({
+                "version": "1.0.4",
+                "name": "test",
+                "id": "test-id",
+                "tree": {
+                    "name": "<root>",
+                    "data": {"git": {"remote_url": null, "head": "abc"}},
+                    "children": [{
+                        "name": "a.rs",
+                        "data": {
+                            "indentation": {
+                                "lines": 1, "minimum": 0, "maximum": 0, "median": 0,
+                                "stddev": 0.0, "sum": 0
+                            },
+                            "git": {
+                                "last_update": 1, "age_in_days": 2, "creation_date": null,
+                                "user_count": 1, "users": [0],
+                                "details": [{
+                                    "commit_day": 0, "users": [0], "commits": 1,
+                                    "lines_added": 1, "lines_deleted": 0
+                                }],
+                                "activity": []
+                            }
+                        }
+                    }, {
+                        "name": "b.rs"
+                    }]
+                },
+                "metadata": {"git": {"users": []}},
+                "features": {"git": true, "coupling": false, "git_details": true, "file_stats": false}
+            })
+        );
+    }
+
+    #[test]
+    fn scan_time_ages_are_dropped_for_1_1() {
+        let mut data = json!({
+            "version": "1.2.0",
+            "tree": {
+                "name": "<root>",
+                "data": {"git": {"remote_url": null, "head": "abc"}},
+                "children": [{
+                    "name": "a.rs",
+                    "data": {
+                        "git": {
+                            "last_update": 1, "age_in_days": 2, "creation_date": 0,
+                            "days_since_creation": 4, "days_since_last_change": 3
+                        },
+                        "commit_sizes": {"commits": 1}
+                    }
+                }]
+            },
+            "metadata": {"scanner": {"config": {"scan_time": 300000, "git_subtree_only": false}}},
+            "diagnostics": []
+        });
+        downgrade(&mut data, FormatVersion::V1_1);
+        assert_eq!(
+            data,
+            json!({
+                "version": "1.1.0",
+                "tree": {
+                    "name": "<root>",
+                    "data": {"git": {"remote_url": null, "head": "abc"}},
+                    "children": [{
+                        "name": "a.rs",
+                        "data": {
+                            "git": {"last_update": 1, "age_in_days": 2, "creation_date": 0},
+                            "commit_sizes": {"commits": 1}
+                        }
+                    }]
+                },
+                "metadata": {"scanner": {"config": {"git_subtree_only": false}}},
+                "diagnostics": []
+            })
+        );
+    }
+
+    #[test]
+    fn latest_is_unchanged() {
+        let mut data = json!({"version": "1.2.0", "diagnostics": []});
+        downgrade(&mut data, FormatVersion::Latest);
+        assert_eq!(data, json!({"version": "1.2.0", "diagnostics": []}));
+    }
+}
@@ -1,9 +1,20 @@
+use crate::blame::{BlameConfig, Blamer};
+use crate::diagnostics::{Diagnostic, DiagnosticCategory};
 use crate::flare::FlareTreeNode;
-use crate::git_file_history::{FileHistoryEntry, GitFileHistory};
-use crate::git_logger::{CommitChange, GitLog, GitLogConfig, User};
+use crate::git_file_history::{activity_timeline, FileHistoryEntry, GitFileHistory};
+use crate::git_logger::{is_sparse_checkout, CommitChange, GitLog, GitLogConfig, User};
+use crate::git_remotes::{
+    browse_url_template, preferred_canonical_url, repository_remotes, GitRemote,
+};
 use crate::git_user_dictionary::GitUserDictionary;
+use crate::inactive::{self, InactiveConfig};
+use crate::line_age;
+use crate::memory::MemoryGuard;
 use crate::polyglot_data::GitMetadata;
+use crate::pull_requests::{PullRequestConfig, PullRequestIndex};
+use crate::teams::Teams;
 use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use crate::working_hours::{self, ContributorHours, WorkingHoursConfig};
 use anyhow::{Context, Error};
 use chrono::{NaiveDateTime, NaiveTime};
 
@@ -11,10 +22,11 @@ use serde::{Deserialize, Serialize};
 
 use std::cmp::Ordering;
 use std::collections::HashSet;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::once;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use git2::Repository;
 
@@ -22,13 +34,45 @@ use git2::Repository;
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct GitData {
     pub last_update: u64,
+    /// days from the last update to the latest commit in the repository, not to the scan - kept
+    /// for older consumers, use `days_since_last_change` instead
     pub age_in_days: u64,
     // we only have a creation date if there was an Add change in the dates scanned
     pub creation_date: Option<u64>,
+    /// whole days from the creation date to the scan time - None if there is no creation date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_since_creation: Option<u64>,
+    /// whole days from the last update to the scan time - None only in data files before 1.2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_since_last_change: Option<u64>,
     pub user_count: usize,
     pub users: Vec<usize>, // dictionary IDs
+    /// dictionary ID of the author of the most recent change
+    pub last_author: Option<usize>,
+    /// the users involved in the most commits, most active first
+    #[serde(default)]
+    pub top_contributors: Vec<Contributor>,
     pub details: Vec<GitDetails>,
     pub activity: Vec<GitActivity>,
+    /// commits by category, e.g. how many were bug fixes - only if commits are being classified
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub commit_categories: BTreeMap<String, u64>,
+    /// commits by team - only if teams are configured, and only for teams that changed the file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub teams: BTreeMap<String, u64>,
+    /// index of the file's repository in the git metadata `repositories` list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<usize>,
+}
+
+/// how many times will we show in `top_contributors`
+const TOP_CONTRIBUTOR_COUNT: usize = 3;
+
+/// A user and how many commits they were involved in, as author, committer or co-author
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct Contributor {
+    pub user: usize,
+    pub commits: u64,
 }
 
 /// Git information for a given day _and_ unique set of users, summarized
@@ -45,6 +89,17 @@ pub struct GitDetails {
     pub commits: u64,
     pub lines_added: u64,
     pub lines_deleted: u64,
+    /// how many of the commits were bulk commits - only if bulk commits are flagged
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub bulk_commits: u64,
+    /// how many of the commits were merges - only if merges are included
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub merge_commits: u64,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // serde needs a reference
+fn is_zero(value: &u64) -> bool {
+    *value == 0
 }
 
 impl Ord for GitDetails {
@@ -80,7 +135,27 @@ pub struct GitActivity {
     pub change: CommitChange,
     pub lines_added: u64,
     pub lines_deleted: u64,
+    /// part of a bulk commit - these are ignored for coupling
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bulk: bool,
+    /// parents of the commit - zero for the first commit, more than one for merges, which are
+    /// only included if configured
+    #[serde(default = "single_parent", skip_serializing_if = "is_single_parent")]
+    pub parent_count: usize,
+    /// ticket ids from the commit message, if tickets are being extracted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tickets: Vec<String>,
 }
+
+fn single_parent() -> usize {
+    1
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // serde needs a reference
+fn is_single_parent(parent_count: &usize) -> bool {
+    *parent_count == 1
+}
+
 impl Ord for GitActivity {
     fn cmp(&self, other: &Self) -> Ordering {
         self.commit_time.cmp(&other.commit_time)
@@ -100,23 +175,135 @@ pub struct GitHistories {
     git_file_histories: Vec<GitFileHistory>,
     /// config used to initialize any git histories
     git_log_config: GitLogConfig,
+    /// total time spent loading git histories
+    load_time: Duration,
+    /// the root of the scan - the first node visited
+    scan_root: Option<PathBuf>,
+    /// directories with files outside any git repository - so we don't search again for each file
+    no_repository_dirs: HashSet<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+    /// told the size of each history as it is loaded, if there is a memory limit
+    memory: Option<MemoryGuard>,
+}
+
+/// which timezone's midnight starts each day, when grouping commits into `GitDetails` days -
+/// days are still stored as midnight UTC of the local date, so they are whole days since the epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitDayTimezone {
+    #[default]
+    Utc,
+    /// each commit's author timezone - a late-night commit counts on the day the author made it
+    Author,
+    /// a fixed timezone, in minutes east of UTC
+    Offset(i32),
+}
+
+impl CommitDayTimezone {
+    pub fn is_utc(&self) -> bool {
+        *self == CommitDayTimezone::Utc
+    }
+
+    fn offset_minutes(self, entry: &FileHistoryEntry) -> i32 {
+        match self {
+            CommitDayTimezone::Utc => 0,
+            CommitDayTimezone::Author => entry.author_offset_minutes,
+            CommitDayTimezone::Offset(minutes) => minutes,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct GitCalculator {
     histories: GitHistories,
     dictionary: GitUserDictionary,
+    timezone: CommitDayTimezone,
+    /// days since creation and last change are measured to this, in seconds since the epoch
+    scan_time: u64,
+    blamer: Option<Blamer>,
+    line_age: bool,
+    teams: Option<Teams>,
+    inactive: Option<InactiveConfig>,
+    pull_requests: Option<PullRequestConfig>,
+    working_hours: Option<WorkingHoursConfig>,
+    contributor_hours: ContributorHours,
+    /// by repository working directory - None if the repository isn't on a known host, or fetching failed
+    pull_request_indexes: HashMap<PathBuf, Option<PullRequestIndex>>,
 }
 
 // Git data for a directory - just remote git info
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GitInfo {
+    /// the url of the `origin` remote
+    pub remote_url: Option<String>,
+    pub head: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remotes: Vec<GitRemote>,
+    /// canonical form of `origin`, or of the first remote if there is no origin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_remote_url: Option<String>,
+    /// for known hosts, a url for browsing files at `head` - replace `{path}` with the path relative to this directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browse_url_template: Option<String>,
+}
+
+/// A scanned repository, for grouping files by repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryInfo {
+    /// the working directory relative to the scan root - empty if the scan root is inside the repository
+    pub path: PathBuf,
+    /// the url of the `origin` remote
     pub remote_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_remote_url: Option<String>,
     pub head: Option<String>,
+    /// commits in the scanned history that changed files
+    pub commits: u64,
+    /// a sparse checkout - only history for checked out files was scanned
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse: bool,
+    /// a linked worktree - other worktrees of the same repository are listed separately
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub worktree: bool,
+}
+
+impl RepositoryInfo {
+    fn new(history: &GitFileHistory, scan_root: Option<&Path>) -> Self {
+        let workdir = history.workdir();
+        let path = scan_root
+            .and_then(|root| root.canonicalize().ok())
+            .and_then(|root| workdir.strip_prefix(root).ok().map(Path::to_path_buf))
+            .unwrap_or_default();
+        let (remote_url, head, sparse, worktree) = match Repository::open(workdir) {
+            Ok(repository) => {
+                let info = GitInfo::new(workdir, &repository);
+                (
+                    info.remote_url,
+                    info.head,
+                    is_sparse_checkout(&repository),
+                    repository.is_worktree(),
+                )
+            }
+            Err(e) => {
+                warn!("Can't reopen git repository at {:?}, {}", workdir, e);
+                (None, None, false, false)
+            }
+        };
+        RepositoryInfo {
+            path,
+            remote_url,
+            canonical_remote_url: history.canonical_remote_url().map(str::to_owned),
+            head,
+            commits: history.commit_count(),
+            sparse,
+            worktree,
+        }
+    }
 }
 
 // Git data for a file _or_ a directory
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+// deserializing relies on order - anything without file fields like `last_update` is a Dir
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum GitNodeData {
     File {
@@ -152,33 +339,113 @@ impl GitInfo {
             }
             Ok(head) => Some(head),
         };
-        GitInfo { remote_url, head }
+        let remotes = repository_remotes(repository);
+        let canonical_remote_url = preferred_canonical_url(&remotes);
+        let browse_url_template = match (&canonical_remote_url, &head) {
+            (Some(canonical_url), Some(head)) => browse_url_template(canonical_url, head),
+            _ => None,
+        };
+        GitInfo {
+            remote_url,
+            head,
+            remotes,
+            canonical_remote_url,
+            browse_url_template,
+        }
     }
 }
 
-fn start_of_day(secs_since_epoch: u64) -> u64 {
-    let date_time = NaiveDateTime::from_timestamp(secs_since_epoch as i64, 0);
+/// the most active users, most active first, with ties in id order
+fn top_contributors(commits_by_user: impl IntoIterator<Item = (usize, u64)>) -> Vec<Contributor> {
+    let mut top_contributors: Vec<Contributor> = commits_by_user
+        .into_iter()
+        .map(|(user, commits)| Contributor { user, commits })
+        .collect();
+    top_contributors.sort_by(|a, b| b.commits.cmp(&a.commits).then(a.user.cmp(&b.user)));
+    top_contributors.truncate(TOP_CONTRIBUTOR_COUNT);
+    top_contributors
+}
+
+fn is_not_found(error: &Error) -> bool {
+    matches!(error.downcast_ref::<git2::Error>(), Some(e) if e.code() == git2::ErrorCode::NotFound)
+}
+
+/// the local date of a time, `offset_minutes` east of UTC, as seconds since the epoch at midnight UTC
+fn start_of_day(secs_since_epoch: u64, offset_minutes: i32) -> u64 {
+    let local_secs = secs_since_epoch as i64 + i64::from(offset_minutes) * 60;
+    let date_time = NaiveDateTime::from_timestamp(local_secs, 0);
     date_time
         .date()
         .and_time(NaiveTime::from_num_seconds_from_midnight(0, 0))
         .timestamp() as u64
 }
 impl GitHistories {
-    fn git_history(&self, filename: &Path) -> Option<&GitFileHistory> {
+    /// the history for a file, and its index in the list of histories
+    fn git_history(&self, filename: &Path) -> Option<(usize, &GitFileHistory)> {
         self.git_file_histories
             .iter()
-            .find(|h| h.is_repo_for(filename).unwrap())
+            .enumerate()
+            .find(|(_, h)| h.is_repo_for(filename).unwrap())
         // TODO can we get rid of unwrap here?
         // it's tricky as we can't return a Result.
     }
 
-    fn add_history_for(&mut self, filename: &Path) -> Result<(), Error> {
+    /// load history for the repository containing the file - returns false if there is no repository
+    fn add_history_for(&mut self, filename: &Path) -> Result<bool, Error> {
+        let parent = filename.parent().unwrap_or(filename).to_path_buf();
+        if self.no_repository_dirs.contains(&parent) {
+            return Ok(false);
+        }
         info!("Adding new git log for {:?}", &filename);
-        let mut git_log = GitLog::new(filename, self.git_log_config)?;
+        let load_start = Instant::now();
+        let mut git_log = match GitLog::new(filename, self.git_log_config.clone()) {
+            Ok(git_log) => git_log,
+            Err(e) if is_not_found(&e) => {
+                // only report the first, otherwise non-git scans will be full of diagnostics
+                if self.no_repository_dirs.is_empty() {
+                    self.diagnostics.push(Diagnostic::new(
+                        DiagnosticCategory::MissingGit,
+                        Some(&parent),
+                        "No git repository found - files outside git repositories have no git data",
+                    ));
+                }
+                self.no_repository_dirs.insert(parent);
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
         info!("Found working dir: {:?}", git_log.workdir());
+        if git_log.is_shallow() {
+            self.diagnostics.push(Diagnostic::new(
+                DiagnosticCategory::ShallowRepository,
+                Some(git_log.workdir()),
+                "Repository is a shallow clone - git history is incomplete",
+            ));
+        }
+        if self.git_log_config.is_subtree_only() {
+            if let Some(scan_root) = &self.scan_root {
+                git_log.limit_to(scan_root)?;
+            }
+        }
         let history = GitFileHistory::new(&mut git_log)?;
+        for file in history.unmatched_files() {
+            self.diagnostics.push(Diagnostic::new(
+                DiagnosticCategory::UnmatchedHistory,
+                Some(&history.workdir().join(file)),
+                "File has git history but is not in the working directory - it may have been renamed or deleted without committing",
+            ));
+        }
+        if let Some(memory) = &self.memory {
+            memory.add_history(
+                history
+                    .files()
+                    .map(|(_, entries)| entries.len() as u64)
+                    .sum(),
+            );
+        }
         self.git_file_histories.push(history);
-        Ok(())
+        self.load_time += load_start.elapsed();
+        Ok(true)
     }
     fn unique_changers(
         history: &FileHistoryEntry,
@@ -199,7 +466,9 @@ impl GitHistories {
     fn stats_from_history(
         dictionary: &mut GitUserDictionary,
         last_commit: u64,
+        scan_time: u64,
         history: &[FileHistoryEntry],
+        timezone: CommitDayTimezone,
     ) -> Option<GitData> {
         // for now, just get latest change - maybe non-trivial change? (i.e. ignore rename/copy) - or this could be configurable
         // and get set of all authors - maybe deduplicate by email.
@@ -208,11 +477,13 @@ impl GitHistories {
         }
         let mut details: HashMap<GitDetailsKey, GitDetails> = HashMap::new();
 
-        let first_date = history.iter().map(|h| h.author_time).min();
+        // history inherited from the file a copy was made from is older than the copy itself
+        let own_history = || history.iter().filter(|h| h.copied_from.is_none());
 
-        let mut creation_date = history
-            .iter()
-            .filter(|h| h.change == CommitChange::Add)
+        let first_date = own_history().map(|h| h.author_time).min();
+
+        let mut creation_date = own_history()
+            .filter(|h| h.change == CommitChange::Add || h.change == CommitChange::Copied)
             .map(|h| h.author_time)
             .min();
 
@@ -232,16 +503,35 @@ impl GitHistories {
 
         let age_in_days = (last_commit - last_update) / (60 * 60 * 24);
 
+        // commit times can be after the scan time if clocks are wrong
+        let days_before_scan = |time: u64| scan_time.saturating_sub(time) / (60 * 60 * 24);
+
         let changers: HashSet<usize> = history
             .iter()
             .flat_map(|h| GitHistories::unique_changers(h, dictionary))
             .collect();
 
+        // registering after all changers, so dictionary IDs are in the same order as before
+        let last_author = history
+            .iter()
+            .max_by_key(|h| h.commit_time)
+            .map(|h| dictionary.register(&h.author));
+
+        let mut commits_by_user: HashMap<usize, u64> = HashMap::new();
+
+        let mut commit_categories: BTreeMap<String, u64> = BTreeMap::new();
+
         let mut activity_vec: Vec<GitActivity> = Vec::new();
 
         for entry in history {
-            let author_day = start_of_day(entry.author_time);
+            let author_day = start_of_day(entry.author_time, timezone.offset_minutes(entry));
+            if let Some(category) = &entry.category {
+                *commit_categories.entry(category.clone()).or_insert(0) += 1;
+            }
             let unique_changers = GitHistories::unique_changers(entry, dictionary);
+            for user in &unique_changers {
+                *commits_by_user.entry(*user).or_insert(0) += 1;
+            }
             let key = GitDetailsKey {
                 commit_day: author_day,
                 users: unique_changers.clone(),
@@ -252,8 +542,16 @@ impl GitHistories {
                 commits: 0,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
             });
             daily_details.commits += 1;
+            if entry.bulk {
+                daily_details.bulk_commits += 1;
+            }
+            if entry.parent_count > 1 {
+                daily_details.merge_commits += 1;
+            }
             daily_details
                 .users
                 .extend(unique_changers.clone().into_iter());
@@ -267,13 +565,21 @@ impl GitHistories {
                 change: entry.change,
                 lines_added: entry.lines_added,
                 lines_deleted: entry.lines_deleted,
+                bulk: entry.bulk,
+                parent_count: entry.parent_count,
+                tickets: entry.tickets.clone(),
             };
-            activity_vec.push(activity);
+            // inherited history would couple copies to their sources' old changes
+            if entry.copied_from.is_none() {
+                activity_vec.push(activity);
+            }
         }
 
         let mut changer_list: Vec<usize> = changers.into_iter().collect();
         changer_list.sort_unstable();
 
+        let top_contributors = top_contributors(commits_by_user);
+
         let mut details_vec: Vec<GitDetails> = details
             .into_iter()
             .map(|(_k, v)| v)
@@ -284,10 +590,17 @@ impl GitHistories {
             last_update,
             age_in_days,
             creation_date,
+            days_since_creation: creation_date.map(days_before_scan),
+            days_since_last_change: Some(days_before_scan(last_update)),
             user_count: changer_list.len(),
             users: changer_list,
+            last_author,
+            top_contributors,
             details: details_vec,
             activity: activity_vec,
+            commit_categories,
+            teams: BTreeMap::new(),
+            repository: None,
         })
     }
 }
@@ -298,8 +611,85 @@ impl GitCalculator {
             histories: GitHistories {
                 git_file_histories: Vec::new(),
                 git_log_config: config,
+                load_time: Duration::ZERO,
+                scan_root: None,
+                no_repository_dirs: HashSet::new(),
+                diagnostics: Vec::new(),
+                memory: None,
             },
             dictionary: GitUserDictionary::default(),
+            timezone: CommitDayTimezone::Utc,
+            scan_time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            blamer: None,
+            line_age: false,
+            teams: None,
+            inactive: None,
+            pull_requests: None,
+            working_hours: None,
+            contributor_hours: ContributorHours::default(),
+            pull_request_indexes: HashMap::new(),
+        }
+    }
+
+    /// group commits into days starting at midnight in this timezone
+    pub fn timezone(self, timezone: CommitDayTimezone) -> Self {
+        GitCalculator { timezone, ..self }
+    }
+
+    /// measure days since creation and last change to this time, rather than now
+    pub fn scan_time(self, scan_time: u64) -> Self {
+        GitCalculator { scan_time, ..self }
+    }
+
+    /// also record line ownership from `git blame`
+    pub fn blame(self, blame: Option<BlameConfig>) -> Self {
+        GitCalculator {
+            blamer: blame.map(Blamer::new),
+            ..self
+        }
+    }
+
+    /// also estimate the age of current lines - exact for blamed files
+    pub fn line_age(self, line_age: bool) -> Self {
+        GitCalculator { line_age, ..self }
+    }
+
+    /// also count commits by team
+    pub fn teams(self, teams: Option<Teams>) -> Self {
+        GitCalculator { teams, ..self }
+    }
+
+    /// also measure how much of each file was last changed by inactive contributors
+    pub fn inactive(self, inactive: Option<InactiveConfig>) -> Self {
+        GitCalculator { inactive, ..self }
+    }
+
+    /// also count commits by hour and weekday, per file and per contributor
+    pub fn working_hours(self, working_hours: Option<WorkingHoursConfig>) -> Self {
+        GitCalculator {
+            working_hours,
+            ..self
+        }
+    }
+
+    /// also count pull requests per file, from the GitHub or GitLab API
+    pub fn pull_requests(self, pull_requests: Option<PullRequestConfig>) -> Self {
+        GitCalculator {
+            pull_requests,
+            ..self
+        }
+    }
+
+    /// add each loaded history to the memory estimate, so git data is dropped before the walk ends
+    pub fn memory(self, memory: Option<MemoryGuard>) -> Self {
+        GitCalculator {
+            histories: GitHistories {
+                memory,
+                ..self.histories
+            },
+            ..self
         }
     }
 }
@@ -309,35 +699,132 @@ impl ToxicityIndicatorCalculator for GitCalculator {
         "git".to_string()
     }
     fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        if self.histories.scan_root.is_none() {
+            // the walker always visits the root first
+            self.histories.scan_root = Some(path.to_owned());
+        }
         if path.is_file() {
             // TODO: refactor this into a method on histories (I tried this but got into a mess with mutable and immutable refs to self!)
-            let history = match self.histories.git_history(path) {
-                Some(history) => history,
+            let (repository_index, history) = match self.histories.git_history(path) {
+                Some(found) => found,
                 None => {
                     info!("Loading git history for {}", path.display());
-                    self.histories
+                    let found = self
+                        .histories
                         .add_history_for(path)
                         .with_context(|| format!("Loading git history based on {:?}", path))?;
+                    if !found {
+                        return Ok(());
+                    }
                     info!("history loaded.");
                     self.histories.git_history(path).unwrap()
                 }
             };
+            node.indicators_mut().stable_id = Some(
+                history
+                    .stable_id_for(path)
+                    .with_context(|| format!("getting stable id for {:?}", path))?,
+            );
             let last_commit = history.last_commit();
             let file_history = history
                 .history_for(path)
                 .with_context(|| format!("getting git file history for {:?}", path))?;
 
+            let mut blame_error = None;
+            let mut pull_request_error = None;
             if let Some(file_history) = file_history {
-                let stats = GitHistories::stats_from_history(
+                let mut stats = GitHistories::stats_from_history(
                     &mut self.dictionary,
                     last_commit,
+                    self.scan_time,
                     file_history,
+                    self.timezone,
                 );
+                if let Some(stats) = &mut stats {
+                    stats.repository = Some(repository_index);
+                }
+                if let (Some(teams), Some(stats)) = (&mut self.teams, &mut stats) {
+                    stats.teams = teams.commits_by_team(
+                        stats.activity.iter().map(|activity| &activity.users),
+                        &self.dictionary,
+                    );
+                }
                 node.indicators_mut().git = stats.map(|stats| GitNodeData::File { data: stats });
+                let mut blamed_line_times = None;
+                if let Some(blamer) = &mut self.blamer {
+                    match blamer
+                        .blame(path, history.workdir(), &mut self.dictionary)
+                        .with_context(|| format!("blaming {:?}", path))
+                    {
+                        Ok(Some((blame, line_times))) => {
+                            node.indicators_mut().blame = Some(blame);
+                            blamed_line_times = Some(line_times);
+                        }
+                        Ok(None) => {}
+                        Err(e) => blame_error = Some(e),
+                    }
+                }
+                if self.line_age {
+                    node.indicators_mut().line_age = match &blamed_line_times {
+                        Some(line_times) => line_age::from_blame(line_times, last_commit),
+                        None => line_age::from_history(file_history, last_commit),
+                    };
+                }
+                if let Some(inactive) = &self.inactive {
+                    let is_inactive = |user: &User| {
+                        inactive.is_inactive(user, history.last_active(user), last_commit)
+                    };
+                    let inactive_code = match &node.indicators().blame {
+                        Some(blame) if blamed_line_times.is_some() => {
+                            inactive::from_blame(blame, &self.dictionary, is_inactive)
+                        }
+                        _ => inactive::from_history(file_history, is_inactive),
+                    };
+                    node.indicators_mut().inactive_code = inactive_code;
+                }
+                if let Some(config) = &self.working_hours {
+                    node.indicators_mut().working_hours =
+                        working_hours::from_history(file_history, config);
+                    self.contributor_hours
+                        .add_history(file_history, &mut self.dictionary, config);
+                }
+                if let Some(config) = &self.pull_requests {
+                    let workdir = history.workdir();
+                    if !self.pull_request_indexes.contains_key(workdir) {
+                        // fetched once per repository, when its first file is visited
+                        let index = match history.canonical_remote_url() {
+                            Some(url) => PullRequestIndex::fetch(url, config)
+                                .with_context(|| format!("fetching pull requests for {}", url))
+                                .unwrap_or_else(|e| {
+                                    pull_request_error = Some((workdir.to_path_buf(), e));
+                                    None
+                                }),
+                            None => None,
+                        };
+                        self.pull_request_indexes
+                            .insert(workdir.to_path_buf(), index);
+                    }
+                    if let Some(Some(index)) = self.pull_request_indexes.get(workdir) {
+                        node.indicators_mut().pull_requests =
+                            index.stats_for(file_history.iter().map(|entry| entry.id.as_str()));
+                    }
+                }
             } else {
                 // probably outside date range
                 debug!("No git history found for file: {:?}", path);
             }
+            if let Some(e) = blame_error {
+                self.histories
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, "blame", &e));
+            }
+            if let Some((workdir, e)) = pull_request_error {
+                self.histories.diagnostics.push(Diagnostic::new(
+                    DiagnosticCategory::RemoteApi,
+                    Some(&workdir),
+                    format!("{:#}", e),
+                ));
+            }
         } else {
             let git_path = path.join(".git");
             if git_path.is_dir() {
@@ -362,25 +849,125 @@ impl ToxicityIndicatorCalculator for GitCalculator {
         &self,
         metadata: &mut crate::polyglot_data::IndicatorMetadata,
     ) -> Result<(), Error> {
+        let parser = self.histories.git_log_config.co_author_parser();
+        let renames = self.histories.git_log_config.rename_config();
         metadata.git = Some(GitMetadata {
             users: self.dictionary.clone(),
+            bulk_commits: self.histories.git_log_config.bulk_commit_config(),
+            commit_categories: self
+                .histories
+                .git_log_config
+                .classifier()
+                .map(|classifier| classifier.rules().to_vec()),
+            co_authors: if parser.is_default() {
+                None
+            } else {
+                Some(parser.rules().clone())
+            },
+            renames: Some(renames).filter(|renames| !renames.is_default()),
+            repositories: self
+                .histories
+                .git_file_histories
+                .iter()
+                .map(|history| RepositoryInfo::new(history, self.histories.scan_root.as_deref()))
+                .collect(),
+            timeline: activity_timeline(&self.histories.git_file_histories),
+            teams: self
+                .teams
+                .as_ref()
+                .map(|teams| teams.members(&self.dictionary))
+                .unwrap_or_default(),
         });
+        metadata.working_hours = self
+            .working_hours
+            .map(|config| self.contributor_hours.metadata(config));
         Ok(())
     }
+
+    fn timings(&self) -> Vec<(String, Duration)> {
+        vec![("git_history_load".to_owned(), self.histories.load_time)]
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.histories.diagnostics.clone()
+    }
 }
 
-// Hacky - I need this constructor for coupling tests, until I build better integration tests
-#[cfg(test)]
+impl GitData {
+    /// change all user ids, e.g. when merging user dictionaries - `new_ids[old_id]` is the new id,
+    /// several old ids may map to one new id
+    pub fn remap_users(&mut self, new_ids: &[usize]) -> Result<(), Error> {
+        let remap = |user: usize| {
+            new_ids
+                .get(user)
+                .copied()
+                .ok_or_else(|| anyhow!("Unknown user id {}", user))
+        };
+        let remap_set = |users: &BTreeSet<usize>| -> Result<BTreeSet<usize>, Error> {
+            users.iter().map(|user| remap(*user)).collect()
+        };
+        let users = remap_set(&self.users.iter().copied().collect())?;
+        self.user_count = users.len();
+        self.users = users.into_iter().collect();
+        self.last_author = self.last_author.map(remap).transpose()?;
+
+        let mut commits_by_user: HashMap<usize, u64> = HashMap::new();
+        for contributor in &self.top_contributors {
+            *commits_by_user.entry(remap(contributor.user)?).or_insert(0) += contributor.commits;
+        }
+        self.top_contributors = top_contributors(commits_by_user);
+
+        let mut details: HashMap<GitDetailsKey, GitDetails> = HashMap::new();
+        for old_details in &self.details {
+            let users = remap_set(&old_details.users)?;
+            let key = GitDetailsKey {
+                commit_day: old_details.commit_day,
+                users: users.clone(),
+            };
+            let new_details = details.entry(key).or_insert(GitDetails {
+                commit_day: old_details.commit_day,
+                users,
+                commits: 0,
+                lines_added: 0,
+                lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
+            });
+            new_details.commits += old_details.commits;
+            new_details.bulk_commits += old_details.bulk_commits;
+            new_details.merge_commits += old_details.merge_commits;
+            new_details.lines_added += old_details.lines_added;
+            new_details.lines_deleted += old_details.lines_deleted;
+        }
+        self.details = details.into_values().collect();
+        self.details.sort();
+
+        for activity in &mut self.activity {
+            activity.users = remap_set(&activity.users)?;
+        }
+        Ok(())
+    }
+}
+
+// Hacky - I need this constructor for coupling tests and benchmarks, until I build better integration tests
+#[cfg(any(test, feature = "bench"))]
 impl GitData {
     pub fn fake_with_activity(activity: Vec<GitActivity>) -> Self {
         Self {
             last_update: 0,
             age_in_days: 0,
             creation_date: None,
+            days_since_creation: None,
+            days_since_last_change: None,
             user_count: 0,
             users: Vec::new(),
+            last_author: None,
+            top_contributors: Vec::new(),
             details: Vec::new(),
             activity,
+            commit_categories: BTreeMap::new(),
+            teams: BTreeMap::new(),
+            repository: None,
         }
     }
 }
@@ -422,14 +1009,26 @@ mod test {
         let mut dictionary = GitUserDictionary::default();
 
         let today = first_day + 5 * one_day_in_secs;
+        // scanned a while after the last commit in the repository
+        let scan_time = today + 10 * one_day_in_secs + 60;
 
-        let stats = GitHistories::stats_from_history(&mut dictionary, today, &events).unwrap();
+        let stats = GitHistories::stats_from_history(
+            &mut dictionary,
+            today,
+            scan_time,
+            &events,
+            CommitDayTimezone::Utc,
+        )
+        .unwrap();
 
         assert_eq!(stats.last_update, first_day + 3 * one_day_in_secs);
         assert_eq!(stats.age_in_days, 2);
         assert_eq!(stats.creation_date, Some(86400));
+        assert_eq!(stats.days_since_creation, Some(15));
+        assert_eq!(stats.days_since_last_change, Some(12));
         assert_eq!(stats.user_count, 3);
         assert_eq!(stats.users, vec![0, 1, 2]);
+        assert_eq!(stats.last_author, Some(2));
         // don't assert details - details used to be optional, so it is tested in next test.
 
         assert_eq!(dictionary.user_count(), 3);
@@ -440,6 +1039,45 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn commit_days_start_at_midnight_in_the_chosen_timezone() -> Result<(), Error> {
+        let one_day_in_secs: u64 = 60 * 60 * 24;
+        // 23:30 UTC on day 2, which was 00:30 on day 3 for the author
+        let late_evening = 2 * one_day_in_secs + 23 * 60 * 60 + 30 * 60;
+        let events: Vec<FileHistoryEntry> = vec![FileHistoryEntryBuilder::test_default()
+            .emails("jo@smith.com")
+            .times(late_evening)
+            .author_offset_minutes(60)
+            .id("1111")
+            .build()
+            .map_err(Error::msg)?];
+        let today = 5 * one_day_in_secs;
+        let commit_day = |timezone| {
+            let stats = GitHistories::stats_from_history(
+                &mut GitUserDictionary::default(),
+                today,
+                today,
+                &events,
+                timezone,
+            )
+            .unwrap();
+            stats.details[0].commit_day
+        };
+
+        assert_eq!(commit_day(CommitDayTimezone::Utc), 2 * one_day_in_secs);
+        assert_eq!(commit_day(CommitDayTimezone::Author), 3 * one_day_in_secs);
+        assert_eq!(
+            commit_day(CommitDayTimezone::Offset(-8 * 60)),
+            2 * one_day_in_secs
+        );
+        assert_eq!(
+            commit_day(CommitDayTimezone::Offset(90)),
+            3 * one_day_in_secs
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn gets_detailed_stats_from_git_events() -> Result<(), Error> {
         let one_day_in_secs: u64 = 60 * 60 * 24;
@@ -465,6 +1103,7 @@ mod test {
                 .times(first_day + 3 * one_day_in_secs)
                 .author(User::new(Some("Why"), Some("y@smith.com")))
                 .id("2222")
+                .parent_count(2usize)
                 .build()
                 .map_err(Error::msg)?,
         ];
@@ -473,7 +1112,13 @@ mod test {
 
         let today = first_day + 5 * one_day_in_secs;
 
-        let stats = GitHistories::stats_from_history(&mut dictionary, today, &events);
+        let stats = GitHistories::stats_from_history(
+            &mut dictionary,
+            today,
+            today,
+            &events,
+            CommitDayTimezone::Utc,
+        );
 
         let jo_set: BTreeSet<usize> = vec![0].into_iter().collect();
         let xy_set: BTreeSet<usize> = vec![1, 2].into_iter().collect();
@@ -486,6 +1131,8 @@ mod test {
                 commits: 1,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
             },
             GitDetails {
                 commit_day: 86400,
@@ -493,6 +1140,8 @@ mod test {
                 commits: 1,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
             },
             GitDetails {
                 commit_day: 345_600,
@@ -500,6 +1149,8 @@ mod test {
                 commits: 1,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 1,
             },
         ];
 
@@ -511,6 +1162,9 @@ mod test {
                 change: CommitChange::Add,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk: false,
+                parent_count: 1,
+                tickets: Vec::new(),
             },
             GitActivity {
                 author_time: 86400,
@@ -519,6 +1173,9 @@ mod test {
                 change: CommitChange::Add,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk: false,
+                parent_count: 1,
+                tickets: Vec::new(),
             },
             GitActivity {
                 author_time: 345_600,
@@ -527,6 +1184,9 @@ mod test {
                 change: CommitChange::Add,
                 lines_added: 0,
                 lines_deleted: 0,
+                bulk: false,
+                parent_count: 2,
+                tickets: Vec::new(),
             },
         ];
 
@@ -536,10 +1196,30 @@ mod test {
                 last_update: first_day + 3 * one_day_in_secs,
                 age_in_days: 2,
                 creation_date: Some(86400),
+                days_since_creation: Some(5),
+                days_since_last_change: Some(2),
                 user_count: 3,
                 users: vec![0, 1, 2],
+                last_author: Some(1),
+                top_contributors: vec![
+                    Contributor {
+                        user: 0,
+                        commits: 2
+                    },
+                    Contributor {
+                        user: 1,
+                        commits: 2
+                    },
+                    Contributor {
+                        user: 2,
+                        commits: 1
+                    },
+                ],
                 details: expected_details,
                 activity: expected_activity,
+                commit_categories: BTreeMap::new(),
+                teams: BTreeMap::new(),
+                repository: None,
             })
         );
 
@@ -550,4 +1230,28 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn activity_only_records_parent_counts_other_than_one() -> Result<(), Error> {
+        let mut activity = GitActivity {
+            author_time: 86400,
+            commit_time: 86400,
+            users: BTreeSet::new(),
+            change: CommitChange::Modify,
+            lines_added: 1,
+            lines_deleted: 0,
+            bulk: false,
+            parent_count: 1,
+            tickets: Vec::new(),
+        };
+        let json = serde_json::to_value(&activity)?;
+        assert_eq!(json.get("parent_count"), None);
+        assert_eq!(serde_json::from_value::<GitActivity>(json)?, activity);
+
+        activity.parent_count = 2;
+        let json = serde_json::to_value(&activity)?;
+        assert_eq!(json["parent_count"], 2);
+        assert_eq!(serde_json::from_value::<GitActivity>(json)?, activity);
+        Ok(())
+    }
 }
@@ -0,0 +1,71 @@
+#![warn(clippy::all)]
+//! Keeping detailed git data for only some files - on big monorepos, per-day details for every
+//! file make the output huge, when only a few subtrees are of interest.
+//!
+//! Patterns are gitignore-style globs relative to the tree root, e.g. `src/core/**` or
+//! `*.rs`. Files that don't match still have summary git data, like their last update and
+//! users - only the per-day details are removed.
+
+use anyhow::{Context, Error};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Serialize, Serializer};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct GitDetailsFilter {
+    patterns: Vec<String>,
+    matcher: Gitignore,
+}
+
+impl GitDetailsFilter {
+    pub fn new(patterns: Vec<String>) -> Result<Self, Error> {
+        let mut builder = GitignoreBuilder::new(".");
+        for pattern in &patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("invalid git details pattern {}", pattern))?;
+        }
+        Ok(GitDetailsFilter {
+            patterns,
+            matcher: builder.build()?,
+        })
+    }
+
+    #[must_use]
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// true if the file's details should be kept - `relative_path` is relative to the tree root
+    #[must_use]
+    pub fn keeps(&self, relative_path: &Path) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(relative_path, false)
+            .is_ignore()
+    }
+}
+
+impl Serialize for GitDetailsFilter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.patterns.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_matching_files_keep_details() -> Result<(), Error> {
+        let filter = GitDetailsFilter::new(vec!["src/core/**".to_owned(), "*.sql".to_owned()])?;
+        assert!(filter.keeps(Path::new("src/core/main.rs")));
+        assert!(filter.keeps(Path::new("src/core/deep/nested.rs")));
+        assert!(filter.keeps(Path::new("db/schema.sql")));
+        assert!(!filter.keeps(Path::new("src/other/main.rs")));
+        assert!(!filter.keeps(Path::new("src/core.rs")));
+        Ok(())
+    }
+}
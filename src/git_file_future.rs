@@ -1,19 +1,24 @@
 #![warn(clippy::all)]
 use git2::Oid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 /// Track file changes for a file - renames and deletes
 #[derive(Debug, Clone)]
 pub struct GitFileFutureRegistry {
     rev_changes: HashMap<Oid, RevChange>,
+    /// the first-parent chain from the first registered commit, which should be HEAD - the
+    /// main branch, which is followed in preference to side branches
+    mainline: HashSet<Oid>,
 }
 
 #[derive(Debug, Clone)]
 struct RevChange {
     files: HashMap<PathBuf, FileNameChange>,
-    /// first child is generally used only, it is the main branch - don't divert into other branches!
+    /// in registration order - which isn't necessarily the main branch first
     children: Vec<Oid>,
+    /// None for root commits, and commits that haven't been registered
+    first_parent: Option<Oid>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +32,7 @@ impl RevChange {
         RevChange {
             files: HashMap::new(),
             children: Vec::new(),
+            first_parent: None,
         }
     }
 }
@@ -35,17 +41,49 @@ impl GitFileFutureRegistry {
     pub fn new() -> Self {
         GitFileFutureRegistry {
             rev_changes: HashMap::new(),
+            mainline: HashSet::new(),
         }
     }
 
+    /// the children of a commit, best first - the child on the main branch, then a child
+    /// continuing this commit's branch, then the rest, which merge this branch into another -
+    /// so merges, including octopus merges, don't divert into branches that aren't HEAD's
+    fn preferred_children(&self, id: &Oid, change: &RevChange) -> Vec<Oid> {
+        let mut children = change.children.clone();
+        // stable, so otherwise in registration order
+        children.sort_by_key(|child| {
+            if self.mainline.contains(child) {
+                0
+            } else if self
+                .rev_changes
+                .get(child)
+                .and_then(|child| child.first_parent)
+                == Some(*id)
+            {
+                1
+            } else {
+                2
+            }
+        });
+        children
+    }
+
     pub fn register(
         &mut self,
         id: &Oid,
         parent_ids: &[Oid],
         file_changes: &[(PathBuf, FileNameChange)],
     ) {
+        if self.rev_changes.is_empty() {
+            // commits are registered in topological order, so the first is HEAD
+            self.mainline.insert(*id);
+        }
+        if self.mainline.contains(id) {
+            self.mainline.extend(parent_ids.first());
+        }
         let entry = self.rev_changes.entry(*id).or_insert_with(RevChange::new);
         entry.files.extend(file_changes.iter().cloned());
+        entry.first_parent = parent_ids.first().copied();
         for parent_id in parent_ids {
             let pentry = self
                 .rev_changes
@@ -57,6 +95,7 @@ impl GitFileFutureRegistry {
 
     /// what is this called in the final revision?
     /// returns None if it is deleted, or Some(final name)
+    /// Only the preferred child of each commit is followed - see `preferred_children`
     pub fn final_name(&self, ref_id: &Oid, file: &Path) -> Option<PathBuf> {
         let mut current_name: &PathBuf = &file.to_path_buf();
         let mut current_ref: Oid = *ref_id;
@@ -69,8 +108,11 @@ impl GitFileFutureRegistry {
                 Some(FileNameChange::Deleted()) => return None,
                 None => (),
             }
-            if let Some(first_child) = current_change.children.get(0) {
-                current_ref = *first_child;
+            if let Some(best_child) = self
+                .preferred_children(&current_ref, current_change)
+                .first()
+            {
+                current_ref = *best_child;
             // and loop will continue
             } else {
                 // no children, so finished looking into the future
@@ -78,6 +120,43 @@ impl GitFileFutureRegistry {
             }
         }
     }
+
+    /// what might this be called in the final revision of any branch?
+    /// `final_name` only follows preferred children, so gets the wrong name if a file was renamed
+    /// on another branch - this searches every branch, preferred children first.
+    pub fn possible_final_names(&self, ref_id: &Oid, file: &Path) -> Vec<PathBuf> {
+        let mut names: Vec<PathBuf> = Vec::new();
+        let mut visited: HashSet<(Oid, PathBuf)> = HashSet::new();
+        let mut pending: Vec<(Oid, PathBuf)> = vec![(*ref_id, file.to_path_buf())];
+        while let Some((current_ref, current_name)) = pending.pop() {
+            if !visited.insert((current_ref, current_name.clone())) {
+                continue;
+            }
+            let current_change = match self.rev_changes.get(&current_ref) {
+                Some(change) => change,
+                None => continue,
+            };
+            let current_name = match current_change.files.get(&current_name) {
+                Some(FileNameChange::Renamed(new_name)) => new_name.clone(),
+                Some(FileNameChange::Deleted()) => continue,
+                None => current_name,
+            };
+            if current_change.children.is_empty() {
+                if !names.contains(&current_name) {
+                    names.push(current_name);
+                }
+            } else {
+                for child in self
+                    .preferred_children(&current_ref, current_change)
+                    .iter()
+                    .rev()
+                {
+                    pending.push((*child, current_name.clone()));
+                }
+            }
+        }
+        names
+    }
 }
 
 #[cfg(test)]
@@ -189,4 +268,90 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn octopus_merges_follow_the_main_branch() -> Result<(), Error> {
+        let mut registry = GitFileFutureRegistry::new();
+        /*
+                 01 add a
+           /      |       \
+          02      03      04
+        a->main a->side1 a->side2
+           \      |       /
+             05 octopus merge
+             main->final
+        */
+        let id_1 = Oid::from_str("01")?;
+        let id_2 = Oid::from_str("02")?;
+        let id_3 = Oid::from_str("03")?;
+        let id_4 = Oid::from_str("04")?;
+        let id_5 = Oid::from_str("05")?;
+
+        registry.register(
+            &id_5,
+            &[id_2, id_3, id_4],
+            &[(pb("main"), FileNameChange::Renamed(pb("final")))],
+        );
+        // side branches registered first, so they are the first children of 01
+        registry.register(
+            &id_4,
+            &[id_1],
+            &[(pb("a"), FileNameChange::Renamed(pb("side2")))],
+        );
+        registry.register(
+            &id_3,
+            &[id_1],
+            &[(pb("a"), FileNameChange::Renamed(pb("side1")))],
+        );
+        registry.register(
+            &id_2,
+            &[id_1],
+            &[(pb("a"), FileNameChange::Renamed(pb("main")))],
+        );
+        registry.register(&id_1, &[], &[]);
+
+        assert_eq!(registry.final_name(&id_1, &pb("a")), Some(pb("final")));
+        assert_eq!(
+            registry.possible_final_names(&id_1, &pb("a")),
+            vec![pb("final"), pb("side2"), pb("side1")]
+        );
+        // off the main branch, commits still reach the merge
+        assert_eq!(registry.final_name(&id_3, &pb("side1")), Some(pb("side1")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn possible_final_names_follow_every_branch() -> Result<(), Error> {
+        let mut registry = GitFileFutureRegistry::new();
+        let id_1 = Oid::from_str("01")?;
+        let id_2 = Oid::from_str("02")?;
+        let id_3 = Oid::from_str("03")?;
+        let id_4 = Oid::from_str("04")?;
+
+        registry.register(
+            &id_2,
+            &[id_1],
+            &[(pb("a"), FileNameChange::Renamed(pb("b")))],
+        );
+        registry.register(
+            &id_3,
+            &[id_1],
+            &[(pb("a"), FileNameChange::Renamed(pb("c")))],
+        );
+        registry.register(&id_4, &[id_1], &[(pb("a"), FileNameChange::Deleted())]);
+        registry.register(&id_1, &[], &[]);
+
+        assert_eq!(registry.final_name(&id_1, &pb("a")), Some(pb("b")));
+        assert_eq!(
+            registry.possible_final_names(&id_1, &pb("a")),
+            vec![pb("b"), pb("c")]
+        );
+        assert_eq!(
+            registry.possible_final_names(&id_4, &pb("a")),
+            Vec::<PathBuf>::new()
+        );
+
+        Ok(())
+    }
 }
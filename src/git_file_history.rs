@@ -1,13 +1,37 @@
 #![warn(clippy::all)]
-use crate::git_logger::{CommitChange, FileChange, GitLog, GitLogEntry, User};
+//! The history of each file in a repository, under its current name.
+//!
+//! `GitFileHistory::new` reads the whole of a `GitLog`, following renames so each file's
+//! history includes changes made under older names, and matching history to files in the
+//! working directory. Queries are by path - `history_for` takes a path on disk, `files` lists
+//! every file with history, relative to the working directory.
+//!
+//! ```no_run
+//! use polyglot_code_scanner::git_file_history::GitFileHistory;
+//! use polyglot_code_scanner::git_logger::{GitLog, GitLogConfig};
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let mut log = GitLog::new(std::path::Path::new("."), GitLogConfig::default())?;
+//! let history = GitFileHistory::new(&mut log)?;
+//! for (file, entries) in history.files() {
+//!     println!("{:?} changed in {} commits", file, entries.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::git_file_future::GitFileFutureRegistry;
+use crate::git_logger::{BulkCommitMode, CommitChange, FileChange, GitLog, GitLogEntry, User};
+use crate::path_interner::{PathId, PathInterner};
+use crate::progress::Progress;
 use anyhow::Error;
 use chrono::offset::TimeZone;
 use chrono::Utc;
 use git2::Oid;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressStyle;
+use path_slash::PathExt;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
+use std::iter::once;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -20,14 +44,37 @@ pub struct FileHistoryEntry {
     pub commit_time: u64,
     pub author: User,
     pub author_time: u64,
+    /// the author's timezone, in minutes east of UTC
+    #[builder(default)]
+    pub author_offset_minutes: i32,
     pub co_authors: Vec<User>,
     pub change: CommitChange,
     pub lines_added: u64,
     pub lines_deleted: u64,
+    /// part of a commit that changed lots of files - see `BulkCommitConfig`
+    #[builder(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bulk: bool,
+    /// how many parents the commit had - more than one is a merge, only seen if merges are included
+    #[builder(default = "1")]
+    pub parent_count: usize,
+    /// the commit category, if commits are being classified
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// ticket ids from the commit message, if tickets are being extracted
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tickets: Vec<String>,
+    /// for history inherited from the file this was copied from, that file's name when copied -
+    /// only if copy history is enabled
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copied_from: Option<PathBuf>,
 }
 
 impl FileHistoryEntry {
-    fn from(entry: &GitLogEntry, file_change: &FileChange) -> FileHistoryEntry {
+    fn from(entry: &GitLogEntry, file_change: &FileChange, bulk: bool) -> FileHistoryEntry {
         let entry = entry.clone();
         let file_change = file_change.clone();
         FileHistoryEntry {
@@ -36,12 +83,39 @@ impl FileHistoryEntry {
             commit_time: *entry.commit_time(),
             author: entry.author().clone(),
             author_time: *entry.author_time(),
+            author_offset_minutes: *entry.author_offset_minutes(),
             co_authors: entry.co_authors().clone(),
             change: *file_change.change(),
             lines_added: *file_change.lines_added(),
             lines_deleted: *file_change.lines_deleted(),
+            bulk,
+            parent_count: entry.parents().len(),
+            category: entry.category().clone(),
+            tickets: entry.tickets().clone(),
+            copied_from: None,
         }
     }
+
+    /// this entry, as history inherited by a copy of the file
+    fn inherited_from(&self, source: &Path) -> FileHistoryEntry {
+        FileHistoryEntry {
+            // copies of copies inherit from the original file
+            copied_from: Some(
+                self.copied_from
+                    .clone()
+                    .unwrap_or_else(|| source.to_path_buf()),
+            ),
+            ..self.clone()
+        }
+    }
+}
+
+/// a file copied from another, to be given the other file's earlier history
+struct CopiedFile {
+    copy: PathId,
+    source: PathId,
+    /// the source's history from here on is older than the copy
+    from_index: usize,
 }
 
 #[cfg(test)]
@@ -63,21 +137,164 @@ impl FileHistoryEntryBuilder {
     }
 }
 
+/// per-file history for a whole repository - see the module docs
 #[derive(Debug, Serialize)]
 pub struct GitFileHistory {
     /// repo work dir - always canonical
     workdir: PathBuf,
-    history_by_file: HashMap<PathBuf, Vec<FileHistoryEntry>>,
+    /// other worktrees of the same repository inside the work dir - their files have their own
+    /// history, so don't belong to this one
+    nested_worktrees: Vec<PathBuf>,
+    /// every file name seen in the history, relative to the workdir
+    paths: PathInterner<Path>,
+    history_by_file: HashMap<PathId, Vec<FileHistoryEntry>>,
     last_commit: u64,
+    /// files with history that aren't in the working directory, relative to the workdir
+    unmatched_files: Vec<PathBuf>,
+    canonical_remote_url: Option<String>,
+    /// activity by month, as "YYYY-MM"
+    monthly_history: BTreeMap<String, MonthHistory>,
+    /// the latest author time of each lower-cased author or co-author
+    last_active: HashMap<User, u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct MonthHistory {
+    commits: u64,
+    /// lower-cased, so the same person with different capitalisation is only counted once
+    authors: HashSet<User>,
+    /// current names, or the last name for deleted files
+    files: HashSet<PathId>,
+}
+
+/// repository activity for one month - authors and files are only counted once per month
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthlyActivity {
+    /// "YYYY-MM", by author time in UTC
+    pub month: String,
+    pub commits: u64,
+    /// authors and co-authors
+    pub authors: u64,
+    pub files: u64,
+}
+
+/// monthly activity across all repositories, oldest first
+pub fn activity_timeline(histories: &[GitFileHistory]) -> Vec<MonthlyActivity> {
+    let mut commits: BTreeMap<&str, u64> = BTreeMap::new();
+    let mut authors: HashMap<&str, HashSet<&User>> = HashMap::new();
+    let mut files: HashMap<&str, u64> = HashMap::new();
+    for history in histories {
+        for (month, month_history) in &history.monthly_history {
+            *commits.entry(month).or_default() += month_history.commits;
+            authors
+                .entry(month)
+                .or_default()
+                .extend(month_history.authors.iter());
+            // files in different repositories are different files
+            *files.entry(month).or_default() += month_history.files.len() as u64;
+        }
+    }
+    commits
+        .into_iter()
+        .map(|(month, commits)| MonthlyActivity {
+            month: month.to_owned(),
+            commits,
+            authors: authors.get(month).map_or(0, |authors| authors.len() as u64),
+            files: files.get(month).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a - not cryptographic, but simple, and stable across platforms and Rust versions
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// an id for a file that is the same across scans - based on the repository remote and the
+/// file's path in the working directory, so it survives the scan root moving, and doesn't depend
+/// on how much history is read. Renamed files get a new id.
+fn stable_id(canonical_remote_url: Option<&str>, path: &Path) -> String {
+    let key = format!(
+        "{}\n{}",
+        canonical_remote_url.unwrap_or(""),
+        path.to_slash_lossy()
+    );
+    format!("{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// maps the final name of a changed file onto a file in the working directory -
+/// if the final name isn't on disk, other branches are searched for a name that is.
+/// Results are cached by final name, so all history for a final name goes to the same file.
+struct PathReconciler<'a> {
+    workdir: &'a Path,
+    registry: &'a GitFileFutureRegistry,
+    reconciled: HashMap<PathBuf, (PathBuf, bool)>,
+}
+
+impl<'a> PathReconciler<'a> {
+    fn new(workdir: &'a Path, registry: &'a GitFileFutureRegistry) -> Self {
+        PathReconciler {
+            workdir,
+            registry,
+            reconciled: HashMap::new(),
+        }
+    }
+
+    fn current_name(&mut self, id: &Oid, file: &Path, final_name: PathBuf) -> PathBuf {
+        let workdir = self.workdir;
+        let registry = self.registry;
+        self.reconciled
+            .entry(final_name)
+            .or_insert_with_key(|final_name| {
+                if workdir.join(final_name).exists() {
+                    return (final_name.clone(), true);
+                }
+                match registry
+                    .possible_final_names(id, file)
+                    .into_iter()
+                    .find(|name| workdir.join(name).exists())
+                {
+                    Some(name) => {
+                        debug!("Reconciled history for {:?} to {:?}", final_name, name);
+                        (name, true)
+                    }
+                    None => (final_name.clone(), false),
+                }
+            })
+            .0
+            .clone()
+    }
+
+    fn unmatched_files(&self) -> Vec<PathBuf> {
+        let mut unmatched: Vec<PathBuf> = self
+            .reconciled
+            .values()
+            .filter(|(_, on_disk)| !on_disk)
+            .map(|(name, _)| name.clone())
+            .collect();
+        unmatched.sort();
+        unmatched.dedup();
+        unmatched
+    }
 }
 
 impl GitFileHistory {
     pub fn new(log: &mut GitLog) -> Result<GitFileHistory, Error> {
         let mut last_commit: u64 = 0;
-        let mut history_by_file = HashMap::<PathBuf, Vec<FileHistoryEntry>>::new();
+        let mut paths = PathInterner::<Path>::default();
+        let mut history_by_file = HashMap::<PathId, Vec<FileHistoryEntry>>::new();
+        let mut monthly_history = BTreeMap::<String, MonthHistory>::new();
+        let mut last_active = HashMap::<User, u64>::new();
         info!("Loading git log");
-        let progress_bar = ProgressBar::new_spinner()
-            .with_style(ProgressStyle::default_spinner().template("[{elapsed}] {msg}")?);
+        let progress_bar = Progress::spinner(
+            "git_log",
+            ProgressStyle::default_spinner().template("[{elapsed}] {msg}")?,
+        );
         progress_bar.tick();
         // TODO: this was removed in indicatif 0.17 - do we need it?
         // see https://github.com/console-rs/indicatif/issues/393
@@ -93,7 +310,9 @@ impl GitFileHistory {
         // I can't find a cleaner way for an iterator to have side effects
         let git_file_future_registry = log_iterator.git_file_future_registry();
         let mut progress_last_updated: u64 = 0;
+        let cancellation = log.config().cancellation_token().clone();
         let log_entries: Vec<Result<GitLogEntry, Error>> = log_iterator
+            .take_while(|_| !cancellation.is_cancelled())
             // .progress_with(progress_bar)
             .inspect(|entry| {
                 if let Ok(entry) = entry {
@@ -113,14 +332,37 @@ impl GitFileHistory {
             })
             .collect();
         progress_bar.finish();
+        cancellation.check()?;
 
         // safe to borrow this now as the iterator has gone and can't mutate any more
         let git_file_future_registry = git_file_future_registry.borrow();
 
+        let bulk_commit_config = log.config().bulk_commit_config();
+        // "all files" is all files changed in the history, as this is cheap and handles subtrees
+        let total_files = if bulk_commit_config.is_some() {
+            log_entries
+                .iter()
+                .flatten()
+                .flat_map(|entry| entry.file_changes().iter().map(FileChange::file))
+                .collect::<HashSet<_>>()
+                .len()
+        } else {
+            0
+        };
+        let mut bulk_count = 0;
+        let mut reconciler = PathReconciler::new(log.workdir(), &git_file_future_registry);
+        let copy_history = log.config().rename_config().copy_history;
+        let mut copies: Vec<CopiedFile> = Vec::new();
+
         info!("Processing git log with {} entries", log_entries.len());
-        let entrybar = ProgressBar::new(log_entries.len().try_into()?);
+        let entrybar = Progress::bar(
+            "git_history",
+            log_entries.len().try_into()?,
+            ProgressStyle::default_bar(),
+        );
         for entry in log_entries {
-            entrybar.tick();
+            cancellation.check()?;
+            entrybar.inc(1);
             match entry {
                 Ok(entry) => {
                     let commit_time = *entry.commit_time();
@@ -129,21 +371,87 @@ impl GitFileHistory {
                     if commit_time > last_commit {
                         last_commit = commit_time;
                     }
+                    let bulk = match bulk_commit_config {
+                        Some(config) => config.is_bulk(entry.file_changes().len(), total_files),
+                        None => false,
+                    };
+                    if bulk {
+                        bulk_count += 1;
+                        if bulk_commit_config.map(|config| config.mode)
+                            == Some(BulkCommitMode::Exclude)
+                        {
+                            debug!("Excluding bulk commit {}", entry.id());
+                            continue;
+                        }
+                    }
+                    let mut touched_files = Vec::new();
+                    let mut copied_files: Vec<(PathId, PathId)> = Vec::new();
+                    // TODO: use Oids so we don't need ugly conversion.
+                    let oid = Oid::from_str(entry.id()).unwrap();
                     for file_change in entry.clone().file_changes() {
-                        // TODO: use Oids so we don't need ugly conversion.
-                        let final_filename = git_file_future_registry
-                            .final_name(&Oid::from_str(entry.id()).unwrap(), file_change.file());
+                        let final_filename =
+                            git_file_future_registry.final_name(&oid, file_change.file());
                         if let Some(filename) = final_filename {
+                            let filename = paths.intern_ref(&reconciler.current_name(
+                                &oid,
+                                file_change.file(),
+                                filename,
+                            ));
+                            touched_files.push(filename);
+                            if copy_history && *file_change.change() == CommitChange::Copied {
+                                let source = file_change.old_file().as_ref();
+                                // no history is kept for deleted sources
+                                if let Some((source, source_name)) = source.and_then(|source| {
+                                    git_file_future_registry
+                                        .final_name(&oid, source)
+                                        .map(|name| (source, name))
+                                }) {
+                                    let source_name = paths.intern_ref(&reconciler.current_name(
+                                        &oid,
+                                        source,
+                                        source_name,
+                                    ));
+                                    copied_files.push((filename, source_name));
+                                }
+                            }
                             let hash_entry =
                                 history_by_file.entry(filename).or_insert_with(Vec::new);
-                            let new_entry = FileHistoryEntry::from(&entry, file_change);
+                            let new_entry = FileHistoryEntry::from(&entry, file_change, bulk);
                             hash_entry.push(new_entry);
                         } else {
                             trace!(
                                 "Not storing history for deleted file {:?}",
                                 file_change.file()
                             );
+                            touched_files.push(paths.intern_ref(file_change.file()));
+                        }
+                    }
+                    // only history older than this commit is inherited
+                    for (copy, source) in copied_files {
+                        if copy != source {
+                            copies.push(CopiedFile {
+                                from_index: history_by_file.get(&source).map_or(0, Vec::len),
+                                copy,
+                                source,
+                            });
+                        }
+                    }
+                    if !touched_files.is_empty() {
+                        let month = monthly_history
+                            .entry(
+                                Utc.timestamp(*entry.author_time() as i64, 0)
+                                    .format("%Y-%m")
+                                    .to_string(),
+                            )
+                            .or_default();
+                        month.commits += 1;
+                        for author in once(entry.author()).chain(entry.co_authors()) {
+                            let author = author.as_lower_case();
+                            let active = last_active.entry(author.clone()).or_insert(0);
+                            *active = (*active).max(*entry.author_time());
+                            month.authors.insert(author);
                         }
+                        month.files.extend(touched_files);
                     }
                 }
                 Err(e) => {
@@ -152,36 +460,143 @@ impl GitFileHistory {
             }
         }
         entrybar.finish();
+        if bulk_count > 0 {
+            info!("Found {} bulk commits", bulk_count);
+        }
+        if !copies.is_empty() {
+            info!("Adding history to {} copied files", copies.len());
+        }
+        // oldest copies first, so copies of copies inherit the whole history
+        for copied in copies.iter().rev() {
+            let inherited: Vec<FileHistoryEntry> = match history_by_file.get(&copied.source) {
+                Some(source_history) => source_history[copied.from_index..]
+                    .iter()
+                    .map(|entry| entry.inherited_from(paths.path(copied.source)))
+                    .collect(),
+                None => continue,
+            };
+            history_by_file
+                .entry(copied.copy)
+                .or_insert_with(Vec::new)
+                .extend(inherited);
+        }
+
+        let unmatched_files = reconciler.unmatched_files();
+        if !unmatched_files.is_empty() {
+            info!(
+                "Found git history for {} files not in the working directory",
+                unmatched_files.len()
+            );
+        }
+
+        let nested_worktrees: Vec<PathBuf> = log
+            .other_worktrees()
+            .into_iter()
+            .filter(|worktree| worktree.starts_with(log.workdir()))
+            .collect();
+        if !nested_worktrees.is_empty() {
+            info!(
+                "Files in worktrees {:?} will use their own git history",
+                nested_worktrees
+            );
+        }
 
         Ok(GitFileHistory {
             workdir: log.workdir().to_owned(),
+            nested_worktrees,
+            paths,
             history_by_file,
             last_commit,
+            unmatched_files,
+            canonical_remote_url: log.canonical_remote_url(),
+            monthly_history,
+            last_active,
         })
     }
 
     /// true if this repo is valid for this file - file must exist (as we canonicalize it)
     pub fn is_repo_for(&self, file: &Path) -> Result<bool, Error> {
         let canonical_file = file.canonicalize()?;
-        Ok(canonical_file.starts_with(&self.workdir))
+        Ok(canonical_file.starts_with(&self.workdir)
+            && !self
+                .nested_worktrees
+                .iter()
+                .any(|worktree| canonical_file.starts_with(worktree)))
     }
 
     /// get git history for this file - file must exist (as we canonicalize it)
     pub fn history_for(&self, file: &Path) -> Result<Option<&Vec<FileHistoryEntry>>, Error> {
         let canonical_file = file.canonicalize()?;
         let relative_file = canonical_file.strip_prefix(&self.workdir)?;
-        Ok(self.history_by_file.get(relative_file))
+        Ok(self
+            .paths
+            .id(relative_file)
+            .and_then(|id| self.history_by_file.get(&id)))
+    }
+
+    /// history for a path relative to the working directory - unlike `history_for`, the file
+    /// doesn't need to exist, though history is only kept for files in the working directory
+    pub fn history_for_relative(&self, relative_file: &Path) -> Option<&[FileHistoryEntry]> {
+        self.paths
+            .id(relative_file)
+            .and_then(|id| self.history_by_file.get(&id))
+            .map(Vec::as_slice)
+    }
+
+    /// every file with history, relative to the working directory, in no particular order
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &[FileHistoryEntry])> {
+        self.history_by_file
+            .iter()
+            .map(|(file, history)| (self.paths.path(*file).as_ref(), history.as_slice()))
+    }
+
+    /// a stable id for this file - file must exist (as we canonicalize it)
+    pub fn stable_id_for(&self, file: &Path) -> Result<String, Error> {
+        let canonical_file = file.canonicalize()?;
+        let relative_file = canonical_file.strip_prefix(&self.workdir)?;
+        Ok(stable_id(
+            self.canonical_remote_url.as_deref(),
+            relative_file,
+        ))
     }
 
     pub fn last_commit(&self) -> u64 {
         self.last_commit
     }
+
+    /// when this user last authored or co-authored a commit in the repository, if ever
+    pub fn last_active(&self, user: &User) -> Option<u64> {
+        self.last_active.get(&user.as_lower_case()).copied()
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    pub fn canonical_remote_url(&self) -> Option<&str> {
+        self.canonical_remote_url.as_deref()
+    }
+
+    /// commits in the scanned history that changed files
+    pub fn commit_count(&self) -> u64 {
+        self.monthly_history
+            .values()
+            .map(|month| month.commits)
+            .sum()
+    }
+
+    /// files with history that couldn't be matched to the working directory - usually deleted
+    /// or renamed without committing, or renamed in ways the rename detection missed
+    pub fn unmatched_files(&self) -> &[PathBuf] {
+        &self.unmatched_files
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::git_logger::GitLogConfig;
+    use crate::git_logger::{BulkCommitConfig, GitLogConfig, RenameConfig};
+    use git2::Repository;
     use pretty_assertions::assert_eq;
     use tempfile::tempdir;
     use test_shared::{assert_eq_json_file, unzip_test_sample};
@@ -198,14 +613,132 @@ mod test {
         assert_eq!(history.workdir.canonicalize()?, git_root.canonicalize()?);
 
         // assert_eq_json_str(&history.history_by_file, "{}");
+        let by_filename: HashMap<&Path, &[FileHistoryEntry]> = history.files().collect();
         assert_eq_json_file(
-            &history.history_by_file,
+            &by_filename,
             "./tests/expected/git/git_sample_by_filename.json",
         );
 
         Ok(())
     }
 
+    #[test]
+    fn stable_ids_use_the_current_name_whatever_the_history() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+        let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        let history = GitFileHistory::new(&mut git_log)?;
+        // the sample is from 2019, so this reads none of it - including the rename of a.txt
+        let mut recent_log = GitLog::new(&git_root, GitLogConfig::default().since_years(Some(1)))?;
+        let recent_history = GitFileHistory::new(&mut recent_log)?;
+
+        let renamed = git_root.join("simple/child/a_renamed.txt");
+        assert_eq!(
+            history.stable_id_for(&renamed)?,
+            stable_id(None, Path::new("simple/child/a_renamed.txt"))
+        );
+        assert_eq!(
+            history.stable_id_for(&renamed)?,
+            recent_history.stable_id_for(&renamed)?
+        );
+        assert_eq!(
+            history.stable_id_for(&git_root.join("simple/parent.clj"))?,
+            "a6bc90fc8d15b8f5"
+        );
+        assert_ne!(
+            stable_id(Some("github.com/foo/bar"), Path::new("simple/parent.clj")),
+            "a6bc90fc8d15b8f5"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn activity_is_counted_by_month() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+        let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        let history = GitFileHistory::new(&mut git_log)?;
+
+        // the merge commit has no changes, and a.txt was renamed to a_renamed.txt
+        assert_eq!(
+            activity_timeline(&[history]),
+            vec![MonthlyActivity {
+                month: "2019-05".to_owned(),
+                commits: 5,
+                authors: 2,
+                files: 2
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_for_files_not_on_disk_is_unmatched() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        std::fs::remove_file(git_root.join("simple/parent.clj"))?;
+
+        let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        let history = GitFileHistory::new(&mut git_log)?;
+
+        assert_eq!(
+            history.unmatched_files(),
+            &[PathBuf::from("simple/parent.clj")]
+        );
+        assert!(history
+            .history_for(&git_root.join("simple/child/a_renamed.txt"))?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_commits_can_be_flagged_or_excluded() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        let file = git_root.join("simple/parent.clj");
+        let bulk_config = BulkCommitConfig {
+            max_files: None,
+            max_percent: Some(50.0),
+            mode: BulkCommitMode::Flag,
+        };
+
+        let mut git_log = GitLog::new(
+            &git_root,
+            GitLogConfig::default().bulk_commits(Some(bulk_config)),
+        )?;
+        let history = GitFileHistory::new(&mut git_log)?;
+        let flags: Vec<_> = history
+            .history_for(&file)?
+            .unwrap()
+            .iter()
+            .map(|h| h.bulk)
+            .collect();
+        assert_eq!(flags, vec![false, true, true]);
+
+        let mut git_log = GitLog::new(
+            &git_root,
+            GitLogConfig::default().bulk_commits(Some(BulkCommitConfig {
+                mode: BulkCommitMode::Exclude,
+                ..bulk_config
+            })),
+        )?;
+        let history = GitFileHistory::new(&mut git_log)?;
+        let ids: Vec<_> = history
+            .history_for(&file)?
+            .unwrap()
+            .iter()
+            .map(|h| &h.id)
+            .collect();
+        assert_eq!(ids, vec!["0dbd54d4c524ecc776f381e660cce9b2dd92162c"]);
+
+        Ok(())
+    }
+
     #[test]
     fn can_tell_if_file_is_in_git_repo() -> Result<(), Error> {
         let gitdir = tempdir()?;
@@ -220,6 +753,97 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn files_in_nested_worktrees_use_the_worktree_history() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        let worktree_root = git_root.join("worktree");
+        Repository::open(&git_root)?.worktree("worktree", &worktree_root, None)?;
+
+        let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        let history = GitFileHistory::new(&mut git_log)?;
+        let mut worktree_log = GitLog::new(&worktree_root, GitLogConfig::default())?;
+        assert!(worktree_log.is_worktree());
+        let worktree_history = GitFileHistory::new(&mut worktree_log)?;
+
+        let worktree_file = worktree_root.join("simple/parent.clj");
+        assert!(!history.is_repo_for(&worktree_file)?);
+        assert!(worktree_history.is_repo_for(&worktree_file)?);
+        assert!(worktree_history.history_for(&worktree_file)?.is_some());
+        assert!(history.is_repo_for(&git_root.join("simple/parent.clj"))?);
+        assert!(!worktree_history.is_repo_for(&git_root.join("simple/parent.clj"))?);
+
+        Ok(())
+    }
+
+    /// a repository where a.txt is added, then copied to b.txt while a.txt is changed
+    fn copied_file_repo(root: &Path) -> Result<(), Error> {
+        let repo = Repository::init(root)?;
+        let original: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let changed = format!("{}line 11\n", original);
+        let mut parents = Vec::new();
+        for (time, files) in [
+            (1000, vec![("a.txt", original.as_str())]),
+            (
+                2000,
+                vec![("a.txt", changed.as_str()), ("b.txt", original.as_str())],
+            ),
+        ] {
+            let mut builder = repo.treebuilder(None)?;
+            for (name, contents) in files {
+                builder.insert(name, repo.blob(contents.as_bytes())?, 0o100_644)?;
+            }
+            let tree = repo.find_tree(builder.write()?)?;
+            let signature = git2::Signature::new("Jo", "jo@smith.com", &git2::Time::new(time, 0))?;
+            let parent_refs: Vec<&git2::Commit<'_>> = parents.iter().collect();
+            let commit = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "change",
+                &tree,
+                &parent_refs,
+            )?;
+            parents = vec![repo.find_commit(commit)?];
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn copied_files_can_inherit_history() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        copied_file_repo(gitdir.path())?;
+        let history_of_copy = |copy_history: bool| -> Result<Vec<_>, Error> {
+            let renames = RenameConfig {
+                copies: true,
+                copy_history,
+                ..RenameConfig::default()
+            };
+            let mut git_log = GitLog::new(gitdir.path(), GitLogConfig::default().renames(renames))?;
+            let history = GitFileHistory::new(&mut git_log)?;
+            Ok(history
+                .history_for_relative(Path::new("b.txt"))
+                .unwrap()
+                .iter()
+                .map(|entry| (entry.change, entry.author_time, entry.copied_from.clone()))
+                .collect())
+        };
+
+        assert_eq!(
+            history_of_copy(false)?,
+            vec![(CommitChange::Copied, 2000, None)]
+        );
+        assert_eq!(
+            history_of_copy(true)?,
+            vec![
+                (CommitChange::Copied, 2000, None),
+                (CommitChange::Add, 1000, Some(PathBuf::from("a.txt")))
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn can_get_history_for_file() -> Result<(), Error> {
         let gitdir = tempdir()?;
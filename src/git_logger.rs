@@ -1,21 +1,350 @@
 #![warn(clippy::all)]
+//! Reading git history as a sequence of simplified commits.
+//!
+//! `GitLog` opens the repository containing a directory, and `GitLog::iterator` walks its
+//! history from HEAD in topological order, yielding a `GitLogEntry` per commit with the files
+//! it changed and lines added and deleted. `GitLogConfig` is a builder for what is included -
+//! merges, how far back to go, bulk commit detection, commit categories and so on.
+//!
+//! Renames are reported as they happened - for the history of each file under its current name,
+//! use `git_file_history::GitFileHistory`.
+//!
+//! ```no_run
+//! use polyglot_code_scanner::git_logger::{GitLog, GitLogConfig};
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let log = GitLog::new(std::path::Path::new("."), GitLogConfig::default().since_years(Some(1)))?;
+//! for entry in log.iterator()? {
+//!     let entry = entry?;
+//!     println!("{} changed {} files", entry.id(), entry.file_changes().len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::cancellation::CancellationToken;
 use crate::git_file_future::{FileNameChange, GitFileFutureRegistry};
-use anyhow::Error;
+use crate::git_remotes::{preferred_canonical_url, repository_remotes};
+use anyhow::{Context, Error};
 use git2::Revwalk;
-use git2::{Commit, Delta, DiffDelta, ObjectType, Odb, Oid, Patch, Repository, Tree};
+use git2::{
+    Commit, Delta, DiffDelta, DiffFindOptions, DiffOptions, ObjectType, Odb, Oid, Patch,
+    Repository, Tree,
+};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Clone, Copy)]
+/// what to do with "bulk" commits that touch lots of files, such as reformatting the whole codebase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkCommitMode {
+    /// keep them, but mark them in details and activity - they are still excluded from coupling
+    Flag,
+    /// ignore them completely
+    Exclude,
+}
+
+/// how to spot bulk commits - a commit is bulk if it exceeds either limit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BulkCommitConfig {
+    /// commits changing more than this many files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+    /// commits changing more than this percentage of all files changed in the scanned history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_percent: Option<f64>,
+    pub mode: BulkCommitMode,
+}
+
+impl BulkCommitConfig {
+    pub fn is_bulk(&self, files_changed: usize, total_files: usize) -> bool {
+        let too_many_files = match self.max_files {
+            Some(max_files) => files_changed > max_files,
+            None => false,
+        };
+        let too_high_percent = match self.max_percent {
+            Some(max_percent) if total_files > 0 => {
+                (files_changed as f64 * 100.0 / total_files as f64) > max_percent
+            }
+            _ => false,
+        };
+        too_many_files || too_high_percent
+    }
+}
+
+/// a named category of commit, e.g. "fix", matched by a regex on the commit summary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitCategoryRule {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// name for commits which don't match any category
+pub static OTHER_COMMIT_CATEGORY: &str = "other";
+
+/// Jira-style ticket ids like "ABC-123"
+pub static DEFAULT_TICKET_PATTERN: &str = r"\b[A-Z][A-Z0-9]+-[0-9]+\b";
+
+/// unique ticket ids in a commit message, in the order they appear
+fn find_tickets(pattern: &Regex, message: &str) -> Vec<String> {
+    let mut tickets: Vec<String> = Vec::new();
+    for found in pattern.find_iter(message) {
+        if !tickets.iter().any(|ticket| ticket == found.as_str()) {
+            tickets.push(found.as_str().to_owned());
+        }
+    }
+    tickets
+}
+
+/// Classifies commits by their summary - the first matching rule wins, and anything unmatched is "other"
+#[derive(Debug, Clone)]
+pub struct CommitClassifier {
+    rules: Vec<CommitCategoryRule>,
+    regexes: Vec<Regex>,
+}
+
+impl CommitClassifier {
+    pub fn new(rules: Vec<CommitCategoryRule>) -> Result<Self, Error> {
+        let regexes = rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&rule.pattern)
+                    .with_context(|| format!("invalid pattern for commit category {}", rule.name))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(CommitClassifier { rules, regexes })
+    }
+
+    /// conventional commit prefixes, plus some common words - fixes must start with a fix word or
+    /// close an issue, as "bug" or "fix" anywhere in a summary matches too many other commits
+    pub fn default_rules() -> Vec<CommitCategoryRule> {
+        [
+            (
+                "fix",
+                r"(?i)^(fix|fixes|fixed|bugfix|hotfix)\b|\bfix(es|ed)? #[0-9]+",
+            ),
+            ("feature", r"(?i)^feat(ure)?(\(.*\))?!?:"),
+            ("refactor", r"(?i)^refactor(\(.*\))?!?:|\brefactor"),
+        ]
+        .iter()
+        .map(|(name, pattern)| CommitCategoryRule {
+            name: (*name).to_owned(),
+            pattern: (*pattern).to_owned(),
+        })
+        .collect()
+    }
+
+    pub fn rules(&self) -> &[CommitCategoryRule] {
+        &self.rules
+    }
+
+    pub fn classify(&self, summary: &str) -> &str {
+        self.regexes
+            .iter()
+            .position(|regex| regex.is_match(summary))
+            .map_or(OTHER_COMMIT_CATEGORY, |ix| self.rules[ix].name.as_str())
+    }
+}
+
+impl Serialize for CommitClassifier {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.rules.serialize(serializer)
+    }
+}
+
+/// how to spot renamed files - the defaults are libgit2's defaults, which can be slow on huge commits.
+/// See <https://libgit2.org/libgit2/#HEAD/type/git_diff_find_options>
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameConfig {
+    /// don't look for renames at all - fastest, but renamed files lose their earlier history
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    /// how similar (as a percentage) a deleted and an added file must be to count as a rename - libgit2 uses 50
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u16>,
+    /// maximum number of files to compare for renames in a single commit - libgit2 uses `diff.renameLimit`, or 200
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// also look for files copied from files modified in the same commit - slower
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub copies: bool,
+    /// give copied files the history of the file they were copied from, up to the copy, so they
+    /// don't look brand new - only useful with `copies`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub copy_history: bool,
+}
+
+impl RenameConfig {
+    pub fn is_default(&self) -> bool {
+        *self == RenameConfig::default()
+    }
+
+    /// options for `find_similar`, or None to use the libgit2 defaults
+    fn find_options(&self) -> Option<DiffFindOptions> {
+        if self.is_default() {
+            return None;
+        }
+        let mut options = DiffFindOptions::new();
+        options.renames(!self.disabled);
+        options.copies(self.copies);
+        if let Some(threshold) = self.threshold {
+            options.rename_threshold(threshold);
+            options.copy_threshold(threshold);
+        }
+        if let Some(limit) = self.limit {
+            options.rename_limit(limit);
+        }
+        Some(options)
+    }
+}
+
+/// where to find extra authors of a commit, beyond the author and committer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoAuthorRules {
+    /// trailer keys such as "Co-authored-by" or "Signed-off-by" - matched ignoring case
+    pub trailers: Vec<String>,
+    /// regexes matched against the commit summary, for pairing markers like "[AB/CD] fix thing".
+    /// Capture group 1 holds names or initials separated by "/", ",", "&" or "+"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub summary_patterns: Vec<String>,
+}
+
+impl Default for CoAuthorRules {
+    fn default() -> Self {
+        CoAuthorRules {
+            trailers: vec!["Co-authored-by".to_owned()],
+            summary_patterns: Vec::new(),
+        }
+    }
+}
+
+/// Finds co-authors in commit messages.
+/// Note that users are only matched by name and email, so an author who is also named by their initials
+/// in a pairing marker will be counted twice.
+#[derive(Debug, Clone)]
+pub struct CoAuthorParser {
+    rules: CoAuthorRules,
+    trailer_line: Option<Regex>,
+    summary_patterns: Vec<Regex>,
+}
+
+impl Default for CoAuthorParser {
+    fn default() -> Self {
+        CoAuthorParser::new(CoAuthorRules::default()).expect("invalid default co-author rules")
+    }
+}
+
+impl CoAuthorParser {
+    pub fn new(rules: CoAuthorRules) -> Result<Self, Error> {
+        let trailer_line = if rules.trailers.is_empty() {
+            None
+        } else {
+            let keys: Vec<String> = rules
+                .trailers
+                .iter()
+                .map(|trailer| regex::escape(trailer))
+                .collect();
+            Some(Regex::new(&format!(
+                r"(?mi)^\s*(?:{}):(.*)$",
+                keys.join("|")
+            ))?)
+        };
+        let summary_patterns = rules
+            .summary_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .with_context(|| format!("invalid co-author summary pattern {}", pattern))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(CoAuthorParser {
+            rules,
+            trailer_line,
+            summary_patterns,
+        })
+    }
+
+    pub fn rules(&self) -> &CoAuthorRules {
+        &self.rules
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.rules == CoAuthorRules::default()
+    }
+
+    pub fn find_coauthors(&self, message: &str) -> Vec<User> {
+        lazy_static! {
+            static ref CO_AUTH_ANGLE_BRACKETS: Regex = Regex::new(r"^(.*)<([^>]+)>\s*$").unwrap();
+            static ref NAME_SEPARATORS: Regex = Regex::new(r"[/,&+]").unwrap();
+        }
+
+        let mut co_authors: Vec<User> = Vec::new();
+        let summary = message.lines().next().unwrap_or("");
+        for pattern in &self.summary_patterns {
+            if let Some(names) = pattern.captures(summary).and_then(|c| c.get(1)) {
+                co_authors.extend(
+                    NAME_SEPARATORS
+                        .split(names.as_str())
+                        .filter_map(trim_string)
+                        .map(|name| User::new(Some(name), None)),
+                );
+            }
+        }
+        if let Some(trailer_line) = &self.trailer_line {
+            co_authors.extend(trailer_line.captures_iter(message).map(|capture_group| {
+                let co_author_text = &capture_group[1];
+                if let Some(co_author_bits) = CO_AUTH_ANGLE_BRACKETS.captures(co_author_text) {
+                    User::new(
+                        trim_string(co_author_bits.get(1).unwrap().as_str()),
+                        trim_string(co_author_bits.get(2).unwrap().as_str()),
+                    )
+                } else if co_author_text.contains('@') {
+                    // no angle brackets, but an @
+                    User::new(None, trim_string(co_author_text))
+                } else {
+                    User::new(trim_string(co_author_text), None)
+                }
+            }));
+        }
+        co_authors
+    }
+}
+
+impl Serialize for CoAuthorParser {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.rules.serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct GitLogConfig {
     /// include merge commits in file stats - usually excluded by `git log` - see https://stackoverflow.com/questions/37801342/using-git-log-to-display-files-changed-during-merge
     include_merges: bool,
     /// earliest commmit for filtering
     earliest_time: Option<u64>,
+    /// only process changes under the scanned directory, if it is a subdirectory of the repository
+    subtree_only: bool,
+    /// how to spot and handle bulk commits - these are handled when building file histories
+    bulk_commits: Option<BulkCommitConfig>,
+    /// if set, commits are classified into categories
+    commit_classifier: Option<CommitClassifier>,
+    /// if set, ticket ids matching this are extracted from commit messages
+    ticket_pattern: Option<Regex>,
+    /// how to find co-authors in commit messages
+    co_authors: CoAuthorParser,
+    /// how to detect renamed files
+    renames: RenameConfig,
+    /// stops loading the log early if cancelled
+    cancellation: CancellationToken,
 }
 
 impl GitLogConfig {
@@ -23,6 +352,13 @@ impl GitLogConfig {
         GitLogConfig {
             include_merges: false,
             earliest_time: None,
+            subtree_only: false,
+            bulk_commits: None,
+            commit_classifier: None,
+            ticket_pattern: None,
+            co_authors: CoAuthorParser::default(),
+            renames: RenameConfig::default(),
+            cancellation: CancellationToken::default(),
         }
     }
 
@@ -51,26 +387,95 @@ impl GitLogConfig {
             self.since(None)
         }
     }
+    /// only process history for the scanned subtree, and older history of files renamed into it -
+    /// faster for a small part of a big repo, as only those changes are diffed line by line
+    pub fn subtree_only(self, subtree_only: bool) -> GitLogConfig {
+        let mut config = self;
+        config.subtree_only = subtree_only;
+        config
+    }
+    pub fn is_subtree_only(&self) -> bool {
+        self.subtree_only
+    }
+    pub fn bulk_commits(self, bulk_commits: Option<BulkCommitConfig>) -> GitLogConfig {
+        let mut config = self;
+        config.bulk_commits = bulk_commits;
+        config
+    }
+    pub fn bulk_commit_config(&self) -> Option<BulkCommitConfig> {
+        self.bulk_commits
+    }
+    pub fn commit_classifier(self, commit_classifier: Option<CommitClassifier>) -> GitLogConfig {
+        let mut config = self;
+        config.commit_classifier = commit_classifier;
+        config
+    }
+    pub fn classifier(&self) -> Option<&CommitClassifier> {
+        self.commit_classifier.as_ref()
+    }
+    pub fn ticket_pattern(self, ticket_pattern: Option<Regex>) -> GitLogConfig {
+        let mut config = self;
+        config.ticket_pattern = ticket_pattern;
+        config
+    }
+    pub fn co_authors(self, co_authors: CoAuthorParser) -> GitLogConfig {
+        let mut config = self;
+        config.co_authors = co_authors;
+        config
+    }
+    pub fn co_author_parser(&self) -> &CoAuthorParser {
+        &self.co_authors
+    }
+    pub fn renames(self, renames: RenameConfig) -> GitLogConfig {
+        let mut config = self;
+        config.renames = renames;
+        config
+    }
+    pub fn rename_config(&self) -> RenameConfig {
+        self.renames
+    }
+    pub fn cancellation(self, cancellation: CancellationToken) -> GitLogConfig {
+        let mut config = self;
+        config.cancellation = cancellation;
+        config
+    }
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
 }
 
+/// a git repository, ready to read history from - see `iterator`
 pub struct GitLog {
     /// repo work dir - always canonical
     workdir: PathBuf,
     repo: Repository,
     config: GitLogConfig,
+    /// if set, only changes under this directory (relative to workdir) are processed - and older
+    /// changes to files renamed into it
+    subtree: Option<PathBuf>,
+    /// for sparse checkouts, the files that are checked out (relative to workdir) - only their
+    /// changes are processed
+    sparse_paths: Option<HashSet<PathBuf>>,
 }
 
+/// commits from HEAD in topological order - see `GitLog::iterator`
 pub struct GitLogIterator<'a> {
     git_log: &'a GitLog,
     odb: Odb<'a>,
     revwalk: Revwalk<'a>,
     // this is an RC as we need to use it after the iterator has been consumed
     git_file_future_registry: Rc<RefCell<GitFileFutureRegistry>>,
+    /// checked-out files for sparse checkouts - grows as renames are found, so older history
+    /// follows files renamed into the checkout
+    sparse_paths: Option<HashSet<PathBuf>>,
+    /// old names of files renamed into the subtree, if the log is limited to one - grows as
+    /// renames are found, so older history follows files renamed into the subtree
+    renamed_into_subtree: HashSet<PathBuf>,
 }
 
 /// simplified user info - based on `git2::Signature`
 /// everything is derived, seems to work OK as the structure is so simple
-#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord, Serialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct User {
     name: Option<String>,
     email: Option<String>,
@@ -84,6 +489,14 @@ impl User {
         }
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
     pub fn as_lower_case(&self) -> User {
         User {
             name: self.name.as_ref().map(|s| s.to_lowercase()),
@@ -102,8 +515,16 @@ pub struct GitLogEntry {
     commit_time: u64,
     author: User,
     author_time: u64,
+    /// the author's timezone, in minutes east of UTC
+    author_offset_minutes: i32,
     co_authors: Vec<User>,
     file_changes: Vec<FileChange>,
+    /// only if commits are being classified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    /// only if tickets are being extracted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tickets: Vec<String>,
 }
 
 /// the various kinds of git change we care about - a serializable subset of `git2::Delta`
@@ -140,14 +561,113 @@ impl GitLog {
             .canonicalize()?;
 
         debug!("work dir: {:?}", workdir);
+        let sparse_paths = checked_out_paths(&repo)?;
 
         Ok(GitLog {
             workdir,
             repo,
             config,
+            subtree: None,
+            sparse_paths,
         })
     }
 
+    pub fn config(&self) -> &GitLogConfig {
+        &self.config
+    }
+
+    pub fn is_shallow(&self) -> bool {
+        self.repo.is_shallow()
+    }
+
+    /// true for sparse checkouts - history is only processed for files that are checked out
+    pub fn is_sparse(&self) -> bool {
+        self.sparse_paths.is_some()
+    }
+
+    /// true for linked worktrees, added with `git worktree add` - they share the git directory
+    /// of the main working tree, but have their own working directory, index and HEAD
+    pub fn is_worktree(&self) -> bool {
+        self.repo.is_worktree()
+    }
+
+    /// the canonical working directories of every other worktree sharing this repository's
+    /// git directory - the main working tree, and any linked worktrees
+    pub fn other_worktrees(&self) -> Vec<PathBuf> {
+        let mut workdirs = Vec::new();
+        if self.repo.is_worktree() {
+            // the common dir is the main repository's git dir - it has no workdir if bare
+            match Repository::open(self.repo.commondir()) {
+                Ok(main) => workdirs.extend(main.workdir().map(Path::to_path_buf)),
+                Err(e) => warn!(
+                    "Can't open main repository for worktree {:?}: {}",
+                    self.workdir, e
+                ),
+            }
+        }
+        match self.repo.worktrees() {
+            Ok(names) => {
+                for name in names.iter().flatten() {
+                    match self.repo.find_worktree(name) {
+                        Ok(worktree) => workdirs.push(worktree.path().to_path_buf()),
+                        Err(e) => warn!("Can't open worktree {:?}: {}", name, e),
+                    }
+                }
+            }
+            Err(e) => warn!("Can't list worktrees for {:?}: {}", self.workdir, e),
+        }
+        workdirs
+            .into_iter()
+            // worktrees can be deleted without pruning, so may not exist
+            .filter_map(|workdir| workdir.canonicalize().ok())
+            .filter(|workdir| workdir != &self.workdir)
+            .collect()
+    }
+
+    /// canonical url of the origin remote, or the first remote - None for local-only repositories
+    pub fn canonical_remote_url(&self) -> Option<String> {
+        preferred_canonical_url(&repository_remotes(&self.repo))
+    }
+
+    /// only process changes under this directory - ignored if the directory is not inside the work dir
+    pub fn limit_to(&mut self, subtree: &Path) -> Result<(), Error> {
+        let canonical_subtree = subtree.canonicalize()?;
+        if let Ok(relative) = canonical_subtree.strip_prefix(&self.workdir) {
+            if relative.as_os_str().is_empty() {
+                self.subtree = None;
+            } else {
+                info!("Limiting git history to {:?}", relative);
+                self.subtree = Some(relative.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    /// files changed since a commit, branch or tag, including uncommitted and untracked changes -
+    /// absolute paths, not including deleted files
+    pub fn changed_files_since(&self, reference: &str) -> Result<Vec<PathBuf>, Error> {
+        let tree = self
+            .repo
+            .revparse_single(reference)
+            .with_context(|| format!("finding git reference {:?}", reference))?
+            .peel_to_tree()?;
+        let mut options = DiffOptions::new();
+        options.include_untracked(true).recurse_untracked_dirs(true);
+        let diff = self
+            .repo
+            .diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))?;
+        let mut files: Vec<PathBuf> = diff
+            .deltas()
+            .filter(|delta| delta.status() != Delta::Deleted)
+            .filter_map(|delta| delta.new_file().path().map(|path| self.workdir.join(path)))
+            .collect();
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// every commit in the configured history, newest first - commits that fail to parse are
+    /// returned as errors, so callers can choose to skip them
     pub fn iterator(&self) -> Result<GitLogIterator<'_>, Error> {
         let odb = self.repo.odb()?;
         let mut revwalk = self.repo.revwalk()?;
@@ -158,10 +678,57 @@ impl GitLog {
             odb,
             revwalk,
             git_file_future_registry: Rc::new(RefCell::new(GitFileFutureRegistry::new())),
+            sparse_paths: self.sparse_paths.clone(),
+            renamed_into_subtree: HashSet::new(),
         })
     }
 }
 
+/// bit in an index entry's extended flags for files left out of a sparse checkout
+const SKIP_WORKTREE: u16 = 1 << 14;
+
+/// true if the repository is a sparse checkout
+pub(crate) fn is_sparse_checkout(repo: &Repository) -> bool {
+    repo.config()
+        .and_then(|config| config.get_bool("core.sparseCheckout"))
+        .unwrap_or(false)
+}
+
+/// for sparse checkouts, the files in the index that are checked out - None for full checkouts
+fn checked_out_paths(repo: &Repository) -> Result<Option<HashSet<PathBuf>>, Error> {
+    if !is_sparse_checkout(repo) {
+        return Ok(None);
+    }
+    let index = repo.index()?;
+    let paths: HashSet<PathBuf> = index
+        .iter()
+        .filter(|entry| entry.flags_extended & SKIP_WORKTREE == 0)
+        .map(|entry| PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned()))
+        .collect();
+    info!(
+        "Sparse checkout - only processing history for {} checked out files",
+        paths.len()
+    );
+    Ok(Some(paths))
+}
+
+/// drops changes to files that aren't checked out - the old names of renamed files are added
+/// to the paths, as older commits changed them under those names
+fn retain_checked_out(entry: &mut GitLogEntry, paths: &mut HashSet<PathBuf>) {
+    entry.file_changes.retain(|change| {
+        paths.contains(&change.file)
+            || change
+                .old_file
+                .as_ref()
+                .map_or(false, |old_file| paths.contains(old_file))
+    });
+    for change in &entry.file_changes {
+        if let Some(old_file) = &change.old_file {
+            paths.insert(old_file.clone());
+        }
+    }
+}
+
 impl<'a> Iterator for GitLogIterator<'a> {
     type Item = Result<GitLogEntry, Error>;
 
@@ -179,7 +746,7 @@ impl<'a> Iterator for GitLogIterator<'a> {
             };
             let c = self.summarise_commit(oid);
             match c {
-                Ok(Some(c)) => {
+                Ok(Some(mut c)) => {
                     let commit_in_range = self
                         .git_log
                         .config
@@ -187,6 +754,17 @@ impl<'a> Iterator for GitLogIterator<'a> {
                         .map_or(true, |earliest| c.commit_time >= earliest);
 
                     if commit_in_range {
+                        if let Some(paths) = &mut self.sparse_paths {
+                            retain_checked_out(&mut c, paths);
+                        }
+                        if self.git_log.subtree.is_some() {
+                            // changes were already limited to the subtree
+                            self.renamed_into_subtree.extend(
+                                c.file_changes
+                                    .iter()
+                                    .filter_map(|change| change.old_file.clone()),
+                            );
+                        }
                         self.register_file_futures(&c);
                         return Some(Ok(c));
                     } else {
@@ -203,10 +781,18 @@ impl<'a> Iterator for GitLogIterator<'a> {
 }
 
 impl<'a> GitLogIterator<'a> {
-    pub fn git_file_future_registry(&self) -> Rc<RefCell<GitFileFutureRegistry>> {
+    pub(crate) fn git_file_future_registry(&self) -> Rc<RefCell<GitFileFutureRegistry>> {
         self.git_file_future_registry.clone()
     }
 
+    /// true if changes to the path should be processed - always, unless limited to a subtree
+    fn is_followed(&self, path: &Path) -> bool {
+        match &self.git_log.subtree {
+            Some(subtree) => path.starts_with(subtree) || self.renamed_into_subtree.contains(path),
+            None => true,
+        }
+    }
+
     /// registers renames and deletes
     fn register_file_futures(&mut self, entry: &GitLogEntry) {
         // TODO: probably should be using Oid not String globally, then this would be simpler:
@@ -261,28 +847,42 @@ impl<'a> GitLogIterator<'a> {
                     );
                 }
                 let co_authors = if let Some(message) = commit.message() {
-                    find_coauthors(message)
+                    self.git_log.config.co_authors.find_coauthors(message)
                 } else {
                     Vec::new()
                 };
+                let tickets = match (&self.git_log.config.ticket_pattern, commit.message()) {
+                    (Some(pattern), Some(message)) => find_tickets(pattern, message),
+                    _ => Vec::new(),
+                };
 
                 let commit_tree = commit.tree()?;
                 let file_changes = commit_file_changes(
                     &self.git_log.repo,
                     &commit,
                     &commit_tree,
-                    self.git_log.config,
+                    &self.git_log.config,
+                    &|path: &Path| self.is_followed(path),
                 );
+                let summary = commit.summary().unwrap_or("[no message]").to_string();
+                let category = self
+                    .git_log
+                    .config
+                    .classifier()
+                    .map(|classifier| classifier.classify(&summary).to_owned());
                 Ok(Some(GitLogEntry {
                     id: oid.to_string(),
-                    summary: commit.summary().unwrap_or("[no message]").to_string(),
+                    summary,
                     parents: commit.parent_ids().map(|p| p.to_string()).collect(),
                     committer: signature_to_user(&committer),
                     commit_time,
                     author: signature_to_user(&author),
                     author_time,
+                    author_offset_minutes: author.when().offset_minutes(),
                     co_authors,
                     file_changes,
+                    category,
+                    tickets,
                 }))
             }
             _ => {
@@ -309,41 +909,26 @@ fn trim_string(s: &str) -> Option<&str> {
     }
 }
 
-fn find_coauthors(message: &str) -> Vec<User> {
-    lazy_static! {
-        static ref CO_AUTH_LINE: Regex = Regex::new(r"(?m)^\s*Co-authored-by:(.*)$").unwrap();
-        static ref CO_AUTH_ANGLE_BRACKETS: Regex = Regex::new(r"^(.*)<([^>]+)>\s*$").unwrap();
-    }
-
-    CO_AUTH_LINE
-        .captures_iter(message)
-        .map(|capture_group| {
-            let co_author_text = &capture_group[1];
-            if let Some(co_author_bits) = CO_AUTH_ANGLE_BRACKETS.captures(co_author_text) {
-                User::new(
-                    trim_string(co_author_bits.get(1).unwrap().as_str()),
-                    trim_string(co_author_bits.get(2).unwrap().as_str()),
-                )
-            } else if co_author_text.contains('@') {
-                // no angle brackets, but an @
-                User::new(None, trim_string(co_author_text))
-            } else {
-                User::new(trim_string(co_author_text), None)
-            }
-        })
-        .collect()
-}
-
 fn commit_file_changes(
     repo: &Repository,
     commit: &Commit<'_>,
     commit_tree: &Tree<'_>,
-    config: GitLogConfig,
+    config: &GitLogConfig,
+    is_followed: &dyn Fn(&Path) -> bool,
 ) -> Vec<FileChange> {
     if commit.parent_count() == 0 {
         info!("Commit {} has no parent", commit.id());
 
-        scan_diffs(repo, commit_tree, None, commit, None).expect("Can't scan for diffs")
+        scan_diffs(
+            repo,
+            commit_tree,
+            None,
+            commit,
+            None,
+            is_followed,
+            &config.renames,
+        )
+        .expect("Can't scan for diffs")
     } else if commit.parent_count() > 1 && !config.include_merges {
         debug!(
             "Not showing file changes for merge commit {:?}",
@@ -356,8 +941,16 @@ fn commit_file_changes(
             .flat_map(|parent| {
                 debug!("Getting changes for parent {:?}:", parent);
                 let parent_tree = parent.tree().expect("can't get parent tree");
-                scan_diffs(repo, commit_tree, Some(&parent_tree), commit, Some(&parent))
-                    .expect("Can't scan for diffs")
+                scan_diffs(
+                    repo,
+                    commit_tree,
+                    Some(&parent_tree),
+                    commit,
+                    Some(&parent),
+                    is_followed,
+                    &config.renames,
+                )
+                .expect("Can't scan for diffs")
             })
             .collect()
     }
@@ -369,13 +962,27 @@ fn scan_diffs(
     parent_tree: Option<&Tree<'_>>,
     commit: &Commit<'_>,
     parent: Option<&Commit<'_>>,
+    is_followed: &dyn Fn(&Path) -> bool,
+    renames: &RenameConfig,
 ) -> Result<Vec<FileChange>, Error> {
-    let mut diff = repo.diff_tree_to_tree(parent_tree, Some(commit_tree), None)?;
+    let mut diff_options = DiffOptions::new();
+    // otherwise changing a file to a symlink, or back, is a delete and an add, losing history
+    diff_options.include_typechange(true);
+    // no pathspec for subtrees - renames must be found across the whole tree, or files renamed
+    // into the subtree look like new files
+    let mut diff =
+        repo.diff_tree_to_tree(parent_tree, Some(commit_tree), Some(&mut diff_options))?;
     // Identify renames, None means default settings - see https://libgit2.org/libgit2/#HEAD/group/diff/git_diff_find_similar
-    diff.find_similar(None)?;
+    diff.find_similar(renames.find_options().as_mut())?;
     let file_changes = diff
         .deltas()
         .enumerate()
+        .filter(|(_, delta)| {
+            [delta.new_file().path(), delta.old_file().path()]
+                .into_iter()
+                .flatten()
+                .any(is_followed)
+        })
         .filter_map(|(delta_index, delta)| {
             // can we / should we get bytes for binary changes?  Adds show as 0 lines.
             let patch =
@@ -430,7 +1037,8 @@ fn summarise_delta(
                 lines_deleted,
             })
         }
-        Delta::Modified => {
+        // a file became a symlink or submodule, or back - for history it is just a change
+        Delta::Modified | Delta::Typechange => {
             let name = delta.new_file().path().unwrap();
             Some(FileChange {
                 file: name.to_path_buf(),
@@ -451,8 +1059,25 @@ fn summarise_delta(
                 lines_deleted,
             })
         }
-        _ => {
-            error!("Not able to handle delta of status {:?}", delta.status());
+        Delta::Conflicted => {
+            // trees can't normally hold conflicts - counted as a change, so it isn't lost
+            let name = delta.new_file().path().unwrap();
+            warn!("Treating conflicted file {:?} as modified", name);
+            Some(FileChange {
+                file: name.to_path_buf(),
+                old_file: None,
+                change: CommitChange::Modify,
+                lines_added,
+                lines_deleted,
+            })
+        }
+        // not changes - only seen with diff options this scanner doesn't use
+        Delta::Unmodified | Delta::Ignored | Delta::Untracked | Delta::Unreadable => {
+            debug!(
+                "Ignoring delta of status {:?} for {:?}",
+                delta.status(),
+                delta.new_file().path()
+            );
             None
         }
     }
@@ -466,6 +1091,42 @@ mod test {
     use tempfile::tempdir;
     use test_shared::{assert_eq_json_file, assert_eq_json_value, unzip_test_sample};
 
+    #[test]
+    fn type_changes_are_modifications() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let repo = Repository::init(gitdir.path())?;
+        let signature = git2::Signature::new("Jo", "jo@smith.com", &git2::Time::new(1000, 0))?;
+        let blob = repo.blob(b"contents")?;
+        let mut parents = Vec::new();
+        // a file, then a symlink with the same name
+        for mode in [0o100_644, 0o120_000] {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("a.txt", blob, mode)?;
+            let tree = repo.find_tree(builder.write()?)?;
+            let parent_refs: Vec<&git2::Commit<'_>> = parents.iter().collect();
+            let commit = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "change",
+                &tree,
+                &parent_refs,
+            )?;
+            parents = vec![repo.find_commit(commit)?];
+        }
+
+        let git_log = GitLog::new(gitdir.path(), GitLogConfig::default())?;
+        let changes: Vec<CommitChange> = git_log
+            .iterator()?
+            .filter_map(Result::ok)
+            .flat_map(|entry| entry.file_changes)
+            .map(|change| change.change)
+            .collect();
+        assert_eq!(changes, vec![CommitChange::Modify, CommitChange::Add]);
+
+        Ok(())
+    }
+
     #[test]
     fn users_can_be_lowercased() {
         assert_eq!(
@@ -483,9 +1144,71 @@ mod test {
         assert_eq!(User::new(None, None).as_lower_case(), User::new(None, None));
     }
 
+    #[test]
+    fn bulk_commits_exceed_either_limit() {
+        let config = BulkCommitConfig {
+            max_files: Some(10),
+            max_percent: Some(50.0),
+            mode: BulkCommitMode::Flag,
+        };
+        assert!(!config.is_bulk(5, 100));
+        assert!(config.is_bulk(11, 100));
+        assert!(config.is_bulk(6, 10));
+        assert!(!config.is_bulk(5, 10));
+        let files_only = BulkCommitConfig {
+            max_percent: None,
+            ..config
+        };
+        assert!(!files_only.is_bulk(6, 10));
+    }
+
+    #[test]
+    fn commits_are_classified_by_the_first_matching_rule() {
+        let classifier = CommitClassifier::new(CommitClassifier::default_rules()).unwrap();
+        assert_eq!(
+            classifier.classify("fix(parser): handle empty files"),
+            "fix"
+        );
+        assert_eq!(classifier.classify("Fixes crash on startup"), "fix");
+        assert_eq!(classifier.classify("Handle empty files, fixes #12"), "fix");
+        assert_eq!(classifier.classify("Add bug report template"), "other");
+        assert_eq!(classifier.classify("Add a prefix fix-up script"), "other");
+        assert_eq!(classifier.classify("feat: add decay score"), "feature");
+        assert_eq!(classifier.classify("refactor: tidy walker"), "refactor");
+        assert_eq!(classifier.classify("Prefix all the things"), "other");
+
+        let tickets = CommitClassifier::new(vec![CommitCategoryRule {
+            name: "ticket".to_owned(),
+            pattern: r"^[A-Z]+-\d+".to_owned(),
+        }])
+        .unwrap();
+        assert_eq!(tickets.classify("ABC-123 fix the thing"), "ticket");
+        assert!(CommitClassifier::new(vec![CommitCategoryRule {
+            name: "bad".to_owned(),
+            pattern: "(".to_owned(),
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn tickets_are_found_once_each() {
+        let pattern = Regex::new(DEFAULT_TICKET_PATTERN).unwrap();
+        assert_eq!(
+            find_tickets(
+                &pattern,
+                "ABC-123 fix the thing\n\nsee also X2-7 and ABC-123, but not abc-1 or UTF-8a"
+            ),
+            vec!["ABC-123".to_owned(), "X2-7".to_owned()]
+        );
+        assert!(find_tickets(&pattern, "no tickets here").is_empty());
+    }
+
     #[test]
     fn authorless_message_has_no_coauthors() {
-        assert_eq!(find_coauthors("do be do be do"), Vec::<User>::new());
+        assert_eq!(
+            CoAuthorParser::default().find_coauthors("do be do be do"),
+            Vec::<User>::new()
+        );
     }
 
     #[test]
@@ -517,7 +1240,38 @@ mod test {
             User::new(None, Some("if there's an @ it's email@thing.com")),
         ];
 
-        assert_eq!(find_coauthors(message), expected);
+        assert_eq!(CoAuthorParser::default().find_coauthors(message), expected);
+    }
+
+    #[test]
+    fn can_get_coauthors_from_other_trailers_and_summaries() {
+        let parser = CoAuthorParser::new(CoAuthorRules {
+            trailers: vec!["Co-authored-by".to_owned(), "Signed-off-by".to_owned()],
+            summary_patterns: vec![r"^\[([^\]]+)\]".to_owned()],
+        })
+        .unwrap();
+        let message = "[AB/CD + EF] pairing on things
+
+        co-authored-by: Lower Case <lower@case.com>
+        Signed-off-by: Signer <signer@thing.com>
+        Reviewed-by: Reviewer <reviewer@thing.com>
+        ";
+
+        let expected = vec![
+            User::new(Some("AB"), None),
+            User::new(Some("CD"), None),
+            User::new(Some("EF"), None),
+            User::new(Some("Lower Case"), Some("lower@case.com")),
+            User::new(Some("Signer"), Some("signer@thing.com")),
+        ];
+
+        assert_eq!(parser.find_coauthors(message), expected);
+        assert!(!parser.is_default());
+        assert!(CoAuthorParser::new(CoAuthorRules {
+            trailers: Vec::new(),
+            summary_patterns: vec!["(".to_owned()],
+        })
+        .is_err());
     }
 
     #[test]
@@ -555,6 +1309,27 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn changed_files_include_uncommitted_changes() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        std::fs::write(git_root.join("simple/new.txt"), "new\n")?;
+
+        let git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        let workdir = git_log.workdir().to_owned();
+
+        assert_eq!(
+            git_log.changed_files_since("HEAD~1")?,
+            vec![
+                workdir.join("simple/child/a_renamed.txt"),
+                workdir.join("simple/new.txt")
+            ]
+        );
+        assert!(git_log.changed_files_since("no-such-branch").is_err());
+
+        Ok(())
+    }
+
     #[allow(clippy::unreadable_literal)]
     #[test]
     fn git_log_can_limit_to_recent_history() -> Result<(), Error> {
@@ -583,6 +1358,136 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn git_log_can_be_limited_to_a_subtree() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+        let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        git_log.limit_to(&git_root.join("simple/child"))?;
+
+        let entries: Vec<_> = git_log.iterator()?.filter_map(Result::ok).collect();
+        // all commits are still logged, only the changes are filtered
+        assert_eq!(entries.len(), 6);
+
+        let changed_files: Vec<PathBuf> = entries
+            .iter()
+            .flat_map(|entry| entry.file_changes.iter().map(|c| c.file.clone()))
+            .collect();
+        assert_eq!(
+            changed_files,
+            vec![
+                PathBuf::from("simple/child/a_renamed.txt"),
+                PathBuf::from("simple/child/a.txt"),
+                PathBuf::from("simple/child/a.txt"),
+                PathBuf::from("simple/child/a.txt"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn subtrees_keep_the_history_of_files_renamed_into_them() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let repo = Repository::init(gitdir.path())?;
+        let signature = git2::Signature::new("Jo", "jo@smith.com", &git2::Time::new(1000, 0))?;
+        let contents: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let blob = repo.blob(contents.as_bytes())?;
+        let other_blob = repo.blob(b"other")?;
+        let dir_with = |file: &str, blob: Oid| -> Result<Oid, Error> {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert(file, blob, 0o100_644)?;
+            Ok(builder.write()?)
+        };
+        // a.txt is added at the top level, then moved into "sub[1]" - "sub1" would match the
+        // directory name as a glob
+        let first = {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("a.txt", blob, 0o100_644)?;
+            builder.insert("sub1", dir_with("x.txt", other_blob)?, 0o040_000)?;
+            builder.write()?
+        };
+        let second = {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("sub[1]", dir_with("a.txt", blob)?, 0o040_000)?;
+            builder.insert("sub1", dir_with("x.txt", other_blob)?, 0o040_000)?;
+            builder.write()?
+        };
+        let mut parents = Vec::new();
+        for tree in [first, second] {
+            let tree = repo.find_tree(tree)?;
+            let parent_refs: Vec<&git2::Commit<'_>> = parents.iter().collect();
+            let commit = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "change",
+                &tree,
+                &parent_refs,
+            )?;
+            parents = vec![repo.find_commit(commit)?];
+        }
+        std::fs::create_dir(gitdir.path().join("sub[1]"))?;
+
+        let mut git_log = GitLog::new(gitdir.path(), GitLogConfig::default())?;
+        git_log.limit_to(&gitdir.path().join("sub[1]"))?;
+        let changes: Vec<(PathBuf, CommitChange)> = git_log
+            .iterator()?
+            .filter_map(Result::ok)
+            .flat_map(|entry| entry.file_changes)
+            .map(|change| (change.file, change.change))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (PathBuf::from("sub[1]/a.txt"), CommitChange::Rename),
+                (PathBuf::from("a.txt"), CommitChange::Add),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_checkouts_only_log_checked_out_files() -> Result<(), Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+        let repo = Repository::open(&git_root)?;
+        repo.config()?.set_bool("core.sparseCheckout", true)?;
+        let mut index = repo.index()?;
+        let entries: Vec<_> = index.iter().collect();
+        for mut entry in entries {
+            if !entry.path.starts_with(b"simple/child/") {
+                entry.flags_extended |= SKIP_WORKTREE;
+                index.add(&entry)?;
+            }
+        }
+        index.write()?;
+
+        let git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+        assert!(git_log.is_sparse());
+
+        let changed_files: Vec<PathBuf> = git_log
+            .iterator()?
+            .filter_map(Result::ok)
+            .flat_map(|entry| entry.file_changes)
+            .map(|change| change.file)
+            .collect();
+        // the rename is followed back to the original name
+        assert_eq!(
+            changed_files,
+            vec![
+                PathBuf::from("simple/child/a_renamed.txt"),
+                PathBuf::from("simple/child/a.txt"),
+                PathBuf::from("simple/child/a.txt"),
+                PathBuf::from("simple/child/a.txt"),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn git_log_tracks_renames() -> Result<(), Error> {
         let gitdir = tempdir()?;
@@ -650,6 +1555,51 @@ mod test {
 
         Ok(())
     }
+
+    fn rename_changes(renames: RenameConfig) -> Result<Vec<Vec<CommitChange>>, Error> {
+        let gitdir = tempdir()?;
+        let git_root = unzip_test_sample("rename_simple", gitdir.path())?;
+
+        let git_log = GitLog::new(&git_root, GitLogConfig::default().renames(renames))?;
+        let mut entries: Vec<_> = git_log.iterator()?.filter_map(Result::ok).collect();
+        entries.sort_by(|a, b| a.author_time.cmp(&b.author_time));
+
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let mut changes: Vec<CommitChange> = entry
+                    .file_changes
+                    .iter()
+                    .map(|change| change.change)
+                    .collect();
+                changes.sort_by_key(|change| format!("{:?}", change));
+                changes
+            })
+            .collect())
+    }
+
+    #[test]
+    fn rename_detection_can_be_configured() -> Result<(), Error> {
+        use CommitChange::{Add, Delete, Rename};
+
+        assert_eq!(
+            rename_changes(RenameConfig {
+                disabled: true,
+                ..RenameConfig::default()
+            })?,
+            vec![vec![Add], vec![Add], vec![Add, Delete], vec![Add, Delete]]
+        );
+        // the last commit changes the file as well as moving it
+        assert_eq!(
+            rename_changes(RenameConfig {
+                threshold: Some(100),
+                ..RenameConfig::default()
+            })?,
+            vec![vec![Add], vec![Add], vec![Rename], vec![Add, Delete]]
+        );
+
+        Ok(())
+    }
 }
 /*
 <Array([
@@ -0,0 +1,170 @@
+#![warn(clippy::all)]
+//! Git remote URL handling - normalising the many ways of writing the same remote
+//! (ssh, https, scp-like syntax) into one canonical form, and building links to hosted sources.
+
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+
+/// A single named remote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitRemote {
+    pub name: String,
+    pub url: String,
+    /// canonical `host/path` form, if the url was a network url we could parse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_url: Option<String>,
+}
+
+/// all remotes for a repository, sorted by name
+pub fn repository_remotes(repository: &Repository) -> Vec<GitRemote> {
+    let names = match repository.remotes() {
+        Ok(names) => names,
+        Err(e) => {
+            warn!("Error fetching remotes: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut remotes: Vec<GitRemote> = names
+        .iter()
+        .flatten()
+        .filter_map(|name| match repository.find_remote(name) {
+            Ok(remote) => remote.url().map(|url| GitRemote {
+                name: name.to_owned(),
+                url: url.to_owned(),
+                canonical_url: canonical_url(url),
+            }),
+            Err(e) => {
+                warn!("Error fetching remote {}: {}", name, e);
+                None
+            }
+        })
+        .collect();
+    remotes.sort_by(|a, b| a.name.cmp(&b.name));
+    remotes
+}
+
+/// the canonical url of the origin remote, or the first remote if there is no origin
+pub fn preferred_canonical_url(remotes: &[GitRemote]) -> Option<String> {
+    remotes
+        .iter()
+        .find(|remote| remote.name == "origin")
+        .or_else(|| remotes.first())
+        .and_then(|remote| remote.canonical_url.clone())
+}
+
+/// Converts a remote url into a canonical `host/path` form, so
+/// `git@github.com:foo/bar.git`, `ssh://git@github.com/foo/bar` and `https://github.com/foo/bar.git`
+/// are all `github.com/foo/bar`
+/// Returns None for local paths and things we can't parse.
+pub fn canonical_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    let (scheme, rest) = match url.find("://") {
+        Some(ix) => (Some(&url[..ix]), &url[ix + 3..]),
+        None => (None, url),
+    };
+    let (authority, path, is_ssh) = match scheme {
+        Some("file") => return None,
+        Some(scheme) => {
+            let ix = rest.find('/')?;
+            let is_ssh = scheme.starts_with("ssh") || scheme == "git";
+            (&rest[..ix], &rest[ix + 1..], is_ssh)
+        }
+        None => {
+            // scp-like syntax - "user@host:path"
+            let ix = rest.find(':')?;
+            if rest[..ix].contains('/') {
+                return None;
+            }
+            (&rest[..ix], &rest[ix + 1..], true)
+        }
+    };
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    // ssh ports are irrelevant to identity, http ports might not be
+    let host = if is_ssh {
+        host.split(':').next().unwrap_or(host)
+    } else {
+        host
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    // a single letter host is almost certainly a windows drive letter
+    if host.len() < 2 || path.is_empty() {
+        return None;
+    }
+    Some(format!("{}/{}", host.to_lowercase(), path))
+}
+
+/// A template for browsing files on known hosting sites - replace `{path}` with the
+/// file path relative to the repository root
+pub fn browse_url_template(canonical_url: &str, head: &str) -> Option<String> {
+    let host = canonical_url.split('/').next()?;
+    let blob_path = if host == "github.com" || host.starts_with("github.") {
+        "blob"
+    } else if host.contains("gitlab") {
+        "-/blob"
+    } else if host == "bitbucket.org" {
+        "src"
+    } else {
+        return None;
+    };
+    Some(format!(
+        "https://{}/{}/{}/{{path}}",
+        canonical_url, blob_path, head
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn equivalent_urls_have_the_same_canonical_form() {
+        let expected = Some("github.com/kornysietsma/polyglot-code-scanner".to_owned());
+        assert_eq!(
+            canonical_url("git@github.com:kornysietsma/polyglot-code-scanner.git"),
+            expected
+        );
+        assert_eq!(
+            canonical_url("ssh://git@github.com:22/kornysietsma/polyglot-code-scanner"),
+            expected
+        );
+        assert_eq!(
+            canonical_url("https://github.com/kornysietsma/polyglot-code-scanner.git"),
+            expected
+        );
+        assert_eq!(
+            canonical_url("https://someone@GitHub.com/kornysietsma/polyglot-code-scanner/"),
+            expected
+        );
+    }
+
+    #[test]
+    fn http_ports_are_kept() {
+        assert_eq!(
+            canonical_url("http://git.internal:8080/team/repo.git"),
+            Some("git.internal:8080/team/repo".to_owned())
+        );
+    }
+
+    #[test]
+    fn local_paths_have_no_canonical_form() {
+        assert_eq!(canonical_url("/home/git/repo.git"), None);
+        assert_eq!(canonical_url("../repo"), None);
+        assert_eq!(canonical_url("file:///home/git/repo.git"), None);
+        assert_eq!(canonical_url("C:\\git\\repo"), None);
+    }
+
+    #[test]
+    fn browse_templates_for_known_hosts() {
+        assert_eq!(
+            browse_url_template("github.com/foo/bar", "abc123"),
+            Some("https://github.com/foo/bar/blob/abc123/{path}".to_owned())
+        );
+        assert_eq!(
+            browse_url_template("gitlab.example.com/foo/bar", "abc123"),
+            Some("https://gitlab.example.com/foo/bar/-/blob/abc123/{path}".to_owned())
+        );
+        assert_eq!(browse_url_template("git.internal/foo/bar", "abc123"), None);
+    }
+}
@@ -1,7 +1,7 @@
 #![warn(clippy::all)]
 use crate::git_logger::User;
 use serde::ser::SerializeSeq;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Default)]
@@ -25,6 +25,10 @@ impl GitUserDictionary {
             }
         }
     }
+    /// all users, in id order
+    pub fn users(&self) -> &[User] {
+        &self.users
+    }
     #[cfg(test)]
     pub fn user_by_id(&self, user_id: usize) -> User {
         self.users
@@ -64,6 +68,38 @@ impl Serialize for GitUserDictionary {
     }
 }
 
+#[derive(Deserialize)]
+struct OwnedUserKey {
+    id: usize,
+    user: User,
+}
+
+impl<'de> Deserialize<'de> for GitUserDictionary {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let keys = Vec::<OwnedUserKey>::deserialize(deserializer)?;
+        let mut dictionary = GitUserDictionary::default();
+        for OwnedUserKey { id, user } in keys {
+            if id != dictionary.next_id {
+                return Err(serde::de::Error::custom(format!(
+                    "User id {} out of sequence, expected {}",
+                    id, dictionary.next_id
+                )));
+            }
+            // don't use `register` - saved ids must be kept even if users differ only by case
+            dictionary
+                .lower_users
+                .entry(user.as_lower_case())
+                .or_insert(id);
+            dictionary.users.push(user);
+            dictionary.next_id += 1;
+        }
+        Ok(dictionary)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -102,4 +138,30 @@ mod test {
         assert_eq!(user1, 0);
         assert_eq!(dict.user_by_id(0), jane);
     }
+
+    #[test]
+    fn dictionary_can_be_deserialized() {
+        let mut dict = GitUserDictionary::default();
+        let jane = User::new(Some("Jane"), Some("JaneDoe@gmail.com"));
+        let bob = User::new(Some("Bob"), None);
+        dict.register(&jane);
+        dict.register(&bob);
+
+        let json = serde_json::to_string(&dict).unwrap();
+        let mut loaded: GitUserDictionary = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.user_count(), 2);
+        assert_eq!(loaded.user_by_id(1), bob);
+        assert_eq!(
+            loaded.register(&User::new(Some("jane"), Some("janedoe@gmail.com"))),
+            0
+        );
+        assert_eq!(loaded.register(&User::new(Some("Alice"), None)), 2);
+    }
+
+    #[test]
+    fn out_of_sequence_user_ids_are_rejected() {
+        let json = r#"[{"id": 1, "user": {"name": "Jane", "email": null}}]"#;
+        assert!(serde_json::from_str::<GitUserDictionary>(json).is_err());
+    }
 }
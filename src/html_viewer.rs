@@ -0,0 +1,68 @@
+#![warn(clippy::all)]
+//! A single self-contained HTML file, with the data file embedded and a minimal treemap viewer,
+//! so results can be emailed or attached without deploying the full explorer.
+//!
+//! The viewer is plain JavaScript with no external scripts or styles, so it works offline. It
+//! sizes files by lines of code, and colours them by one of a few indicators - anything deeper
+//! needs the real explorer, which can load the same data extracted from the page.
+
+use anyhow::Error;
+use std::io::{self, BufWriter, Write};
+
+use crate::field_selection::FieldSelection;
+use crate::format_version::FormatVersion;
+use crate::polyglot_data::PolyglotData;
+use crate::report::html_escape;
+
+static VIEWER_TEMPLATE: &str = include_str!("html_viewer.html");
+
+/// JSON can only contain `<` inside strings, where `\u003c` means the same thing - escaping it
+/// stops names like `</script>` ending the embedded data early
+fn escape_script_json(json: &str) -> String {
+    json.replace('<', "\\u003c")
+}
+
+/// writes the data embedded in a standalone viewer page
+pub fn write_html_viewer<W: io::Write>(
+    data: &PolyglotData,
+    out: W,
+    version: FormatVersion,
+    fields: Option<&FieldSelection>,
+) -> Result<(), Error> {
+    let title = html_escape(data.name());
+    let mut json = Vec::new();
+    // embedded in the page, so can't be streamed anyway
+    data.write_json_copy(&mut json, version, fields, false)?;
+    let json = escape_script_json(&String::from_utf8(json)?);
+    let (before_data, after_data) = VIEWER_TEMPLATE
+        .split_once("{{DATA}}")
+        .expect("viewer template has a data placeholder");
+    let mut out = BufWriter::new(out);
+    out.write_all(before_data.replace("{{TITLE}}", &title).as_bytes())?;
+    out.write_all(json.as_bytes())?;
+    out.write_all(after_data.replace("{{TITLE}}", &title).as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flare::FlareTreeNode;
+    use crate::FeatureFlags;
+
+    #[test]
+    fn data_is_embedded_safely() {
+        let mut root = FlareTreeNode::dir("<root>");
+        root.append_child(FlareTreeNode::file("</script><b>.rs"));
+        let data = PolyglotData::new("a & b", None, root, FeatureFlags::default());
+        let mut out = Vec::new();
+        write_html_viewer(&data, &mut out, FormatVersion::Latest, None).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains("<title>a &amp; b</title>"));
+        assert!(html.contains("\"\\u003c/script>\\u003cb>.rs\""));
+        assert!(!html.contains("{{DATA}}"));
+        assert_eq!(html.matches("</script>").count(), 2);
+    }
+}
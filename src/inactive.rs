@@ -0,0 +1,207 @@
+#![warn(clippy::all)]
+//! Code last touched by contributors who are no longer active - a complement to decay's
+//! attrition score, which only counts contributors, not how much of the file is theirs.
+//!
+//! Contributors are inactive if they have an end date on or before the latest commit in their
+//! repository (e.g. people who have left), or if they have made no commits anywhere in the
+//! repository for a given number of months. Lines come from blame if the file was blamed,
+//! otherwise they are estimated from git history, as for line ages.
+
+use crate::blame::BlameData;
+use crate::git_file_history::FileHistoryEntry;
+use crate::git_logger::User;
+use crate::git_user_dictionary::GitUserDictionary;
+use crate::line_age::LineAgeSource;
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// months are approximate - close enough for spotting people who have stopped committing
+const DAYS_PER_MONTH: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContributorEndDate {
+    pub email: String,
+    /// unix time of the contributor's last active day
+    pub end_date: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InactiveConfig {
+    /// contributors with no commits in this many months are inactive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inactive_months: Option<u64>,
+    /// contributors are inactive after these dates, whatever their commits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub end_dates: Vec<ContributorEndDate>,
+}
+
+impl InactiveConfig {
+    /// is this user inactive, given when they were last active in the repository and its latest commit?
+    #[must_use]
+    pub fn is_inactive(&self, user: &User, last_active: Option<u64>, latest_commit: u64) -> bool {
+        let ended = user.email().map_or(false, |email| {
+            self.end_dates.iter().any(|end_date| {
+                end_date.email.eq_ignore_ascii_case(email) && end_date.end_date <= latest_commit
+            })
+        });
+        let idle = self.inactive_months.map_or(false, |months| {
+            let cutoff = latest_commit.saturating_sub(months * DAYS_PER_MONTH * SECONDS_PER_DAY);
+            last_active.map_or(true, |last_active| last_active < cutoff)
+        });
+        ended || idle
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InactiveCodeData {
+    pub source: LineAgeSource,
+    /// current lines - estimated, if from history
+    pub lines: u64,
+    /// the proportion of lines last changed by inactive contributors
+    pub inactive_share: f64,
+}
+
+fn inactive_code(active: f64, inactive: f64, source: LineAgeSource) -> Option<InactiveCodeData> {
+    let total = active + inactive;
+    if total < 0.5 {
+        return None;
+    }
+    Some(InactiveCodeData {
+        source,
+        lines: total.round() as u64,
+        inactive_share: inactive / total,
+    })
+}
+
+/// exact shares from blamed line ownership
+pub fn from_blame(
+    blame: &BlameData,
+    dictionary: &GitUserDictionary,
+    is_inactive: impl Fn(&User) -> bool,
+) -> Option<InactiveCodeData> {
+    let (mut active, mut inactive) = (0.0, 0.0);
+    for owner in &blame.owners {
+        let owner_inactive = dictionary
+            .users()
+            .get(owner.user)
+            .map_or(false, &is_inactive);
+        if owner_inactive {
+            inactive += owner.lines as f64;
+        } else {
+            active += owner.lines as f64;
+        }
+    }
+    inactive_code(active, inactive, LineAgeSource::Blame)
+}
+
+/// estimated shares from file history, in any order - deletions remove lines from active and
+/// inactive contributors in proportion, and lines are credited to commit authors
+pub fn from_history(
+    history: &[FileHistoryEntry],
+    is_inactive: impl Fn(&User) -> bool,
+) -> Option<InactiveCodeData> {
+    let mut changes: Vec<&FileHistoryEntry> = history.iter().collect();
+    changes.sort_by_key(|entry| entry.author_time);
+    let (mut active, mut inactive) = (0.0, 0.0);
+    for change in changes {
+        let total = active + inactive;
+        if change.lines_deleted > 0 && total > 0.0 {
+            let kept = (total - change.lines_deleted as f64).max(0.0) / total;
+            active *= kept;
+            inactive *= kept;
+        }
+        if is_inactive(&change.author) {
+            inactive += change.lines_added as f64;
+        } else {
+            active += change.lines_added as f64;
+        }
+    }
+    inactive_code(active, inactive, LineAgeSource::History)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blame::LineOwnership;
+    use crate::git_file_history::FileHistoryEntryBuilder;
+    use anyhow::Error;
+    use pretty_assertions::assert_eq;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+    const NOW: u64 = 2000 * DAY;
+
+    fn user(email: &str) -> User {
+        User::new(None, Some(email))
+    }
+
+    #[test]
+    fn users_are_inactive_after_end_dates_or_idle_months() {
+        let config = InactiveConfig {
+            inactive_months: Some(6),
+            end_dates: vec![ContributorEndDate {
+                email: "Left@example.com".to_owned(),
+                end_date: NOW - DAY,
+            }],
+        };
+        let recent = Some(NOW - 10 * DAY);
+        assert!(config.is_inactive(&user("left@example.com"), recent, NOW));
+        assert!(!config.is_inactive(&user("jo@example.com"), recent, NOW));
+        assert!(config.is_inactive(&user("jo@example.com"), Some(NOW - 200 * DAY), NOW));
+        assert!(!InactiveConfig::default().is_inactive(&user("jo@example.com"), Some(0), NOW));
+    }
+
+    #[test]
+    fn blamed_lines_by_inactive_users_are_counted() {
+        let mut dictionary = GitUserDictionary::default();
+        dictionary.register(&user("jo@example.com"));
+        dictionary.register(&user("left@example.com"));
+        let blame = BlameData {
+            lines: 4,
+            owners: vec![
+                LineOwnership { user: 0, lines: 3 },
+                LineOwnership { user: 1, lines: 1 },
+            ],
+        };
+        let data = from_blame(&blame, &dictionary, |user| {
+            user.email() == Some("left@example.com")
+        });
+        assert_eq!(
+            data,
+            Some(InactiveCodeData {
+                source: LineAgeSource::Blame,
+                lines: 4,
+                inactive_share: 0.25
+            })
+        );
+    }
+
+    #[test]
+    fn history_deletions_remove_lines_proportionally() -> Result<(), Error> {
+        let change = |email: &str, days_ago: u64, added: u64, deleted: u64| {
+            FileHistoryEntryBuilder::test_default()
+                .emails(email)
+                .id("1111")
+                .times(NOW - days_ago * DAY)
+                .lines_added(added)
+                .lines_deleted(deleted)
+                .build()
+                .map_err(Error::msg)
+        };
+        let history = vec![
+            change("jo@example.com", 10, 10, 20)?,
+            change("left@example.com", 1000, 40, 0)?,
+        ];
+        // 40 inactive lines, halved to 20, then 10 active lines added
+        let data = from_history(&history, |user| user.email() == Some("left@example.com"));
+        assert_eq!(
+            data,
+            Some(InactiveCodeData {
+                source: LineAgeSource::History,
+                lines: 30,
+                inactive_share: 20.0 / 30.0
+            })
+        );
+        Ok(())
+    }
+}
@@ -1,39 +1,172 @@
+use crate::binary::lfs_pointer_size;
+use crate::diagnostics::Diagnostic;
 use crate::flare::FlareTreeNode;
 use crate::polyglot_data::IndicatorMetadata;
 
 use super::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
 use anyhow::{Context, Error};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use content_inspector::{inspect, ContentType};
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use tokei::{Config, LanguageType};
 
-use super::code_line_data::CodeLines;
+use super::comment_filter::{default_comment_prefixes, CommentFilter};
 
+use super::code_line_data::{
+    should_stream, CodeLines, ParseLimits, DEFAULT_MAX_LINE_BYTES, DEFAULT_MAX_PARSE_BYTES,
+};
+
+use hdrhistogram::serialization::{Serializer, V2DeflateSerializer};
 use hdrhistogram::Histogram;
 
+/// How many spaces a tab counts as - this varies a lot by language, e.g. Go uses tabs for
+/// indentation but Python rarely does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndentationConfig {
+    pub tab_width: u32,
+    /// overrides by language name, as named by tokei e.g. "Makefile" - matched ignoring case
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub language_tab_widths: BTreeMap<String, u32>,
+    /// only process this many lines per file - large files are also streamed rather than parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    /// only measure this many bytes of each line - files with longer lines are streamed as text
+    #[serde(default = "default_max_line_bytes")]
+    pub max_line_bytes: usize,
+    /// files bigger than this are streamed as text rather than parsed by language
+    #[serde(default = "default_max_parse_bytes")]
+    pub max_parse_bytes: u64,
+    /// lines starting with these are treated as comments, in files tokei has no language for
+    #[serde(default = "default_comment_prefixes")]
+    pub comment_prefixes: Vec<String>,
+    /// which percentiles of indentation to output for each file, from 0 to 100 - 50 is the median
+    #[serde(default = "default_percentiles")]
+    pub percentiles: Vec<u8>,
+    /// also output each file's full indentation histogram, compressed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub histogram: bool,
+}
+
+pub const DEFAULT_PERCENTILES: [u8; 4] = [50, 75, 90, 99];
+
+fn default_percentiles() -> Vec<u8> {
+    DEFAULT_PERCENTILES.to_vec()
+}
+
+fn default_max_line_bytes() -> usize {
+    DEFAULT_MAX_LINE_BYTES
+}
+
+fn default_max_parse_bytes() -> u64 {
+    DEFAULT_MAX_PARSE_BYTES
+}
+
+impl Default for IndentationConfig {
+    fn default() -> Self {
+        IndentationConfig {
+            tab_width: 4,
+            language_tab_widths: BTreeMap::new(),
+            max_lines: None,
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            max_parse_bytes: DEFAULT_MAX_PARSE_BYTES,
+            comment_prefixes: default_comment_prefixes(),
+            percentiles: default_percentiles(),
+            histogram: false,
+        }
+    }
+}
+
+impl IndentationConfig {
+    /// limits for parsing files - these are shared by all calculators that read file contents
+    pub(crate) fn limits(&self) -> ParseLimits {
+        ParseLimits {
+            max_lines: self.max_lines,
+            max_line_bytes: self.max_line_bytes,
+            max_parse_bytes: self.max_parse_bytes,
+        }
+    }
+
+    /// the name of a language as tokei shows it, so per-language settings can check the language
+    /// exists rather than being silently ignored
+    pub fn language_name(name: &str) -> Option<&'static str> {
+        name.parse::<LanguageType>()
+            .ok()
+            .map(|language| language.name())
+    }
+
+    pub(crate) fn tab_width_for(&self, language: Option<LanguageType>) -> u32 {
+        language
+            .and_then(|language| {
+                self.language_tab_widths
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(language.name()))
+                    .map(|(_, width)| *width)
+            })
+            .unwrap_or(self.tab_width)
+    }
+
+    /// reads lines of a file that isn't parsed by language - comments are only filtered if tokei
+    /// doesn't know the language, as a too big file in a known language may use the comment
+    /// prefixes for code
+    pub(crate) fn stream_lines(
+        &self,
+        filename: &Path,
+        language: Option<LanguageType>,
+    ) -> Result<CodeLines, Error> {
+        let path = PathBuf::from(filename);
+        if language.is_some() {
+            CodeLines::new(&path, &self.limits())
+        } else {
+            CodeLines::without_comments(
+                &path,
+                &self.limits(),
+                &CommentFilter::new(&self.comment_prefixes),
+            )
+        }
+    }
+}
+
 /// a struct representing file indentation data
-#[derive(Debug, PartialEq, Serialize, Clone)]
+///
+/// Only the configured percentiles are output - the median, p75, p90 and p99 by default. Others
+/// are named for their percentile, e.g. `p95`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct IndentationData {
     pub lines: u64,
     pub minimum: u64,
     pub maximum: u64,
-    pub median: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub median: Option<u64>,
     pub stddev: f64,
-    pub p75: u64,
-    pub p90: u64,
-    pub p99: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p75: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p90: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p99: Option<u64>,
+    /// any other configured percentiles, by name
+    #[serde(flatten)]
+    pub percentiles: BTreeMap<String, u64>,
     /// the sum of indentations - probably best measure according to [HGH08]
     pub sum: u64,
+    /// the file had more lines than the configured maximum, so only the first lines were measured
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// the full histogram of line indentations, if configured - in the standard HdrHistogram
+    /// V2 compressed format, base64 encoded, which HdrHistogram libraries in most languages can
+    /// decode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<String>,
 }
 
 impl IndentationData {
-    fn new(code_lines: CodeLines) -> Option<Self> {
+    fn new(code_lines: CodeLines, tab_width: u32, config: &IndentationConfig) -> Option<Self> {
         // we used to have this - reinstate if creating histogram for every file is too slow.  But who knows, file I/O might be much bigger.
         // lazy_static! {
         //     static ref HISTOGRAM: Mutex<Histogram<u64>> =
@@ -41,9 +174,12 @@ impl IndentationData {
         // }
         let mut histogram = Histogram::<u64>::new(3).expect("Can't create histogram");
         let mut sum: u64 = 0;
+        let truncated = code_lines.truncated;
         for line in code_lines.lines {
             if line.text > 0 {
-                let indentation = line.spaces + line.tabs * 4;
+                let indentation = line
+                    .spaces
+                    .saturating_add(line.tabs.saturating_mul(tab_width));
                 histogram
                     .record(indentation as u64)
                     .expect("Invalid histogram value!");
@@ -51,27 +187,49 @@ impl IndentationData {
             }
         }
         if histogram.is_empty() {
-            None
-        } else {
-            Some(IndentationData {
-                lines: histogram.len(),
-                minimum: histogram.low(),
-                maximum: histogram.high(),
-                median: histogram.value_at_quantile(0.5),
-                stddev: histogram.stdev(),
-                p75: histogram.value_at_quantile(0.75),
-                p90: histogram.value_at_quantile(0.90),
-                p99: histogram.value_at_quantile(0.99),
-                sum,
-            })
+            return None;
+        }
+        let mut data = IndentationData {
+            lines: histogram.len(),
+            minimum: histogram.low(),
+            maximum: histogram.high(),
+            median: None,
+            stddev: histogram.stdev(),
+            p75: None,
+            p90: None,
+            p99: None,
+            percentiles: BTreeMap::new(),
+            sum,
+            truncated,
+            histogram: None,
+        };
+        for percentile in &config.percentiles {
+            let value = histogram.value_at_quantile(f64::from(*percentile) / 100.0);
+            match percentile {
+                50 => data.median = Some(value),
+                75 => data.p75 = Some(value),
+                90 => data.p90 = Some(value),
+                99 => data.p99 = Some(value),
+                _ => {
+                    data.percentiles.insert(format!("p{}", percentile), value);
+                }
+            }
+        }
+        if config.histogram {
+            let mut compressed = Vec::new();
+            V2DeflateSerializer::new()
+                .serialize(&histogram, &mut compressed)
+                .expect("Can't serialize histogram");
+            data.histogram = Some(base64::encode(&compressed));
         }
+        Some(data)
     }
 }
 
 // TODO: remove duplication with loc.rs
 const MAX_PEEK_SIZE: usize = 1024;
 
-fn file_content_type(filename: &Path) -> Result<ContentType, Error> {
+pub(crate) fn file_content_type(filename: &Path) -> Result<ContentType, Error> {
     let file = File::open(filename)?;
     let mut buffer: Vec<u8> = vec![];
 
@@ -79,28 +237,53 @@ fn file_content_type(filename: &Path) -> Result<ContentType, Error> {
     Ok(inspect(&buffer))
 }
 
-fn parse_file(filename: &Path) -> Result<Option<IndentationData>, Error> {
+pub(crate) fn parse_file(
+    filename: &Path,
+    indentation_config: &IndentationConfig,
+) -> Result<Option<IndentationData>, Error> {
+    // LFS pointers are binary content that hasn't been fetched
+    if lfs_pointer_size(filename)?.is_some() {
+        return Ok(None);
+    }
     let config = Config::default();
-    let code_lines = match LanguageType::from_path(filename, &config) {
-        Some(language) => {
+    let language = LanguageType::from_path(filename, &config);
+    let limits = indentation_config.limits();
+    let code_lines = match language {
+        Some(language) if !should_stream(filename, &limits) => {
             let report = language
                 .parse(PathBuf::from(filename), &config)
                 .map_err(|(error, _pathbuf)| error);
-            CodeLines::from_stats(&report?.stats)
+            CodeLines::from_stats(&report?.stats, &limits)
         }
-        None => {
+        _ => {
             if file_content_type(filename)? == ContentType::BINARY {
                 return Ok(None);
             }
-            debug!("Unknown language in {:?} - treating as text", filename);
-            CodeLines::new(&PathBuf::from(filename))?
+            debug!("Streaming {:?} as text", filename);
+            indentation_config.stream_lines(filename, language)?
         }
     };
-    Ok(IndentationData::new(code_lines))
+    Ok(IndentationData::new(
+        code_lines,
+        indentation_config.tab_width_for(language),
+        indentation_config,
+    ))
+}
+
+#[derive(Debug, Default)]
+pub struct IndentationCalculator {
+    config: IndentationConfig,
+    diagnostics: Vec<Diagnostic>,
 }
 
-#[derive(Debug)]
-pub struct IndentationCalculator {}
+impl IndentationCalculator {
+    pub fn new(config: IndentationConfig) -> Self {
+        IndentationCalculator {
+            config,
+            diagnostics: Vec::new(),
+        }
+    }
+}
 
 impl ToxicityIndicatorCalculator for IndentationCalculator {
     fn name(&self) -> String {
@@ -109,59 +292,192 @@ impl ToxicityIndicatorCalculator for IndentationCalculator {
 
     fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
         if path.is_file() {
-            let indentation =
-                parse_file(path).with_context(|| format!("parsing indentation for {:?}", path))?;
-            node.indicators_mut().indentation = indentation;
+            match parse_file(path, &self.config)
+                .with_context(|| format!("parsing indentation for {:?}", path))
+            {
+                Ok(indentation) => node.indicators_mut().indentation = indentation,
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, &self.name(), &e)),
+            }
         }
         Ok(())
     }
 
-    fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+    fn apply_metadata(&self, metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        metadata.indentation = Some(self.config.clone());
         Ok(())
     }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::code_line_data::CodeLineData;
 
     #[test]
     fn can_get_indentation_data_for_a_file() {
-        let indentation = parse_file(Path::new("./tests/data/simple/parent.clj"))
-            .unwrap()
-            .unwrap();
+        let indentation = parse_file(
+            Path::new("./tests/data/simple/parent.clj"),
+            &IndentationConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
         assert_eq!(indentation.lines, 3);
-        assert_eq!(indentation.p99, 2);
+        assert_eq!(indentation.p99, Some(2));
         assert_eq!(indentation.sum, 2);
     }
 
     #[test]
     fn unknown_files_are_treated_as_code() {
-        let indentation = parse_file(Path::new("./tests/data/languages/foo.unknown"))
+        let indentation = parse_file(
+            Path::new("./tests/data/languages/foo.unknown"),
+            &IndentationConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(indentation.lines, 2);
+        assert_eq!(indentation.p99, Some(2));
+        assert_eq!(indentation.sum, 2);
+    }
+
+    #[test]
+    fn comments_in_unknown_files_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("licensed.unknown");
+        std::fs::write(
+            &path,
+            "# Copyright someone\n#   all rights reserved\n\nvalue:\n  nested\n",
+        )
+        .unwrap();
+        let indentation = parse_file(&path, &IndentationConfig::default())
             .unwrap()
             .unwrap();
         assert_eq!(indentation.lines, 2);
-        assert_eq!(indentation.p99, 2);
         assert_eq!(indentation.sum, 2);
+
+        let unfiltered = IndentationConfig {
+            comment_prefixes: Vec::new(),
+            ..IndentationConfig::default()
+        };
+        let indentation = parse_file(&path, &unfiltered).unwrap().unwrap();
+        assert_eq!(indentation.lines, 4);
     }
 
     #[test]
     fn pf_files_are_fortran_unit_tests() {
-        let indentation = parse_file(Path::new("./tests/data/languages/pfunit_test.pf"))
-            .unwrap()
-            .unwrap();
+        let indentation = parse_file(
+            Path::new("./tests/data/languages/pfunit_test.pf"),
+            &IndentationConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
         assert_eq!(indentation.lines, 13);
-        assert_eq!(indentation.p99, 6);
+        assert_eq!(indentation.p99, Some(6));
         assert_eq!(indentation.sum, 39);
     }
 
     #[test]
-    fn non_utf8_text_files_are_parsed() {
-        let indentation = parse_file(Path::new("./tests/data/languages/non-utf8.properties"))
+    fn tab_width_can_be_set_per_language() {
+        let mut config = IndentationConfig {
+            tab_width: 2,
+            ..IndentationConfig::default()
+        };
+        config.language_tab_widths.insert("makefile".to_owned(), 8);
+        assert_eq!(config.tab_width_for(Some(LanguageType::Makefile)), 8);
+        assert_eq!(config.tab_width_for(Some(LanguageType::Go)), 2);
+        assert_eq!(config.tab_width_for(None), 2);
+    }
+
+    #[test]
+    fn only_known_languages_have_names() {
+        assert_eq!(
+            IndentationConfig::language_name("Makefile"),
+            Some("Makefile")
+        );
+        assert_eq!(IndentationConfig::language_name("Not a language"), None);
+    }
+
+    #[test]
+    fn huge_tab_widths_do_not_overflow() {
+        let code_lines = CodeLines {
+            lines: vec![CodeLineData {
+                spaces: 1,
+                tabs: 2,
+                text: 3,
+            }],
+            truncated: false,
+        };
+        let indentation =
+            IndentationData::new(code_lines, u32::MAX, &IndentationConfig::default()).unwrap();
+        assert_eq!(indentation.sum, u64::from(u32::MAX));
+    }
+
+    #[test]
+    fn tabs_are_counted_using_the_tab_width() {
+        let code_lines = CodeLines {
+            lines: vec![
+                CodeLineData {
+                    spaces: 1,
+                    tabs: 2,
+                    text: 3,
+                },
+                CodeLineData {
+                    spaces: 0,
+                    tabs: 1,
+                    text: 3,
+                },
+            ],
+            truncated: false,
+        };
+        let indentation =
+            IndentationData::new(code_lines, 8, &IndentationConfig::default()).unwrap();
+        assert_eq!(indentation.maximum, 17);
+        assert_eq!(indentation.sum, 25);
+    }
+
+    #[test]
+    fn percentiles_and_histogram_can_be_configured() {
+        let config = IndentationConfig {
+            percentiles: vec![50, 95],
+            histogram: true,
+            ..IndentationConfig::default()
+        };
+        let indentation = parse_file(Path::new("./tests/data/languages/pfunit_test.pf"), &config)
             .unwrap()
             .unwrap();
-        assert_eq!(indentation.lines, 2);
-        assert_eq!(indentation.p99, 0);
+        assert_eq!(indentation.median, Some(3));
+        assert_eq!(indentation.p75, None);
+        assert_eq!(indentation.p99, None);
+        assert_eq!(indentation.percentiles.get("p95"), Some(&6));
+
+        let compressed = base64::decode(indentation.histogram.as_ref().unwrap()).unwrap();
+        let histogram: Histogram<u64> = hdrhistogram::serialization::Deserializer::new()
+            .deserialize(&mut compressed.as_slice())
+            .unwrap();
+        assert_eq!(histogram.len(), indentation.lines);
+        assert_eq!(histogram.high(), indentation.maximum);
+
+        let json = serde_json::to_value(&indentation).unwrap();
+        assert_eq!(json["p95"], 6);
+        assert!(json.get("p75").is_none());
+    }
+
+    #[test]
+    fn non_utf8_text_files_are_parsed() {
+        let indentation = parse_file(
+            Path::new("./tests/data/languages/non-utf8.properties"),
+            &IndentationConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
+        // the first line is a comment
+        assert_eq!(indentation.lines, 1);
+        assert_eq!(indentation.p99, Some(0));
         assert_eq!(indentation.sum, 0);
     }
 }
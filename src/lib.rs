@@ -25,41 +25,158 @@ extern crate derive_builder;
 #[macro_use]
 extern crate derive_getters;
 
+use annotations::AnnotationsCalculator;
 use anyhow::{Context, Error};
+use architecture::ArchitectureConfig;
+use branch_count::BranchCountCalculator;
+use calculator_options::set_option;
+use categories::CategoryCalculator;
 use file_stats::FileStatsCalculator;
+use license::LicenseCalculator;
 use postprocessing::postprocess_tree;
-use serde::Serialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashSet;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
 
+mod annotations;
+mod architecture;
+mod areas_of_interest;
+mod atomic_output;
+mod bench_fixture;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod benchmarks;
+mod binary;
+mod blame;
+mod branch_count;
+mod calculator_options;
+mod cancellation;
+mod categories;
+mod churn_per_contributor;
 mod code_line_data;
+mod comment_filter;
+mod commit_sizes;
+mod contributor_churn;
+mod decay;
+mod diagnostics;
+mod directory_tree;
 // pub mod coupling;
 mod file_walker;
 // public so main.rs can access structures TODO: can this be done better? expose here just what main needs?
 pub mod coupling;
+mod field_selection;
 mod file_stats;
 mod flare;
+mod format_version;
 mod git;
+mod git_details_filter;
 mod git_file_future;
+// public so other tools can reuse rename-aware git history without the rest of the scanner
+pub mod git_file_history;
+pub mod git_logger;
+mod git_remotes;
 mod git_user_dictionary;
+mod html_viewer;
+mod inactive;
 mod indentation;
+mod license;
+mod line_age;
 mod loc;
+mod loc_report;
+mod memory;
+mod merge;
+mod nesting;
+mod node_limit;
+mod object_output;
+mod pairing;
+mod path_interner;
 mod polyglot_data;
 mod postprocessing;
+mod progress;
+mod pull_requests;
+mod quadrant;
+mod redact;
+mod report;
+mod scan_iter;
+mod secrets;
+mod sonar;
+mod summary_output;
+mod survey;
+mod syntax;
+mod teams;
+mod time_budget;
+mod timings;
 mod toxicity_indicator_calculator;
+mod upload;
+mod validate;
+mod vendored;
+mod working_hours;
 
-mod git_file_history;
-mod git_logger;
-
-use crate::coupling::CouplingConfig;
+pub use crate::annotations::{Annotations, ANNOTATIONS_FILE};
+pub use crate::areas_of_interest::AreasOfInterestConfig;
+pub use crate::atomic_output::{with_timestamp_suffix, AtomicFile};
+pub use crate::bench_fixture::{generate_bench_fixture, BenchFixtureConfig};
+pub use crate::blame::BlameConfig;
+pub use crate::cancellation::{CancellationToken, ScanCancelled};
+pub use crate::categories::{CategoryRule, FileCategorizer};
+pub use crate::churn_per_contributor::ChurnPerContributorConfig;
+pub use crate::comment_filter::default_comment_prefixes;
+pub use crate::commit_sizes::CommitSizeConfig;
+pub use crate::contributor_churn::ContributorChurnConfig;
+use crate::coupling::{CouplingConfig, CouplingRun};
+pub use crate::decay::DecayConfig;
+pub use crate::diagnostics::{Diagnostic, DiagnosticCategory};
+pub use crate::directory_tree::DirectoryTreeConfig;
+pub use crate::field_selection::FieldSelection;
+pub use crate::file_walker::global_ignore_file;
+pub use crate::flare::{IndicatorData, OutputFormat};
+pub use crate::format_version::FormatVersion;
+pub use crate::git::CommitDayTimezone;
+pub use crate::git_details_filter::GitDetailsFilter;
+pub use crate::git_logger::{
+    BulkCommitConfig, BulkCommitMode, CoAuthorParser, CoAuthorRules, CommitCategoryRule,
+    CommitClassifier, RenameConfig, DEFAULT_TICKET_PATTERN,
+};
+pub use crate::inactive::{ContributorEndDate, InactiveConfig};
+pub use crate::indentation::{IndentationConfig, DEFAULT_PERCENTILES};
+pub use crate::node_limit::{NodeLimitConfig, NodeLimitStrategy};
+pub use crate::object_output::{upload_to_object_store, ObjectLocation, ObjectStoreKind};
+pub use crate::polyglot_data::IndicatorMetadata;
+pub use crate::progress::{set_progress_format, ProgressFormat};
+pub use crate::pull_requests::PullRequestConfig;
+pub use crate::quadrant::QuadrantConfig;
+pub use crate::redact::RedactConfig;
+pub use crate::report::{ReportConfig, ReportFormat};
+pub use crate::scan_iter::{ScanIter, ScannedFile};
+pub use crate::secrets::{SecretRule, SecretScanner};
+pub use crate::sonar::{SonarIssue, SonarIssues, SonarLocation, SonarThresholds};
+pub use crate::survey::{
+    recommend, survey, FeatureEstimate, ProjectSurvey, RepositorySurvey, ScanEstimate,
+    ScanRecommendation,
+};
+pub use crate::teams::{TeamConfig, Teams, TeamsConfig};
+pub use crate::time_budget::TimeBudgetConfig;
+pub use crate::upload::{upload_file, UploadConfig};
+pub use crate::validate::ValidationProblem;
+pub use crate::vendored::{VendoredConfig, VendoredMode, DEFAULT_VENDORED_NAMES};
+pub use crate::working_hours::WorkingHoursConfig;
 use git::GitCalculator;
-use git_logger::GitLogConfig;
+use git_logger::{GitLog, GitLogConfig};
 use indentation::IndentationCalculator;
 use loc::LocCalculator;
+use nesting::NestingCalculator;
+use secrets::SecretsCalculator;
+#[cfg(feature = "tree-sitter")]
+use syntax::SyntaxCalculator;
+use timings::Profiler;
 use toxicity_indicator_calculator::ToxicityIndicatorCalculator;
 
 #[allow(clippy::struct_excessive_bools)]
-#[derive(Debug, Default, Clone, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FeatureFlags {
     pub git: bool,
     pub coupling: bool,
@@ -67,13 +184,188 @@ pub struct FeatureFlags {
     pub file_stats: bool,
 }
 
-// general config for the scanner and calculators - could be split if it grows too far
+// general config for the scanner and calculators - could be split if it grows too far.
+// It is saved in metadata as the effective configuration, so fields that don't describe the
+// scanned data are skipped
+#[derive(Serialize)]
 pub struct ScannerConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub git_years: Option<u64>,
+    /// when scanning a subdirectory, only process git changes under it, and older changes to
+    /// files renamed into it
+    pub git_subtree_only: bool,
+    /// spot commits that touch lots of files, and flag or exclude them from git stats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bulk_commits: Option<BulkCommitConfig>,
+    /// classify commits by their summary, and count commits per category for each file
+    #[serde(rename = "commit_categories", skip_serializing_if = "Option::is_none")]
+    pub commit_classifier: Option<CommitClassifier>,
+    /// extract ticket ids matching this from commit messages, into git activity
+    #[serde(
+        serialize_with = "serialize_pattern",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ticket_pattern: Option<Regex>,
+    /// how to find co-authors in commit messages
+    pub co_authors: CoAuthorParser,
+    /// how to detect renamed files in git history
+    pub renames: RenameConfig,
+    /// whose midnight starts each day, when grouping commits by day
+    #[serde(skip_serializing_if = "CommitDayTimezone::is_utc")]
+    pub timezone: CommitDayTimezone,
+    /// the time git days since creation and last change are measured to, in seconds since the
+    /// epoch - now, unless set for reproducible output
+    pub scan_time: u64,
     pub follow_symlinks: bool,
+    /// include hidden files and directories, other than `.git`
+    pub include_hidden: bool,
+    /// don't use git ignore rules - `.polyglot_code_scanner_ignore` files are still used
+    pub no_ignore: bool,
+    /// maximum directory depth to scan below the root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// extra ignore rule files, in `.gitignore` syntax - used even if `no_ignore` is set
+    #[serde(
+        serialize_with = "serialize_file_names",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub ignore_files: Vec<PathBuf>,
+    /// detect vendored dependency directories such as `node_modules`, and skip or tag them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendored: Option<VendoredConfig>,
+    /// put the whole tree under these directories, e.g. "backend", to combine it with other scans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<PathBuf>,
+    /// record the absolute path of the scanned directory in metadata - off by default, as it can
+    /// show user names and the layout of the machine that ran the scan
+    #[serde(skip)]
+    pub local_paths: bool,
+    /// only scan these files, relative to the root, instead of walking the whole directory
+    #[serde(
+        rename = "listed_files",
+        serialize_with = "serialize_count",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub file_list: Option<Vec<PathBuf>>,
+    /// only scan files changed since this git commit, branch or tag, including uncommitted changes -
+    /// the output is a partial tree, flagged in metadata as incremental. Can't be used with `file_list`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_since: Option<String>,
+    #[serde(skip)]
     pub name: String,
+    #[serde(skip)]
     pub data_id: Option<String>,
+    #[serde(skip)]
     pub features: FeatureFlags,
+    /// extra coupling configurations, stored by name - only used if coupling is enabled
+    #[serde(
+        serialize_with = "serialize_named_coupling",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub named_coupling: Vec<(String, CouplingConfig)>,
+    /// JSON file of architecture components, to summarise coupling by component - only used if coupling is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture_file: Option<PathBuf>,
+    /// save coupling progress to this file, and resume from it - only used if coupling is enabled
+    #[serde(skip)]
+    pub coupling_checkpoint: Option<PathBuf>,
+    /// keep fine-grained git activity in the output, so coupling can be recalculated later
+    pub keep_git_activity: bool,
+    /// only keep detailed git data for files matching these globs - all files if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_details_for: Option<GitDetailsFilter>,
+    /// tab widths and line limits for the indentation and nesting calculators - the parsing limits are also used by loc and branch counts
+    pub indentation: IndentationConfig,
+    /// record line ownership from `git blame` - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blame: Option<BlameConfig>,
+    /// estimate how old current lines are, from blame if it is enabled, otherwise from git history - needs git
+    pub line_age: bool,
+    /// group users into teams, and count commits per team for each file - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub teams: Option<Teams>,
+    /// measure how much of each file was last changed by inactive contributors - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inactive: Option<InactiveConfig>,
+    /// count merged pull requests per file, from the GitHub or GitLab API - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pull_requests: Option<PullRequestConfig>,
+    /// count commits by hour and weekday in authors' timezones, per file and contributor - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_hours: Option<WorkingHoursConfig>,
+    /// how many lines at the start of each file are searched for a license, if the license calculator is used
+    pub license_lines: usize,
+    /// rules for finding possible secrets, if the secrets calculator is used
+    pub secrets: SecretScanner,
+    /// rules for file categories, if the categories calculator is used
+    pub categories: FileCategorizer,
+    /// calculate a code decay score per file - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decay: Option<DecayConfig>,
+    /// classify files by churn and complexity, relative to the rest of the scan - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quadrant: Option<QuadrantConfig>,
+    /// count new and departed contributors per directory - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributor_churn: Option<ContributorChurnConfig>,
+    /// measure recent changes per contributor-month per directory - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub churn_per_contributor: Option<ChurnPerContributorConfig>,
+    /// measure the share of commits with co-authors per file and directory - needs git
+    pub pairing: bool,
+    /// summarise lines changed per commit for each file - needs git
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_sizes: Option<CommitSizeConfig>,
+    /// list notable files and directories in metadata, for highlights without walking the tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub areas_of_interest: Option<AreasOfInterestConfig>,
+    /// include scan timings in the output metadata
+    pub timings: bool,
+    /// also write scan timings to a separate file
+    #[serde(skip)]
+    pub timings_file: Option<PathBuf>,
+    /// also write a human-readable report of the top files
+    #[serde(skip)]
+    pub report_file: Option<PathBuf>,
+    pub report: ReportConfig,
+    /// plan the scan to finish within this time, leaving things out if it won't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_budget: Option<TimeBudgetConfig>,
+    /// soft memory limit in megabytes - if the tree is estimated to be bigger, git activity and details are dropped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memory_mb: Option<u64>,
+    /// cancel this from another thread to stop the scan early - `run` then fails with `ScanCancelled`
+    #[serde(skip)]
+    pub cancellation: CancellationToken,
+    /// write an older data file format, for explorers that don't understand the current one
+    #[serde(skip)]
+    pub format_version: FormatVersion,
+    /// write a polyglot data file, or a classic D3 flare file
+    #[serde(skip)]
+    pub output_format: OutputFormat,
+    /// also write these files from the same scan, each in its own format - written before the
+    /// main output, and only once the data is complete
+    #[serde(skip)]
+    pub extra_outputs: Vec<(PathBuf, OutputFormat)>,
+    /// never read file contents - only names, sizes and git history. Calculators that read
+    /// contents, and blame, are refused
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_content: bool,
+    /// replace file and directory names with hashes, so the output can be shared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redact_names: Option<RedactConfig>,
+    /// aggregate nodes into synthetic `<other>` files if the tree has more nodes than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_limit: Option<NodeLimitConfig>,
+    /// only write these indicator fields for each node - polyglot output only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<FieldSelection>,
+    /// indent polyglot output, with sorted keys, for reading and diffing - it can't be streamed
+    #[serde(skip)]
+    pub pretty: bool,
+    /// replace files with totals in their directories, for a directory-only tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory_tree: Option<DirectoryTreeConfig>,
 }
 
 impl ScannerConfig {
@@ -81,32 +373,709 @@ impl ScannerConfig {
     pub fn default(name: &str) -> Self {
         ScannerConfig {
             git_years: None,
+            git_subtree_only: false,
+            bulk_commits: None,
+            commit_classifier: None,
+            ticket_pattern: None,
+            co_authors: CoAuthorParser::default(),
+            timezone: CommitDayTimezone::default(),
+            scan_time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            renames: RenameConfig::default(),
             follow_symlinks: false,
+            include_hidden: false,
+            no_ignore: false,
+            max_depth: None,
+            ignore_files: Vec::new(),
+            vendored: None,
+            path_prefix: None,
+            local_paths: false,
+            file_list: None,
+            changed_since: None,
             name: name.to_owned(),
             data_id: None,
             features: FeatureFlags::default(),
+            named_coupling: Vec::new(),
+            architecture_file: None,
+            coupling_checkpoint: None,
+            keep_git_activity: false,
+            git_details_for: None,
+            indentation: IndentationConfig::default(),
+            blame: None,
+            line_age: false,
+            teams: None,
+            inactive: None,
+            working_hours: None,
+            pull_requests: None,
+            license_lines: license::DEFAULT_LICENSE_LINES,
+            secrets: SecretScanner::default(),
+            categories: FileCategorizer::default(),
+            decay: None,
+            quadrant: None,
+            contributor_churn: None,
+            churn_per_contributor: None,
+            pairing: false,
+            commit_sizes: None,
+            areas_of_interest: None,
+            timings: false,
+            timings_file: None,
+            report_file: None,
+            report: ReportConfig::default(),
+            time_budget: None,
+            max_memory_mb: None,
+            cancellation: CancellationToken::new(),
+            format_version: FormatVersion::Latest,
+            output_format: OutputFormat::Polyglot,
+            extra_outputs: Vec::new(),
+            no_content: false,
+            redact_names: None,
+            node_limit: None,
+            fields: None,
+            pretty: false,
+            directory_tree: None,
+        }
+    }
+
+    /// sets an option for a calculator by name, e.g. `indentation.tab_width` - see
+    /// `calculator_options` for how values are parsed
+    pub fn set_calculator_option(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let (calculator, option) = name
+            .split_once('.')
+            .ok_or_else(|| anyhow!("Option '{}' should be CALCULATOR.OPTION", name))?;
+        match calculator {
+            // nesting shares the indentation config
+            "indentation" | "nesting" => set_option(&mut self.indentation, option, value),
+            "license" if option == "lines" => {
+                self.license_lines = value
+                    .parse()
+                    .with_context(|| format!("Invalid value {} for option '{}'", value, name))?;
+                Ok(())
+            }
+            "license" => bail!("Unknown option '{}'", name),
+            _ if CALCULATORS.contains(&calculator) => {
+                bail!("The {} calculator has no options", calculator)
+            }
+            _ => bail!("Unknown calculator '{}'", calculator),
+        }
+    }
+
+    fn walk_options(&self) -> file_walker::WalkOptions {
+        file_walker::WalkOptions {
+            follow_symlinks: self.follow_symlinks,
+            include_hidden: self.include_hidden,
+            no_ignore: self.no_ignore,
+            max_depth: self.max_depth,
+            ignore_files: self.ignore_files.clone(),
+            vendored: self.vendored.clone(),
+            sample_one_in: None,
         }
     }
+
+    /// the configuration as saved in metadata, with the coupling configuration actually used
+    fn metadata(
+        &self,
+        coupling_config: Option<CouplingConfig>,
+    ) -> Result<serde_json::Value, Error> {
+        let mut metadata = serde_json::to_value(self)?;
+        if let Some(coupling_config) = coupling_config {
+            metadata["coupling"] = serde_json::to_value(coupling_config)?;
+        }
+        Ok(metadata)
+    }
 }
 
+#[allow(clippy::ref_option)] // serde needs a reference
+fn serialize_pattern<S: Serializer>(
+    pattern: &Option<Regex>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    pattern.as_ref().map(Regex::as_str).serialize(serializer)
+}
+
+/// ignore files may be outside the scanned directory, e.g. in the user's home directory, so only
+/// their names are saved
+fn serialize_file_names<S: Serializer>(
+    files: &[PathBuf],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        files
+            .iter()
+            .map(|file| file.file_name().map_or(file.as_path(), Path::new)),
+    )
+}
+
+/// listed files are saved as a count, not the whole list
+#[allow(clippy::ref_option)] // serde needs a reference
+fn serialize_count<S: Serializer>(
+    file_list: &Option<Vec<PathBuf>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    file_list.as_ref().map(Vec::len).serialize(serializer)
+}
+
+fn serialize_named_coupling<S: Serializer>(
+    named_coupling: &[(String, CouplingConfig)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(named_coupling.iter().map(|(name, config)| (name, config)))
+}
+
+/// every file calculator, in the order they run
+pub const CALCULATORS: &[&str] = &[
+    "loc",
+    "indentation",
+    "nesting",
+    "branch_count",
+    "license",
+    "secrets",
+    "categories",
+    "syntax",
+    "annotations",
+    "git",
+    "file_stats",
+];
+
+/// calculators that read file contents, so can't be used with `no_content`
+pub const CONTENT_CALCULATORS: &[&str] = &[
+    "loc",
+    "indentation",
+    "nesting",
+    "branch_count",
+    "license",
+    "secrets",
+    "syntax",
+];
+
 #[must_use]
 pub fn named_toxicity_indicator_calculator(
     name: &str,
     config: &ScannerConfig,
+) -> Option<Box<dyn ToxicityIndicatorCalculator>> {
+    calculator_with_git_years(name, config, config.git_years, None)
+}
+
+/// a calculator, reading `git_years` of history rather than the configured years - the time
+/// budget may need fewer
+fn calculator_with_git_years(
+    name: &str,
+    config: &ScannerConfig,
+    git_years: Option<u64>,
+    memory: Option<&memory::MemoryGuard>,
 ) -> Option<Box<dyn ToxicityIndicatorCalculator>> {
     match name {
-        "loc" => Some(Box::new(LocCalculator {})),
-        "git" => Some(Box::new(GitCalculator::new(
-            GitLogConfig::default()
-                .include_merges(true)
-                .since_years(config.git_years),
+        "loc" => Some(Box::new(LocCalculator::new(config.indentation.limits()))),
+        "git" => Some(Box::new(
+            GitCalculator::new(
+                GitLogConfig::default()
+                    .include_merges(true)
+                    .since_years(git_years)
+                    .subtree_only(config.git_subtree_only)
+                    .bulk_commits(config.bulk_commits)
+                    .commit_classifier(config.commit_classifier.clone())
+                    .ticket_pattern(config.ticket_pattern.clone())
+                    .co_authors(config.co_authors.clone())
+                    .renames(config.renames)
+                    .cancellation(config.cancellation.clone()),
+            )
+            .timezone(config.timezone)
+            .scan_time(config.scan_time)
+            .blame(config.blame)
+            .line_age(config.line_age)
+            .teams(config.teams.clone())
+            .inactive(config.inactive.clone())
+            .working_hours(config.working_hours)
+            .pull_requests(config.pull_requests.clone())
+            .memory(memory.cloned()),
+        )),
+        "indentation" => Some(Box::new(IndentationCalculator::new(
+            config.indentation.clone(),
         ))),
-        "indentation" => Some(Box::new(IndentationCalculator {})),
-        "file_stats" => Some(Box::new(FileStatsCalculator {})),
+        "nesting" => Some(Box::new(NestingCalculator::new(config.indentation.clone()))),
+        "branch_count" => Some(Box::new(BranchCountCalculator::new(
+            config.indentation.limits(),
+        ))),
+        // without contents there is no loc data, so sizes are recorded with the file stats
+        "file_stats" => Some(Box::new(FileStatsCalculator::new(config.no_content))),
+        "license" => Some(Box::new(LicenseCalculator::new(config.license_lines))),
+        "secrets" => Some(Box::new(SecretsCalculator::new(config.secrets.clone()))),
+        "categories" => Some(Box::new(CategoryCalculator::new(config.categories.clone()))),
+        "annotations" => Some(Box::new(AnnotationsCalculator::default())),
+        #[cfg(feature = "tree-sitter")]
+        "syntax" => Some(Box::new(SyntaxCalculator::default())),
         _ => None,
     }
 }
 
+/// calculators for these names, checking they are allowed by the configured features
+fn named_calculators(
+    config: &ScannerConfig,
+    toxicity_indicator_calculator_names: &[&str],
+    git_years: Option<u64>,
+    memory: Option<&memory::MemoryGuard>,
+) -> Result<Vec<Box<dyn ToxicityIndicatorCalculator>>, Error> {
+    if toxicity_indicator_calculator_names.contains(&"git") && !config.features.git {
+        bail!("Logic error - using git calculator when git is disabled!");
+    }
+    if toxicity_indicator_calculator_names.contains(&"file_stats") && !config.features.file_stats {
+        bail!("Logic error - using file_stats calculator when file_stats is disabled!");
+    }
+    if toxicity_indicator_calculator_names.contains(&"syntax") && !cfg!(feature = "tree-sitter") {
+        bail!("The syntax calculator needs a build with the tree-sitter feature");
+    }
+    if config.no_content {
+        if let Some(name) = toxicity_indicator_calculator_names
+            .iter()
+            .find(|name| CONTENT_CALCULATORS.contains(name))
+        {
+            bail!(
+                "The {} calculator reads file contents, which is not allowed",
+                name
+            );
+        }
+        if config.blame.is_some() {
+            bail!("Blame reads file contents, which is not allowed");
+        }
+    }
+    toxicity_indicator_calculator_names
+        .iter()
+        .map(|name| {
+            calculator_with_git_years(name, config, git_years, memory)
+                .ok_or_else(|| anyhow!("No toxicity indicator calculator named {}", name))
+        })
+        .collect()
+}
+
+/// the files to scan, if not the whole directory - changed files are relative to the root
+fn files_to_scan(root: &Path, config: &ScannerConfig) -> Result<Option<Vec<PathBuf>>, Error> {
+    match (&config.changed_since, &config.file_list) {
+        (Some(_), Some(_)) => bail!("Can't scan both listed files and changed files"),
+        (Some(reference), None) => {
+            info!("Finding files changed since {}", reference);
+            let git_log = GitLog::new(root, GitLogConfig::default())
+                .with_context(|| format!("finding git repository for {:?}", root))?;
+            let canonical_root = root
+                .canonicalize()
+                .with_context(|| format!("canonicalizing {:?}", root))?;
+            let changed: Vec<PathBuf> = git_log
+                .changed_files_since(reference)?
+                .into_iter()
+                .filter_map(|file| {
+                    file.strip_prefix(&canonical_root)
+                        .ok()
+                        .map(Path::to_path_buf)
+                })
+                .collect();
+            // only files a full scan would include - a changed file may be ignored or vendored
+            let files = file_walker::walkable_files(root, &changed, &config.walk_options())?;
+            info!(
+                "Found {} changed files, {} to scan",
+                changed.len(),
+                files.len()
+            );
+            Ok(Some(files))
+        }
+        (None, files) => Ok(files.clone()),
+    }
+}
+
+fn load_architecture(
+    config: &ScannerConfig,
+    coupling_config: Option<CouplingConfig>,
+) -> Result<Option<ArchitectureConfig>, Error> {
+    match &config.architecture_file {
+        Some(file) if coupling_config.is_some() => Ok(Some(ArchitectureConfig::from_file(file)?)),
+        _ => Ok(None),
+    }
+}
+
+/// runs a pass over the whole tree, recording its time as a scan phase
+fn timed_phase<T>(profiler: &mut Profiler, name: &str, pass: impl FnOnce() -> T) -> T {
+    let phase_start = Instant::now();
+    let result = pass();
+    profiler.record_phase(name, phase_start.elapsed());
+    result
+}
+
+/// indicators calculated from the git details and activity of the whole tree
+fn git_tree_passes(
+    polyglot_data: &mut polyglot_data::PolyglotData,
+    config: &ScannerConfig,
+    profiler: &mut Profiler,
+) {
+    if let Some(decay_config) = &config.decay {
+        timed_phase(profiler, "decay", || {
+            decay::calculate_decay(polyglot_data.tree_mut(), decay_config);
+        });
+        polyglot_data.metadata().decay = Some(*decay_config);
+    }
+    if let Some(quadrant_config) = config.quadrant {
+        let quadrants = timed_phase(profiler, "quadrant", || {
+            quadrant::calculate_quadrants(polyglot_data.tree_mut(), quadrant_config)
+        });
+        polyglot_data.metadata().quadrant = Some(quadrants);
+    }
+    if let Some(churn_config) = &config.contributor_churn {
+        timed_phase(profiler, "contributor_churn", || {
+            contributor_churn::calculate_contributor_churn(polyglot_data.tree_mut(), churn_config);
+        });
+    }
+    if let Some(churn_config) = &config.churn_per_contributor {
+        timed_phase(profiler, "churn_per_contributor", || {
+            churn_per_contributor::calculate_churn_per_contributor(
+                polyglot_data.tree_mut(),
+                churn_config,
+            );
+        });
+    }
+    if config.pairing {
+        timed_phase(profiler, "pairing", || {
+            pairing::calculate_pairing(polyglot_data.tree_mut());
+        });
+    }
+    if let Some(commit_size_config) = &config.commit_sizes {
+        timed_phase(profiler, "commit_sizes", || {
+            commit_sizes::calculate_commit_sizes(polyglot_data.tree_mut(), commit_size_config);
+        });
+    }
+}
+
+/// main coupling, named coupling, and architecture summaries
+fn gather_all_coupling(
+    polyglot_data: &mut polyglot_data::PolyglotData,
+    coupling_config: CouplingConfig,
+    config: &ScannerConfig,
+    architecture: Option<&ArchitectureConfig>,
+) -> Result<(), Error> {
+    let mut names = HashSet::new();
+    if let Some((name, _)) = config
+        .named_coupling
+        .iter()
+        .find(|(name, _)| !names.insert(name))
+    {
+        // results are stored by name, so a later config would overwrite an earlier one
+        bail!("Named coupling '{}' is specified more than once", name);
+    }
+    let run = CouplingRun {
+        cancellation: config.cancellation.clone(),
+        checkpoint: config.coupling_checkpoint.clone(),
+    };
+    info!("gathering coupling");
+    coupling::gather_coupling(polyglot_data, coupling_config, None, &run)
+        .or_else(|e| cancelled_with_diagnostics(e, polyglot_data))?;
+    for (name, named_config) in &config.named_coupling {
+        check_cancelled(config, polyglot_data)?;
+        info!("gathering coupling for {}", name);
+        coupling::gather_coupling(polyglot_data, *named_config, Some(name), &run)
+            .or_else(|e| cancelled_with_diagnostics(e, polyglot_data))?;
+    }
+    if let Some(architecture) = architecture {
+        info!("summarising coupling by architecture component");
+        architecture::gather_architecture_coupling(polyglot_data, architecture)?;
+    }
+    Ok(())
+}
+
+/// Reprocess an existing data file - if `coupling_config` is set, coupling is recalculated from
+/// git activity (so the file must have been scanned with `keep_git_activity`), and postprocessing
+/// is re-applied, using `config.features.git_details`, `config.git_details_for` and
+/// `config.keep_git_activity`.
+/// The name, id and other settings are kept from the original file.
+pub fn reprocess<W>(
+    input: &Path,
+    config: &ScannerConfig,
+    coupling_config: Option<CouplingConfig>,
+    out: W,
+) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    let architecture = load_architecture(config, coupling_config)?;
+    info!("loading {:?}", input);
+    let mut polyglot_data = polyglot_data::PolyglotData::from_file(input)?;
+    if let Some(cc) = coupling_config {
+        if !postprocessing::has_git_activity(polyglot_data.tree()) {
+            bail!(
+                "{:?} has no git activity - it must be scanned with git activity kept to recalculate coupling",
+                input
+            );
+        }
+        postprocessing::remove_coupling(polyglot_data.tree_mut());
+        polyglot_data.clear_coupling_metadata();
+        polyglot_data.features_mut().coupling = true;
+        gather_all_coupling(&mut polyglot_data, cc, config, architecture.as_ref())?;
+    }
+    // details can be removed but not restored!
+    let git_details = polyglot_data.features().git_details && config.features.git_details;
+    polyglot_data.features_mut().git_details = git_details;
+    let mut postprocess_config = ScannerConfig::default(polyglot_data.name());
+    postprocess_config.features = polyglot_data.features().clone();
+    postprocess_config.keep_git_activity = config.keep_git_activity;
+    postprocess_config.git_details_for = config.git_details_for.clone();
+    info!("postprocessing tree");
+    postprocess_tree(polyglot_data.tree_mut(), &postprocess_config)?;
+    info!("saving as JSON");
+    save(polyglot_data, config, out)?;
+    Ok(())
+}
+
+/// merge existing data files into a single file, under a synthetic root
+pub fn merge<W>(files: &[PathBuf], name: &str, id: Option<&str>, out: W) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    let sources = files
+        .iter()
+        .map(|file| {
+            info!("loading {:?}", file);
+            polyglot_data::PolyglotData::from_file(file)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    info!("merging {} data files", sources.len());
+    let merged = merge::merge_data(sources, name, id)?;
+    info!("saving as JSON");
+    merged.write_json(out)?;
+    Ok(())
+}
+
+/// writes any extra outputs, then the data in the configured output format
+fn save<W>(
+    polyglot_data: polyglot_data::PolyglotData,
+    config: &ScannerConfig,
+    out: W,
+) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    for (path, format) in &config.extra_outputs {
+        info!("saving {:?} as {:?}", path, format);
+        let mut file =
+            AtomicFile::create(path).with_context(|| format!("creating output {:?}", path))?;
+        save_copy(&polyglot_data, *format, config, &mut file)?;
+        file.commit()?;
+    }
+    match config.output_format {
+        // these stream the tree, dropping it as they go
+        OutputFormat::Polyglot => polyglot_data.write_json_as(
+            out,
+            config.format_version,
+            config.fields.as_ref(),
+            config.pretty,
+        ),
+        OutputFormat::Flare => polyglot_data.write_classic_flare(out),
+        format => save_copy(&polyglot_data, format, config, out),
+    }
+}
+
+/// writes the data in an output format, keeping it to write again
+fn save_copy<W>(
+    polyglot_data: &polyglot_data::PolyglotData,
+    format: OutputFormat,
+    config: &ScannerConfig,
+    out: W,
+) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    match format {
+        OutputFormat::Polyglot => polyglot_data.write_json_copy(
+            out,
+            config.format_version,
+            config.fields.as_ref(),
+            config.pretty,
+        ),
+        OutputFormat::Flare => polyglot_data.write_classic_flare_copy(out),
+        OutputFormat::LocReport => loc_report::write_loc_report(polyglot_data.tree(), out),
+        OutputFormat::Html => html_viewer::write_html_viewer(
+            polyglot_data,
+            out,
+            config.format_version,
+            config.fields.as_ref(),
+        ),
+        OutputFormat::Csv => summary_output::write_csv(polyglot_data.tree(), out),
+        OutputFormat::Prometheus => summary_output::write_prometheus(polyglot_data, out),
+    }
+}
+
+/// writes SonarQube generic issues for files in an existing data file that are over the thresholds
+pub fn sonar_export<W>(input: &Path, thresholds: &SonarThresholds, out: W) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    info!("loading {:?}", input);
+    let polyglot_data = polyglot_data::PolyglotData::from_file(input)?;
+    let issues = sonar::sonar_issues(polyglot_data.tree(), thresholds);
+    info!("writing {} issues", issues.issues.len());
+    serde_json::to_writer_pretty(out, &issues)?;
+    Ok(())
+}
+
+/// writes a human-readable report of the top files in an existing data file
+pub fn report<W>(input: &Path, config: &ReportConfig, out: W) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    info!("loading {:?}", input);
+    let mut polyglot_data = polyglot_data::PolyglotData::from_file(input)?;
+    report::write_report(&mut polyglot_data, config, out)
+}
+
+/// checks an existing data file is compatible and consistent, returning any problems found
+pub fn validate(input: &Path) -> Result<Vec<ValidationProblem>, Error> {
+    info!("validating {:?}", input);
+    validate::validate_file(input)
+}
+
+/// adds the diagnostics found so far to a cancellation from inside a phase
+fn cancelled_with_diagnostics(
+    error: Error,
+    polyglot_data: &polyglot_data::PolyglotData,
+) -> Result<(), Error> {
+    if cancellation::is_cancellation(&error) {
+        info!("scan cancelled");
+        return Err(cancellation::cancelled_with(
+            &error,
+            polyglot_data.diagnostics().to_vec(),
+        ));
+    }
+    Err(error)
+}
+
+/// stops between phases if the scan was cancelled, keeping the diagnostics found so far
+fn check_cancelled(
+    config: &ScannerConfig,
+    polyglot_data: &polyglot_data::PolyglotData,
+) -> Result<(), Error> {
+    if config.cancellation.is_cancelled() {
+        info!("scan cancelled");
+        return Err(ScanCancelled {
+            diagnostics: polyglot_data.diagnostics().to_vec(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Runs calculators on a single file, quickly, for editor integrations and pre-commit hooks.
+/// Git needs the whole repository so can't be used - problems with the file are logged as
+/// warnings, not returned. The path in the result is the path given.
+pub fn scan_file(
+    file: &Path,
+    config: &ScannerConfig,
+    toxicity_indicator_calculator_names: &[&str],
+) -> Result<ScannedFile, Error> {
+    if toxicity_indicator_calculator_names.contains(&"git") {
+        bail!("Can't use the git calculator for a single file");
+    }
+    if !file.is_file() {
+        bail!("{:?} is not a file", file);
+    }
+    let mut tics = named_calculators(
+        config,
+        toxicity_indicator_calculator_names,
+        config.git_years,
+        None,
+    )?;
+    let name = file
+        .file_name()
+        .ok_or_else(|| anyhow!("{:?} has no file name", file))?;
+    let mut node = flare::FlareTreeNode::new(name, true);
+    file_walker::apply_calculators_to_node(&mut node, file, &mut tics, &mut Profiler::new())?;
+    Ok(ScannedFile {
+        path: file.to_path_buf(),
+        indicators: node.indicators().clone(),
+    })
+}
+
+/// Scans files one at a time, without building the whole tree - see `ScanIter`.
+/// Uses the same calculators and config as `run`, but coupling, decay, path prefixes, memory
+/// limits and timings aren't supported.
+pub fn scan_iter(
+    root: &Path,
+    config: &ScannerConfig,
+    toxicity_indicator_calculator_names: &[&str],
+) -> Result<ScanIter, Error> {
+    let tics = named_calculators(
+        config,
+        toxicity_indicator_calculator_names,
+        config.git_years,
+        None,
+    )?;
+    let (paths, link_guard) = match files_to_scan(root, config)? {
+        Some(files) => {
+            let paths: Box<dyn Iterator<Item = Result<PathBuf, Diagnostic>>> =
+                Box::new(file_walker::file_list_paths(root, &files).into_iter());
+            (paths, None)
+        }
+        None => file_walker::directory_paths(root, &config.walk_options())?,
+    };
+    Ok(ScanIter::new(
+        root,
+        paths,
+        link_guard,
+        tics,
+        config.features.git_details,
+        config.keep_git_activity,
+        config.vendored.clone(),
+        config.cancellation.clone(),
+    ))
+}
+
+/// surveys the project, and plans the scan to fit the time budget
+fn plan_time_budget(
+    root: &Path,
+    config: &ScannerConfig,
+    time_budget: TimeBudgetConfig,
+    coupling: bool,
+    toxicity_indicator_calculator_names: &[&str],
+) -> Result<time_budget::BudgetPlan, Error> {
+    let started = Instant::now();
+    info!("Surveying to plan the time budget");
+    let years = config.git_years.unwrap_or(survey::ALL_HISTORY_YEARS);
+    let project = survey::survey(root, years)?;
+    let file_calculators = toxicity_indicator_calculator_names
+        .iter()
+        .filter(|name| **name != "git")
+        .count();
+    let mut features = config.features.clone();
+    features.coupling = features.coupling && coupling;
+    Ok(time_budget::plan(
+        &project,
+        time_budget,
+        config.git_years,
+        file_calculators,
+        &features,
+        config.keep_git_activity,
+        started,
+    ))
+}
+
+/// Estimates how long a scan with these settings would take, and how much output each enabled
+/// feature would add, without scanning - see `survey`. The estimate is written as a table.
+pub fn estimate<W>(
+    root: &Path,
+    config: &ScannerConfig,
+    coupling_config: Option<CouplingConfig>,
+    toxicity_indicator_calculator_names: &[&str],
+    out: W,
+) -> Result<(), Error>
+where
+    W: io::Write,
+{
+    let years = config.git_years.unwrap_or(survey::ALL_HISTORY_YEARS);
+    let project = survey::survey(root, years)?;
+    let file_calculators = toxicity_indicator_calculator_names
+        .iter()
+        .filter(|name| **name != "git")
+        .count();
+    let mut features = config.features.clone();
+    features.coupling = features.coupling && coupling_config.is_some();
+    project
+        .estimate_scan(years, file_calculators, &features, config.keep_git_activity)
+        .write_report(out)
+}
+
 pub fn run<W>(
     root: &Path,
     config: &ScannerConfig,
@@ -117,46 +1086,208 @@ pub fn run<W>(
 where
     W: io::Write,
 {
-    if toxicity_indicator_calculator_names.contains(&"git") && !config.features.git {
-        bail!("Logic error - using git calculator when git is disabled!");
-    }
-    if toxicity_indicator_calculator_names.contains(&"file_stats") && !config.features.file_stats {
-        bail!("Logic error - using file_stats calculator when file_stats is disabled!");
+    let mut profiler = Profiler::new();
+
+    let mut budget = match config.time_budget {
+        Some(time_budget) => {
+            let phase_start = Instant::now();
+            let plan = plan_time_budget(
+                root,
+                config,
+                time_budget,
+                coupling_config.is_some(),
+                toxicity_indicator_calculator_names,
+            )?;
+            profiler.record_phase("time_budget", phase_start.elapsed());
+            Some(plan)
+        }
+        None => None,
+    };
+    let git_years = budget
+        .as_ref()
+        .map_or(config.git_years, |plan| plan.git_years);
+    let memory = config.max_memory_mb.map(memory::MemoryGuard::new);
+    let mut tics = named_calculators(
+        config,
+        toxicity_indicator_calculator_names,
+        git_years,
+        memory.as_ref(),
+    )?;
+    if let Some(memory) = &memory {
+        // last, so it sees the data added by every other calculator
+        tics.push(Box::new(memory.clone()));
     }
-    let maybe_tics: Option<Vec<_>> = toxicity_indicator_calculator_names
-        .iter()
-        .map(|name| named_toxicity_indicator_calculator(name, config))
-        .collect();
 
-    let mut tics = maybe_tics.expect("Some toxicity indicator calculator names don't exist!");
+    // load this before scanning so we fail fast on a bad file
+    let architecture = load_architecture(config, coupling_config)?;
 
     info!("Walking directory tree");
-    let mut polyglot_data = file_walker::walk_directory(
-        root,
-        &config.name,
-        config.data_id.as_deref(),
-        config.follow_symlinks,
-        &mut tics,
-        &config.features,
-    )?;
+    let phase_start = Instant::now();
+    let files = files_to_scan(root, config)?;
+    let mut polyglot_data = match &files {
+        Some(files) => file_walker::walk_file_list(
+            root,
+            files,
+            &config.name,
+            config.data_id.as_deref(),
+            &mut tics,
+            &config.features,
+            &mut profiler,
+            &config.cancellation,
+        )?,
+        None => file_walker::walk_directory(
+            root,
+            &config.name,
+            config.data_id.as_deref(),
+            &file_walker::WalkOptions {
+                sample_one_in: budget.as_ref().and_then(|plan| plan.sample_one_in),
+                ..config.walk_options()
+            },
+            &mut tics,
+            &config.features,
+            &mut profiler,
+            &config.cancellation,
+        )?,
+    };
+    if let Some(vendored) = &config.vendored {
+        // listed files aren't skipped even if vendored, so are tagged in any mode
+        vendored::tag_vendored(
+            polyglot_data.tree_mut(),
+            root,
+            Path::new(""),
+            false,
+            vendored,
+        );
+    }
+    profiler.record_phase("walk", phase_start.elapsed());
+    if let (Some(reference), Some(files)) = (&config.changed_since, &files) {
+        polyglot_data.metadata().incremental = Some(polyglot_data::IncrementalMetadata {
+            changed_since: reference.clone(),
+            changed_files: files.len(),
+        });
+    }
 
     info!("adding metadata");
+    let phase_start = Instant::now();
     for tic in tics {
         tic.apply_metadata(polyglot_data.metadata())
             .with_context(|| format!("applying metadata for {}", tic.name()))?;
+        for (name, elapsed) in tic.timings() {
+            profiler.record_phase(&name, elapsed);
+        }
+        for diagnostic in tic.diagnostics() {
+            polyglot_data.add_diagnostic(diagnostic.relative_to(root));
+        }
     }
+    profiler.record_phase("metadata", phase_start.elapsed());
+    check_cancelled(config, &polyglot_data)?;
 
-    if let Some(cc) = coupling_config {
+    let coupling_possible = memory
+        .as_ref()
+        .map_or(true, |memory| memory.apply(&mut polyglot_data));
+
+    // checked last, so coupling is only recorded as skipped for time if it would have run
+    let coupling_in_budget = coupling_possible
+        && budget
+            .as_mut()
+            .map_or(true, time_budget::BudgetPlan::coupling_fits);
+    if let Some(cc) = coupling_config.filter(|_| coupling_in_budget) {
         // TODO: fix this to take the data
-        info!("gathering coupling");
-        coupling::gather_coupling(&mut polyglot_data, cc)?;
+        let phase_start = Instant::now();
+        gather_all_coupling(&mut polyglot_data, cc, config, architecture.as_ref())?;
+        profiler.record_phase("coupling", phase_start.elapsed());
+        check_cancelled(config, &polyglot_data)?;
     }
 
+    // postprocessing removes git details and activity unless they are kept, so indicators
+    // calculated from them across the whole tree must run first
+    git_tree_passes(&mut polyglot_data, config, &mut profiler);
+
     info!("postprocessing tree");
+    let phase_start = Instant::now();
     // TODO: fix this to take the data
     postprocess_tree(polyglot_data.tree_mut(), config)?;
+    profiler.record_phase("postprocess", phase_start.elapsed());
+    check_cancelled(config, &polyglot_data)?;
+
+    if let Some(directory_tree) = config.directory_tree {
+        // after postprocessing, so totals include everything - and before the node limit, as
+        // the tree may be small enough without it
+        let phase_start = Instant::now();
+        directory_tree::apply_directory_tree(polyglot_data.tree_mut(), directory_tree);
+        profiler.record_phase("directory_tree", phase_start.elapsed());
+    }
+
+    if let Some(node_limit) = config.node_limit {
+        // after postprocessing, so directory summaries include everything
+        let phase_start = Instant::now();
+        polyglot_data.metadata().node_limit =
+            node_limit::apply_node_limit(&mut polyglot_data, node_limit);
+        profiler.record_phase("node_limit", phase_start.elapsed());
+    }
+
+    if let Some(plan) = &budget {
+        polyglot_data.metadata().time_budget = Some(plan.metadata());
+    }
+    polyglot_data.metadata().scanner = Some(polyglot_data::ScannerMetadata {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        calculators: toxicity_indicator_calculator_names
+            .iter()
+            .map(|name| (*name).to_owned())
+            .collect(),
+        config: config.metadata(coupling_config)?,
+    });
+    if config.local_paths || config.path_prefix.is_some() {
+        polyglot_data.metadata().paths = Some(polyglot_data::PathMetadata {
+            root: if config.local_paths {
+                Some(
+                    root.canonicalize()
+                        .with_context(|| format!("canonicalizing {:?}", root))?,
+                )
+            } else {
+                None
+            },
+            path_prefix: config.path_prefix.clone(),
+        });
+    }
+    if let Some(prefix) = &config.path_prefix {
+        // after everything else, so calculators and coupling only see real paths
+        merge::add_path_prefix(&mut polyglot_data, prefix)?;
+    }
+    if let Some(areas_config) = config.areas_of_interest {
+        // after the path prefix, so paths match the tree
+        let areas = areas_of_interest::find_areas_of_interest(polyglot_data.tree(), areas_config);
+        polyglot_data.metadata().areas_of_interest = Some(areas);
+    }
+    if let Some(redact) = &config.redact_names {
+        // last of all, so everything else sees real names
+        redact::redact_names(&mut polyglot_data, redact);
+    }
+
+    if let Some(report_file) = &config.report_file {
+        info!("saving report to {:?}", report_file);
+        let mut report_out = AtomicFile::create(report_file)
+            .with_context(|| format!("creating report file {:?}", report_file))?;
+        report::write_report(&mut polyglot_data, &config.report, &mut report_out)?;
+        report_out.commit()?;
+    }
+
+    if config.timings {
+        // can't include the time taken to save the data itself!
+        polyglot_data.metadata().timings = Some(profiler.summary());
+    }
 
     info!("saving as JSON");
-    serde_json::to_writer(out, &polyglot_data)?;
+    let phase_start = Instant::now();
+    save(polyglot_data, config, out)?;
+    profiler.record_phase("save", phase_start.elapsed());
+
+    if let Some(timings_file) = &config.timings_file {
+        info!("saving timings to {:?}", timings_file);
+        let mut timings_out = AtomicFile::create(timings_file)
+            .with_context(|| format!("creating timings file {:?}", timings_file))?;
+        serde_json::to_writer_pretty(&mut timings_out, &profiler.summary())?;
+        timings_out.commit()?;
+    }
     Ok(())
 }
@@ -0,0 +1,238 @@
+#![warn(clippy::all)]
+//! License headers - the license each file declares in its first few lines, so a scan also gives
+//! a tree-wide license map.
+//!
+//! SPDX identifiers (`SPDX-License-Identifier: MIT`) are used as-is. Without one, the common
+//! Apache, MIT, BSD, GPL family and MPL header texts are recognised, and recorded as the closest
+//! SPDX id. Copyright lines are recorded as well. Binary files are skipped.
+
+use crate::diagnostics::Diagnostic;
+use crate::flare::FlareTreeNode;
+use crate::polyglot_data::IndicatorMetadata;
+use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use anyhow::{Context, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// license texts, lower-cased, and the SPDX id to record - the first match wins, so more
+/// specific texts come first
+const LICENSE_TEXTS: &[(&str, &str)] = &[
+    ("apache license, version 2.0", "Apache-2.0"),
+    ("gnu lesser general public license", "LGPL"),
+    ("gnu affero general public license", "AGPL"),
+    ("gnu general public license", "GPL"),
+    ("mozilla public license, v. 2.0", "MPL-2.0"),
+    ("permission is hereby granted, free of charge", "MIT"),
+    ("redistribution and use in source and binary forms", "BSD"),
+];
+
+const SPDX_TAG: &str = "spdx-license-identifier:";
+
+/// comment closers that might follow an SPDX id on the same line
+const COMMENT_ENDS: &[&str] = &["*/", "-->", "--}}", "#}", "*)"];
+
+/// header lines are only this long, so minified files don't need reading in full
+const MAX_LINE_BYTES: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LicenseData {
+    /// an SPDX license id or expression - None if no license was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// the first copyright line, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copyright: Option<String>,
+}
+
+/// files per license across the scan
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LicenseSummary {
+    pub licenses: BTreeMap<String, u64>,
+    /// text files with no license found
+    pub unlicensed: u64,
+}
+
+fn spdx_id(line: &str) -> Option<String> {
+    // ascii lower-casing doesn't move byte offsets
+    let start = line.to_ascii_lowercase().find(SPDX_TAG)? + SPDX_TAG.len();
+    let mut id = line.get(start..)?.trim();
+    for end in COMMENT_ENDS {
+        id = id.strip_suffix(end).unwrap_or(id).trim();
+    }
+    Some(id.to_owned()).filter(|id| !id.is_empty())
+}
+
+fn copyright(line: &str) -> Option<String> {
+    let start = line.to_ascii_lowercase().find("copyright")?;
+    let text = line.get(start..)?.trim();
+    for end in COMMENT_ENDS {
+        if let Some(text) = text.strip_suffix(end) {
+            return Some(text.trim().to_owned());
+        }
+    }
+    Some(text.to_owned())
+}
+
+/// the license in these header lines - None if the bytes don't look like text
+fn parse_header(header: &[u8], max_lines: usize) -> Option<LicenseData> {
+    if header.contains(&0) {
+        return None;
+    }
+    let header = String::from_utf8_lossy(header);
+    let lines: Vec<&str> = header.lines().take(max_lines).collect();
+    let mut id = lines.iter().find_map(|line| spdx_id(line));
+    if id.is_none() {
+        // header texts are often wrapped, so match against the whole header
+        let text = lines
+            .iter()
+            .map(|line| {
+                line.trim_start_matches(|c: char| !c.is_alphanumeric())
+                    .trim()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        id = LICENSE_TEXTS
+            .iter()
+            .find(|(license_text, _)| text.contains(license_text))
+            .map(|(_, id)| (*id).to_owned());
+    }
+    Some(LicenseData {
+        id,
+        copyright: lines.iter().find_map(|line| copyright(line)),
+    })
+}
+
+fn parse_file(file: &Path, max_lines: usize) -> Result<Option<LicenseData>, Error> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut header = Vec::new();
+    for _ in 0..max_lines {
+        let read = (&mut reader)
+            .take(MAX_LINE_BYTES)
+            .read_until(b'\n', &mut header)?;
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(parse_header(&header, max_lines))
+}
+
+#[derive(Debug)]
+pub struct LicenseCalculator {
+    max_lines: usize,
+    summary: LicenseSummary,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// how many lines are searched by default
+pub const DEFAULT_LICENSE_LINES: usize = 20;
+
+impl LicenseCalculator {
+    pub fn new(max_lines: usize) -> Self {
+        LicenseCalculator {
+            max_lines,
+            summary: LicenseSummary::default(),
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl ToxicityIndicatorCalculator for LicenseCalculator {
+    fn name(&self) -> String {
+        "license".to_string()
+    }
+
+    fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        if path.is_file() {
+            match parse_file(path, self.max_lines)
+                .with_context(|| format!("finding license for {:?}", path))
+            {
+                Ok(Some(license)) => {
+                    match &license.id {
+                        Some(id) => *self.summary.licenses.entry(id.clone()).or_insert(0) += 1,
+                        None => self.summary.unlicensed += 1,
+                    }
+                    node.indicators_mut().license = Some(license);
+                }
+                Ok(None) => {}
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, &self.name(), &e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_metadata(&self, metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        metadata.licenses = Some(self.summary.clone());
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn license(header: &str) -> Option<LicenseData> {
+        parse_header(header.as_bytes(), 20)
+    }
+
+    #[test]
+    fn spdx_ids_are_used_as_is() {
+        assert_eq!(
+            license("/* SPDX-License-Identifier: MIT OR Apache-2.0 */\nfn main() {}\n"),
+            Some(LicenseData {
+                id: Some("MIT OR Apache-2.0".to_owned()),
+                copyright: None
+            })
+        );
+    }
+
+    #[test]
+    fn wrapped_header_texts_are_recognised() {
+        let header = "// Copyright 2021 Example Ltd\n\
+                      //\n\
+                      // Licensed under the Apache License,\n\
+                      // Version 2.0 (the \"License\");\n";
+        assert_eq!(
+            license(header),
+            Some(LicenseData {
+                id: Some("Apache-2.0".to_owned()),
+                copyright: Some("Copyright 2021 Example Ltd".to_owned())
+            })
+        );
+    }
+
+    #[test]
+    fn unlicensed_and_binary_files_differ() {
+        assert_eq!(
+            license("(ns parent)\n"),
+            Some(LicenseData {
+                id: None,
+                copyright: None
+            })
+        );
+        assert_eq!(parse_header(b"\x89PNG\r\n\x1a\n\0\0", 20), None);
+    }
+
+    #[test]
+    fn only_the_first_lines_are_read() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("late.rs");
+        std::fs::write(&file, "fn a() {}\n\n// SPDX-License-Identifier: MIT\n")?;
+        assert_eq!(parse_file(&file, 2)?.and_then(|data| data.id), None);
+        assert_eq!(
+            parse_file(&file, 3)?.and_then(|data| data.id),
+            Some("MIT".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+#![warn(clippy::all)]
+//! How old the lines in a file are now - not just when the file last changed.
+//!
+//! If the file was blamed, ages are exact. Otherwise they are estimated from git history:
+//! each change adds lines at its time, and deletions remove lines from every earlier change in
+//! proportion, as history doesn't say which lines were deleted.
+//! Ages are relative to the latest commit in the repository, like `age_in_days`.
+
+use crate::git_file_history::FileHistoryEntry;
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// lines older than this are counted in `old_lines`
+const OLD_LINE_DAYS: u64 = 2 * 365;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineAgeSource {
+    Blame,
+    History,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineAgeData {
+    pub source: LineAgeSource,
+    /// current lines - estimated, if from history
+    pub lines: u64,
+    pub median_age_days: u64,
+    /// the proportion of lines more than two years old
+    pub old_lines: f64,
+}
+
+/// summarises (time, lines) pairs, which must be oldest first - None if there are no lines
+fn summarise(
+    line_times: &[(u64, f64)],
+    latest_commit: u64,
+    source: LineAgeSource,
+) -> Option<LineAgeData> {
+    let total: f64 = line_times.iter().map(|(_, lines)| lines).sum();
+    if total < 0.5 {
+        return None;
+    }
+    let age_days = |time: u64| latest_commit.saturating_sub(time) / SECONDS_PER_DAY;
+    let mut cumulative = 0.0;
+    let mut median_age_days = 0;
+    for (time, lines) in line_times {
+        cumulative += lines;
+        if cumulative >= total / 2.0 {
+            median_age_days = age_days(*time);
+            break;
+        }
+    }
+    let old: f64 = line_times
+        .iter()
+        .filter(|(time, _)| age_days(*time) > OLD_LINE_DAYS)
+        .map(|(_, lines)| lines)
+        .sum();
+    Some(LineAgeData {
+        source,
+        lines: total.round() as u64,
+        median_age_days,
+        old_lines: old / total,
+    })
+}
+
+/// exact ages from blame - (author time, lines) pairs in any order
+pub fn from_blame(line_times: &[(u64, u64)], latest_commit: u64) -> Option<LineAgeData> {
+    let mut line_times: Vec<(u64, f64)> = line_times
+        .iter()
+        .map(|(time, lines)| (*time, *lines as f64))
+        .collect();
+    line_times.sort_by_key(|(time, _)| *time);
+    summarise(&line_times, latest_commit, LineAgeSource::Blame)
+}
+
+/// estimated ages from file history, in any order
+pub fn from_history(history: &[FileHistoryEntry], latest_commit: u64) -> Option<LineAgeData> {
+    let mut changes: Vec<&FileHistoryEntry> = history.iter().collect();
+    changes.sort_by_key(|entry| entry.author_time);
+    let mut line_times: Vec<(u64, f64)> = Vec::new();
+    for change in changes {
+        let total: f64 = line_times.iter().map(|(_, lines)| lines).sum();
+        if change.lines_deleted > 0 && total > 0.0 {
+            let kept = (total - change.lines_deleted as f64).max(0.0) / total;
+            for (_, lines) in &mut line_times {
+                *lines *= kept;
+            }
+        }
+        if change.lines_added > 0 {
+            line_times.push((change.author_time, change.lines_added as f64));
+        }
+    }
+    summarise(&line_times, latest_commit, LineAgeSource::History)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git_file_history::FileHistoryEntryBuilder;
+    use anyhow::Error;
+    use pretty_assertions::assert_eq;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+    const NOW: u64 = 2000 * DAY;
+
+    #[test]
+    fn blame_ages_are_exact() {
+        let data = from_blame(&[(NOW - 10 * DAY, 3), (NOW - 1000 * DAY, 1)], NOW).unwrap();
+        assert_eq!(
+            data,
+            LineAgeData {
+                source: LineAgeSource::Blame,
+                lines: 4,
+                median_age_days: 10,
+                old_lines: 0.25
+            }
+        );
+        assert_eq!(from_blame(&[], NOW), None);
+    }
+
+    #[test]
+    fn history_deletions_remove_lines_proportionally() -> Result<(), Error> {
+        let change = |days_ago: u64, added: u64, deleted: u64| {
+            FileHistoryEntryBuilder::test_default()
+                .emails("jo@smith.com")
+                .id("1111")
+                .times(NOW - days_ago * DAY)
+                .lines_added(added)
+                .lines_deleted(deleted)
+                .build()
+                .map_err(Error::msg)
+        };
+        let history = vec![
+            change(10, 20, 10)?,
+            change(1000, 40, 0)?,
+            change(900, 0, 20)?,
+        ];
+        // 40 old lines, halved to 20, halved again to 10, then 20 new lines added
+        let data = from_history(&history, NOW).unwrap();
+        assert_eq!(
+            data,
+            LineAgeData {
+                source: LineAgeSource::History,
+                lines: 30,
+                median_age_days: 10,
+                old_lines: 10.0 / 30.0
+            }
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
 #![warn(clippy::all)]
+use crate::binary;
+use crate::code_line_data::{count_lines, should_stream, ParseLimits};
+use crate::diagnostics::Diagnostic;
 use crate::polyglot_data::IndicatorMetadata;
 
 use super::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
 use anyhow::Error;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use content_inspector::{inspect, ContentType};
 
@@ -14,7 +17,7 @@ use std::path::{Path, PathBuf};
 use tokei::{Config, LanguageType};
 
 /// a struct representing tokei language data - based on `tokei::Stats` and `tokei::Languages::name`
-#[derive(Debug, PartialEq, Eq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct LanguageLocData {
     /// Canonical language name
     pub language: String,
@@ -57,6 +60,19 @@ impl LanguageLocData {
             bytes: file_size(filename)?,
         })
     }
+
+    /// LFS pointers are counted as the binary file they point to
+    fn from_lfs_pointer(language_name: String, size: u64) -> Self {
+        LanguageLocData {
+            language: language_name,
+            binary: true,
+            blanks: 0,
+            code: 0,
+            comments: 0,
+            lines: 0,
+            bytes: size,
+        }
+    }
 }
 
 const MAX_PEEK_SIZE: usize = 1024;
@@ -69,7 +85,35 @@ fn file_content_type(filename: &Path) -> Result<ContentType, Error> {
     Ok(inspect(&buffer))
 }
 
-fn parse_file(filename: &Path) -> Result<LanguageLocData, Error> {
+/// large files are counted a line at a time - all non-blank lines are counted as code
+fn count_large_file(
+    language_name: String,
+    filename: &Path,
+    limits: &ParseLimits,
+) -> Result<LanguageLocData, Error> {
+    debug!(
+        "Streaming large file {:?} - comments won't be counted",
+        filename
+    );
+    let (lines, blanks) = count_lines(filename, limits)?;
+    Ok(LanguageLocData {
+        language: language_name,
+        binary: false,
+        blanks,
+        code: lines - blanks,
+        comments: 0,
+        lines,
+        bytes: file_size(filename)?,
+    })
+}
+
+fn parse_file(filename: &Path, limits: &ParseLimits) -> Result<LanguageLocData, Error> {
+    if let Some(size) = binary::lfs_pointer_size(filename)? {
+        return Ok(LanguageLocData::from_lfs_pointer(
+            safe_extension(filename),
+            size,
+        ));
+    }
     let config = Config::default();
     let mut language_name = None;
     let language = match LanguageType::from_path(filename, &config) {
@@ -83,6 +127,9 @@ fn parse_file(filename: &Path) -> Result<LanguageLocData, Error> {
         }
     };
     let language_name = language_name.unwrap_or_else(|| language.name().to_string());
+    if should_stream(filename, limits) {
+        return count_large_file(language_name, filename, limits);
+    }
     let report = language.parse(PathBuf::from(filename), &config);
 
     match report {
@@ -99,8 +146,21 @@ fn parse_file(filename: &Path) -> Result<LanguageLocData, Error> {
     }
 }
 
-#[derive(Debug)]
-pub struct LocCalculator {}
+#[derive(Debug, Default)]
+pub struct LocCalculator {
+    /// large files, and files with huge lines, are streamed - see `code_line_data::should_stream`
+    limits: ParseLimits,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl LocCalculator {
+    pub fn new(limits: ParseLimits) -> Self {
+        LocCalculator {
+            limits,
+            diagnostics: Vec::new(),
+        }
+    }
+}
 
 impl ToxicityIndicatorCalculator for LocCalculator {
     fn name(&self) -> String {
@@ -113,8 +173,24 @@ impl ToxicityIndicatorCalculator for LocCalculator {
         path: &Path,
     ) -> Result<(), Error> {
         if path.is_file() {
-            let stats = parse_file(path)?;
-            node.indicators_mut().loc = Some(stats);
+            match parse_file(path, &self.limits) {
+                Ok(stats) => {
+                    if stats.binary {
+                        match binary::describe(path) {
+                            Ok(binary) => node.indicators_mut().binary = Some(binary),
+                            Err(e) => self.diagnostics.push(Diagnostic::file_error(
+                                path,
+                                &self.name(),
+                                &e,
+                            )),
+                        }
+                    }
+                    node.indicators_mut().loc = Some(stats);
+                }
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, &self.name(), &e)),
+            }
         }
         Ok(())
     }
@@ -122,6 +198,10 @@ impl ToxicityIndicatorCalculator for LocCalculator {
     fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
         Ok(())
     }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
 }
 
 #[cfg(test)]
@@ -130,8 +210,30 @@ mod test {
 
     #[test]
     fn can_get_loc_data_for_a_file() {
-        let stats = parse_file(Path::new("./tests/data/simple/parent.clj")).unwrap();
+        let stats = parse_file(
+            Path::new("./tests/data/simple/parent.clj"),
+            &ParseLimits::default(),
+        )
+        .unwrap();
         assert_eq!(stats.code, 3);
         assert_eq!(stats.language, "Clojure");
     }
+
+    #[test]
+    fn lfs_pointers_are_counted_as_the_binary_they_point_to() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let pointer = dir.path().join("video.mp4");
+        std::fs::write(
+            &pointer,
+            "version https://git-lfs.github.com/spec/v1\n\
+             oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+             size 12345678\n",
+        )?;
+        let stats = parse_file(&pointer, &ParseLimits::default())?;
+        assert!(stats.binary);
+        assert_eq!(stats.lines, 0);
+        assert_eq!(stats.bytes, 12345678);
+        assert_eq!(stats.language, "mp4");
+        Ok(())
+    }
 }
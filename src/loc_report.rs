@@ -0,0 +1,156 @@
+#![warn(clippy::all)]
+//! A cloc/scc style summary of lines of code per language, for a quick answer on the console
+//! without the JSON workflow.
+//!
+//! Binary and vendored files aren't counted, nor are files whose language isn't known.
+
+use crate::flare::FlareTreeNode;
+use anyhow::Error;
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguageSummary {
+    pub files: u64,
+    pub blanks: u64,
+    pub comments: u64,
+    pub code: u64,
+}
+
+impl LanguageSummary {
+    fn add(&mut self, other: &LanguageSummary) {
+        self.files += other.files;
+        self.blanks += other.blanks;
+        self.comments += other.comments;
+        self.code += other.code;
+    }
+}
+
+fn gather(node: &FlareTreeNode, summaries: &mut BTreeMap<String, LanguageSummary>) {
+    if node.indicators().vendored {
+        return;
+    }
+    if let Some(loc) = &node.indicators().loc {
+        if !loc.binary {
+            let summary = summaries.entry(loc.language.clone()).or_default();
+            summary.add(&LanguageSummary {
+                files: 1,
+                blanks: loc.blanks as u64,
+                comments: loc.comments as u64,
+                code: loc.code as u64,
+            });
+        }
+    }
+    for child in node.get_children() {
+        gather(child, summaries);
+    }
+}
+
+/// line counts per language in the tree
+#[must_use]
+pub fn summarise(tree: &FlareTreeNode) -> BTreeMap<String, LanguageSummary> {
+    let mut summaries = BTreeMap::new();
+    gather(tree, &mut summaries);
+    summaries
+}
+
+/// writes a table of languages, most code first, with a total
+pub fn write_loc_report<W: io::Write>(tree: &FlareTreeNode, mut out: W) -> Result<(), Error> {
+    let summaries = summarise(tree);
+    let mut languages: Vec<(&String, &LanguageSummary)> = summaries.iter().collect();
+    languages.sort_by(|(a_name, a), (b_name, b)| b.code.cmp(&a.code).then(a_name.cmp(b_name)));
+    let mut total = LanguageSummary::default();
+    for (_, summary) in &languages {
+        total.add(summary);
+    }
+    let width = languages
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(std::iter::once("Language".len()))
+        .max()
+        .unwrap_or_default();
+    let rule = "-".repeat(width + 44);
+    let row = |out: &mut W, name: &str, summary: &LanguageSummary| {
+        writeln!(
+            out,
+            "{:<width$} {:>10} {:>10} {:>10} {:>10}",
+            name,
+            summary.files,
+            summary.blanks,
+            summary.comments,
+            summary.code,
+            width = width
+        )
+    };
+    writeln!(out, "{}", rule)?;
+    writeln!(
+        out,
+        "{:<width$} {:>10} {:>10} {:>10} {:>10}",
+        "Language",
+        "Files",
+        "Blank",
+        "Comment",
+        "Code",
+        width = width
+    )?;
+    writeln!(out, "{}", rule)?;
+    for (name, summary) in &languages {
+        row(&mut out, name, summary)?;
+    }
+    writeln!(out, "{}", rule)?;
+    row(&mut out, "Total", &total)?;
+    writeln!(out, "{}", rule)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::loc::LanguageLocData;
+    use pretty_assertions::assert_eq;
+
+    fn file(name: &str, language: &str, code: usize, binary: bool) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: language.to_owned(),
+            binary,
+            blanks: 1,
+            code,
+            comments: 2,
+            lines: code + 3,
+            bytes: 100,
+        });
+        file
+    }
+
+    #[test]
+    fn languages_are_listed_most_code_first() {
+        let mut root = FlareTreeNode::dir("<root>");
+        root.append_child(file("a.rs", "Rust", 10, false));
+        root.append_child(file("b.rs", "Rust", 20, false));
+        root.append_child(file("c.py", "Python", 50, false));
+        root.append_child(file("d.png", "PNG", 0, true));
+        let mut vendor = FlareTreeNode::dir("vendor");
+        vendor.indicators_mut().vendored = true;
+        vendor.append_child(file("e.py", "Python", 1000, false));
+        root.append_child(vendor);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        write_loc_report(&root, &mut buffer).unwrap();
+
+        let rule = "-".repeat(52);
+        let expected = [
+            rule.as_str(),
+            "Language      Files      Blank    Comment       Code",
+            rule.as_str(),
+            "Python            1          1          2         50",
+            "Rust              2          2          4         30",
+            rule.as_str(),
+            "Total             3          3          6         80",
+            rule.as_str(),
+            "",
+        ]
+        .join("\n");
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+    }
+}
@@ -3,17 +3,34 @@
 #![warn(clippy::pedantic)]
 #![warn(rust_2018_idioms)]
 
-use anyhow::Error;
-use clap::{CommandFactory, ErrorKind, Parser};
+use anyhow::{Context, Error};
+use chrono::{NaiveDate, Utc};
+use clap::{
+    ArgMatches, Args, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand, ValueEnum,
+    ValueSource,
+};
 use polyglot_code_scanner::coupling::CouplingConfig;
-use polyglot_code_scanner::{FeatureFlags, ScannerConfig};
+use polyglot_code_scanner::{
+    default_comment_prefixes, global_ignore_file, set_progress_format, with_timestamp_suffix,
+    AreasOfInterestConfig, AtomicFile, BenchFixtureConfig, BlameConfig, BulkCommitConfig,
+    BulkCommitMode, CancellationToken, CategoryRule, ChurnPerContributorConfig, CoAuthorParser,
+    CoAuthorRules, CommitCategoryRule, CommitClassifier, CommitDayTimezone, CommitSizeConfig,
+    ContributorChurnConfig, ContributorEndDate, DecayConfig, DirectoryTreeConfig, FeatureFlags,
+    FieldSelection, FileCategorizer, FormatVersion, GitDetailsFilter, InactiveConfig,
+    IndentationConfig, NodeLimitConfig, NodeLimitStrategy, ObjectLocation, OutputFormat,
+    ProgressFormat, PullRequestConfig, QuadrantConfig, RedactConfig, RenameConfig, ReportConfig,
+    ReportFormat, ScannerConfig, SecretRule, SecretScanner, SonarThresholds, Teams, TeamsConfig,
+    TimeBudgetConfig, UploadConfig, VendoredConfig, VendoredMode, WorkingHoursConfig, CALCULATORS,
+    CONTENT_CALCULATORS, DEFAULT_PERCENTILES, DEFAULT_TICKET_PATTERN,
+};
+use regex::Regex;
 use std::fs::File;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser)]
-#[clap(author, version)]
+#[clap(author, version, args_conflicts_with_subcommands = true)]
 /// Polyglot Code Scanner
 ///
 /// Scans source code and generates indicators that may (or may not) show toxic code.
@@ -27,12 +44,21 @@ struct Cli {
     )]
     /// Logging verbosity, v = error, vv = warn, vvv = info (default), vvvv = debug, vvvvv = trace
     verbose: u8,
-    /// Output file, stdout if not present, or not used if sending to web server
-    #[clap(short = 'o', long = "output", parse(from_os_str))]
-    output: Option<PathBuf>,
-    /// project name - identifies the selected data for display and state storage
+    #[clap(value_parser, short = 'q', long = "quiet", conflicts_with = "verbose")]
+    /// Only log errors, and show no progress - overrides `--progress-format`
+    quiet: bool,
+    /// Output file, stdout if not present, or not used if uploading - `s3://bucket/key` or `gs://bucket/key` writes to object storage, if built with the object-store feature
+    /// Files are written alongside with ".tmp" appended, and only replace the output once complete
+    /// Can be given several times to write several files from one scan - files ending in ".csv", ".prom" or ".html" are written
+    /// in that format, others in `--output-format`. Only the first can be in object storage, or uploaded
+    #[clap(short = 'o', long = "output", value_parser)]
+    output: Vec<PathBuf>,
+    #[clap(value_parser, long = "append-suffix-timestamp")]
+    /// Add the UTC scan time to the output file name, before the extension - e.g. "project-20221121T093000Z.json" - to keep dated outputs
+    append_suffix_timestamp: bool,
+    /// project name - identifies the selected data for display and state storage. Required when scanning, except for `--loc-report`
     #[clap(value_parser, short = 'n', long = "name")]
-    name: String,
+    name: Option<String>,
 
     /// data file ID - used to identify unique data files for browser storage, generates a UUID if not specified
     #[clap(value_parser, long = "id")]
@@ -41,26 +67,641 @@ struct Cli {
     #[clap(parse(from_os_str))]
     root: Option<PathBuf>,
 
+    #[clap(value_enum, long = "profile")]
+    /// Preset options for common uses - "quick" for a fast first look, "full" for every indicator that needs no
+    /// extra config plus coupling, "monorepo" for coupling between projects in a big repository.
+    /// Options given on the command line override the profile
+    profile: Option<ProfileArg>,
+
     // global indicator flags
+    #[clap(value_parser, long = "enable", value_delimiter = ',')]
+    /// Run these calculators as well as the defaults, e.g. "branch_count,license" - can be specified multiple times.
+    /// Calculators are: loc, indentation, nesting, branch_count, license, secrets, categories, syntax, annotations, git and file_stats
+    enable: Vec<String>,
+    #[clap(value_parser, long = "disable", value_delimiter = ',')]
+    /// Don't run these calculators, e.g. "nesting,file_stats" - can be specified multiple times
+    disable: Vec<String>,
+    #[clap(value_parser, long = "calc-config")]
+    /// Set a calculator option as CALCULATOR.OPTION=VALUE, e.g. "indentation.tab_width=2" - options are named as in the
+    /// output metadata, and values are JSON if they parse as JSON, otherwise strings. Can be specified multiple times
+    calc_config: Vec<String>,
     #[clap(value_parser, long = "no-git")]
-    /// Do not scan for git repositories
+    /// Do not scan for git repositories - the same as `--disable git`
     no_git: bool,
-    #[clap(value_parser, short = 'c', long = "coupling")]
-    /// include temporal coupling data
-    coupling: bool,
     #[clap(value_parser, long = "no-detailed-git")]
     /// Don't include detailed git information - output may be big!
     no_detailed_git: bool,
+    #[clap(
+        value_parser,
+        long = "git-details-for",
+        conflicts_with = "no_detailed_git"
+    )]
+    /// Only include detailed git information for files matching this gitignore-style glob, e.g. "src/core/**" - other files
+    /// still get summary git information. Can be specified multiple times
+    git_details_for: Vec<String>,
+    #[clap(value_parser, long = "nesting")]
+    /// Estimate block nesting depth per file, from braces or indentation - the same as `--enable nesting`
+    nesting: bool,
+    #[clap(value_parser, long = "branch-count")]
+    /// Count branching keywords and operators per file, as a cheap complexity estimate similar to scc
+    branch_count: bool,
+    #[clap(value_parser, long = "licenses")]
+    /// Find the license of each file from SPDX identifiers or common license headers, and summarise licenses in metadata
+    licenses: bool,
+    #[clap(value_parser, long = "secrets")]
+    /// Search file contents for possible secrets such as cloud keys and private keys - only rule names and counts are recorded
+    secrets: bool,
+    #[clap(value_parser, long = "secret-rule")]
+    /// Custom secret rule as NAME:REGEX - replaces the default rules. Can be specified multiple times
+    secret_rule: Vec<String>,
+    #[clap(value_parser, long = "annotations")]
+    /// Attach the component, owner and deprecation status declared in `.polyglot_annotations.toml` files to the directories and files they name
+    annotations: bool,
+    #[clap(value_parser, long = "categories")]
+    /// Tag each file with a category - build, ci, infrastructure, test, docs, asset or source - from its path
+    categories: bool,
+    #[clap(value_parser, long = "category")]
+    /// Custom category rule as NAME:GLOB, with a gitignore-style glob relative to the root - checked before the built-in rules. Can be specified multiple times
+    category: Vec<String>,
+    #[clap(value_parser, long = "syntax")]
+    /// Count functions, classes and parameters from parse trees - needs a build with the tree-sitter feature
+    syntax: bool,
+    #[clap(value_parser, long = "license-lines", default_value = "20")]
+    /// How many lines at the start of each file are searched for a license
+    license_lines: usize,
     #[clap(value_parser, long = "no-file-stats")]
-    /// Do not scan for file stats - mainly an option as this is very hard to unit test
+    /// Do not scan for file stats - mainly an option as this is very hard to unit test. The same as `--disable file_stats`
     no_file_stats: bool,
+    #[clap(value_parser, long = "no-content")]
+    /// Never read file contents - only names, sizes and git history are recorded, for repositories where content
+    /// inspection isn't allowed. Sizes are stored in file stats, as there is no loc data
+    no_content: bool,
+    #[clap(value_parser, long = "max-nodes")]
+    /// If the tree has more files and directories than this, replace some with synthetic "<other>" files so the
+    /// explorer can still render it - what was replaced is recorded in metadata
+    max_nodes: Option<u64>,
+    #[clap(value_enum, long = "max-nodes-strategy", default_value = "aggregate")]
+    /// How to keep under `--max-nodes` - "aggregate" replaces the smallest files in each directory first,
+    /// "truncate" replaces the contents of the deepest directories
+    max_nodes_strategy: NodeLimitStrategyArg,
+    #[clap(value_parser, long = "dirs-only")]
+    /// Leave out files - each directory gets totals for everything under it, for architecture-level views of huge systems
+    dirs_only: bool,
+    #[clap(value_parser, long = "depth-limit")]
+    /// As `--dirs-only`, but also leave out directories deeper than this, counting them in their ancestors - 1 keeps
+    /// only the top-level directories
+    depth_limit: Option<usize>,
+    #[clap(value_parser, long = "fields")]
+    /// Only write these indicator fields for each file and directory, e.g. "loc.code,git.age_in_days,indentation.p99" - a
+    /// bare indicator name like "indentation" writes all of it. Metadata is still written in full
+    fields: Option<String>,
+    #[clap(value_parser, long = "pretty")]
+    /// Write indented JSON, with the keys of every object sorted, so data files are readable and can be diffed. This needs
+    /// a copy of the whole tree in memory while writing
+    pretty: bool,
+    #[clap(value_parser, long = "redact-names")]
+    /// Replace file and directory names in the output with stable hashes, and remove remote urls, so the data can be
+    /// shared without leaking product names
+    redact_names: bool,
+    #[clap(
+        value_parser,
+        long = "redact-keep-extensions",
+        requires = "redact_names"
+    )]
+    /// Keep file extensions when redacting names, so languages are still visible
+    redact_keep_extensions: bool,
+    #[clap(value_parser, long = "redact-salt", requires = "redact_names")]
+    /// Secret mixed into redacted names, so common names can't be guessed from their hashes - use the same salt
+    /// to compare scans. Defaults to the POLYGLOT_REDACT_SALT environment variable
+    redact_salt: Option<String>,
 
     #[clap(value_parser, long = "years", default_value = "3")]
     /// how many years of git history to parse - default only scan the last 3 years (from now, not git head)
     git_years: u64,
+    #[clap(value_parser, long = "git-subtree-only")]
+    /// When scanning a subdirectory of a git repository, only process git changes under that subdirectory, and older changes to files renamed into it - faster for a small part of a big repository
+    git_subtree_only: bool,
+    #[clap(value_parser = parse_timezone, long = "timezone")]
+    /// Timezone for grouping commits into days - "utc" (the default), "author" for each commit's author timezone,
+    /// or a fixed offset like "+10:00" or "-05:30"
+    timezone: Option<CommitDayTimezone>,
+    #[clap(value_parser, long = "scan-time")]
+    /// Measure git days since creation and last change to this time, in seconds since the epoch, rather than now - for reproducible output
+    scan_time: Option<u64>,
+    #[clap(value_parser, long = "bulk-commit-files")]
+    /// Treat commits changing more than this many files as bulk commits, e.g. reformatting everything
+    bulk_commit_files: Option<usize>,
+    #[clap(value_parser, long = "bulk-commit-percent")]
+    /// Treat commits changing more than this percentage of all files changed in the git history as bulk commits
+    bulk_commit_percent: Option<f64>,
+    #[clap(value_parser, long = "classify-commits")]
+    /// Classify commits as fix/feature/refactor/other from their summaries, and count each category per file
+    classify_commits: bool,
+    #[clap(value_parser, long = "commit-category")]
+    /// Custom commit category as NAME:REGEX, matched against commit summaries - replaces the default categories.
+    /// Can be specified multiple times, the first match wins
+    commit_category: Vec<String>,
+    #[clap(value_parser, long = "ticket-pattern")]
+    /// Regex for ticket ids in commit messages, stored in git activity - defaults to Jira-style ids like "ABC-123" if coupling by ticket
+    ticket_pattern: Option<String>,
+    #[clap(value_parser, long = "co-author-trailer")]
+    /// Extra commit message trailer naming co-authors, e.g. "Signed-off-by" - "Co-authored-by" is always used.
+    /// Can be specified multiple times
+    co_author_trailer: Vec<String>,
+    #[clap(value_parser, long = "pair-pattern")]
+    /// Regex matched against commit summaries to find pairs, e.g. '^\[([^\]]+)\]' for "[AB/CD] summary".
+    /// The first capture group should hold names or initials separated by "/", ",", "&" or "+".
+    /// Can be specified multiple times
+    pair_pattern: Vec<String>,
+    #[clap(value_parser, long = "no-renames")]
+    /// Don't detect renamed files in git history - faster, but renamed files lose their earlier history
+    no_renames: bool,
+    #[clap(value_parser = clap::value_parser!(u16).range(0..=100), long = "rename-threshold")]
+    /// Percentage similarity for a deleted and added file to count as a rename - git's default is 50
+    rename_threshold: Option<u16>,
+    #[clap(value_parser, long = "rename-limit")]
+    /// Maximum number of files to compare for renames in a single commit - lower is faster on huge commits
+    rename_limit: Option<usize>,
+    #[clap(value_parser, long = "find-copies")]
+    /// Also detect files copied from other files changed in the same commit - slower
+    find_copies: bool,
+    #[clap(value_parser, long = "copy-history", requires = "find_copies")]
+    /// Give copied files the git history of the file they were copied from, up to the copy, so copy-pasted files
+    /// don't look brand new and unowned - inherited history is flagged, and not used for coupling
+    copy_history: bool,
+    #[clap(value_enum, long = "bulk-commits", default_value = "flag")]
+    /// What to do with bulk commits - "flag" marks them in git details and ignores them for coupling, "exclude" ignores them completely
+    bulk_commits: BulkCommitModeArg,
     #[clap(value_parser, long = "follow-symlinks")]
     /// Follow symbolic links when traversing directories
     follow_symlinks: bool,
+    #[clap(value_parser, long = "hidden")]
+    /// Include hidden files and directories - `.git` directories are never scanned
+    hidden: bool,
+    #[clap(value_parser, long = "no-ignore")]
+    /// Don't skip files matched by `.gitignore`, `.ignore` or global git excludes - `.polyglot_code_scanner_ignore` files still apply
+    no_ignore: bool,
+    #[clap(value_parser, long = "max-depth")]
+    /// Don't scan more than this many directories below the root
+    max_depth: Option<usize>,
+    #[clap(long = "ignore-file", parse(from_os_str))]
+    /// Extra file of ignore rules in `.gitignore` syntax. Can be specified multiple times
+    ignore_file: Vec<PathBuf>,
+    #[clap(value_parser, long = "global-ignore")]
+    /// Also use your own ignore rules from `~/.config/polyglot_code_scanner/ignore`, or under `$XDG_CONFIG_HOME` if set -
+    /// off by default, so scans of the same code give the same results for everyone
+    global_ignore: bool,
+    #[clap(value_enum, long = "vendored", default_value = "skip")]
+    /// What to do with vendored dependency directories such as node_modules, vendor, third_party, Pods and Python virtualenvs -
+    /// "skip" doesn't scan them, "tag" scans them with a `vendored` flag, "include" treats them as normal code
+    vendored: VendoredModeArg,
+    #[clap(value_parser, long = "vendored-name")]
+    /// Extra directory name to treat as vendored, wherever it is. Can be specified multiple times
+    vendored_name: Vec<String>,
+    #[clap(long = "not-vendored", parse(from_os_str))]
+    /// Directory relative to the root that is never vendored, e.g. "src/vendor" for a project's own code. Can be specified multiple times
+    not_vendored: Vec<PathBuf>,
+    #[clap(long = "path-prefix", parse(from_os_str))]
+    /// Put everything in the output under these directories, e.g. "backend/" - useful for combining scans
+    path_prefix: Option<PathBuf>,
+    #[clap(value_parser, long = "local-paths")]
+    /// Record the absolute path of the scanned directory in metadata - this can show user names and how the scanning
+    /// machine is laid out, so is off by default
+    local_paths: bool,
+    #[clap(long = "files-from", parse(from_os_str))]
+    /// Only scan the files listed in this file, one per line relative to the root - use "-" to read from stdin.
+    /// Ignore files are not checked for listed files.
+    files_from: Option<PathBuf>,
+    #[clap(value_parser, long = "changed-since", conflicts_with = "files_from")]
+    /// Only scan files changed since this git commit, branch or tag, including uncommitted changes -
+    /// for fast scans of pull requests. The output is a partial tree, marked as incremental in its metadata
+    changed_since: Option<String>,
+    #[clap(value_parser, long = "tab-width", default_value = "4")]
+    /// How many spaces a tab counts as, when calculating indentation and nesting
+    tab_width: u32,
+    #[clap(value_parser, long = "language-tab-width")]
+    /// Tab width for a single language, as LANGUAGE:WIDTH e.g. "Makefile:8" - language names are as shown in loc data
+    /// Can be specified multiple times
+    language_tab_width: Vec<String>,
+    #[clap(value_parser, long = "max-lines")]
+    /// Only measure indentation and nesting for this many lines per file, marking larger files as truncated.
+    /// When set, files over 16MiB are also read a line at a time instead of being loaded into memory - their comments are counted as code
+    max_lines: Option<usize>,
+    #[clap(value_parser, long = "max-line-bytes", default_value = "1048576")]
+    /// Only measure this many bytes of each line. Files with longer lines - usually generated or minified - are read a line at a time
+    /// instead of being parsed by language, so their comments are counted as code
+    max_line_bytes: usize,
+    #[clap(value_parser, long = "max-parse-bytes", default_value = "268435456")]
+    /// Files bigger than this are read a line at a time instead of being parsed by language, even without `--max-lines`
+    max_parse_bytes: u64,
+    #[clap(value_parser, long = "comment-prefix")]
+    /// For files in languages that aren't recognised, lines starting with this are comments, ignored for indentation and nesting.
+    /// Can be specified multiple times, replacing the defaults of "#", "//", "/*", "*", "--", ";" and "<!--"
+    comment_prefix: Vec<String>,
+    #[clap(
+        value_parser,
+        long = "no-comment-filter",
+        conflicts_with = "comment_prefix"
+    )]
+    /// Treat every line of files in unrecognised languages as code
+    no_comment_filter: bool,
+    #[clap(value_parser = clap::value_parser!(u8).range(0..=100), long = "indentation-percentile")]
+    /// Output this percentile of each file's indentation, e.g. 95 for "p95" - 50 is output as the median.
+    /// Can be specified multiple times, replacing the defaults of 50, 75, 90 and 99
+    indentation_percentile: Vec<u8>,
+    #[clap(value_parser, long = "indentation-histogram")]
+    /// Also output each file's full indentation histogram, in the compressed HdrHistogram format, for calculating other statistics later
+    indentation_histogram: bool,
+    #[clap(flatten)]
+    coupling: CouplingArgs,
+    #[clap(value_parser, long = "keep-git-activity")]
+    /// Keep fine-grained git activity in the output, so coupling can be recalculated later with `reprocess` - output will be much bigger!
+    keep_git_activity: bool,
+    #[clap(value_parser, long = "blame")]
+    /// Record current line ownership per user from `git blame` - slow, so limited to smaller files and can be sampled
+    blame: bool,
+    #[clap(value_parser, long = "blame-max-bytes", default_value = "262144")]
+    /// Files bigger than this aren't blamed
+    blame_max_bytes: u64,
+    #[clap(value_parser, long = "blame-max-files")]
+    /// Stop blaming after this many files
+    blame_max_files: Option<usize>,
+    #[clap(value_parser, long = "blame-sample-every", default_value = "1")]
+    /// Only blame every Nth file that is small enough, e.g. 10 to blame a tenth of files
+    blame_sample_every: usize,
+    #[clap(value_parser, long = "line-age")]
+    /// Estimate how old the current lines of each file are, from git history - exact for files that are blamed
+    line_age: bool,
+    #[clap(value_parser, long = "inactive-months")]
+    /// Contributors with no commits in this many months are inactive - the share of each file last changed by inactive contributors is recorded
+    inactive_months: Option<u64>,
+    #[clap(value_parser, long = "contributor-end-date")]
+    /// Contributor end date as EMAIL=YYYY-MM-DD, e.g. for people who have left - they are inactive after this date.
+    /// Can be specified multiple times
+    contributor_end_date: Vec<String>,
+    #[clap(value_parser, long = "pull-requests")]
+    /// Fetch recent merged pull requests from the GitHub or GitLab API, and count them for each file, with their mean turnaround.
+    /// Needs a build with the pull-requests feature
+    pull_requests: bool,
+    #[clap(
+        long = "api-token-file",
+        parse(from_os_str),
+        requires = "pull_requests"
+    )]
+    /// File holding a GitHub or GitLab API token for `--pull-requests` - defaults to the GITHUB_TOKEN or GITLAB_TOKEN environment variable.
+    /// Tokens aren't taken as arguments, as those can be seen by other users
+    api_token_file: Option<PathBuf>,
+    #[clap(value_parser, long = "max-pull-requests", default_value = "200")]
+    /// How many of the most recent merged pull requests to fetch per repository - each needs an API call
+    max_pull_requests: usize,
+    #[clap(value_parser, long = "working-hours")]
+    /// Count commits by hour of the day and day of the week, in each author's timezone, for each file and contributor -
+    /// including weekend and late-night commits
+    working_hours: bool,
+    #[clap(value_parser = clap::value_parser!(u8).range(0..=23), long = "late-night-from", default_value = "22")]
+    /// Commits from this hour of the day count as late night, for `--working-hours`
+    late_night_from: u8,
+    #[clap(value_parser = clap::value_parser!(u8).range(0..=23), long = "late-night-until", default_value = "6")]
+    /// Commits before this hour of the day count as late night, for `--working-hours`
+    late_night_until: u8,
+    #[clap(long = "teams", parse(from_os_str))]
+    /// JSON file mapping users to teams by email, email domain or pattern - commits per team are counted for each file
+    teams: Option<PathBuf>,
+    #[clap(value_parser, long = "decay")]
+    /// Calculate a code decay score per file, combining age, churn and contributors who have left
+    decay: bool,
+    #[clap(value_parser, long = "decay-weights", default_value = "1,1,1")]
+    /// Weights for the decay score, as AGE,CHURN,ATTRITION
+    decay_weights: String,
+    #[clap(value_parser, long = "decay-max-age-days", default_value = "730")]
+    /// Files unchanged for this many days have the maximum decay age
+    decay_max_age_days: u64,
+    #[clap(value_parser, long = "decay-inactive-days", default_value = "365")]
+    /// Contributors with no commits for this many days are treated as having left, for decay attrition
+    decay_inactive_days: u64,
+    #[clap(value_parser, long = "quadrant")]
+    /// Classify each file as high or low churn and high or low complexity, relative to the rest of the scan - for
+    /// prioritised refactoring lists. Needs git and indentation
+    quadrant: bool,
+    #[clap(value_parser = clap::value_parser!(u8).range(0..=100), long = "quadrant-churn-percentile", default_value = "75")]
+    /// Files with more commits than this percentile of files are high churn
+    quadrant_churn_percentile: u8,
+    #[clap(value_parser = clap::value_parser!(u8).range(0..=100), long = "quadrant-complexity-percentile", default_value = "75")]
+    /// Files with a bigger indentation sum than this percentile of files are high complexity
+    quadrant_complexity_percentile: u8,
+    #[clap(value_parser, long = "contributor-churn")]
+    /// Count new and departed contributors per directory, to find onboarding and attrition hotspots
+    contributor_churn: bool,
+    #[clap(value_parser, long = "contributor-churn-days", default_value = "180")]
+    /// Contributors whose first commit to a directory is within this many days of the latest change are new; those with no commits in that time have departed
+    contributor_churn_days: u64,
+    #[clap(value_parser, long = "churn-per-contributor")]
+    /// Measure recent commits and lines changed per contributor-month for each directory, so areas with big teams don't look
+    /// like hotspots just because of headcount
+    churn_per_contributor: bool,
+    #[clap(
+        value_parser,
+        long = "churn-per-contributor-days",
+        default_value = "180"
+    )]
+    /// Only changes within this many days of the latest change are counted for churn per contributor
+    churn_per_contributor_days: u64,
+    #[clap(value_parser, long = "pairing")]
+    /// Measure the share of commits with co-authors per file and directory, for pairing and mobbing
+    pairing: bool,
+    #[clap(value_parser, long = "commit-sizes")]
+    /// Summarise the lines each commit changed in each file - median, mean, and the share of changes from giant commits - to
+    /// tell files changed by small tweaks from files hit by occasional big edits
+    commit_sizes: bool,
+    #[clap(value_parser, long = "giant-commit-lines", default_value = "500")]
+    /// Commits changing more lines of a file than this are giant commits, for commit sizes
+    giant_commit_lines: u64,
+    #[clap(value_parser, long = "areas-of-interest")]
+    /// List this many notable files and directories in metadata - the largest files, the oldest untouched files, the files with
+    /// the most authors, and the deepest directories - so simple tools can show highlights without walking the tree
+    areas_of_interest: Option<usize>,
+    #[clap(value_enum, long = "progress-format", default_value = "bars")]
+    /// How to show progress - "json" writes one JSON event per line to stderr, for wrapping the scanner in other tools
+    progress_format: ProgressFormatArg,
+    #[clap(value_parser, long = "timings")]
+    /// Include timings for each scan phase and calculator in the output metadata
+    timings: bool,
+    #[clap(value_parser = parse_time_budget, long = "time-budget")]
+    /// Plan the scan to finish within this time, e.g. "30m", "2h" or "90s" - if it won't, coupling is skipped, then fewer
+    /// years of git history are read, then only a sample of files is scanned. What was left out is recorded in metadata
+    time_budget: Option<u64>,
+    #[clap(value_parser, long = "max-memory")]
+    /// Soft memory limit in MB - checked while walking and loading git history: if the scan is estimated to be bigger, git activity and then detailed git data are dropped, and coupling is skipped
+    max_memory: Option<u64>,
+    #[clap(long = "timings-file", parse(from_os_str))]
+    /// Write timings for each scan phase and calculator to a separate JSON file
+    timings_file: Option<PathBuf>,
+    #[clap(long = "report", parse(from_os_str))]
+    /// Also write a report of the top files to this file - HTML if it ends in .html, otherwise markdown
+    report: Option<PathBuf>,
+    #[clap(value_enum, long = "format-version", default_value = "1.2")]
+    /// Data file format to write - older formats drop everything newer, for explorers that only understand them
+    format_version: FormatVersionArg,
+    #[clap(value_enum, long = "output-format", default_value = "polyglot")]
+    /// Output structure - flare writes a classic D3 flare file, with just names, children, and lines of code as each file's value.
+    /// html writes a standalone page with the data embedded in a minimal treemap viewer, for sharing without the explorer.
+    /// csv writes a row of summary values per file, prometheus writes totals per language as Prometheus metrics
+    output_format: OutputFormatArg,
+    #[clap(value_parser, long = "loc-report")]
+    /// Just count lines of code, and print a table of files, blank, comment and code lines per language instead of JSON - like cloc or scc
+    loc_report: bool,
+    #[clap(value_parser, long = "estimate")]
+    /// Don't scan - sample the tree and git log, and print a rough estimate of scan time and output size for each enabled feature
+    estimate: bool,
+    #[clap(value_parser, long = "upload")]
+    /// POST the data file to this url when the scan is done - as well as writing it, if `--output` is given.
+    /// Needs a build with the upload feature
+    upload: Option<String>,
+    #[clap(long = "upload-token-file", parse(from_os_str), requires = "upload")]
+    /// File holding a bearer token for uploads - defaults to the POLYGLOT_UPLOAD_TOKEN environment variable.
+    /// Tokens aren't taken as arguments, as those can be seen by other users
+    upload_token_file: Option<PathBuf>,
+    #[clap(value_parser, long = "upload-retries", default_value = "3")]
+    /// How many times to retry uploads after connection failures or server errors
+    upload_retries: u32,
+    #[clap(value_parser, long = "bench-fixture", parse(from_os_str))]
+    /// Don't scan - generate a synthetic git repository in this new directory, for benchmarking and profiling
+    bench_fixture: Option<PathBuf>,
+    #[clap(value_parser, long = "bench-fixture-files", default_value = "1000")]
+    /// How many files the benchmark fixture has
+    bench_fixture_files: usize,
+    #[clap(value_parser, long = "bench-fixture-commits", default_value = "500")]
+    /// How many commits the benchmark fixture has
+    bench_fixture_commits: usize,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BulkCommitModeArg {
+    Flag,
+    Exclude,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum VendoredModeArg {
+    Skip,
+    Tag,
+    Include,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatVersionArg {
+    #[clap(name = "1.0")]
+    V1_0,
+    #[clap(name = "1.1")]
+    V1_1,
+    #[clap(name = "1.2")]
+    V1_2,
+}
+
+impl FormatVersionArg {
+    fn version(self) -> FormatVersion {
+        match self {
+            FormatVersionArg::V1_0 => FormatVersion::V1_0,
+            FormatVersionArg::V1_1 => FormatVersion::V1_1,
+            FormatVersionArg::V1_2 => FormatVersion::Latest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormatArg {
+    Polyglot,
+    Flare,
+    Html,
+    Csv,
+    Prometheus,
+}
+
+impl OutputFormatArg {
+    fn format(self) -> OutputFormat {
+        match self {
+            OutputFormatArg::Polyglot => OutputFormat::Polyglot,
+            OutputFormatArg::Flare => OutputFormat::Flare,
+            OutputFormatArg::Html => OutputFormat::Html,
+            OutputFormatArg::Csv => OutputFormat::Csv,
+            OutputFormatArg::Prometheus => OutputFormat::Prometheus,
+        }
+    }
+}
+
+/// the format for an output file, from its extension if that names a format
+fn output_format_for(output: &Path, default: OutputFormatArg) -> OutputFormat {
+    match output.extension().and_then(std::ffi::OsStr::to_str) {
+        Some("csv") => OutputFormat::Csv,
+        Some("prom") => OutputFormat::Prometheus,
+        Some("html") => OutputFormat::Html,
+        _ => default.format(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ReportFormatArg {
+    Markdown,
+    Html,
+}
+
+impl ReportFormatArg {
+    fn format(self) -> ReportFormat {
+        match self {
+            ReportFormatArg::Markdown => ReportFormat::Markdown,
+            ReportFormatArg::Html => ReportFormat::Html,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum NodeLimitStrategyArg {
+    Aggregate,
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ProgressFormatArg {
+    Bars,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ProfileArg {
+    Quick,
+    Full,
+    Monorepo,
+}
+
+/// applies `--profile` settings to any options that weren't given on the command line
+fn apply_profile(args: &mut Cli, matches: &ArgMatches) {
+    let profile = match args.profile {
+        Some(profile) => profile,
+        None => return,
+    };
+    let defaulted = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    let git = !(args.no_git || args.disable.iter().any(|name| name == "git"));
+    let content = !args.no_content;
+    match profile {
+        ProfileArg::Quick => {
+            if defaulted("git_years") {
+                args.git_years = 1;
+            }
+            if defaulted("no_detailed_git") && args.git_details_for.is_empty() {
+                args.no_detailed_git = true;
+            }
+        }
+        ProfileArg::Full => {
+            for (id, enabled, needs_content) in [
+                ("nesting", &mut args.nesting, true),
+                ("branch_count", &mut args.branch_count, true),
+                ("licenses", &mut args.licenses, true),
+                ("secrets", &mut args.secrets, true),
+                ("categories", &mut args.categories, false),
+            ] {
+                if defaulted(id) && (content || !needs_content) {
+                    *enabled = true;
+                }
+            }
+            if git {
+                if defaulted("git_years") {
+                    args.git_years = 5;
+                }
+                if defaulted("line_age") {
+                    args.line_age = true;
+                }
+                if defaulted("coupling") {
+                    args.coupling.coupling = true;
+                }
+            }
+        }
+        ProfileArg::Monorepo => {
+            if git {
+                if defaulted("git_years") {
+                    args.git_years = 2;
+                }
+                if defaulted("coupling") {
+                    args.coupling.coupling = true;
+                }
+                // e.g. "packages/a/..." and "packages/b/..." but not two files in "packages/a"
+                if defaulted("coupling_max_common_roots") {
+                    args.coupling.coupling_max_common_roots = Some(1);
+                }
+            }
+        }
+    }
+}
+
+/// checks `--enable` and `--disable` names, and applies them to the calculators with their own flags - other
+/// options depend on git, file stats and nesting, so check those flags rather than calculator names
+fn apply_calculator_selection(args: &mut Cli) {
+    for name in args.enable.iter().chain(&args.disable) {
+        if !CALCULATORS.contains(&name.as_str()) {
+            custom_validation_conflict(&format!(
+                "Unknown calculator '{}' - should be one of {}",
+                name,
+                CALCULATORS.join(", ")
+            ));
+        }
+        if args.enable.contains(name) && args.disable.contains(name) {
+            custom_validation_conflict(&format!(
+                "Can't both enable and disable the {} calculator!",
+                name
+            ));
+        }
+        if args.no_content
+            && args.enable.contains(name)
+            && CONTENT_CALCULATORS.contains(&name.as_str())
+        {
+            custom_validation_conflict(&format!(
+                "Can't enable the {} calculator without reading file contents!",
+                name
+            ));
+        }
+    }
+    for (name, disabled) in [
+        ("git", &mut args.no_git),
+        ("file_stats", &mut args.no_file_stats),
+    ] {
+        if args.enable.iter().any(|enabled| enabled == name) {
+            *disabled = false;
+        }
+        if args.disable.iter().any(|disabled| disabled == name) {
+            *disabled = true;
+        }
+    }
+    if args.enable.iter().any(|enabled| enabled == "nesting") {
+        args.nesting = true;
+    }
+    if args.disable.iter().any(|disabled| disabled == "nesting") {
+        args.nesting = false;
+    }
+}
+
+/// parses and sets a "calculator.option=value" spec - exits if invalid
+fn apply_calculator_option(config: &mut ScannerConfig, spec: &str) {
+    let result = match spec.split_once('=') {
+        Some((option, value)) => config.set_calculator_option(option, value),
+        None => Err(anyhow::anyhow!("should be CALCULATOR.OPTION=VALUE")),
+    };
+    if let Err(e) = result {
+        let mut cmd = Cli::command();
+        cmd.error(
+            ErrorKind::ValueValidation,
+            format!("Invalid calculator option '{}' - {}", spec, e),
+        )
+        .exit()
+    }
+}
+
+/// Coupling options, shared by scanning and reprocessing
+#[derive(Debug, Args)]
+struct CouplingArgs {
+    #[clap(value_parser, short = 'c', long = "coupling")]
+    /// include temporal coupling data
+    coupling: bool,
     #[clap(value_parser, long = "coupling-bucket-days", default_value = "91")]
     /// Number of days in a single "bucket" of coupling activity
     bucket_days: u64,
@@ -94,6 +735,338 @@ struct Cli {
     /// e.g. "foo/src/controller/a.c" and "foo/src/service/b.c" have two common ancestors, if you
     /// set this value to 3 they won't show as coupled.
     coupling_max_common_roots: Option<usize>,
+    #[clap(value_parser, long = "coupling-max-p-value")]
+    /// Only keep coupling this unlikely to be coincidence, given how often each file changes - e.g. 0.05
+    /// The p-value of each coupled file is stored alongside the coupling counts
+    coupling_max_p_value: Option<f64>,
+    #[clap(value_parser, long = "coupling-by-ticket")]
+    /// Couple files changed for the same ticket id, however far apart in time, instead of files changed at around the same time
+    /// Each ticket counts as an activity burst - see `--ticket-pattern`
+    coupling_by_ticket: bool,
+    #[clap(value_parser, long = "coupling-named")]
+    /// Extra named coupling data with a different bucket size, as NAME:BUCKET_DAYS e.g. "yearly:365"
+    /// Can be specified multiple times, other coupling settings are shared with the main coupling data
+    coupling_named: Vec<String>,
+    #[clap(long = "architecture", parse(from_os_str))]
+    /// JSON file mapping paths to architecture components - coupling is summarised by component, and coupling across component boundaries is reported
+    architecture: Option<PathBuf>,
+    #[clap(long = "coupling-checkpoint", parse(from_os_str))]
+    /// Save coupling progress to this file every few minutes, and resume from it if a previous run was interrupted
+    /// It is deleted once coupling finishes - named coupling uses this name with ".NAME" appended
+    coupling_checkpoint: Option<PathBuf>,
+}
+
+impl CouplingArgs {
+    /// validates and builds coupling configs - the main config, and any named configs
+    fn configs(&self) -> (Option<CouplingConfig>, Vec<(String, CouplingConfig)>) {
+        if !self.coupling && !self.coupling_named.is_empty() {
+            custom_validation_conflict("Can't specify named coupling without enabling coupling!");
+        }
+        if !self.coupling && self.architecture.is_some() {
+            custom_validation_conflict(
+                "Can't specify an architecture file without enabling coupling!",
+            );
+        }
+        if !self.coupling && self.coupling_checkpoint.is_some() {
+            custom_validation_conflict(
+                "Can't specify a coupling checkpoint without enabling coupling!",
+            );
+        }
+        if self.coupling_by_ticket && self.coupling_max_p_value.is_some() {
+            custom_validation_conflict(
+                "Can't test coupling significance when coupling by ticket - there is no time window!",
+            );
+        }
+        if let Some(p_value) = self.coupling_max_p_value {
+            if !(0.0..=1.0).contains(&p_value) {
+                custom_validation_conflict("Coupling max p-value must be between 0 and 1!");
+            }
+        }
+        let named_coupling: Vec<(String, u64)> = self
+            .coupling_named
+            .iter()
+            .map(|spec| parse_named_coupling(spec))
+            .collect();
+        for (index, (name, _)) in named_coupling.iter().enumerate() {
+            if named_coupling[..index]
+                .iter()
+                .any(|(earlier, _)| earlier == name)
+            {
+                custom_validation_conflict(&format!(
+                    "Named coupling '{}' is specified more than once!",
+                    name
+                ));
+            }
+        }
+        if !self.coupling {
+            return (None, Vec::new());
+        }
+        let coupling_config = CouplingConfig::new(
+            self.bucket_days,
+            self.min_activity_bursts,
+            self.min_coupling_ratio,
+            self.min_activity_gap_minutes * 60,
+            self.min_overlap_minutes * 60,
+            self.coupling_min_distance,
+            self.coupling_max_common_roots,
+        )
+        .with_max_p_value(self.coupling_max_p_value)
+        .with_ticket_coupling(self.coupling_by_ticket);
+        let named_coupling = named_coupling
+            .into_iter()
+            .map(|(name, bucket_days)| (name, coupling_config.with_bucket_days(bucket_days)))
+            .collect();
+        (Some(coupling_config), named_coupling)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Merge existing data files into one, with each file as a top-level directory
+    Merge {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        /// Output file, stdout if not present
+        #[clap(short = 'o', long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// project name for the merged data
+        #[clap(value_parser, short = 'n', long = "name")]
+        name: String,
+        /// data file ID for the merged data, generates a UUID if not specified
+        #[clap(value_parser, long = "id")]
+        id: Option<String>,
+        /// Data files to merge
+        #[clap(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Export files over indicator thresholds from an existing data file as SonarQube generic issues
+    Sonar {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        /// Output file, stdout if not present
+        #[clap(short = 'o', long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[clap(value_parser, long = "max-code-lines", default_value = "1000")]
+        /// Files with more lines of code than this are "huge-file" issues - 0 disables the check
+        max_code_lines: usize,
+        #[clap(value_parser, long = "max-indentation", default_value = "24")]
+        /// Files where 10% of lines are indented more spaces than this are "deep-indentation" issues - 0 disables the check
+        max_indentation: u64,
+        #[clap(value_parser, long = "max-commits", default_value = "100")]
+        /// Files changed in more commits than this are "high-churn" issues - needs detailed git data. 0 disables the check
+        max_commits: u64,
+        /// Data file to export
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Write a human-readable report of hotspots, the biggest files, the most coupled files, and files mostly changed by one person,
+    /// from an existing data file
+    Report {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        /// Output file, stdout if not present
+        #[clap(short = 'o', long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[clap(value_enum, long = "format", default_value = "markdown")]
+        /// Report format
+        format: ReportFormatArg,
+        #[clap(value_parser, long = "top", default_value = "10")]
+        /// How many files to list in each section
+        top: usize,
+        #[clap(value_parser, long = "min-owner-commits", default_value = "5")]
+        /// Files with fewer commits than this aren't listed as mostly changed by one person - needs detailed git data
+        min_owner_commits: u64,
+        /// Data file to report on
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Check an existing data file - that its version is compatible, and that the users, repositories and coupled files
+    /// it refers to exist - useful after hand-merging or editing data files. Exits with an error if there are problems
+    Validate {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        /// Data file to check
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Scan a single file with the file-level calculators and print its indicators as JSON - quick enough for editors and pre-commit hooks,
+    /// as git and coupling are skipped
+    File {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        #[clap(value_parser, long = "nesting")]
+        /// Estimate block nesting depth, as for scanning
+        nesting: bool,
+        #[clap(value_parser, long = "branch-count")]
+        /// Count branching keywords and operators, as for scanning
+        branch_count: bool,
+        #[clap(value_parser, long = "licenses")]
+        /// Find the file's license, as for scanning
+        licenses: bool,
+        #[clap(value_parser, long = "secrets")]
+        /// Search the file for possible secrets, as for scanning
+        secrets: bool,
+        #[clap(value_parser, long = "categories")]
+        /// Find the file's category from its name, with the built-in rules
+        categories: bool,
+        #[clap(value_parser, long = "syntax")]
+        /// Count functions, classes and parameters, as for scanning
+        syntax: bool,
+        /// File to scan
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Reprocess an existing data file - recalculate coupling, or strip details, without rescanning
+    Reprocess {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        /// Output file, stdout if not present
+        #[clap(short = 'o', long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[clap(value_parser, long = "no-detailed-git")]
+        /// Remove detailed git information
+        no_detailed_git: bool,
+        #[clap(
+            value_parser,
+            long = "git-details-for",
+            conflicts_with = "no_detailed_git"
+        )]
+        /// Remove detailed git information except for files matching this glob, as for scanning
+        git_details_for: Vec<String>,
+        #[clap(flatten)]
+        coupling: CouplingArgs,
+        #[clap(value_parser, long = "keep-git-activity")]
+        /// Keep fine-grained git activity in the output, so it can be reprocessed again
+        keep_git_activity: bool,
+        #[clap(value_enum, long = "format-version", default_value = "1.2")]
+        /// Data file format to write, as for scanning
+        format_version: FormatVersionArg,
+        #[clap(value_enum, long = "output-format", default_value = "polyglot")]
+        /// Output structure, as for scanning
+        output_format: OutputFormatArg,
+        #[clap(value_parser, long = "pretty")]
+        /// Write indented JSON with sorted keys, as for scanning
+        pretty: bool,
+        /// Data file to reprocess - to recalculate coupling it must have been scanned with `--keep-git-activity`
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Look at a directory - files, languages and git history - and write a script to scan it with recommended settings,
+    /// warning about settings that will be slow
+    Init {
+        #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        /// Logging verbosity, as for scanning
+        verbose: u8,
+        #[clap(value_parser, short = 'y', long = "yes")]
+        /// Accept the recommended settings without asking
+        yes: bool,
+        #[clap(
+            short = 'o',
+            long = "output",
+            parse(from_os_str),
+            default_value = "polyglot_scan.sh"
+        )]
+        /// Script to write
+        output: PathBuf,
+        /// Root directory to look at, current dir if not present
+        #[clap(parse(from_os_str))]
+        root: Option<PathBuf>,
+    },
+}
+
+/// asks a question on stdin, returning the default for an empty answer
+fn ask(question: &str, default: &str) -> Result<String, Error> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_owned()
+    } else {
+        answer.to_owned()
+    })
+}
+
+fn init(root: &Path, output: &Path, yes: bool) -> Result<(), Error> {
+    if output.exists() {
+        anyhow::bail!(
+            "{} already exists - remove it or choose another --output",
+            output.display()
+        );
+    }
+    let max_years = 3;
+    let survey = polyglot_code_scanner::survey(root, max_years)?;
+    let mut languages: Vec<(&String, &u64)> = survey.languages.iter().collect();
+    languages.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+    println!(
+        "{} files, {} bytes - mostly {}",
+        survey.files,
+        survey.bytes,
+        languages
+            .iter()
+            .take(5)
+            .map(|(name, files)| format!("{} ({})", name, files))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for repository in &survey.repositories {
+        println!(
+            "{}: {}{} commits in the last {} years, ~{:.1} files per commit",
+            repository.workdir.display(),
+            repository.commits(max_years),
+            if repository.truncated { "+" } else { "" },
+            max_years,
+            repository.files_per_commit
+        );
+    }
+    let mut recommendation = polyglot_code_scanner::recommend(&survey, max_years);
+    for warning in &recommendation.warnings {
+        println!("Warning: {}", warning);
+    }
+    let default_name = root.canonicalize()?.file_name().map_or_else(
+        || "project".to_owned(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let name = if yes {
+        default_name
+    } else {
+        let name = ask("Project name", &default_name)?;
+        recommendation.git_years = ask(
+            "Years of git history",
+            &recommendation.git_years.to_string(),
+        )?
+        .parse()
+        .context("years of git history must be a number")?;
+        let coupling = ask(
+            &format!(
+                "Calculate coupling - roughly {} seconds",
+                survey.coupling_estimate(recommendation.git_years).as_secs()
+            ),
+            if recommendation.coupling { "y" } else { "n" },
+        )?;
+        recommendation.coupling = coupling.to_lowercase().starts_with('y');
+        name
+    };
+    let data_file = PathBuf::from(format!("{}.json", name));
+    std::fs::write(output, recommendation.script(&name, root, &data_file))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o755))?;
+    }
+    println!(
+        "Wrote {} - run it to scan {}",
+        output.display(),
+        root.display()
+    );
+    Ok(())
 }
 
 // very basic logging - just so I can have a nice default, and hide verbose tokei logs
@@ -133,73 +1106,1051 @@ fn custom_validation_conflict(message: &str) {
     cmd.error(ErrorKind::ArgumentConflict, message).exit()
 }
 
+/// parses "name:bucket_days" named coupling specs - exits if invalid
+fn parse_named_coupling(spec: &str) -> (String, u64) {
+    if let Some((name, days)) = spec.split_once(':') {
+        if let Ok(days) = days.parse::<u64>() {
+            if !name.is_empty() && days > 0 {
+                return (name.to_owned(), days);
+            }
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!(
+            "Invalid named coupling '{}' - should be NAME:BUCKET_DAYS",
+            spec
+        ),
+    )
+    .exit()
+}
+
+/// parses "name:regex" specs - exits if invalid, describing the spec as `kind`
+fn parse_named_pattern(spec: &str, kind: &str) -> (String, String) {
+    if let Some((name, pattern)) = spec.split_once(':') {
+        if !name.is_empty() && !pattern.is_empty() {
+            return (name.to_owned(), pattern.to_owned());
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!("Invalid {} '{}' - should be NAME:REGEX", kind, spec),
+    )
+    .exit()
+}
+
+/// parses "name:regex" commit category specs - exits if invalid
+fn parse_commit_category(spec: &str) -> CommitCategoryRule {
+    let (name, pattern) = parse_named_pattern(spec, "commit category");
+    CommitCategoryRule { name, pattern }
+}
+
+/// parses "name:regex" secret rule specs - exits if invalid
+fn parse_secret_rule(spec: &str) -> SecretRule {
+    let (name, pattern) = parse_named_pattern(spec, "secret rule");
+    SecretRule { name, pattern }
+}
+
+/// a filter for which files keep git details, if any patterns are given
+fn git_details_filter(patterns: Vec<String>) -> Result<Option<GitDetailsFilter>, Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(GitDetailsFilter::new(patterns)?))
+}
+
+/// parses "name:glob" category rule specs - exits if invalid
+fn parse_category_rule(spec: &str) -> CategoryRule {
+    if let Some((category, pattern)) = spec.split_once(':') {
+        if !category.is_empty() && !pattern.is_empty() {
+            return CategoryRule {
+                category: category.to_owned(),
+                pattern: pattern.to_owned(),
+            };
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!("Invalid category rule '{}' - should be NAME:GLOB", spec),
+    )
+    .exit()
+}
+
+/// parses "email=YYYY-MM-DD" contributor end dates - exits if invalid
+fn parse_contributor_end_date(spec: &str) -> ContributorEndDate {
+    if let Some((email, date)) = spec.rsplit_once('=') {
+        let end_date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| u64::try_from(date.and_hms(0, 0, 0).timestamp()).ok());
+        if let Some(end_date) = end_date {
+            if !email.is_empty() {
+                return ContributorEndDate {
+                    email: email.to_owned(),
+                    end_date,
+                };
+            }
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!(
+            "Invalid contributor end date '{}' - should be EMAIL=YYYY-MM-DD",
+            spec
+        ),
+    )
+    .exit()
+}
+
+/// parses "loc.code,git.age_in_days" field selections - exits if invalid
+fn parse_fields(spec: &str) -> FieldSelection {
+    FieldSelection::parse(spec).unwrap_or_else(|e| {
+        let mut cmd = Cli::command();
+        cmd.error(ErrorKind::ValueValidation, e.to_string()).exit()
+    })
+}
+
+/// parses "30m" style durations into seconds - "s", "m" or "h", or seconds with no unit
+fn parse_time_budget(spec: &str) -> Result<u64, String> {
+    let spec = spec.trim();
+    let (number, multiplier) = match spec.chars().last() {
+        Some('s') => (&spec[..spec.len() - 1], 1),
+        Some('m') => (&spec[..spec.len() - 1], 60),
+        Some('h') => (&spec[..spec.len() - 1], 60 * 60),
+        _ => (spec, 1),
+    };
+    match number
+        .parse::<u64>()
+        .ok()
+        .filter(|value| *value > 0)
+        .and_then(|value| value.checked_mul(multiplier))
+    {
+        Some(seconds) => Ok(seconds),
+        None => Err(format!(
+            "Invalid time budget '{}' - should be a number of seconds, or e.g. 30m or 2h",
+            spec
+        )),
+    }
+}
+
+/// parses "utc", "author" or "+HH:MM" style timezone offsets
+fn parse_timezone(spec: &str) -> Result<CommitDayTimezone, String> {
+    let spec = spec.trim();
+    match spec.to_ascii_lowercase().as_str() {
+        "utc" | "z" => return Ok(CommitDayTimezone::Utc),
+        "author" => return Ok(CommitDayTimezone::Author),
+        _ => {}
+    }
+    let invalid = || {
+        format!(
+            "Invalid timezone '{}' - should be utc, author, or an offset like +10:00",
+            spec
+        )
+    };
+    let sign = match spec.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = spec[1..].split_once(':').unwrap_or((&spec[1..], "0"));
+    match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+        (Ok(hours), Ok(minutes)) if (0..=14).contains(&hours) && (0..60).contains(&minutes) => {
+            Ok(CommitDayTimezone::Offset(sign * (hours * 60 + minutes)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// parses "language:width" tab width specs - exits if invalid
+fn parse_language_tab_width(spec: &str) -> (String, u32) {
+    if let Some((language, width)) = spec.rsplit_once(':') {
+        if let Ok(width) = width.parse::<u32>() {
+            if let Some(language) = IndentationConfig::language_name(language) {
+                return (language.to_owned(), width);
+            }
+            if !language.is_empty() {
+                let mut cmd = Cli::command();
+                cmd.error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "Unknown language '{}' in tab width '{}' - languages are named as shown in loc data",
+                        language, spec
+                    ),
+                )
+                .exit()
+            }
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!(
+            "Invalid language tab width '{}' - should be LANGUAGE:WIDTH",
+            spec
+        ),
+    )
+    .exit()
+}
+
+/// parses "age,churn,attrition" decay weights - exits if invalid
+fn parse_decay_weights(spec: &str) -> (f64, f64, f64) {
+    // None if any weight is invalid, rather than skipping it and using the rest
+    let weights: Option<Vec<f64>> = spec
+        .split(',')
+        .map(|weight| {
+            weight
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|weight| weight.is_finite() && *weight >= 0.0)
+        })
+        .collect();
+    if let Some(&[age, churn, attrition]) = weights.as_deref() {
+        if age + churn + attrition > 0.0 {
+            return (age, churn, attrition);
+        }
+    }
+    let mut cmd = Cli::command();
+    cmd.error(
+        ErrorKind::ValueValidation,
+        format!(
+            "Invalid decay weights '{}' - should be AGE,CHURN,ATTRITION with at least one above zero",
+            spec
+        ),
+    )
+    .exit()
+}
+
+/// reads a newline-separated file list, from stdin if the file is "-"
+fn read_file_list(file: &Path) -> Result<Vec<PathBuf>, Error> {
+    let reader: Box<dyn BufRead> = if file == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(
+            File::open(file).with_context(|| format!("opening file list {:?}", file))?,
+        ))
+    };
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            files.push(PathBuf::from(line));
+        }
+    }
+    Ok(files)
+}
+
+/// where output goes - files are only replaced once `finish` is called, so a failed run never
+/// leaves truncated output behind
+enum OutputWriter {
+    File(AtomicFile),
+    Stdout(io::Stdout),
+}
+
+impl io::Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::File(file) => file.write(buf),
+            OutputWriter::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::File(file) => file.flush(),
+            OutputWriter::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl OutputWriter {
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            OutputWriter::File(file) => file.commit(),
+            OutputWriter::Stdout(mut stdout) => Ok(stdout.flush()?),
+        }
+    }
+}
+
+fn output_writer(output: Option<&Path>) -> Result<OutputWriter, Error> {
+    Ok(if let Some(output) = output {
+        OutputWriter::File(AtomicFile::create(output)?)
+    } else {
+        OutputWriter::Stdout(io::stdout())
+    })
+}
+
+/// sends a written data file to object storage and a web server, if they were requested
+fn send_output(
+    output: &Path,
+    object_output: Option<&ObjectLocation>,
+    upload: Option<&UploadConfig>,
+) -> Result<(), Error> {
+    if let Some(location) = object_output {
+        log::info!("writing {:?} to {}", output, location);
+        polyglot_code_scanner::upload_to_object_store(output, location)?;
+    }
+    if let Some(upload) = upload {
+        log::info!("uploading {:?} to {}", output, upload.url);
+        polyglot_code_scanner::upload_file(output, upload)?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Error> {
-    let args = Cli::from_args();
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    apply_profile(&mut args, &matches);
+    apply_calculator_selection(&mut args);
+
+    match args.command {
+        Some(Command::Merge {
+            verbose,
+            output,
+            name,
+            id,
+            files,
+        }) => {
+            setup_logging(verbose)?;
+            let mut out = output_writer(output.as_deref())?;
+            polyglot_code_scanner::merge(&files, &name, id.as_deref(), &mut out)?;
+            return out.finish();
+        }
+        Some(Command::Sonar {
+            verbose,
+            output,
+            max_code_lines,
+            max_indentation,
+            max_commits,
+            input,
+        }) => {
+            setup_logging(verbose)?;
+            let thresholds = SonarThresholds {
+                max_code_lines: Some(max_code_lines).filter(|max| *max > 0),
+                max_indentation_p90: Some(max_indentation).filter(|max| *max > 0),
+                max_commits: Some(max_commits).filter(|max| *max > 0),
+            };
+            let mut out = output_writer(output.as_deref())?;
+            polyglot_code_scanner::sonar_export(&input, &thresholds, &mut out)?;
+            return out.finish();
+        }
+        Some(Command::Report {
+            verbose,
+            output,
+            format,
+            top,
+            min_owner_commits,
+            input,
+        }) => {
+            setup_logging(verbose)?;
+            let config = ReportConfig {
+                format: format.format(),
+                top,
+                min_owner_commits,
+            };
+            let mut out = output_writer(output.as_deref())?;
+            polyglot_code_scanner::report(&input, &config, &mut out)?;
+            return out.finish();
+        }
+        Some(Command::Validate { verbose, input }) => {
+            setup_logging(verbose)?;
+            let problems = polyglot_code_scanner::validate(&input)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                anyhow::bail!("{} problems found in {:?}", problems.len(), input);
+            }
+            return Ok(());
+        }
+        Some(Command::File {
+            verbose,
+            nesting,
+            branch_count,
+            licenses,
+            secrets,
+            categories,
+            syntax,
+            file,
+        }) => {
+            setup_logging(verbose)?;
+            set_progress_format(ProgressFormat::Hidden);
+            let mut calculator_names: Vec<&str> = vec!["loc", "indentation"];
+            for (enabled, name) in [
+                (nesting, "nesting"),
+                (branch_count, "branch_count"),
+                (licenses, "license"),
+                (secrets, "secrets"),
+                (categories, "categories"),
+                (syntax, "syntax"),
+            ] {
+                if enabled {
+                    calculator_names.push(*name);
+                }
+            }
+            let config = ScannerConfig::default("");
+            let scanned = polyglot_code_scanner::scan_file(&file, &config, &calculator_names)?;
+            serde_json::to_writer_pretty(io::stdout(), &scanned)?;
+            println!();
+            return Ok(());
+        }
+        Some(Command::Reprocess {
+            verbose,
+            output,
+            no_detailed_git,
+            git_details_for,
+            coupling,
+            keep_git_activity,
+            format_version,
+            output_format,
+            pretty,
+            input,
+        }) => {
+            let (coupling_config, named_coupling) = coupling.configs();
+            setup_logging(verbose)?;
+            let mut config = ScannerConfig::default("");
+            config.features.git_details = !no_detailed_git;
+            config.git_details_for = git_details_filter(git_details_for)?;
+            config.named_coupling = named_coupling;
+            config.architecture_file = coupling.architecture;
+            config.coupling_checkpoint = coupling.coupling_checkpoint;
+            config.keep_git_activity = keep_git_activity;
+            config.format_version = format_version.version();
+            config.output_format = output_format.format();
+            config.pretty = pretty;
+            let mut out = output_writer(output.as_deref())?;
+            polyglot_code_scanner::reprocess(&input, &config, coupling_config, &mut out)?;
+            return out.finish();
+        }
+        Some(Command::Init {
+            verbose,
+            yes,
+            output,
+            root,
+        }) => {
+            setup_logging(verbose)?;
+            let root = root.unwrap_or_else(|| PathBuf::from("."));
+            return init(&root, &output, yes);
+        }
+        None => {}
+    }
+
+    if let Some(root) = &args.bench_fixture {
+        setup_logging(args.verbose)?;
+        let config = BenchFixtureConfig {
+            files: args.bench_fixture_files,
+            commits: args.bench_fixture_commits,
+            ..BenchFixtureConfig::default()
+        };
+        return polyglot_code_scanner::generate_bench_fixture(root, config);
+    }
+
+    let name = if let Some(name) = args.name {
+        name
+    } else if args.loc_report {
+        // not shown in reports
+        String::new()
+    } else {
+        let mut cmd = Cli::command();
+        cmd.error(
+            ErrorKind::MissingRequiredArgument,
+            "The project name must be specified with --name",
+        )
+        .exit()
+    };
 
     // custom validation - easier than trying to wrangle clap to do this!
+    if args.fields.is_some()
+        && (args.loc_report || matches!(args.output_format, OutputFormatArg::Flare))
+    {
+        custom_validation_conflict("Can't select fields for flare or loc report output!");
+    }
+    if args.pretty && (args.loc_report || matches!(args.output_format, OutputFormatArg::Flare)) {
+        custom_validation_conflict("Can't pretty-print flare or loc report output!");
+    }
+    if args.max_line_bytes == 0 || args.max_parse_bytes == 0 {
+        custom_validation_conflict("Parsing limits must be more than 0!");
+    }
     if args.no_git {
-        if args.coupling {
+        if args.coupling.coupling {
             custom_validation_conflict("Can't enable coupling when git is disabled!");
         }
         if args.no_detailed_git {
             custom_validation_conflict("Can't specify no_detailed_git when git is disabled!");
         }
+        if args.keep_git_activity {
+            custom_validation_conflict("Can't keep git activity when git is disabled!");
+        }
+        if !args.git_details_for.is_empty() {
+            custom_validation_conflict("Can't keep git details for files when git is disabled!");
+        }
+        if args.decay {
+            custom_validation_conflict("Can't calculate decay when git is disabled!");
+        }
+        if args.quadrant {
+            custom_validation_conflict("Can't calculate quadrants when git is disabled!");
+        }
+        if args.contributor_churn {
+            custom_validation_conflict("Can't calculate contributor churn when git is disabled!");
+        }
+        if args.churn_per_contributor {
+            custom_validation_conflict(
+                "Can't calculate churn per contributor when git is disabled!",
+            );
+        }
+        if args.pairing {
+            custom_validation_conflict("Can't measure pairing when git is disabled!");
+        }
+        if args.commit_sizes {
+            custom_validation_conflict("Can't measure commit sizes when git is disabled!");
+        }
+        if args.blame {
+            custom_validation_conflict("Can't blame files when git is disabled!");
+        }
+        if args.line_age {
+            custom_validation_conflict("Can't calculate line ages when git is disabled!");
+        }
+        if args.inactive_months.is_some() || !args.contributor_end_date.is_empty() {
+            custom_validation_conflict("Can't find inactive contributors when git is disabled!");
+        }
+        if args.pull_requests {
+            custom_validation_conflict("Can't count pull requests when git is disabled!");
+        }
+        if args.working_hours {
+            custom_validation_conflict("Can't count working hours when git is disabled!");
+        }
+        if args.teams.is_some() {
+            custom_validation_conflict("Can't map users to teams when git is disabled!");
+        }
+        if args.bulk_commit_files.is_some() || args.bulk_commit_percent.is_some() {
+            custom_validation_conflict("Can't detect bulk commits when git is disabled!");
+        }
+        if args.classify_commits || !args.commit_category.is_empty() {
+            custom_validation_conflict("Can't classify commits when git is disabled!");
+        }
+        if args.no_renames
+            || args.rename_threshold.is_some()
+            || args.rename_limit.is_some()
+            || args.find_copies
+        {
+            custom_validation_conflict("Can't detect renames when git is disabled!");
+        }
+        if !args.co_author_trailer.is_empty() || !args.pair_pattern.is_empty() {
+            custom_validation_conflict("Can't find co-authors when git is disabled!");
+        }
+        if args.timezone.is_some() {
+            custom_validation_conflict("Can't group commits by timezone when git is disabled!");
+        }
+        if args.scan_time.is_some() {
+            custom_validation_conflict("Can't set a scan time when git is disabled!");
+        }
+    }
+    if args.no_content {
+        if args.branch_count {
+            custom_validation_conflict("Can't count branches without reading file contents!");
+        }
+        if args.licenses {
+            custom_validation_conflict("Can't find licenses without reading file contents!");
+        }
+        if args.secrets || !args.secret_rule.is_empty() {
+            custom_validation_conflict("Can't search for secrets without reading file contents!");
+        }
+        if args.syntax {
+            custom_validation_conflict("Can't parse syntax without reading file contents!");
+        }
+        if args.blame {
+            custom_validation_conflict("Can't blame files without reading file contents!");
+        }
+        if args.loc_report {
+            custom_validation_conflict("Can't count lines of code without reading file contents!");
+        }
+        if args.quadrant {
+            custom_validation_conflict(
+                "Can't calculate quadrants without reading file contents for indentation!",
+            );
+        }
+    }
+    let (coupling_config, named_coupling) = args.coupling.configs();
+    let indentation = IndentationConfig {
+        tab_width: args.tab_width,
+        language_tab_widths: args
+            .language_tab_width
+            .iter()
+            .map(|spec| parse_language_tab_width(spec))
+            .collect(),
+        max_lines: args.max_lines,
+        max_line_bytes: args.max_line_bytes,
+        max_parse_bytes: args.max_parse_bytes,
+        comment_prefixes: if args.no_comment_filter {
+            Vec::new()
+        } else if args.comment_prefix.is_empty() {
+            default_comment_prefixes()
+        } else {
+            args.comment_prefix.clone()
+        },
+        percentiles: if args.indentation_percentile.is_empty() {
+            DEFAULT_PERCENTILES.to_vec()
+        } else {
+            args.indentation_percentile.clone()
+        },
+        histogram: args.indentation_histogram,
+    };
+    let bulk_commits = if args.bulk_commit_files.is_some() || args.bulk_commit_percent.is_some() {
+        Some(BulkCommitConfig {
+            max_files: args.bulk_commit_files,
+            max_percent: args.bulk_commit_percent,
+            mode: match args.bulk_commits {
+                BulkCommitModeArg::Flag => BulkCommitMode::Flag,
+                BulkCommitModeArg::Exclude => BulkCommitMode::Exclude,
+            },
+        })
+    } else {
+        None
+    };
+    let commit_classifier = if args.classify_commits || !args.commit_category.is_empty() {
+        let rules = if args.commit_category.is_empty() {
+            CommitClassifier::default_rules()
+        } else {
+            args.commit_category
+                .iter()
+                .map(|spec| parse_commit_category(spec))
+                .collect()
+        };
+        Some(CommitClassifier::new(rules)?)
+    } else {
+        None
+    };
+    let ticket_pattern = match &args.ticket_pattern {
+        Some(pattern) => Some(pattern.as_str()),
+        None if args.coupling.coupling_by_ticket => Some(DEFAULT_TICKET_PATTERN),
+        None => None,
     }
+    .map(|pattern| {
+        Regex::new(pattern).with_context(|| format!("invalid ticket pattern {}", pattern))
+    })
+    .transpose()?;
+    let mut co_author_rules = CoAuthorRules::default();
+    co_author_rules
+        .trailers
+        .extend(args.co_author_trailer.iter().cloned());
+    co_author_rules.summary_patterns = args.pair_pattern.clone();
+    let co_authors = CoAuthorParser::new(co_author_rules)?;
+    let blame = if args.blame {
+        Some(BlameConfig {
+            max_file_bytes: args.blame_max_bytes,
+            max_files: args.blame_max_files,
+            sample_every: args.blame_sample_every.max(1),
+        })
+    } else {
+        None
+    };
+
+    let pull_requests = if args.pull_requests {
+        if !cfg!(feature = "pull-requests") {
+            // fail before scanning, not after
+            custom_validation_conflict(
+                "--pull-requests needs a build with the pull-requests feature",
+            );
+        }
+        let token = match &args.api_token_file {
+            Some(file) => Some(
+                std::fs::read_to_string(file)
+                    .with_context(|| format!("reading API token from {:?}", file))?
+                    .trim()
+                    .to_owned(),
+            ),
+            None => std::env::var("GITHUB_TOKEN")
+                .or_else(|_| std::env::var("GITLAB_TOKEN"))
+                .ok(),
+        };
+        Some(PullRequestConfig {
+            token,
+            max_pull_requests: args.max_pull_requests,
+        })
+    } else {
+        None
+    };
 
-    setup_logging(args.verbose)?;
+    let working_hours = args.working_hours.then(|| WorkingHoursConfig {
+        late_night_from: args.late_night_from,
+        late_night_until: args.late_night_until,
+    });
+
+    let inactive = if args.inactive_months.is_some() || !args.contributor_end_date.is_empty() {
+        Some(InactiveConfig {
+            inactive_months: args.inactive_months,
+            end_dates: args
+                .contributor_end_date
+                .iter()
+                .map(|spec| parse_contributor_end_date(spec))
+                .collect(),
+        })
+    } else {
+        None
+    };
+    let secrets = if args.secret_rule.is_empty() {
+        SecretScanner::default()
+    } else {
+        SecretScanner::new(
+            args.secret_rule
+                .iter()
+                .map(|spec| parse_secret_rule(spec))
+                .collect(),
+        )?
+    };
+    let categories = FileCategorizer::with_custom_rules(
+        args.category
+            .iter()
+            .map(|spec| parse_category_rule(spec))
+            .collect(),
+    )?;
+    let teams = args
+        .teams
+        .as_deref()
+        .map(|file| Teams::new(TeamsConfig::from_file(file)?))
+        .transpose()?;
+
+    let decay = if args.decay {
+        let (age_weight, churn_weight, attrition_weight) = parse_decay_weights(&args.decay_weights);
+        Some(DecayConfig {
+            age_weight,
+            churn_weight,
+            attrition_weight,
+            max_age_days: args.decay_max_age_days,
+            inactive_days: args.decay_inactive_days,
+        })
+    } else {
+        None
+    };
+
+    let quadrant = args.quadrant.then(|| QuadrantConfig {
+        churn_percentile: args.quadrant_churn_percentile,
+        complexity_percentile: args.quadrant_complexity_percentile,
+    });
+
+    let contributor_churn = args.contributor_churn.then(|| ContributorChurnConfig {
+        window_days: args.contributor_churn_days,
+    });
+
+    let churn_per_contributor = args
+        .churn_per_contributor
+        .then(|| ChurnPerContributorConfig {
+            window_days: args.churn_per_contributor_days,
+        });
+
+    if args.quiet {
+        setup_logging(1)?;
+        set_progress_format(ProgressFormat::Hidden);
+    } else {
+        setup_logging(args.verbose)?;
+        set_progress_format(match args.progress_format {
+            ProgressFormatArg::Bars => ProgressFormat::Bars,
+            ProgressFormatArg::Json => ProgressFormat::Json,
+        });
+    }
 
     let root = args.root.unwrap_or_else(|| PathBuf::from("."));
 
-    let features = FeatureFlags {
-        git: !args.no_git,
-        coupling: args.coupling,
-        git_details: !(args.no_detailed_git || args.no_git),
-        file_stats: !args.no_file_stats,
+    let file_list = args.files_from.as_deref().map(read_file_list).transpose()?;
+
+    let global_ignore = if args.global_ignore {
+        let file = global_ignore_file();
+        if file.is_none() {
+            custom_validation_conflict(
+                "No global ignore file found in ~/.config/polyglot_code_scanner/ignore!",
+            );
+        }
+        file
+    } else {
+        None
     };
+    let ignore_files: Vec<PathBuf> = global_ignore
+        .into_iter()
+        .chain(args.ignore_file.iter().cloned())
+        .collect();
+
+    let vendored = match args.vendored {
+        VendoredModeArg::Skip => Some(VendoredMode::Skip),
+        VendoredModeArg::Tag => Some(VendoredMode::Tag),
+        VendoredModeArg::Include => None,
+    }
+    .map(|mode| VendoredConfig::new(mode, &args.vendored_name, &args.not_vendored));
 
-    let scanner_config = ScannerConfig {
+    let features = if args.loc_report {
+        FeatureFlags {
+            git: false,
+            coupling: false,
+            git_details: false,
+            file_stats: false,
+        }
+    } else {
+        FeatureFlags {
+            git: !args.no_git,
+            coupling: args.coupling.coupling,
+            git_details: !(args.no_detailed_git || args.no_git),
+            file_stats: !args.no_file_stats,
+        }
+    };
+
+    if args.append_suffix_timestamp {
+        if args.output.is_empty() {
+            custom_validation_conflict("Can't add a timestamp suffix without --output!");
+        }
+        let now = Utc::now();
+        for output in &mut args.output {
+            *output = with_timestamp_suffix(output, now);
+        }
+    }
+    // the first output is the main one - only it can be in object storage, or uploaded
+    let mut outputs = std::mem::take(&mut args.output).into_iter();
+    let main_output = outputs.next();
+    let extra_outputs: Vec<(PathBuf, OutputFormat)> = outputs
+        .map(|output| {
+            let in_object_storage = match output.to_str() {
+                Some(output) => ObjectLocation::parse(output)?.is_some(),
+                None => false,
+            };
+            if in_object_storage {
+                custom_validation_conflict("Only the first --output can be in object storage!");
+            }
+            let format = output_format_for(&output, args.output_format);
+            Ok((output, format))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let mut scanner_config = ScannerConfig {
         git_years: Some(args.git_years),
+        git_subtree_only: args.git_subtree_only,
+        bulk_commits,
+        commit_classifier,
+        ticket_pattern,
+        co_authors,
+        renames: RenameConfig {
+            disabled: args.no_renames,
+            threshold: args.rename_threshold,
+            limit: args.rename_limit,
+            copies: args.find_copies,
+            copy_history: args.copy_history,
+        },
+        timezone: args.timezone.unwrap_or_default(),
+        scan_time: args
+            .scan_time
+            .unwrap_or_else(|| u64::try_from(Utc::now().timestamp()).unwrap_or(0)),
         data_id: args.id,
-        name: args.name,
+        name,
         follow_symlinks: args.follow_symlinks,
+        include_hidden: args.hidden,
+        no_ignore: args.no_ignore,
+        max_depth: args.max_depth,
+        ignore_files,
+        vendored,
+        path_prefix: args.path_prefix,
+        local_paths: args.local_paths,
+        file_list,
+        changed_since: args.changed_since,
         features,
+        named_coupling,
+        architecture_file: args.coupling.architecture,
+        coupling_checkpoint: args.coupling.coupling_checkpoint,
+        keep_git_activity: args.keep_git_activity,
+        git_details_for: git_details_filter(args.git_details_for)?,
+        indentation,
+        blame,
+        line_age: args.line_age,
+        teams,
+        inactive,
+        pull_requests,
+        working_hours,
+        license_lines: args.license_lines,
+        secrets,
+        categories,
+        decay,
+        quadrant,
+        contributor_churn,
+        churn_per_contributor,
+        pairing: args.pairing,
+        commit_sizes: args.commit_sizes.then(|| CommitSizeConfig {
+            giant_commit_lines: args.giant_commit_lines,
+        }),
+        areas_of_interest: args
+            .areas_of_interest
+            .map(|top| AreasOfInterestConfig { top }),
+        timings: args.timings,
+        timings_file: args.timings_file,
+        report: ReportConfig {
+            format: if args.report.as_ref().map_or(false, |report| {
+                report
+                    .extension()
+                    .map_or(false, |extension| extension.eq_ignore_ascii_case("html"))
+            }) {
+                ReportFormat::Html
+            } else {
+                ReportFormat::Markdown
+            },
+            ..ReportConfig::default()
+        },
+        report_file: args.report,
+        time_budget: args.time_budget.map(|seconds| TimeBudgetConfig { seconds }),
+        max_memory_mb: args.max_memory,
+        cancellation: CancellationToken::new(),
+        format_version: args.format_version.version(),
+        output_format: match &main_output {
+            _ if args.loc_report => OutputFormat::LocReport,
+            Some(output) => output_format_for(output, args.output_format),
+            None => args.output_format.format(),
+        },
+        extra_outputs,
+        no_content: args.no_content,
+        node_limit: args.max_nodes.map(|max_nodes| NodeLimitConfig {
+            max_nodes,
+            strategy: match args.max_nodes_strategy {
+                NodeLimitStrategyArg::Aggregate => NodeLimitStrategy::Aggregate,
+                NodeLimitStrategyArg::Truncate => NodeLimitStrategy::Truncate,
+            },
+        }),
+        fields: args.fields.as_deref().map(parse_fields),
+        pretty: args.pretty,
+        directory_tree: (args.dirs_only || args.depth_limit.is_some()).then(|| {
+            DirectoryTreeConfig {
+                depth_limit: args.depth_limit,
+            }
+        }),
+        redact_names: args.redact_names.then(|| RedactConfig {
+            keep_extensions: args.redact_keep_extensions,
+            salt: args
+                .redact_salt
+                .clone()
+                .or_else(|| std::env::var("POLYGLOT_REDACT_SALT").ok())
+                .unwrap_or_default(),
+        }),
     };
 
-    let coupling_config = if args.coupling {
-        Some(CouplingConfig::new(
-            args.bucket_days,
-            args.min_activity_bursts,
-            args.min_coupling_ratio,
-            args.min_activity_gap_minutes * 60,
-            args.min_overlap_minutes * 60,
-            args.coupling_min_distance,
-            args.coupling_max_common_roots,
-        ))
-    } else {
-        None
-    };
+    for spec in &args.calc_config {
+        apply_calculator_option(&mut scanner_config, spec);
+    }
 
-    let mut out: Box<dyn io::Write> = if let Some(output) = args.output {
-        Box::new(File::create(output)?)
+    if args.upload.is_some() && !cfg!(feature = "upload") {
+        // fail before scanning, not after
+        custom_validation_conflict("--upload needs a build with the upload feature");
+    }
+    let upload_token = match &args.upload_token_file {
+        Some(file) => Some(
+            std::fs::read_to_string(file)
+                .with_context(|| format!("reading upload token from {:?}", file))?
+                .trim()
+                .to_owned(),
+        ),
+        None => std::env::var("POLYGLOT_UPLOAD_TOKEN").ok(),
+    };
+    let upload = args.upload.as_deref().map(|url| UploadConfig {
+        retries: args.upload_retries,
+        ..UploadConfig::new(url, upload_token)
+    });
+    let object_output = match main_output.as_deref().and_then(Path::to_str) {
+        Some(output) => ObjectLocation::parse(output)?,
+        None => None,
+    };
+    if object_output.is_some() && !cfg!(feature = "object-store") {
+        // fail before scanning, not after
+        custom_validation_conflict(
+            "s3:// and gs:// outputs need a build with the object-store feature",
+        );
+    }
+    // uploads are sent from a file, so they can be streamed, and retried - the file is deleted
+    // when dropped, even if the scan fails
+    let temp_output = if object_output.is_some() || (upload.is_some() && main_output.is_none()) {
+        Some(
+            tempfile::Builder::new()
+                .prefix("polyglot-")
+                .suffix(".json")
+                .tempfile()
+                .context("creating a temporary file to upload")?,
+        )
     } else {
-        Box::new(io::stdout())
+        None
     };
+    let output = temp_output
+        .as_ref()
+        .map(|temp| temp.path().to_path_buf())
+        .or(main_output);
 
-    let mut calculator_names: Vec<&str> = vec!["loc", "indentation"];
+    let mut calculator_names: Vec<&str> = Vec::new();
+    if !args.no_content {
+        calculator_names.push("loc");
+        calculator_names.push("indentation");
+    }
+    if args.nesting && !args.no_content {
+        calculator_names.push("nesting");
+    }
+    if args.branch_count {
+        calculator_names.push("branch_count");
+    }
+    if args.licenses {
+        calculator_names.push("license");
+    }
+    if args.secrets || !args.secret_rule.is_empty() {
+        calculator_names.push("secrets");
+    }
+    if args.categories || !args.category.is_empty() {
+        calculator_names.push("categories");
+    }
+    if args.syntax {
+        calculator_names.push("syntax");
+    }
+    if args.annotations {
+        calculator_names.push("annotations");
+    }
     if !args.no_git {
         calculator_names.push("git");
     }
     if !args.no_file_stats {
         calculator_names.push("file_stats");
     }
+    for name in CALCULATORS {
+        if args.enable.iter().any(|enabled| enabled == name) && !calculator_names.contains(name) {
+            calculator_names.push(*name);
+        }
+    }
+    calculator_names.retain(|name| !args.disable.iter().any(|disabled| disabled == name));
+    calculator_names.sort_by_key(|name| CALCULATORS.iter().position(|known| known == name));
+
+    if args.loc_report {
+        // the report only needs lines of code, so skip the slow calculators
+        calculator_names = vec!["loc"];
+    }
+
+    if args.estimate {
+        return polyglot_code_scanner::estimate(
+            &root,
+            &scanner_config,
+            coupling_config,
+            &calculator_names,
+            io::stdout(),
+        );
+    }
+
+    let mut out = output_writer(output.as_deref())?;
 
     polyglot_code_scanner::run(
         &root,
         &scanner_config,
-        coupling_config,
+        coupling_config.filter(|_| !args.loc_report),
         &calculator_names,
         &mut out,
     )?;
+    out.finish()?;
+
+    if let Some(output) = output {
+        let uploaded = send_output(&output, object_output.as_ref(), upload.as_ref());
+        if let Some(temp_output) = temp_output {
+            temp_output.close()?;
+        }
+        uploaded?;
+    }
 
     Ok(())
 }
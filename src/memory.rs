@@ -0,0 +1,383 @@
+#![warn(clippy::all)]
+//! Rough memory guardrails for huge repositories.
+//!
+//! Memory is estimated from the tree as it is walked - node counts, and the git details and
+//! activity kept for each file - plus the git histories loaded while walking. The estimate is
+//! checked after each node and each history, and once more on the finished tree before coupling,
+//! which is where memory use peaks. If it's over the limit, git activity is dropped and coupling
+//! skipped, then git details are dropped if that isn't enough - new nodes have the data stripped
+//! straight away, and nodes already walked have it dropped once the walk ends. Every step is
+//! recorded as a diagnostic, including being over the limit with nothing left to drop.
+//! It's an estimate, not a hard limit.
+
+use crate::diagnostics::{Diagnostic, DiagnosticCategory};
+use crate::flare::FlareTreeNode;
+use crate::git::{GitActivity, GitDetails, GitNodeData};
+use crate::git_file_history::FileHistoryEntry;
+use crate::polyglot_data::{IndicatorMetadata, PolyglotData};
+use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use anyhow::Error;
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// allowance for the heap data of each entry - names, indicator structures, user sets
+const HEAP_ALLOWANCE: u64 = 64;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TreeSize {
+    pub nodes: u64,
+    pub details: u64,
+    pub activity: u64,
+}
+
+impl TreeSize {
+    pub fn of(node: &FlareTreeNode) -> Self {
+        let mut size = TreeSize {
+            nodes: 1,
+            ..TreeSize::default()
+        };
+        if let Some(GitNodeData::File { data }) = &node.indicators().git {
+            size.details = data.details.len() as u64;
+            size.activity = data.activity.len() as u64;
+        }
+        for child in node.get_children() {
+            size.add(TreeSize::of(child));
+        }
+        size
+    }
+
+    pub fn add(&mut self, other: TreeSize) {
+        self.nodes += other.nodes;
+        self.details += other.details;
+        self.activity += other.activity;
+    }
+
+    pub fn estimated_bytes(&self) -> u64 {
+        self.nodes * (size_of::<FlareTreeNode>() as u64 + HEAP_ALLOWANCE)
+            + self.details * (size_of::<GitDetails>() as u64 + HEAP_ALLOWANCE)
+            + self.activity * (size_of::<GitActivity>() as u64 + HEAP_ALLOWANCE)
+    }
+}
+
+fn drop_git_data(node: &mut FlareTreeNode, activity: bool, details: bool) {
+    if let Some(GitNodeData::File { data }) = &mut node.indicators_mut().git {
+        if activity {
+            data.activity = Vec::new();
+        }
+        if details {
+            data.details = Vec::new();
+        }
+    }
+    for child in node.get_children_mut() {
+        drop_git_data(child, activity, details);
+    }
+}
+
+#[derive(Debug)]
+struct GuardState {
+    max_mb: u64,
+    tree: TreeSize,
+    /// entries in the git histories loaded so far - these are held until the walk ends
+    history_entries: u64,
+    drop_activity: bool,
+    drop_details: bool,
+    /// over the limit with no git data left to drop
+    exhausted: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl GuardState {
+    fn estimated_bytes(&self) -> u64 {
+        self.tree.estimated_bytes()
+            + self.history_entries * (size_of::<FileHistoryEntry>() as u64 + HEAP_ALLOWANCE)
+    }
+
+    /// flags git data to drop until the estimate is under the limit, with a diagnostic for each step
+    fn check(&mut self, stage: &str) {
+        while self.estimated_bytes() > self.max_mb.saturating_mul(BYTES_PER_MB) {
+            let estimated_mb = self.estimated_bytes() / BYTES_PER_MB;
+            let has_history = self.history_entries > 0;
+            let dropped = if !self.drop_activity && (self.tree.activity > 0 || has_history) {
+                self.drop_activity = true;
+                self.tree.activity = 0;
+                "dropped git activity, so coupling is skipped"
+            } else if !self.drop_details && (self.tree.details > 0 || has_history) {
+                self.drop_details = true;
+                self.tree.details = 0;
+                "dropped detailed git data"
+            } else {
+                if !self.exhausted {
+                    self.exhausted = true;
+                    self.diagnostics.push(Diagnostic::new(
+                        DiagnosticCategory::MemoryLimit,
+                        None,
+                        format!(
+                            "Estimated memory {}MB is over the {}MB limit while {} - there is no git data left to drop",
+                            estimated_mb, self.max_mb, stage
+                        ),
+                    ));
+                }
+                return;
+            };
+            self.diagnostics.push(Diagnostic::new(
+                DiagnosticCategory::MemoryLimit,
+                None,
+                format!(
+                    "Estimated memory {}MB is over the {}MB limit while {} - {}",
+                    estimated_mb, self.max_mb, stage, dropped
+                ),
+            ));
+        }
+    }
+}
+
+/// cheap to clone - all clones share the same estimate, so the walk and the git calculator can
+/// both add to it.  Added to the calculators last, so it sees the data every other calculator added.
+#[derive(Debug, Clone)]
+pub struct MemoryGuard(Arc<Mutex<GuardState>>);
+
+impl MemoryGuard {
+    pub fn new(max_mb: u64) -> Self {
+        MemoryGuard(Arc::new(Mutex::new(GuardState {
+            max_mb,
+            tree: TreeSize::default(),
+            history_entries: 0,
+            drop_activity: false,
+            drop_details: false,
+            exhausted: false,
+            diagnostics: Vec::new(),
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, GuardState> {
+        self.0.lock().expect("poisoned memory guard")
+    }
+
+    /// counts a git history that has just been loaded
+    pub fn add_history(&self, entries: u64) {
+        let mut state = self.state();
+        state.history_entries += entries;
+        state.check("loading git history");
+    }
+
+    /// checks the finished tree, dropping any git data flagged during the walk, and adds all
+    /// diagnostics to the data - returns false if coupling can't be calculated
+    pub fn apply(&self, data: &mut PolyglotData) -> bool {
+        let mut state = self.state();
+        // histories belong to the git calculator, which is dropped once the walk is finished
+        state.history_entries = 0;
+        drop_git_data(data.tree_mut(), state.drop_activity, state.drop_details);
+        state.tree = TreeSize::of(data.tree());
+        info!(
+            "Estimated tree memory: {}MB for {} nodes",
+            state.estimated_bytes() / BYTES_PER_MB,
+            state.tree.nodes
+        );
+        state.check("checking the scanned tree");
+        drop_git_data(data.tree_mut(), state.drop_activity, state.drop_details);
+        for diagnostic in std::mem::take(&mut state.diagnostics) {
+            data.add_diagnostic(diagnostic);
+        }
+        !(state.drop_activity || state.exhausted)
+    }
+}
+
+impl ToxicityIndicatorCalculator for MemoryGuard {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    /// nodes have no children yet when visited, so each is only counted once
+    fn visit_node(&mut self, node: &mut FlareTreeNode, _path: &Path) -> Result<(), Error> {
+        let mut state = self.state();
+        drop_git_data(node, state.drop_activity, state.drop_details);
+        state.tree.add(TreeSize::of(node));
+        state.check("walking");
+        drop_git_data(node, state.drop_activity, state.drop_details);
+        Ok(())
+    }
+
+    fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::GitData;
+    use crate::git_logger::CommitChange;
+    use crate::FeatureFlags;
+    use std::collections::BTreeSet;
+
+    fn activity() -> GitActivity {
+        GitActivity {
+            author_time: 0,
+            commit_time: 0,
+            users: BTreeSet::new(),
+            change: CommitChange::Add,
+            lines_added: 1,
+            lines_deleted: 0,
+            bulk: false,
+            parent_count: 1,
+            tickets: Vec::new(),
+        }
+    }
+
+    fn details() -> GitDetails {
+        GitDetails {
+            commit_day: 0,
+            users: BTreeSet::new(),
+            commits: 1,
+            lines_added: 1,
+            lines_deleted: 0,
+            bulk_commits: 0,
+            merge_commits: 0,
+        }
+    }
+
+    fn busy_file(name: &str, details_count: usize) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        let mut data = GitData::fake_with_activity(vec![activity(); 1000]);
+        data.details = vec![details(); details_count];
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file
+    }
+
+    fn busy_data(details_count: usize) -> PolyglotData {
+        let mut root = FlareTreeNode::dir("root");
+        for name in ["a.rs", "b.rs"] {
+            root.append_child(busy_file(name, details_count));
+        }
+        PolyglotData::new("test", Some("test"), root, FeatureFlags::default())
+    }
+
+    fn messages(data: &PolyglotData) -> Vec<String> {
+        data.diagnostics()
+            .iter()
+            .inspect(|diagnostic| assert_eq!(diagnostic.category, DiagnosticCategory::MemoryLimit))
+            .map(|diagnostic| diagnostic.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn tree_size_counts_nodes_and_activity() {
+        let data = busy_data(0);
+        let size = TreeSize::of(data.tree());
+        assert_eq!(
+            size,
+            TreeSize {
+                nodes: 3,
+                details: 0,
+                activity: 2000
+            }
+        );
+    }
+
+    #[test]
+    fn activity_is_kept_under_the_limit() {
+        let mut data = busy_data(0);
+        assert!(MemoryGuard::new(100).apply(&mut data));
+        assert_eq!(TreeSize::of(data.tree()).activity, 2000);
+        assert!(data.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn activity_is_dropped_over_the_limit() {
+        let mut data = busy_data(0);
+        assert!(!MemoryGuard::new(0).apply(&mut data));
+        assert_eq!(TreeSize::of(data.tree()).activity, 0);
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with("dropped git activity, so coupling is skipped"));
+        assert!(messages[1].ends_with("there is no git data left to drop"));
+    }
+
+    #[test]
+    fn details_are_dropped_if_dropping_activity_is_not_enough() {
+        // details alone are well over 1MB, the nodes are tiny
+        let mut data = busy_data(10_000);
+        assert!(!MemoryGuard::new(1).apply(&mut data));
+        let size = TreeSize::of(data.tree());
+        assert_eq!(size.activity, 0);
+        assert_eq!(size.details, 0);
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with("dropped git activity, so coupling is skipped"));
+        assert!(messages[1].ends_with("dropped detailed git data"));
+    }
+
+    #[test]
+    fn over_the_limit_with_no_git_data_is_reported() {
+        let mut data = PolyglotData::new(
+            "test",
+            Some("test"),
+            FlareTreeNode::dir("root"),
+            FeatureFlags::default(),
+        );
+        assert!(!MemoryGuard::new(0).apply(&mut data));
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("while checking the scanned tree"));
+        assert!(messages[0].ends_with("there is no git data left to drop"));
+    }
+
+    #[test]
+    fn data_is_dropped_while_walking() {
+        let guard = MemoryGuard::new(1);
+        let mut calculator = guard.clone();
+        let mut root = FlareTreeNode::dir("root");
+        calculator.visit_node(&mut root, Path::new("")).unwrap();
+        let mut big = busy_file("big.rs", 10_000);
+        calculator
+            .visit_node(&mut big, Path::new("big.rs"))
+            .unwrap();
+        let mut later = busy_file("later.rs", 1);
+        calculator
+            .visit_node(&mut later, Path::new("later.rs"))
+            .unwrap();
+        // nodes walked after the limit was reached are stripped straight away
+        assert_eq!(TreeSize::of(&big), TreeSize::of(&FlareTreeNode::file("x")));
+        assert_eq!(
+            TreeSize::of(&later),
+            TreeSize::of(&FlareTreeNode::file("x"))
+        );
+        root.append_child(big);
+        root.append_child(later);
+
+        let mut data = PolyglotData::new("test", Some("test"), root, FeatureFlags::default());
+        assert!(!guard.apply(&mut data));
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("while walking"));
+        assert!(messages[1].contains("while walking"));
+        assert!(messages[1].ends_with("dropped detailed git data"));
+    }
+
+    #[test]
+    fn data_is_dropped_while_loading_git_history() {
+        let guard = MemoryGuard::new(1);
+        let mut calculator = guard.clone();
+        guard.add_history(100_000);
+        let mut root = FlareTreeNode::dir("root");
+        calculator.visit_node(&mut root, Path::new("")).unwrap();
+        let mut file = busy_file("a.rs", 1);
+        calculator.visit_node(&mut file, Path::new("a.rs")).unwrap();
+        assert_eq!(TreeSize::of(&file).activity, 0);
+        assert_eq!(TreeSize::of(&file).details, 0);
+        root.append_child(file);
+
+        let mut data = PolyglotData::new("test", Some("test"), root, FeatureFlags::default());
+        // the histories are gone by the time the tree is checked
+        assert!(!guard.apply(&mut data));
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 3);
+        assert!(messages
+            .iter()
+            .all(|message| message.contains("while loading git history")));
+        assert!(messages[2].ends_with("there is no git data left to drop"));
+    }
+}
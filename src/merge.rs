@@ -0,0 +1,486 @@
+#![warn(clippy::all)]
+//! Merging existing data files into one, under a synthetic root
+//!
+//! Each file's tree becomes a top-level directory named after that file's project name.
+//! Git users are merged into one dictionary (so the same person in two repositories is one user)
+//! and user ids in the tree are remapped to match. Repository lists are combined, and files'
+//! repository indexes remapped likewise.
+//!
+//! Coupling buckets differ between scans, so coupling is only kept if just one input file has it.
+//! Timings, architecture and license summaries, activity timelines and team members are dropped,
+//! they don't make sense for merged data.
+//!
+//! A single scan can also be moved under a path prefix, so it can be stitched into a combined
+//! dataset later.
+
+use anyhow::Error;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::coupling::{CouplingMetadata, PathVec, SerializableCouplingData};
+use crate::file_walker::DirSize;
+use crate::flare::{FlareTreeNode, ROOT_NAME};
+use crate::git::{GitNodeData, RepositoryInfo};
+use crate::git_user_dictionary::GitUserDictionary;
+use crate::polyglot_data::{GitMetadata, IndicatorMetadata, PolyglotData};
+use crate::FeatureFlags;
+
+fn remap_users(node: &mut FlareTreeNode, new_ids: &[usize]) -> Result<(), Error> {
+    if let Some(GitNodeData::File { data }) = &mut node.indicators_mut().git {
+        data.remap_users(new_ids)?;
+    }
+    if let Some(blame) = &mut node.indicators_mut().blame {
+        blame.remap_users(new_ids)?;
+    }
+    for child in node.get_children_mut() {
+        remap_users(child, new_ids)?;
+    }
+    Ok(())
+}
+
+/// repository indexes point into the git metadata list, which is being appended to
+fn offset_repositories(node: &mut FlareTreeNode, offset: usize) {
+    if let Some(GitNodeData::File { data }) = &mut node.indicators_mut().git {
+        if let Some(repository) = &mut data.repository {
+            *repository += offset;
+        }
+    }
+    for child in node.get_children_mut() {
+        offset_repositories(child, offset);
+    }
+}
+
+fn prefix_repositories(repositories: &mut [RepositoryInfo], prefix: &Path) {
+    for repository in repositories {
+        repository.path = if repository.path.as_os_str().is_empty() {
+            prefix.to_path_buf()
+        } else {
+            prefix.join(&repository.path)
+        };
+    }
+}
+
+fn prefix_coupled_files(coupling: &mut SerializableCouplingData, prefix: &Path) {
+    for bucket in &mut coupling.buckets {
+        for (file, _) in &mut bucket.coupled_files {
+            *file = Rc::new(PathVec::from(prefix.join(file.to_path_buf())));
+        }
+    }
+}
+
+fn prefix_coupling_summary(metadata: &mut CouplingMetadata, prefix: &Path) {
+    for bucket in &mut metadata.summary {
+        for edge in &mut bucket.strongest_edges {
+            edge.source = Rc::new(PathVec::from(prefix.join(edge.source.to_path_buf())));
+            edge.target = Rc::new(PathVec::from(prefix.join(edge.target.to_path_buf())));
+        }
+    }
+}
+
+/// coupling metadata summaries have paths too
+fn relocate_coupling_metadata(metadata: &mut IndicatorMetadata, prefix: &Path) {
+    if let Some(coupling) = &mut metadata.coupling {
+        prefix_coupling_summary(coupling, prefix);
+    }
+    for coupling in metadata.named_coupling.values_mut() {
+        prefix_coupling_summary(coupling, prefix);
+    }
+}
+
+/// coupling paths are relative to the root, so need the new top-level directory added
+fn relocate_coupling(node: &mut FlareTreeNode, prefix: &Path, keep_coupling: bool) {
+    let indicators = node.indicators_mut();
+    if keep_coupling {
+        if let Some(coupling) = &mut indicators.coupling {
+            prefix_coupled_files(coupling, prefix);
+        }
+        for coupling in indicators.named_coupling.values_mut() {
+            prefix_coupled_files(coupling, prefix);
+        }
+    } else {
+        indicators.coupling = None;
+        indicators.named_coupling.clear();
+    }
+    for child in node.get_children_mut() {
+        relocate_coupling(child, prefix, keep_coupling);
+    }
+}
+
+fn unique_name(name: &str, used_names: &mut HashSet<String>) -> String {
+    let mut candidate = name.to_owned();
+    let mut suffix = 1;
+    while used_names.contains(&candidate) {
+        suffix += 1;
+        candidate = format!("{}_{}", name, suffix);
+    }
+    if candidate != name {
+        warn!("Duplicate data name {} - renamed to {}", name, candidate);
+    }
+    used_names.insert(candidate.clone());
+    candidate
+}
+
+fn has_coupling(metadata: &IndicatorMetadata) -> bool {
+    metadata.coupling.is_some() || !metadata.named_coupling.is_empty()
+}
+
+/// moves the whole tree under `prefix`, e.g. "backend/api" - the old root becomes the innermost
+/// directory. Coupling and relative diagnostic paths are prefixed to match.
+pub fn add_path_prefix(data: &mut PolyglotData, prefix: &Path) -> Result<(), Error> {
+    let names = prefix
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => Ok(name.to_owned()),
+            _ => Err(anyhow!(
+                "Invalid path prefix {:?} - it must be a relative path with no '..'",
+                prefix
+            )),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if names.is_empty() {
+        return Ok(());
+    }
+    relocate_coupling(data.tree_mut(), prefix, true);
+    relocate_coupling_metadata(data.metadata(), prefix);
+    if let Some(git) = &mut data.metadata().git {
+        prefix_repositories(&mut git.repositories, prefix);
+    }
+    for diagnostic in data.diagnostics_mut() {
+        if let Some(path) = &diagnostic.path {
+            if path.is_relative() {
+                diagnostic.path = Some(prefix.join(path));
+            }
+        }
+    }
+    let mut node = std::mem::replace(data.tree_mut(), FlareTreeNode::new(ROOT_NAME, false));
+    let dir_size = node.indicators().dir_size;
+    let mut names = names.into_iter().rev();
+    if let Some(innermost) = names.next() {
+        node.set_name(&innermost);
+    }
+    for name in names {
+        let mut parent = FlareTreeNode::dir(name);
+        parent.indicators_mut().dir_size = dir_size;
+        parent.append_child(node);
+        node = parent;
+    }
+    data.tree_mut().indicators_mut().dir_size = dir_size;
+    data.tree_mut().append_child(node);
+    Ok(())
+}
+
+/// merge data files - see module docs for what is kept
+pub fn merge_data(
+    sources: Vec<PolyglotData>,
+    name: &str,
+    id: Option<&str>,
+) -> Result<PolyglotData, Error> {
+    let coupling_sources = sources
+        .iter()
+        .filter(|data| data.features().coupling)
+        .count();
+    let keep_coupling = coupling_sources <= 1;
+    if !keep_coupling {
+        warn!(
+            "{} data files have coupling data - coupling can't be merged so will be dropped",
+            coupling_sources
+        );
+    }
+
+    let mut features = FeatureFlags::default();
+    let mut root = FlareTreeNode::new(ROOT_NAME, false);
+    let mut dictionary = GitUserDictionary::default();
+    let mut repositories: Vec<RepositoryInfo> = Vec::new();
+    let mut has_git = false;
+    let mut coupling: Option<CouplingMetadata> = None;
+    let mut named_coupling = Default::default();
+    let mut used_names = HashSet::new();
+    let mut dir_size: Option<DirSize> = None;
+
+    for source in sources {
+        let source_name = unique_name(source.name(), &mut used_names);
+        let source_features = source.features().clone();
+        features.git |= source_features.git;
+        features.git_details |= source_features.git_details;
+        features.file_stats |= source_features.file_stats;
+        features.coupling |= source_features.coupling && keep_coupling;
+
+        let (mut tree, mut metadata) = source.into_parts();
+        if let Some(git) = &mut metadata.git {
+            has_git = true;
+            let new_ids: Vec<usize> = git
+                .users
+                .users()
+                .iter()
+                .map(|user| dictionary.register(user))
+                .collect();
+            remap_users(&mut tree, &new_ids)?;
+            offset_repositories(&mut tree, repositories.len());
+            prefix_repositories(&mut git.repositories, &PathBuf::from(&source_name));
+            repositories.append(&mut git.repositories);
+        }
+        if keep_coupling && has_coupling(&metadata) {
+            relocate_coupling_metadata(&mut metadata, &PathBuf::from(&source_name));
+            coupling = metadata.coupling.take();
+            named_coupling = std::mem::take(&mut metadata.named_coupling);
+        }
+        relocate_coupling(&mut tree, &PathBuf::from(&source_name), keep_coupling);
+
+        if let Some(source_size) = tree.indicators().dir_size {
+            dir_size
+                .get_or_insert_with(DirSize::default)
+                .add(source_size);
+        }
+        tree.set_name(OsStr::new(&source_name));
+        root.append_child(tree);
+    }
+
+    root.indicators_mut().dir_size = dir_size;
+    let mut merged = PolyglotData::new(name, id, root, features);
+    let metadata = merged.metadata();
+    if has_git {
+        metadata.git = Some(GitMetadata {
+            users: dictionary,
+            bulk_commits: None,
+            commit_categories: None,
+            co_authors: None,
+            renames: None,
+            repositories,
+            timeline: Vec::new(),
+            teams: Vec::new(),
+        });
+    }
+    metadata.coupling = coupling;
+    metadata.named_coupling = named_coupling;
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coupling::SerializableCouplingBucketData;
+    use crate::diagnostics::{Diagnostic, DiagnosticCategory};
+    use crate::git::{Contributor, GitData};
+    use crate::git_logger::User;
+    use pretty_assertions::assert_eq;
+    use std::ffi::OsString;
+
+    fn git_source(name: &str, users: &[&str], file_users: &[usize]) -> PolyglotData {
+        let mut dictionary = GitUserDictionary::default();
+        for user in users {
+            dictionary.register(&User::new(Some(user), None));
+        }
+        let mut git_data = GitData::fake_with_activity(Vec::new());
+        git_data.users = file_users.to_vec();
+        git_data.user_count = file_users.len();
+        git_data.last_author = file_users.last().copied();
+        git_data.repository = Some(0);
+        git_data.top_contributors = file_users
+            .iter()
+            .map(|user| Contributor {
+                user: *user,
+                commits: 1,
+            })
+            .collect();
+        let mut file = FlareTreeNode::file("file.txt");
+        file.indicators_mut().git = Some(GitNodeData::File { data: git_data });
+        let mut tree = FlareTreeNode::dir(ROOT_NAME);
+        tree.append_child(file);
+        let features = FeatureFlags {
+            git: true,
+            ..FeatureFlags::default()
+        };
+        let mut data = PolyglotData::new(name, None, tree, features);
+        data.metadata().git = Some(GitMetadata {
+            users: dictionary,
+            bulk_commits: None,
+            commit_categories: None,
+            co_authors: None,
+            renames: None,
+            repositories: vec![RepositoryInfo {
+                path: PathBuf::new(),
+                remote_url: None,
+                canonical_remote_url: None,
+                head: Some(format!("{}-head", name)),
+                commits: 1,
+                sparse: false,
+                worktree: false,
+            }],
+            timeline: Vec::new(),
+            teams: Vec::new(),
+        });
+        data
+    }
+
+    fn file_git_data(data: &PolyglotData, dir: &str) -> GitData {
+        let path = PathBuf::from(dir).join("file.txt");
+        match &data
+            .tree()
+            .get_in(&mut path.components())
+            .unwrap()
+            .indicators()
+            .git
+        {
+            Some(GitNodeData::File { data }) => data.clone(),
+            other => panic!("unexpected git data {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sources_are_merged_under_new_root_with_users_remapped() {
+        let first = git_source("first", &["Jo", "Sam"], &[0, 1]);
+        let second = git_source("second", &["Kim", "jo"], &[0, 1]);
+
+        let mut merged = merge_data(vec![first, second], "merged", Some("merged-id")).unwrap();
+
+        let names: Vec<OsString> = merged
+            .tree()
+            .get_children()
+            .iter()
+            .map(|child| child.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            vec![OsString::from("first"), OsString::from("second")]
+        );
+
+        let users = &merged.metadata().git.as_ref().unwrap().users;
+        assert_eq!(users.user_count(), 3);
+        assert_eq!(users.user_by_id(2), User::new(Some("Kim"), None));
+
+        let second_data = file_git_data(&merged, "second");
+        assert_eq!(second_data.users, vec![0, 2]);
+        assert_eq!(second_data.last_author, Some(0));
+        assert_eq!(second_data.repository, Some(1));
+        assert_eq!(file_git_data(&merged, "first").users, vec![0, 1]);
+        assert_eq!(file_git_data(&merged, "first").repository, Some(0));
+
+        let repositories: Vec<(PathBuf, Option<String>)> = merged
+            .metadata()
+            .git
+            .as_ref()
+            .unwrap()
+            .repositories
+            .iter()
+            .map(|repository| (repository.path.clone(), repository.head.clone()))
+            .collect();
+        assert_eq!(
+            repositories,
+            vec![
+                (PathBuf::from("first"), Some("first-head".to_owned())),
+                (PathBuf::from("second"), Some("second-head".to_owned()))
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_made_unique() {
+        let merged = merge_data(
+            vec![
+                git_source("repo", &["Jo"], &[0]),
+                git_source("repo", &["Jo"], &[0]),
+            ],
+            "merged",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            merged.tree().get_children()[1].name(),
+            &OsString::from("repo_2")
+        );
+    }
+
+    #[test]
+    fn single_source_coupling_is_kept_with_new_paths() {
+        let mut coupled = git_source("coupled", &["Jo"], &[0]);
+        let file_path = PathBuf::from("file.txt");
+        let file = coupled
+            .tree_mut()
+            .get_in_mut(&mut file_path.components())
+            .unwrap();
+        file.indicators_mut().coupling = Some(SerializableCouplingData {
+            buckets: vec![SerializableCouplingBucketData {
+                bucket_start: 0,
+                bucket_end: 100,
+                activity_bursts: 1,
+                coupled_files: vec![(Rc::new(PathVec::from("other.txt")), 1)],
+                p_values: Vec::new(),
+            }],
+        });
+        coupled.features_mut().coupling = true;
+
+        let merged = merge_data(
+            vec![coupled, git_source("plain", &["Jo"], &[0])],
+            "merged",
+            None,
+        )
+        .unwrap();
+
+        assert!(merged.features().coupling);
+        let path = PathBuf::from("coupled/file.txt");
+        let coupling = merged
+            .tree()
+            .get_in(&mut path.components())
+            .unwrap()
+            .indicators()
+            .coupling
+            .clone()
+            .unwrap();
+        assert_eq!(
+            coupling.buckets[0].coupled_files[0].0.to_path_buf(),
+            PathBuf::from("coupled/other.txt")
+        );
+    }
+
+    #[test]
+    fn path_prefix_moves_tree_coupling_and_diagnostics() {
+        let mut data = git_source("prefixed", &["Jo"], &[0]);
+        let file_path = PathBuf::from("file.txt");
+        data.tree_mut()
+            .get_in_mut(&mut file_path.components())
+            .unwrap()
+            .indicators_mut()
+            .coupling = Some(SerializableCouplingData {
+            buckets: vec![SerializableCouplingBucketData {
+                bucket_start: 0,
+                bucket_end: 100,
+                activity_bursts: 1,
+                coupled_files: vec![(Rc::new(PathVec::from("other.txt")), 1)],
+                p_values: Vec::new(),
+            }],
+        });
+        data.add_diagnostic(Diagnostic::new(
+            DiagnosticCategory::SkippedPath,
+            Some(Path::new("broken")),
+            "test",
+        ));
+
+        add_path_prefix(&mut data, Path::new("backend/api/")).unwrap();
+
+        assert_eq!(data.tree().name(), &OsString::from(ROOT_NAME));
+        let path = PathBuf::from("backend/api/file.txt");
+        let coupling = data
+            .tree()
+            .get_in(&mut path.components())
+            .unwrap()
+            .indicators()
+            .coupling
+            .clone()
+            .unwrap();
+        assert_eq!(
+            coupling.buckets[0].coupled_files[0].0.to_path_buf(),
+            PathBuf::from("backend/api/other.txt")
+        );
+        assert_eq!(
+            data.diagnostics()[0].path,
+            Some(PathBuf::from("backend/api/broken"))
+        );
+        assert_eq!(
+            data.metadata().git.as_ref().unwrap().repositories[0].path,
+            PathBuf::from("backend/api")
+        );
+        assert!(add_path_prefix(&mut data, Path::new("../elsewhere")).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+#![warn(clippy::all)]
+//! Estimated block nesting depth per file
+//!
+//! For languages that use braces for blocks, depth is the number of open braces at the start of
+//! each code line. For other languages (or text files) each increase in indentation is one level
+//! deeper, and a decrease drops back to the matching level.
+//!
+//! Very large files are treated as text when a line cap is set - see `code_line_data`.
+//!
+//! These are estimates - braces in strings are counted, and brace-free code in a brace language
+//! has no depth at all - but depth is a useful signal where indentation sums are skewed by
+//! style, such as deep continuation indents.
+
+use crate::binary::lfs_pointer_size;
+use crate::code_line_data::{should_stream, CodeLines};
+use crate::diagnostics::Diagnostic;
+use crate::flare::FlareTreeNode;
+use crate::indentation::{file_content_type, IndentationConfig};
+use crate::polyglot_data::IndicatorMetadata;
+
+use super::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use anyhow::{Context, Error};
+use content_inspector::ContentType;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokei::{Config, LanguageType};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct NestingData {
+    /// the deepest nesting of any line
+    pub maximum: u64,
+    /// mean nesting of code lines
+    pub mean: f64,
+    /// "braces" or "indentation"
+    pub method: String,
+}
+
+fn uses_braces(language: LanguageType) -> bool {
+    use LanguageType::{
+        CHeader, CSharp, Cpp, CppHeader, Css, Dart, Go, Groovy, Java, JavaScript, Json, Jsx,
+        Kotlin, Less, ObjectiveC, ObjectiveCpp, Php, Rust, Sass, Scala, Swift, Tsx, TypeScript, C,
+    };
+    matches!(
+        language,
+        C | CHeader
+            | Cpp
+            | CppHeader
+            | CSharp
+            | Css
+            | Dart
+            | Go
+            | Groovy
+            | Java
+            | JavaScript
+            | Json
+            | Jsx
+            | Kotlin
+            | Less
+            | ObjectiveC
+            | ObjectiveCpp
+            | Php
+            | Rust
+            | Sass
+            | Scala
+            | Swift
+            | Tsx
+            | TypeScript
+    )
+}
+
+fn summarise(depths: &[u64], method: &str) -> Option<NestingData> {
+    if depths.is_empty() {
+        return None;
+    }
+    Some(NestingData {
+        maximum: depths.iter().copied().max().unwrap_or(0),
+        mean: depths.iter().sum::<u64>() as f64 / depths.len() as f64,
+        method: method.to_owned(),
+    })
+}
+
+/// depth of each line is the open braces before it, less any closing braces it starts with
+fn brace_depths<'a>(lines: impl Iterator<Item = &'a [u8]>) -> Vec<u64> {
+    let mut depth: u64 = 0;
+    let mut depths = Vec::new();
+    for line in lines {
+        let trimmed = match line.iter().position(|c| !c.is_ascii_whitespace()) {
+            Some(start) => &line[start..],
+            None => continue,
+        };
+        let leading_closes = trimmed.iter().take_while(|c| **c == b'}').count() as u64;
+        depths.push(depth.saturating_sub(leading_closes));
+        for c in trimmed {
+            match c {
+                b'{' => depth += 1,
+                b'}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+    depths
+}
+
+/// each distinct deeper indentation is one more level
+fn indentation_depths(code_lines: &CodeLines, tab_width: u32) -> Vec<u64> {
+    let mut levels: Vec<u32> = vec![0];
+    let mut depths = Vec::new();
+    for line in &code_lines.lines {
+        if line.text == 0 {
+            continue;
+        }
+        let indentation = line
+            .spaces
+            .saturating_add(line.tabs.saturating_mul(tab_width));
+        while levels.len() > 1 && indentation < *levels.last().unwrap_or(&0) {
+            levels.pop();
+        }
+        if indentation > *levels.last().unwrap_or(&0) {
+            levels.push(indentation);
+        }
+        depths.push((levels.len() - 1) as u64);
+    }
+    depths
+}
+
+fn parse_file(
+    filename: &Path,
+    indentation_config: &IndentationConfig,
+) -> Result<Option<NestingData>, Error> {
+    // LFS pointers are binary content that hasn't been fetched
+    if lfs_pointer_size(filename)?.is_some() {
+        return Ok(None);
+    }
+    let config = Config::default();
+    let language = LanguageType::from_path(filename, &config);
+    let tab_width = indentation_config.tab_width_for(language);
+    let limits = indentation_config.limits();
+    match language {
+        Some(language) if !should_stream(filename, &limits) => {
+            let report = language
+                .parse(PathBuf::from(filename), &config)
+                .map_err(|(error, _pathbuf)| error)?;
+            if uses_braces(language) {
+                Ok(summarise(
+                    &brace_depths(
+                        report
+                            .stats
+                            .code_lines
+                            .iter()
+                            .take(limits.max_lines.unwrap_or(usize::MAX))
+                            .map(|line| limits.line(line)),
+                    ),
+                    "braces",
+                ))
+            } else {
+                let code_lines = CodeLines::from_stats(&report.stats, &limits);
+                Ok(summarise(
+                    &indentation_depths(&code_lines, tab_width),
+                    "indentation",
+                ))
+            }
+        }
+        _ => {
+            if file_content_type(filename)? == ContentType::BINARY {
+                return Ok(None);
+            }
+            let code_lines = indentation_config.stream_lines(filename, language)?;
+            Ok(summarise(
+                &indentation_depths(&code_lines, tab_width),
+                "indentation",
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NestingCalculator {
+    config: IndentationConfig,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl NestingCalculator {
+    pub fn new(config: IndentationConfig) -> Self {
+        NestingCalculator {
+            config,
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl ToxicityIndicatorCalculator for NestingCalculator {
+    fn name(&self) -> String {
+        "nesting".to_string()
+    }
+
+    fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        if path.is_file() {
+            match parse_file(path, &self.config)
+                .with_context(|| format!("parsing nesting for {:?}", path))
+            {
+                Ok(nesting) => node.indicators_mut().nesting = nesting,
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::file_error(path, &self.name(), &e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn brace_depth_counts_open_blocks() {
+        let code = [
+            "fn main() {",
+            "    if x {",
+            "        foo();",
+            "    } else {",
+            "        bar(|| { baz() });",
+            "    }",
+            "}",
+        ];
+        let depths = brace_depths(code.iter().map(|line| line.as_bytes()));
+        assert_eq!(depths, vec![0, 1, 2, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn indentation_depth_counts_distinct_levels() {
+        let code_lines = CodeLines {
+            lines: [(0, 0), (4, 0), (8, 0), (12, 0), (4, 0), (0, 1), (2, 0)]
+                .iter()
+                .map(|(spaces, tabs)| crate::code_line_data::CodeLineData {
+                    spaces: *spaces,
+                    tabs: *tabs,
+                    text: 1,
+                })
+                .collect(),
+            truncated: false,
+        };
+        assert_eq!(
+            indentation_depths(&code_lines, 4),
+            vec![0, 1, 2, 3, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn can_get_nesting_for_a_file() {
+        let nesting = parse_file(
+            Path::new("./tests/data/simple/parent.clj"),
+            &IndentationConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(nesting.method, "indentation");
+        assert_eq!(nesting.maximum, 1);
+    }
+}
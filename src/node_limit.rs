@@ -0,0 +1,406 @@
+#![warn(clippy::all)]
+//! Keeping huge trees small enough for the explorer to render.
+//!
+//! If the tree has more nodes than the limit, files and directories are replaced by synthetic
+//! `<other>` files, which have the summed lines of code of what they replace and an `aggregated`
+//! count of the files and directories in it. Either the smallest files in each directory are
+//! aggregated, or the contents of the deepest directories - if aggregating files isn't enough,
+//! directories are truncated as well.
+//!
+//! This happens after coupling, so coupling to files that were aggregated still names them,
+//! even though they are no longer in the tree.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::flare::FlareTreeNode;
+use crate::loc::LanguageLocData;
+use crate::polyglot_data::PolyglotData;
+
+/// the name of synthetic nodes, in the same style as the root name so it can't clash with files
+pub const OTHER_NAME: &str = "<other>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeLimitStrategy {
+    /// replace the contents of the deepest directories
+    Truncate,
+    /// replace the smallest files in each directory, then truncate if that isn't enough
+    Aggregate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeLimitConfig {
+    pub max_nodes: u64,
+    pub strategy: NodeLimitStrategy,
+}
+
+/// what an `<other>` node replaced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregatedData {
+    pub files: u64,
+    pub directories: u64,
+}
+
+/// what was done to keep under the node limit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeLimitMetadata {
+    #[serde(flatten)]
+    pub config: NodeLimitConfig,
+    /// nodes in the tree before applying the limit
+    pub original_nodes: u64,
+    pub nodes: u64,
+    /// files replaced by `<other>` nodes, including those in truncated directories
+    pub aggregated_files: u64,
+    /// directories whose contents were replaced by an `<other>` node
+    pub truncated_directories: u64,
+}
+
+fn count_nodes(node: &FlareTreeNode) -> u64 {
+    1 + node.get_children().iter().map(count_nodes).sum::<u64>()
+}
+
+fn add_loc(total: &mut LanguageLocData, loc: &LanguageLocData) {
+    total.blanks += loc.blanks;
+    total.code += loc.code;
+    total.comments += loc.comments;
+    total.lines += loc.lines;
+    total.bytes += loc.bytes;
+}
+
+fn add_to_aggregate(
+    node: &FlareTreeNode,
+    aggregated: &mut AggregatedData,
+    loc: &mut Option<LanguageLocData>,
+) {
+    if node.is_file() {
+        match node.indicators().aggregated {
+            Some(existing) => {
+                aggregated.files += existing.files;
+                aggregated.directories += existing.directories;
+            }
+            None => aggregated.files += 1,
+        }
+        if let Some(file_loc) = &node.indicators().loc {
+            let total = loc.get_or_insert_with(|| LanguageLocData {
+                language: OTHER_NAME.to_owned(),
+                binary: false,
+                blanks: 0,
+                code: 0,
+                comments: 0,
+                lines: 0,
+                bytes: 0,
+            });
+            add_loc(total, file_loc);
+        }
+    } else {
+        aggregated.directories += 1;
+        for child in node.get_children() {
+            add_to_aggregate(child, aggregated, loc);
+        }
+    }
+}
+
+/// an `<other>` file standing in for these nodes
+fn other_node(nodes: &[FlareTreeNode]) -> FlareTreeNode {
+    let mut aggregated = AggregatedData::default();
+    let mut loc = None;
+    for node in nodes {
+        add_to_aggregate(node, &mut aggregated, &mut loc);
+    }
+    let mut other = FlareTreeNode::new(OTHER_NAME, true);
+    other.indicators_mut().aggregated = Some(aggregated);
+    other.indicators_mut().loc = loc;
+    other
+}
+
+fn is_other(node: &FlareTreeNode) -> bool {
+    node.is_file() && node.name() == OTHER_NAME
+}
+
+/// files already replaced by `<other>` nodes under this node
+fn aggregated_files(node: &FlareTreeNode) -> u64 {
+    node.indicators()
+        .aggregated
+        .map_or(0, |aggregated| aggregated.files)
+        + node
+            .get_children()
+            .iter()
+            .map(aggregated_files)
+            .sum::<u64>()
+}
+
+/// index paths of directories worth truncating at this depth, and their sizes in nodes
+fn directories_at_depth(
+    node: &FlareTreeNode,
+    depth: usize,
+    path: &mut Vec<usize>,
+    found: &mut Vec<(u64, Vec<usize>)>,
+) {
+    if depth == 0 {
+        // replacing a single child saves nothing
+        let size = count_nodes(node);
+        if size > 2 {
+            found.push((size, path.clone()));
+        }
+        return;
+    }
+    for (index, child) in node.get_children().iter().enumerate() {
+        if !child.is_file() {
+            path.push(index);
+            directories_at_depth(child, depth - 1, path, found);
+            path.pop();
+        }
+    }
+}
+
+fn max_depth(node: &FlareTreeNode) -> usize {
+    node.get_children()
+        .iter()
+        .filter(|child| !child.is_file())
+        .map(|child| 1 + max_depth(child))
+        .max()
+        .unwrap_or(0)
+}
+
+/// replaces the contents of the deepest directories, biggest first, until under the limit
+fn truncate(root: &mut FlareTreeNode, max_nodes: u64, metadata: &mut NodeLimitMetadata) {
+    for depth in (1..=max_depth(root)).rev() {
+        let mut found = Vec::new();
+        directories_at_depth(root, depth, &mut Vec::new(), &mut found);
+        found.sort_by(|(a_size, a_path), (b_size, b_path)| {
+            b_size.cmp(a_size).then(a_path.cmp(b_path))
+        });
+        for (size, path) in found {
+            if metadata.nodes <= max_nodes {
+                return;
+            }
+            if let Some(directory) = root.get_by_indices_mut(&path) {
+                let children = std::mem::take(directory.get_children_mut());
+                let other = other_node(&children);
+                // files in `<other>` nodes have already been counted
+                let already_aggregated: u64 = children.iter().map(aggregated_files).sum();
+                metadata.aggregated_files += other
+                    .indicators()
+                    .aggregated
+                    .map_or(0, |aggregated| aggregated.files)
+                    - already_aggregated;
+                directory.append_child(other);
+                metadata.nodes -= size - 2;
+                metadata.truncated_directories += 1;
+            }
+        }
+    }
+}
+
+/// the smallest files, with index paths of their directories
+fn files_by_size(
+    node: &FlareTreeNode,
+    path: &mut Vec<usize>,
+    found: &mut Vec<((usize, u64), Vec<usize>, usize)>,
+) {
+    for (index, child) in node.get_children().iter().enumerate() {
+        if child.is_file() {
+            if !is_other(child) {
+                let size = child
+                    .indicators()
+                    .loc
+                    .as_ref()
+                    .map_or((0, 0), |loc| (loc.lines, loc.bytes));
+                found.push((size, path.clone(), index));
+            }
+        } else {
+            path.push(index);
+            files_by_size(child, path, found);
+            path.pop();
+        }
+    }
+}
+
+/// replaces the smallest files in each directory with an `<other>` node, until under the limit
+fn aggregate(root: &mut FlareTreeNode, max_nodes: u64, metadata: &mut NodeLimitMetadata) {
+    let mut files = Vec::new();
+    files_by_size(root, &mut Vec::new(), &mut files);
+    files.sort();
+    // the first file aggregated in a directory is replaced by the new node, so saves nothing
+    let mut chosen: BTreeMap<Vec<usize>, Vec<usize>> = BTreeMap::new();
+    let mut saved = 0;
+    for (_, directory, index) in files {
+        if metadata.nodes - saved <= max_nodes {
+            break;
+        }
+        let indexes = chosen.entry(directory).or_default();
+        if !indexes.is_empty() {
+            saved += 1;
+        }
+        indexes.push(index);
+    }
+    for (path, mut indexes) in chosen {
+        if indexes.len() < 2 {
+            continue;
+        }
+        if let Some(directory) = root.get_by_indices_mut(&path) {
+            indexes.sort_unstable();
+            let children = directory.get_children_mut();
+            // highest first, so earlier indexes stay valid
+            let aggregated: Vec<FlareTreeNode> = indexes
+                .iter()
+                .rev()
+                .map(|index| children.remove(*index))
+                .collect();
+            metadata.aggregated_files += aggregated.len() as u64;
+            metadata.nodes -= aggregated.len() as u64 - 1;
+            directory.append_child(other_node(&aggregated));
+        }
+    }
+}
+
+/// aggregates nodes if the tree is too big - returns what was done, or None if nothing was needed
+pub fn apply_node_limit(
+    data: &mut PolyglotData,
+    config: NodeLimitConfig,
+) -> Option<NodeLimitMetadata> {
+    let original_nodes = count_nodes(data.tree());
+    if original_nodes <= config.max_nodes {
+        return None;
+    }
+    let mut metadata = NodeLimitMetadata {
+        config,
+        original_nodes,
+        nodes: original_nodes,
+        aggregated_files: 0,
+        truncated_directories: 0,
+    };
+    if config.strategy == NodeLimitStrategy::Aggregate {
+        aggregate(data.tree_mut(), config.max_nodes, &mut metadata);
+    }
+    if metadata.nodes > config.max_nodes {
+        truncate(data.tree_mut(), config.max_nodes, &mut metadata);
+    }
+    if metadata.nodes > config.max_nodes {
+        warn!(
+            "Couldn't reduce the tree below {} nodes - it still has {}",
+            config.max_nodes, metadata.nodes
+        );
+    }
+    info!(
+        "Reduced tree from {} to {} nodes",
+        metadata.original_nodes, metadata.nodes
+    );
+    Some(metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flare::ROOT_NAME;
+    use crate::FeatureFlags;
+    use pretty_assertions::assert_eq;
+
+    fn file_with_lines(name: &str, lines: usize) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: "Rust".to_owned(),
+            binary: false,
+            blanks: 0,
+            code: lines,
+            comments: 0,
+            lines,
+            bytes: lines as u64 * 10,
+        });
+        file
+    }
+
+    /// root/a.rs, root/b.rs, root/c.rs, root/deep/{x.rs, y.rs, deeper/z.rs} - 9 nodes
+    fn test_data() -> PolyglotData {
+        let mut deeper = FlareTreeNode::dir("deeper");
+        deeper.append_child(file_with_lines("z.rs", 5));
+        let mut deep = FlareTreeNode::dir("deep");
+        deep.append_child(file_with_lines("x.rs", 100));
+        deep.append_child(file_with_lines("y.rs", 200));
+        deep.append_child(deeper);
+        let mut root = FlareTreeNode::dir(ROOT_NAME);
+        root.append_child(file_with_lines("a.rs", 1));
+        root.append_child(file_with_lines("b.rs", 2));
+        root.append_child(file_with_lines("c.rs", 300));
+        root.append_child(deep);
+        PolyglotData::new("test", Some("test"), root, FeatureFlags::default())
+    }
+
+    fn names(node: &FlareTreeNode) -> Vec<String> {
+        node.get_children()
+            .iter()
+            .map(|child| child.name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn small_trees_are_unchanged() {
+        let mut data = test_data();
+        let config = NodeLimitConfig {
+            max_nodes: 9,
+            strategy: NodeLimitStrategy::Aggregate,
+        };
+        assert_eq!(apply_node_limit(&mut data, config), None);
+        assert_eq!(count_nodes(data.tree()), 9);
+    }
+
+    #[test]
+    fn smallest_files_are_aggregated() {
+        let mut data = test_data();
+        let config = NodeLimitConfig {
+            max_nodes: 8,
+            strategy: NodeLimitStrategy::Aggregate,
+        };
+        let metadata = apply_node_limit(&mut data, config).unwrap();
+
+        assert_eq!(names(data.tree()), vec!["c.rs", "deep", OTHER_NAME]);
+        let other = &data.tree().get_children()[2];
+        assert_eq!(
+            other.indicators().aggregated,
+            Some(AggregatedData {
+                files: 2,
+                directories: 0
+            })
+        );
+        assert_eq!(other.indicators().loc.as_ref().unwrap().code, 3);
+        assert_eq!(metadata.nodes, 8);
+        assert_eq!(count_nodes(data.tree()), 8);
+        assert_eq!(metadata.aggregated_files, 2);
+        assert_eq!(metadata.truncated_directories, 0);
+    }
+
+    #[test]
+    fn deepest_directories_are_truncated() {
+        let mut data = test_data();
+        let config = NodeLimitConfig {
+            max_nodes: 6,
+            strategy: NodeLimitStrategy::Truncate,
+        };
+        let metadata = apply_node_limit(&mut data, config).unwrap();
+
+        let deep = &data.tree().get_children()[3];
+        assert_eq!(names(deep), vec![OTHER_NAME]);
+        assert_eq!(
+            deep.get_children()[0].indicators().aggregated,
+            Some(AggregatedData {
+                files: 3,
+                directories: 1
+            })
+        );
+        assert_eq!(
+            deep.get_children()[0]
+                .indicators()
+                .loc
+                .as_ref()
+                .unwrap()
+                .code,
+            305
+        );
+        assert_eq!(metadata.nodes, 6);
+        assert_eq!(count_nodes(data.tree()), 6);
+        assert_eq!(metadata.aggregated_files, 3);
+        // "deeper" only has one file, so isn't worth truncating
+        assert_eq!(metadata.truncated_directories, 1);
+    }
+}
@@ -0,0 +1,142 @@
+#![warn(clippy::all)]
+//! Writing data files to object storage - `s3://bucket/key` or `gs://bucket/key` outputs, so
+//! scheduled scans can land where the explorer reads them.
+//!
+//! The data file is written locally first, then streamed up as a multipart upload. Credentials
+//! and regions come from the usual environment variables, e.g. `AWS_ACCESS_KEY_ID` and
+//! `AWS_DEFAULT_REGION`, or `GOOGLE_SERVICE_ACCOUNT`. Storage clients are big, so they are only
+//! available with the `object-store` cargo feature.
+
+use anyhow::Error;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectStoreKind {
+    S3,
+    Gcs,
+}
+
+/// where in object storage to write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectLocation {
+    pub kind: ObjectStoreKind,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl ObjectLocation {
+    /// parses an object storage url - None if the output isn't in object storage
+    pub fn parse(output: &str) -> Result<Option<Self>, Error> {
+        let (kind, rest) = if let Some(rest) = output.strip_prefix("s3://") {
+            (ObjectStoreKind::S3, rest)
+        } else if let Some(rest) = output.strip_prefix("gs://") {
+            (ObjectStoreKind::Gcs, rest)
+        } else {
+            return Ok(None);
+        };
+        match rest.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() && !key.ends_with('/') => {
+                Ok(Some(ObjectLocation {
+                    kind,
+                    bucket: bucket.to_owned(),
+                    key: key.to_owned(),
+                }))
+            }
+            _ => bail!(
+                "object storage output {} must be a bucket and a file name, e.g. s3://bucket/scans/project.json",
+                output
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ObjectLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ObjectStoreKind::S3 => "s3",
+            ObjectStoreKind::Gcs => "gs",
+        };
+        write!(f, "{}://{}/{}", scheme, self.bucket, self.key)
+    }
+}
+
+/// uploads a local file to object storage
+#[cfg(feature = "object-store")]
+pub fn upload_to_object_store(file: &Path, location: &ObjectLocation) -> Result<(), Error> {
+    use anyhow::Context;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::{path::Path as ObjectPath, ObjectStore};
+    use tokio::io::AsyncWriteExt;
+
+    let store: Box<dyn ObjectStore> = match location.kind {
+        ObjectStoreKind::S3 => Box::new(
+            AmazonS3Builder::from_env()
+                .with_bucket_name(&location.bucket)
+                .build()?,
+        ),
+        ObjectStoreKind::Gcs => Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(&location.bucket)
+                .build()?,
+        ),
+    };
+    let key = ObjectPath::from(location.key.as_str());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime
+        .block_on(async {
+            let mut source = tokio::fs::File::open(file).await?;
+            let (upload_id, mut target) = store.put_multipart(&key).await?;
+            if let Err(e) = tokio::io::copy(&mut source, &mut target).await {
+                // don't leave a half-finished upload behind
+                store.abort_multipart(&key, &upload_id).await?;
+                return Err(Error::from(e));
+            }
+            target.shutdown().await?;
+            Ok(())
+        })
+        .with_context(|| format!("uploading {:?} to {}", file, location))
+}
+
+/// uploads a local file to object storage
+#[cfg(not(feature = "object-store"))]
+pub fn upload_to_object_store(_file: &Path, location: &ObjectLocation) -> Result<(), Error> {
+    bail!(
+        "Can't write to {} - object storage outputs need a build with the object-store feature",
+        location
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn object_urls_are_parsed() -> Result<(), Error> {
+        assert_eq!(
+            ObjectLocation::parse("s3://scans/nightly/project.json")?,
+            Some(ObjectLocation {
+                kind: ObjectStoreKind::S3,
+                bucket: "scans".to_owned(),
+                key: "nightly/project.json".to_owned()
+            })
+        );
+        assert_eq!(
+            ObjectLocation::parse("gs://scans/project.json")?.map(|l| l.kind),
+            Some(ObjectStoreKind::Gcs)
+        );
+        assert_eq!(ObjectLocation::parse("out/s3://not-a-url.json")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn object_urls_need_a_bucket_and_key() {
+        assert!(ObjectLocation::parse("s3://scans").is_err());
+        assert!(ObjectLocation::parse("s3://scans/").is_err());
+        assert!(ObjectLocation::parse("gs:///project.json").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+#![warn(clippy::all)]
+//! Pairing and mobbing - the share of commits with more than one author, from co-author
+//! trailers, per file and summed per directory.
+//!
+//! This is only as good as the co-author data - see `CoAuthorParser` for the trailers that are
+//! recognised.
+
+use serde::{Deserialize, Serialize};
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PairingData {
+    pub commits: u64,
+    /// commits with co-authors
+    pub paired_commits: u64,
+    /// `paired_commits / commits`
+    pub paired_share: f64,
+}
+
+impl PairingData {
+    fn new(commits: u64, paired_commits: u64) -> Self {
+        PairingData {
+            commits,
+            paired_commits,
+            paired_share: if commits == 0 {
+                0.0
+            } else {
+                paired_commits as f64 / commits as f64
+            },
+        }
+    }
+}
+
+/// sets pairing data on each node with commits, returning (commits, paired commits) under it
+fn apply_pairing(node: &mut FlareTreeNode) -> (u64, u64) {
+    let (mut commits, mut paired_commits) = (0, 0);
+    if let Some(GitNodeData::File { data }) = &node.indicators().git {
+        for details in &data.details {
+            commits += details.commits;
+            if details.users.len() > 1 {
+                paired_commits += details.commits;
+            }
+        }
+    }
+    for child in node.get_children_mut() {
+        let (child_commits, child_paired_commits) = apply_pairing(child);
+        commits += child_commits;
+        paired_commits += child_paired_commits;
+    }
+    if commits > 0 {
+        node.indicators_mut().pairing = Some(PairingData::new(commits, paired_commits));
+    }
+    (commits, paired_commits)
+}
+
+/// adds pairing data to every file and directory with git details
+pub fn calculate_pairing(tree: &mut FlareTreeNode) {
+    info!("Calculating pairing");
+    apply_pairing(tree);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::{GitData, GitDetails};
+    use pretty_assertions::assert_eq;
+
+    /// a file with commits by each set of users
+    fn git_file(name: &str, commits: &[(&[usize], u64)]) -> FlareTreeNode {
+        let mut data = GitData::fake_with_activity(Vec::new());
+        data.details = commits
+            .iter()
+            .enumerate()
+            .map(|(day, (users, commits))| GitDetails {
+                commit_day: day as u64,
+                users: users.iter().copied().collect(),
+                commits: *commits,
+                lines_added: 1,
+                lines_deleted: 0,
+                bulk_commits: 0,
+                merge_commits: 0,
+            })
+            .collect();
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file
+    }
+
+    #[test]
+    fn paired_commits_are_counted_and_summed() {
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(git_file("solo.rs", &[(&[0], 3)]));
+        root.append_child(git_file(
+            "paired.rs",
+            &[(&[0], 1), (&[0, 1], 2), (&[0, 1, 2], 1)],
+        ));
+        root.append_child(FlareTreeNode::file("untracked.rs"));
+
+        calculate_pairing(&mut root);
+
+        let pairing_of = |name: &str| {
+            root.get_children()
+                .iter()
+                .find(|child| child.name() == name)
+                .and_then(|child| child.indicators().pairing.clone())
+        };
+        assert_eq!(pairing_of("solo.rs"), Some(PairingData::new(3, 0)));
+        assert_eq!(
+            pairing_of("paired.rs"),
+            Some(PairingData {
+                commits: 4,
+                paired_commits: 3,
+                paired_share: 0.75
+            })
+        );
+        assert_eq!(pairing_of("untracked.rs"), None);
+        assert_eq!(
+            root.indicators().pairing,
+            Some(PairingData {
+                commits: 7,
+                paired_commits: 3,
+                paired_share: 3.0 / 7.0
+            })
+        );
+    }
+}
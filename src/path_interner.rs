@@ -0,0 +1,86 @@
+#![warn(clippy::all)]
+//! Paths stored once and referred to by id, for data that holds the same paths over and over -
+//! git history keeps each file's name per month and per rename, and coupling hashes and compares
+//! paths constantly, which is much faster with ids than with whole paths.
+//!
+//! Interned paths are `Rc`s, so data that needs the paths themselves shares them rather than
+//! copying - coupling data in the tree holds the same paths as the coupling interner.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// a path in a `PathInterner` - cheap to copy, hash and compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct PathId(usize);
+
+/// paths are serialized in id order, without the lookup table
+#[derive(Debug, Serialize)]
+pub struct PathInterner<P: ?Sized + Eq + Hash> {
+    #[serde(skip)]
+    ids: HashMap<Rc<P>, PathId>,
+    paths: Vec<Rc<P>>,
+}
+
+impl<P: ?Sized + Eq + Hash> Default for PathInterner<P> {
+    fn default() -> Self {
+        PathInterner {
+            ids: HashMap::new(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl<P: ?Sized + Eq + Hash> PathInterner<P> {
+    /// the id for a path that's already shared, keeping the `Rc` if the path is new
+    pub fn intern(&mut self, path: &Rc<P>) -> PathId {
+        if let Some(id) = self.ids.get(path) {
+            return *id;
+        }
+        let id = PathId(self.paths.len());
+        self.paths.push(path.clone());
+        self.ids.insert(path.clone(), id);
+        id
+    }
+
+    /// the id for a path, only copying it if it's new
+    pub fn intern_ref(&mut self, path: &P) -> PathId
+    where
+        for<'a> Rc<P>: From<&'a P>,
+    {
+        match self.ids.get(path) {
+            Some(id) => *id,
+            None => self.intern(&Rc::from(path)),
+        }
+    }
+
+    pub fn id(&self, path: &P) -> Option<PathId> {
+        self.ids.get(path).copied()
+    }
+
+    pub fn path(&self, id: PathId) -> &Rc<P> {
+        &self.paths[id.0]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    #[test]
+    fn paths_are_only_stored_once() {
+        let mut interner: PathInterner<Path> = PathInterner::default();
+        let foo = interner.intern_ref(Path::new("src/foo.rs"));
+        let bar = interner.intern_ref(Path::new("src/bar.rs"));
+        assert_ne!(foo, bar);
+        assert_eq!(interner.intern_ref(Path::new("src/foo.rs")), foo);
+        assert_eq!(interner.intern(&Rc::from(Path::new("src/bar.rs"))), bar);
+        assert_eq!(interner.id(Path::new("src/bar.rs")), Some(bar));
+        assert_eq!(interner.id(Path::new("src/baz.rs")), None);
+        assert_eq!(interner.path(foo).as_ref(), Path::new("src/foo.rs"));
+        assert_eq!(interner.paths.len(), 2);
+    }
+}
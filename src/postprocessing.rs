@@ -1,21 +1,53 @@
 use crate::{flare::FlareTreeNode, git::GitNodeData, ScannerConfig};
 use anyhow::Error;
+use std::path::Path;
 
-fn remove_details(node: &mut FlareTreeNode, config: &ScannerConfig) -> Result<(), Error> {
+/// `path` is relative to the tree root
+fn remove_details(
+    node: &mut FlareTreeNode,
+    path: &Path,
+    config: &ScannerConfig,
+) -> Result<(), Error> {
     if let Some(GitNodeData::File { data }) = &mut node.indicators_mut().git {
-        if !config.features.git_details {
+        let keep_details = config.features.git_details
+            && config
+                .git_details_for
+                .as_ref()
+                .map_or(true, |filter| filter.keeps(path));
+        if !keep_details {
             data.details = Vec::new();
         }
-        data.activity = Vec::new();
+        if !config.keep_git_activity {
+            data.activity = Vec::new();
+        }
     }
     for child in node.get_children_mut() {
-        remove_details(child, config)?;
+        let child_path = path.join(child.name());
+        remove_details(child, &child_path, config)?;
     }
     Ok(())
 }
 
+/// is there any fine-grained git activity in the tree? Needed to calculate coupling
+pub fn has_git_activity(node: &FlareTreeNode) -> bool {
+    let has_activity = matches!(
+        &node.indicators().git,
+        Some(GitNodeData::File { data }) if !data.activity.is_empty()
+    );
+    has_activity || node.get_children().iter().any(has_git_activity)
+}
+
+pub fn remove_coupling(node: &mut FlareTreeNode) {
+    let indicators = node.indicators_mut();
+    indicators.coupling = None;
+    indicators.named_coupling.clear();
+    for child in node.get_children_mut() {
+        remove_coupling(child);
+    }
+}
+
 pub fn postprocess_tree(tree: &mut FlareTreeNode, config: &ScannerConfig) -> Result<(), Error> {
     info!("Postprocessing tree before persisting");
-    remove_details(tree, config)?;
+    remove_details(tree, Path::new(""), config)?;
     Ok(())
 }
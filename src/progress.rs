@@ -0,0 +1,319 @@
+#![warn(clippy::all)]
+//! Progress reporting - either as indicatif progress bars, or as JSON lines on stderr
+//! for tools that wrap the scanner.
+//!
+//! The format is global, as progress is reported from deep inside calculators. Bars can't be
+//! drawn if stderr isn't a terminal, as in CI logs - so instead progress is logged now and then.
+
+use indicatif::{HumanDuration, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// progress bars for humans - these are drawn on stderr, and logged occasionally instead if
+    /// stderr isn't a terminal
+    Bars,
+    /// one JSON object per line on stderr
+    Json,
+    /// no progress at all
+    Hidden,
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// don't flood consumers with events - this is per progress instance
+const JSON_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// logs are read by people, and often kept - so log progress much less often
+const LOG_EVENT_INTERVAL: Duration = Duration::from_secs(30);
+
+pub fn set_progress_format(format: ProgressFormat) {
+    let value = match format {
+        ProgressFormat::Bars => 0,
+        ProgressFormat::Json => 1,
+        ProgressFormat::Hidden => 2,
+    };
+    FORMAT.store(value, Ordering::Relaxed);
+}
+
+fn progress_format() -> ProgressFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => ProgressFormat::Json,
+        2 => ProgressFormat::Hidden,
+        _ => ProgressFormat::Bars,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProgressEvent<'a> {
+    event: &'a str,
+    phase: &'a str,
+    position: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percent: Option<f64>,
+    elapsed_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eta_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+/// where counted progress is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CountedOutput {
+    Json,
+    Log,
+}
+
+/// progress without a drawn bar, reported every so often
+#[derive(Debug)]
+struct CountedProgress {
+    output: CountedOutput,
+    phase: String,
+    total: Option<u64>,
+    started: Instant,
+    last_event: Cell<Option<Instant>>,
+    position: Cell<u64>,
+    message: RefCell<Option<String>>,
+}
+
+impl CountedProgress {
+    fn new(output: CountedOutput, phase: &str, total: Option<u64>) -> Self {
+        CountedProgress {
+            output,
+            phase: phase.to_owned(),
+            total,
+            started: Instant::now(),
+            last_event: Cell::new(None),
+            position: Cell::new(0),
+            message: RefCell::new(None),
+        }
+    }
+
+    fn event<'a>(&'a self, event: &'a str, message: Option<&'a str>) -> ProgressEvent<'a> {
+        let position = self.position.get();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let percent = self
+            .total
+            .filter(|total| *total > 0)
+            .map(|total| (position as f64 * 100.0) / total as f64);
+        let eta_secs = match self.total {
+            Some(total) if position > 0 && position <= total => {
+                Some(elapsed * (total - position) as f64 / position as f64)
+            }
+            _ => None,
+        };
+        ProgressEvent {
+            event,
+            phase: &self.phase,
+            position,
+            total: self.total,
+            percent,
+            elapsed_secs: elapsed,
+            eta_secs,
+            message,
+        }
+    }
+
+    fn emit(&self, event: &str) {
+        let message = self.message.borrow();
+        let progress_event = self.event(event, message.as_deref());
+        match self.output {
+            CountedOutput::Json => match serde_json::to_string(&progress_event) {
+                Ok(json) => eprintln!("{}", json),
+                Err(e) => warn!("Can't serialize progress event: {}", e),
+            },
+            CountedOutput::Log => info!("{}", log_line(&progress_event)),
+        }
+        self.last_event.set(Some(Instant::now()));
+    }
+
+    fn maybe_emit(&self) {
+        let interval = match self.output {
+            CountedOutput::Json => JSON_EVENT_INTERVAL,
+            CountedOutput::Log => LOG_EVENT_INTERVAL,
+        };
+        let due = match self.last_event.get() {
+            Some(last) => last.elapsed() >= interval,
+            // logs start quietly - most phases finish before the first interval
+            None => self.output == CountedOutput::Json || self.started.elapsed() >= interval,
+        };
+        if due {
+            self.emit("progress");
+        }
+    }
+}
+
+/// a progress event as a human-readable log line
+fn log_line(event: &ProgressEvent) -> String {
+    let mut line = match (event.total, event.percent) {
+        (Some(total), Some(percent)) => format!(
+            "{}: {}/{} ({:.1}%)",
+            event.phase, event.position, total, percent
+        ),
+        _ => format!("{}: {}", event.phase, event.position),
+    };
+    if event.event == "finish" {
+        line.push_str(&format!(
+            " finished in {}",
+            HumanDuration(Duration::from_secs_f64(event.elapsed_secs))
+        ));
+    } else if let Some(eta) = event.eta_secs {
+        line.push_str(&format!(
+            ", about {} left",
+            HumanDuration(Duration::from_secs_f64(eta))
+        ));
+    }
+    if let Some(message) = event.message {
+        line.push_str(&format!(" - {}", message));
+    }
+    line
+}
+
+#[derive(Debug)]
+enum ProgressKind {
+    Bar(ProgressBar),
+    Counted(CountedProgress),
+}
+
+/// Progress for a single phase of work
+#[derive(Debug)]
+pub struct Progress {
+    kind: ProgressKind,
+}
+
+impl Progress {
+    /// `logged` is false for phases that are only reported as JSON
+    fn new(
+        phase: &str,
+        total: Option<u64>,
+        logged: bool,
+        bar: impl FnOnce() -> ProgressBar,
+    ) -> Self {
+        let kind = match progress_format() {
+            // indicatif's default draw target is stderr, and it only draws if stderr is a terminal
+            ProgressFormat::Bars if logged && ProgressDrawTarget::stderr().is_hidden() => {
+                ProgressKind::Counted(CountedProgress::new(CountedOutput::Log, phase, total))
+            }
+            ProgressFormat::Bars => ProgressKind::Bar(bar()),
+            ProgressFormat::Hidden => ProgressKind::Bar(ProgressBar::hidden()),
+            ProgressFormat::Json => {
+                let progress = CountedProgress::new(CountedOutput::Json, phase, total);
+                progress.emit("start");
+                ProgressKind::Counted(progress)
+            }
+        };
+        Progress { kind }
+    }
+
+    /// progress towards a known total
+    pub fn bar(phase: &str, total: u64, style: ProgressStyle) -> Self {
+        Progress::new(phase, Some(total), true, || {
+            ProgressBar::new(total).with_style(style)
+        })
+    }
+
+    /// progress with no known total
+    pub fn spinner(phase: &str, style: ProgressStyle) -> Self {
+        Progress::new(phase, None, true, || {
+            ProgressBar::new_spinner().with_style(style)
+        })
+    }
+
+    /// progress that is only reported as JSON - for phases that have never had a progress bar
+    pub fn counter(phase: &str) -> Self {
+        Progress::new(phase, None, false, ProgressBar::hidden)
+    }
+
+    pub fn inc(&self, delta: u64) {
+        match &self.kind {
+            ProgressKind::Bar(bar) => bar.inc(delta),
+            ProgressKind::Counted(counted) => {
+                counted.position.set(counted.position.get() + delta);
+                counted.maybe_emit();
+            }
+        }
+    }
+
+    pub fn tick(&self) {
+        match &self.kind {
+            ProgressKind::Bar(bar) => bar.tick(),
+            ProgressKind::Counted(counted) => counted.maybe_emit(),
+        }
+    }
+
+    pub fn set_message(&self, message: String) {
+        match &self.kind {
+            ProgressKind::Bar(bar) => bar.set_message(message),
+            ProgressKind::Counted(counted) => {
+                *counted.message.borrow_mut() = Some(message);
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        match &self.kind {
+            ProgressKind::Bar(bar) => bar.finish(),
+            // quick phases aren't worth a log line
+            ProgressKind::Counted(counted)
+                if counted.output == CountedOutput::Log && counted.last_event.get().is_none() => {}
+            ProgressKind::Counted(counted) => counted.emit("finish"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+
+    #[test]
+    fn json_events_are_single_lines_with_progress_so_far() {
+        let progress = CountedProgress::new(CountedOutput::Json, "walk", Some(4));
+        progress.position.set(1);
+        let json = serde_json::to_string(&progress.event("progress", Some("src/main.rs"))).unwrap();
+        assert!(!json.contains('\n'));
+
+        let mut event: Value = serde_json::from_str(&json).unwrap();
+        let fields = event.as_object_mut().unwrap();
+        assert!(fields.remove("elapsed_secs").unwrap().is_f64());
+        assert!(fields.remove("eta_secs").unwrap().is_f64());
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "event": "progress",
+                "phase": "walk",
+                "position": 1,
+                "total": 4,
+                "percent": 25.0,
+                "message": "src/main.rs"
+            })
+        );
+    }
+
+    #[test]
+    fn json_events_without_a_total_have_no_percent_or_eta() {
+        let progress = CountedProgress::new(CountedOutput::Json, "git", None);
+        let event = serde_json::to_value(progress.event("start", None)).unwrap();
+        let mut keys: Vec<&String> = event.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["elapsed_secs", "event", "phase", "position"]);
+    }
+
+    #[test]
+    fn the_progress_format_can_be_switched() {
+        set_progress_format(ProgressFormat::Json);
+        assert_eq!(progress_format(), ProgressFormat::Json);
+        set_progress_format(ProgressFormat::Hidden);
+        assert_eq!(progress_format(), ProgressFormat::Hidden);
+        // the default, so other tests aren't affected
+        set_progress_format(ProgressFormat::Bars);
+        assert_eq!(progress_format(), ProgressFormat::Bars);
+    }
+}
@@ -0,0 +1,325 @@
+#![warn(clippy::all)]
+// without the feature nothing is fetched, so the API parsing is only used by tests
+#![cfg_attr(not(feature = "pull-requests"), allow(dead_code))]
+//! Pull request and merge request counts per file, from the GitHub or GitLab API - linking
+//! process metrics to the code map.
+//!
+//! The most recent merged pull requests are fetched for each repository, with their commits, and
+//! matched to files by the commit shas in each file's history. Squash merges match by their
+//! merge commit. Turnaround is the time from opening a pull request to merging it.
+//!
+//! This makes one API call per pull request, so a token is needed for all but tiny scans - the
+//! GitHub rate limit without one is 60 calls an hour. Most scans don't need an http client, so
+//! fetching is only available with the `pull-requests` cargo feature.
+
+use anyhow::{Context, Error};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "pull-requests")]
+use std::time::Duration;
+
+const PAGE_SIZE: usize = 100;
+
+#[cfg(feature = "pull-requests")]
+const API_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullRequestConfig {
+    /// a GitHub or GitLab API token
+    #[serde(skip)]
+    pub token: Option<String>,
+    /// how many of the most recent merged pull requests to fetch per repository
+    pub max_pull_requests: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PullRequestData {
+    /// merged pull requests with commits changing this file
+    pub count: u64,
+    /// mean time from opening to merging those pull requests
+    pub mean_turnaround_hours: f64,
+}
+
+/// where to find pull requests for a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PullRequestApi {
+    GitHub { api_url: String, repository: String },
+    GitLab { api_url: String, project: String },
+}
+
+impl PullRequestApi {
+    /// the API for a canonical `host/path` remote url - None for unknown hosts
+    fn for_remote(canonical_url: &str) -> Option<Self> {
+        let (host, path) = canonical_url.split_once('/')?;
+        if host == "github.com" {
+            Some(PullRequestApi::GitHub {
+                api_url: "https://api.github.com".to_owned(),
+                repository: path.to_owned(),
+            })
+        } else if host.starts_with("github.") {
+            // GitHub Enterprise
+            Some(PullRequestApi::GitHub {
+                api_url: format!("https://{}/api/v3", host),
+                repository: path.to_owned(),
+            })
+        } else if host.contains("gitlab") {
+            Some(PullRequestApi::GitLab {
+                api_url: format!("https://{}/api/v4", host),
+                // gitlab wants the whole path as one url-encoded segment
+                project: path.replace('/', "%2F"),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn list_url(&self, page: usize) -> String {
+        match self {
+            PullRequestApi::GitHub { api_url, repository } => format!(
+                "{}/repos/{}/pulls?state=closed&sort=created&direction=desc&per_page={}&page={}",
+                api_url, repository, PAGE_SIZE, page
+            ),
+            PullRequestApi::GitLab { api_url, project } => format!(
+                "{}/projects/{}/merge_requests?state=merged&order_by=created_at&sort=desc&per_page={}&page={}",
+                api_url, project, PAGE_SIZE, page
+            ),
+        }
+    }
+
+    fn commits_url(&self, number: u64) -> String {
+        match self {
+            PullRequestApi::GitHub {
+                api_url,
+                repository,
+            } => format!(
+                "{}/repos/{}/pulls/{}/commits?per_page={}",
+                api_url, repository, number, PAGE_SIZE
+            ),
+            PullRequestApi::GitLab { api_url, project } => format!(
+                "{}/projects/{}/merge_requests/{}/commits?per_page={}",
+                api_url, project, number, PAGE_SIZE
+            ),
+        }
+    }
+}
+
+/// the fields we need from GitHub pull requests and GitLab merge requests
+#[derive(Debug, Deserialize)]
+struct ApiPullRequest {
+    /// github
+    number: Option<u64>,
+    /// gitlab
+    iid: Option<u64>,
+    created_at: String,
+    merged_at: Option<String>,
+    merge_commit_sha: Option<String>,
+    /// gitlab
+    squash_commit_sha: Option<String>,
+}
+
+/// GitHub commits have a `sha`, GitLab commits an `id`
+#[derive(Debug, Deserialize)]
+struct ApiCommit {
+    sha: Option<String>,
+    id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PullRequest {
+    turnaround_hours: f64,
+}
+
+fn parse_time(time: &str) -> Result<i64, Error> {
+    Ok(DateTime::parse_from_rfc3339(time)
+        .with_context(|| format!("parsing API time {}", time))?
+        .timestamp())
+}
+
+/// merged pull requests for a repository, by the shas of their commits
+#[derive(Debug, Default)]
+pub struct PullRequestIndex {
+    pull_requests: Vec<PullRequest>,
+    by_commit: HashMap<String, Vec<usize>>,
+}
+
+impl PullRequestIndex {
+    fn add(&mut self, pull_request: &ApiPullRequest, commits: &[ApiCommit]) -> Result<(), Error> {
+        let merged_at = match &pull_request.merged_at {
+            Some(merged_at) => parse_time(merged_at)?,
+            None => return Ok(()),
+        };
+        let created_at = parse_time(&pull_request.created_at)?;
+        let index = self.pull_requests.len();
+        self.pull_requests.push(PullRequest {
+            turnaround_hours: (merged_at - created_at).max(0) as f64 / 3600.0,
+        });
+        let shas = commits
+            .iter()
+            .filter_map(|commit| commit.sha.as_ref().or(commit.id.as_ref()))
+            .chain(&pull_request.merge_commit_sha)
+            .chain(&pull_request.squash_commit_sha);
+        for sha in shas {
+            let indices = self.by_commit.entry(sha.clone()).or_default();
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// pull request stats for a file's commits - None if none of them were in pull requests
+    pub fn stats_for<'a>(
+        &self,
+        shas: impl IntoIterator<Item = &'a str>,
+    ) -> Option<PullRequestData> {
+        let matched: BTreeSet<usize> = shas
+            .into_iter()
+            .filter_map(|sha| self.by_commit.get(sha))
+            .flatten()
+            .copied()
+            .collect();
+        if matched.is_empty() {
+            return None;
+        }
+        let total_hours: f64 = matched
+            .iter()
+            .map(|index| self.pull_requests[*index].turnaround_hours)
+            .sum();
+        Some(PullRequestData {
+            count: matched.len() as u64,
+            mean_turnaround_hours: total_hours / matched.len() as f64,
+        })
+    }
+
+    /// fetches recent merged pull requests for a remote - None if the host isn't GitHub or GitLab
+    #[cfg(feature = "pull-requests")]
+    pub fn fetch(canonical_url: &str, config: &PullRequestConfig) -> Result<Option<Self>, Error> {
+        let api = match PullRequestApi::for_remote(canonical_url) {
+            Some(api) => api,
+            None => return Ok(None),
+        };
+        let agent = ureq::AgentBuilder::new().timeout(API_TIMEOUT).build();
+        let get = |url: &str| -> Result<ureq::Response, Error> {
+            let mut request = agent.get(url);
+            if let Some(token) = &config.token {
+                request = request.set("Authorization", &format!("Bearer {}", token));
+            }
+            request.call().with_context(|| format!("fetching {}", url))
+        };
+        let mut index = PullRequestIndex::default();
+        let mut fetched = 0;
+        let mut page = 1;
+        while fetched < config.max_pull_requests {
+            let pull_requests: Vec<ApiPullRequest> = get(&api.list_url(page))?.into_json()?;
+            if pull_requests.is_empty() {
+                break;
+            }
+            for pull_request in pull_requests
+                .iter()
+                .filter(|pull_request| pull_request.merged_at.is_some())
+                .take(config.max_pull_requests - fetched)
+            {
+                let number = pull_request
+                    .number
+                    .or(pull_request.iid)
+                    .context("pull request has no number")?;
+                let commits: Vec<ApiCommit> = get(&api.commits_url(number))?.into_json()?;
+                index.add(pull_request, &commits)?;
+                fetched += 1;
+            }
+            page += 1;
+        }
+        info!(
+            "fetched {} merged pull requests for {}",
+            fetched, canonical_url
+        );
+        Ok(Some(index))
+    }
+
+    /// fetches recent merged pull requests for a remote - None if the host isn't GitHub or GitLab
+    #[cfg(not(feature = "pull-requests"))]
+    pub fn fetch(canonical_url: &str, _config: &PullRequestConfig) -> Result<Option<Self>, Error> {
+        bail!(
+            "Can't fetch pull requests for {} - this needs a build with the pull-requests feature",
+            canonical_url
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn pull_request(value: serde_json::Value) -> ApiPullRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn commits(value: serde_json::Value) -> Vec<ApiCommit> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn apis_are_found_from_remotes() {
+        assert_eq!(
+            PullRequestApi::for_remote("github.com/kornysietsma/polyglot-code-scanner"),
+            Some(PullRequestApi::GitHub {
+                api_url: "https://api.github.com".to_owned(),
+                repository: "kornysietsma/polyglot-code-scanner".to_owned()
+            })
+        );
+        assert_eq!(
+            PullRequestApi::for_remote("gitlab.example.com/group/sub/project")
+                .map(|api| api.commits_url(7)),
+            Some(
+                "https://gitlab.example.com/api/v4/projects/group%2Fsub%2Fproject/merge_requests/7/commits?per_page=100"
+                    .to_owned()
+            )
+        );
+        assert_eq!(PullRequestApi::for_remote("bitbucket.org/foo/bar"), None);
+    }
+
+    #[test]
+    fn files_match_pull_requests_by_commit() -> Result<(), Error> {
+        let mut index = PullRequestIndex::default();
+        index.add(
+            &pull_request(json!({
+                "number": 1,
+                "created_at": "2022-11-01T10:00:00Z",
+                "merged_at": "2022-11-02T10:00:00Z",
+                "merge_commit_sha": "m1"
+            })),
+            &commits(json!([{"sha": "a"}, {"sha": "b"}])),
+        )?;
+        index.add(
+            &pull_request(json!({
+                "iid": 2,
+                "created_at": "2022-11-03T10:00:00Z",
+                "merged_at": "2022-11-03T22:00:00Z",
+                "merge_commit_sha": null,
+                "squash_commit_sha": "s2"
+            })),
+            &commits(json!([{"id": "c"}])),
+        )?;
+        index.add(
+            &pull_request(json!({
+                "number": 3,
+                "created_at": "2022-11-03T10:00:00Z",
+                "merged_at": null
+            })),
+            &commits(json!([{"sha": "d"}])),
+        )?;
+
+        assert_eq!(
+            index.stats_for(["a", "b", "s2", "d", "unknown"]),
+            Some(PullRequestData {
+                count: 2,
+                mean_turnaround_hours: 18.0
+            })
+        );
+        assert_eq!(index.stats_for(["d"]), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+#![warn(clippy::all)]
+//! Churn vs complexity quadrants - classifying each file as high or low churn, and high or low
+//! complexity, relative to the rest of the scan.
+//!
+//! Churn is the number of commits to a file, complexity is its indentation sum. A file is
+//! "high" for either if it is above the configured percentile of all files with both values -
+//! so high churn, high complexity files are the ones to refactor first. Thresholds are recorded
+//! in metadata, so explorers can explain the labels.
+
+use serde::{Deserialize, Serialize};
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuadrantConfig {
+    /// files with more commits than this percentile of files are high churn
+    pub churn_percentile: u8,
+    /// files with a bigger indentation sum than this percentile of files are high complexity
+    pub complexity_percentile: u8,
+}
+
+impl Default for QuadrantConfig {
+    fn default() -> Self {
+        QuadrantConfig {
+            churn_percentile: 75,
+            complexity_percentile: 75,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quadrant {
+    HighChurnHighComplexity,
+    HighChurnLowComplexity,
+    LowChurnHighComplexity,
+    LowChurnLowComplexity,
+}
+
+impl Quadrant {
+    fn new(high_churn: bool, high_complexity: bool) -> Self {
+        match (high_churn, high_complexity) {
+            (true, true) => Quadrant::HighChurnHighComplexity,
+            (true, false) => Quadrant::HighChurnLowComplexity,
+            (false, true) => Quadrant::LowChurnHighComplexity,
+            (false, false) => Quadrant::LowChurnLowComplexity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuadrantMetadata {
+    pub config: QuadrantConfig,
+    /// files with more commits than this are high churn
+    pub churn_threshold: u64,
+    /// files with a bigger indentation sum than this are high complexity
+    pub complexity_threshold: u64,
+    /// how many files were classified
+    pub files: usize,
+}
+
+/// commits and indentation sum, for files with both
+fn churn_and_complexity(node: &FlareTreeNode) -> Option<(u64, u64)> {
+    let indicators = node.indicators();
+    let commits = match &indicators.git {
+        Some(GitNodeData::File { data }) => data.details.iter().map(|d| d.commits).sum(),
+        _ => return None,
+    };
+    let complexity = indicators.indentation.as_ref()?.sum;
+    Some((commits, complexity))
+}
+
+fn gather_values(node: &FlareTreeNode, values: &mut Vec<(u64, u64)>) {
+    if node.is_file() {
+        values.extend(churn_and_complexity(node));
+    }
+    for child in node.get_children() {
+        gather_values(child, values);
+    }
+}
+
+/// the nearest-rank percentile of unsorted values
+fn percentile(mut values: Vec<u64>, percentile: u8) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    let rank = (f64::from(percentile.min(100)) / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.max(1) - 1]
+}
+
+fn apply_quadrants(node: &mut FlareTreeNode, metadata: &QuadrantMetadata) {
+    if node.is_file() {
+        if let Some((churn, complexity)) = churn_and_complexity(node) {
+            node.indicators_mut().quadrant = Some(Quadrant::new(
+                churn > metadata.churn_threshold,
+                complexity > metadata.complexity_threshold,
+            ));
+        }
+    }
+    for child in node.get_children_mut() {
+        apply_quadrants(child, metadata);
+    }
+}
+
+/// adds a quadrant to every file with git details and indentation
+pub fn calculate_quadrants(tree: &mut FlareTreeNode, config: QuadrantConfig) -> QuadrantMetadata {
+    info!("Calculating churn and complexity quadrants");
+    let mut values = Vec::new();
+    gather_values(tree, &mut values);
+    let metadata = QuadrantMetadata {
+        config,
+        churn_threshold: percentile(
+            values.iter().map(|(churn, _)| *churn).collect(),
+            config.churn_percentile,
+        ),
+        complexity_threshold: percentile(
+            values.iter().map(|(_, complexity)| *complexity).collect(),
+            config.complexity_percentile,
+        ),
+        files: values.len(),
+    };
+    apply_quadrants(tree, &metadata);
+    metadata
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::{GitData, GitDetails};
+    use crate::indentation::IndentationData;
+    use pretty_assertions::assert_eq;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn file(name: &str, commits: u64, indentation_sum: u64) -> FlareTreeNode {
+        let mut data = GitData::fake_with_activity(Vec::new());
+        data.details = vec![GitDetails {
+            commit_day: 0,
+            users: BTreeSet::new(),
+            commits,
+            lines_added: 0,
+            lines_deleted: 0,
+            bulk_commits: 0,
+            merge_commits: 0,
+        }];
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().git = Some(GitNodeData::File { data });
+        file.indicators_mut().indentation = Some(IndentationData {
+            lines: 10,
+            minimum: 0,
+            maximum: 0,
+            median: Some(0),
+            stddev: 0.0,
+            p75: Some(0),
+            p90: Some(0),
+            p99: Some(0),
+            percentiles: BTreeMap::new(),
+            sum: indentation_sum,
+            truncated: false,
+            histogram: None,
+        });
+        file
+    }
+
+    fn quadrant_of(tree: &FlareTreeNode, name: &str) -> Option<Quadrant> {
+        tree.get_children()
+            .iter()
+            .find(|child| child.name() == name)
+            .and_then(|child| child.indicators().quadrant)
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        assert_eq!(percentile(vec![4, 1, 3, 2], 50), 2);
+        assert_eq!(percentile(vec![4, 1, 3, 2], 75), 3);
+        assert_eq!(percentile(vec![4, 1, 3, 2], 0), 1);
+        assert_eq!(percentile(vec![4, 1, 3, 2], 100), 4);
+        assert_eq!(percentile(Vec::new(), 50), 0);
+    }
+
+    #[test]
+    fn files_are_classified_against_the_rest_of_the_scan() {
+        let mut root = FlareTreeNode::dir("root");
+        root.append_child(file("hot_complex.rs", 40, 400));
+        root.append_child(file("hot_simple.rs", 30, 10));
+        root.append_child(file("cold_complex.rs", 1, 300));
+        root.append_child(file("cold_simple.rs", 2, 20));
+        root.append_child(FlareTreeNode::file("no_data.rs"));
+
+        let metadata = calculate_quadrants(
+            &mut root,
+            QuadrantConfig {
+                churn_percentile: 50,
+                complexity_percentile: 50,
+            },
+        );
+
+        assert_eq!(metadata.churn_threshold, 2);
+        assert_eq!(metadata.complexity_threshold, 20);
+        assert_eq!(metadata.files, 4);
+        assert_eq!(
+            quadrant_of(&root, "hot_complex.rs"),
+            Some(Quadrant::HighChurnHighComplexity)
+        );
+        assert_eq!(
+            quadrant_of(&root, "hot_simple.rs"),
+            Some(Quadrant::HighChurnLowComplexity)
+        );
+        assert_eq!(
+            quadrant_of(&root, "cold_complex.rs"),
+            Some(Quadrant::LowChurnHighComplexity)
+        );
+        assert_eq!(
+            quadrant_of(&root, "cold_simple.rs"),
+            Some(Quadrant::LowChurnLowComplexity)
+        );
+        assert_eq!(quadrant_of(&root, "no_data.rs"), None);
+    }
+}
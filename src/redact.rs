@@ -0,0 +1,269 @@
+#![warn(clippy::all)]
+//! Replacing file and directory names with stable hashes, so data files can be shared outside an
+//! organisation without leaking product names.
+//!
+//! Each name is hashed on its own, so the same name is the same hash everywhere - in the tree,
+//! in coupling, and in repository and diagnostic paths - and across scans with the same salt.
+//! Without a salt, common names like "src" can be guessed from their hashes.
+//!
+//! Remote urls are removed, as they usually name the project, and diagnostic messages are
+//! dropped as they often include paths. Names from config files - teams, architecture components
+//! and the like - are kept, as they are chosen by whoever runs the scan.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::coupling::{CouplingMetadata, PathVec, SerializableCouplingData};
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use crate::git_file_history::fnv1a;
+use crate::polyglot_data::PolyglotData;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactConfig {
+    /// keep file extensions, e.g. "3f2a...c1.rs", so languages are still visible
+    pub keep_extensions: bool,
+    /// mixed into every hash, so names can't be guessed - never saved in the output
+    #[serde(skip)]
+    pub salt: String,
+}
+
+impl RedactConfig {
+    /// the redacted form of a single file or directory name
+    fn name(&self, name: &OsStr, is_file: bool) -> OsString {
+        let name = name.to_string_lossy();
+        let (stem, extension) = match name.rsplit_once('.') {
+            // dotfiles like ".gitignore" are all stem
+            Some((stem, extension)) if is_file && self.keep_extensions && !stem.is_empty() => {
+                (stem, Some(extension))
+            }
+            _ => (&*name, None),
+        };
+        let hash = format!(
+            "{:016x}",
+            fnv1a(format!("{}\n{}", self.salt, stem).as_bytes())
+        );
+        match extension {
+            Some(extension) => format!("{}.{}", hash, extension).into(),
+            None => hash.into(),
+        }
+    }
+
+    /// redacts every name in a path - only the last component can be a file
+    fn path(&self, path: &Path, is_file: bool) -> PathBuf {
+        let last = path.components().count().saturating_sub(1);
+        path.components()
+            .enumerate()
+            .map(|(index, component)| match component {
+                Component::Normal(name) => self.name(name, is_file && index == last),
+                other => other.as_os_str().to_owned(),
+            })
+            .collect()
+    }
+
+    /// redacts a path saved as a JSON string, e.g. in the scanner config
+    fn json_path(&self, value: &mut Value, is_file: bool) {
+        if let Some(path) = value.as_str() {
+            *value = Value::from(self.path(Path::new(path), is_file).to_string_lossy());
+        }
+    }
+
+    fn path_vec(&self, path: &PathVec) -> Rc<PathVec> {
+        Rc::new(PathVec::from(self.path(&path.to_path_buf(), true)))
+    }
+
+    fn coupling(&self, coupling: &mut SerializableCouplingData) {
+        for bucket in &mut coupling.buckets {
+            for (file, _) in &mut bucket.coupled_files {
+                *file = self.path_vec(file);
+            }
+        }
+    }
+
+    fn coupling_summary(&self, metadata: &mut CouplingMetadata) {
+        for bucket in &mut metadata.summary {
+            for edge in &mut bucket.strongest_edges {
+                edge.source = self.path_vec(&edge.source);
+                edge.target = self.path_vec(&edge.target);
+            }
+        }
+    }
+
+    fn tree(&self, node: &mut FlareTreeNode, is_root: bool) {
+        if !is_root {
+            let name = self.name(node.name(), node.is_file());
+            node.set_name(&name);
+        }
+        let indicators = node.indicators_mut();
+        if let Some(coupling) = &mut indicators.coupling {
+            self.coupling(coupling);
+        }
+        for coupling in indicators.named_coupling.values_mut() {
+            self.coupling(coupling);
+        }
+        if let Some(GitNodeData::Dir { data }) = &mut indicators.git {
+            data.remote_url = None;
+            data.remotes.clear();
+            data.canonical_remote_url = None;
+            data.browse_url_template = None;
+        }
+        for child in node.get_children_mut() {
+            self.tree(child, false);
+        }
+    }
+}
+
+/// redacts names throughout a data file - see module docs for what is changed
+pub fn redact_names(data: &mut PolyglotData, config: &RedactConfig) {
+    config.tree(data.tree_mut(), true);
+    let metadata = data.metadata();
+    if let Some(coupling) = &mut metadata.coupling {
+        config.coupling_summary(coupling);
+    }
+    for coupling in metadata.named_coupling.values_mut() {
+        config.coupling_summary(coupling);
+    }
+    if let Some(architecture) = &mut metadata.architecture {
+        for violation in &mut architecture.violations {
+            violation.from_file = config
+                .path(Path::new(&violation.from_file), true)
+                .to_string_lossy()
+                .into_owned();
+            violation.to_file = config
+                .path(Path::new(&violation.to_file), true)
+                .to_string_lossy()
+                .into_owned();
+        }
+    }
+    if let Some(areas) = &mut metadata.areas_of_interest {
+        let files = areas
+            .largest_files
+            .iter_mut()
+            .chain(&mut areas.oldest_untouched_files)
+            .chain(&mut areas.most_authors);
+        for file in files {
+            file.path = config.path(&file.path, true);
+        }
+        for dir in &mut areas.deepest_directories {
+            dir.path = config.path(&dir.path, false);
+        }
+    }
+    if let Some(git) = &mut metadata.git {
+        for repository in &mut git.repositories {
+            repository.path = config.path(&repository.path, false);
+            repository.remote_url = None;
+            repository.canonical_remote_url = None;
+        }
+    }
+    if let Some(paths) = &mut metadata.paths {
+        paths.root = paths.root.as_deref().map(|root| config.path(root, false));
+        paths.path_prefix = paths
+            .path_prefix
+            .as_deref()
+            .map(|prefix| config.path(prefix, false));
+    }
+    if let Some(scanner) = &mut metadata.scanner {
+        let scanned = &mut scanner.config;
+        if let Some(prefix) = scanned.get_mut("path_prefix") {
+            config.json_path(prefix, false);
+        }
+        if let Some(Value::Array(files)) = scanned.get_mut("ignore_files") {
+            for file in files {
+                config.json_path(file, true);
+            }
+        }
+        if let Some(file) = scanned.get_mut("architecture_file") {
+            config.json_path(file, true);
+        }
+    }
+    for diagnostic in data.diagnostics_mut() {
+        diagnostic.path = diagnostic
+            .path
+            .as_deref()
+            .map(|path| config.path(path, true));
+        diagnostic.message = "redacted".to_owned();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flare::ROOT_NAME;
+    use crate::FeatureFlags;
+    use pretty_assertions::assert_eq;
+
+    fn redacted(name: &str, is_file: bool, keep_extensions: bool) -> String {
+        let config = RedactConfig {
+            keep_extensions,
+            salt: String::new(),
+        };
+        config
+            .name(OsStr::new(name), is_file)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn names_are_replaced_by_stable_hashes() {
+        let hashed = redacted("payments", false, false);
+        assert_eq!(hashed.len(), 16);
+        assert_ne!(hashed, redacted("billing", false, false));
+        assert_eq!(hashed, redacted("payments", false, false));
+        assert_eq!(
+            redacted("payments.rs", true, true),
+            format!("{}.rs", hashed)
+        );
+        assert!(!redacted("payments.rs", true, false).ends_with(".rs"));
+        // directories and dotfiles have no extension to keep
+        assert_eq!(redacted("payments.d", false, true).len(), 16);
+        assert_eq!(redacted(".gitignore", true, true).len(), 16);
+    }
+
+    #[test]
+    fn salts_change_hashes() {
+        let salted = RedactConfig {
+            keep_extensions: false,
+            salt: "secret".to_owned(),
+        };
+        assert_ne!(
+            salted.name(OsStr::new("payments"), false).to_string_lossy(),
+            redacted("payments", false, false)
+        );
+    }
+
+    #[test]
+    fn tree_names_and_coupling_paths_match() {
+        let config = RedactConfig::default();
+        let mut file = FlareTreeNode::file("invoice.rs");
+        file.indicators_mut().coupling = Some(SerializableCouplingData {
+            buckets: vec![crate::coupling::SerializableCouplingBucketData {
+                bucket_start: 0,
+                bucket_end: 1,
+                activity_bursts: 1,
+                coupled_files: vec![(
+                    Rc::new(PathVec::from(PathBuf::from("payments/invoice.rs"))),
+                    1,
+                )],
+                p_values: Vec::new(),
+            }],
+        });
+        let mut dir = FlareTreeNode::dir("payments");
+        dir.append_child(file);
+        let mut tree = FlareTreeNode::dir(ROOT_NAME);
+        tree.append_child(dir);
+        let mut data = PolyglotData::new("test", None, tree, FeatureFlags::default());
+
+        redact_names(&mut data, &config);
+
+        let tree = data.tree();
+        assert_eq!(tree.name(), ROOT_NAME);
+        let dir = &tree.get_children()[0];
+        let file = &dir.get_children()[0];
+        let expected_path: PathBuf = [dir.name(), file.name()].iter().collect();
+        let coupled = &file.indicators().coupling.as_ref().unwrap().buckets[0].coupled_files;
+        assert_eq!(coupled[0].0.to_path_buf(), expected_path);
+    }
+}
@@ -0,0 +1,400 @@
+#![warn(clippy::all)]
+//! Human-readable reports of the top files by a few simple measures - for people who want
+//! answers rather than a JSON file to explore.
+//!
+//! - hotspots are files changed often that are also big - commits times lines of code, as in
+//!   Adam Tornhill's "Your Code as a Crime Scene"
+//! - biggest files are by lines of code
+//! - coupled pairs are the strongest edges from any coupling bucket, if coupling was calculated
+//! - single owners are files where one person made most of the commits - a knowledge risk
+//!
+//! Git measures need detailed git data. Vendored files, and `<other>` nodes standing in for
+//! aggregated files, are left out.
+
+use anyhow::Error;
+use path_slash::PathExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use crate::git_logger::User;
+use crate::polyglot_data::PolyglotData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReportConfig {
+    pub format: ReportFormat,
+    /// how many files are listed in each section
+    pub top: usize,
+    /// files with fewer commits than this aren't listed as single-owner - one person writing a
+    /// file that has hardly changed is normal
+    pub min_owner_commits: u64,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig {
+            format: ReportFormat::Markdown,
+            top: 10,
+            min_owner_commits: 5,
+        }
+    }
+}
+
+/// a section of the report - a titled table
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    title: &'static str,
+    /// shown if the table is empty, usually because the data wasn't scanned
+    empty: &'static str,
+    headings: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FileFacts {
+    path: String,
+    code: Option<usize>,
+    commits: Option<u64>,
+    /// the top contributor, and their share of commits
+    owner: Option<(usize, u64)>,
+}
+
+fn gather_files(node: &FlareTreeNode, path: &Path, files: &mut Vec<FileFacts>) {
+    for child in node.get_children() {
+        let indicators = child.indicators();
+        if indicators.vendored || indicators.aggregated.is_some() {
+            continue;
+        }
+        let child_path = path.join(child.name());
+        if !child.is_file() {
+            gather_files(child, &child_path, files);
+            continue;
+        }
+        let mut facts = FileFacts {
+            path: child_path.to_slash_lossy().into_owned(),
+            code: indicators.loc.as_ref().map(|loc| loc.code),
+            ..FileFacts::default()
+        };
+        if let Some(GitNodeData::File { data }) = &indicators.git {
+            if !data.details.is_empty() {
+                facts.commits = Some(data.details.iter().map(|details| details.commits).sum());
+            }
+            facts.owner = data
+                .top_contributors
+                .first()
+                .map(|contributor| (contributor.user, contributor.commits));
+        }
+        files.push(facts);
+    }
+}
+
+fn user_name(users: &[User], id: usize) -> String {
+    users
+        .get(id)
+        .and_then(|user| user.name().or_else(|| user.email()))
+        .map_or_else(|| format!("user {}", id), ToOwned::to_owned)
+}
+
+fn hotspots(files: &[FileFacts], top: usize) -> Section {
+    let mut scored: Vec<(u64, &FileFacts)> = files
+        .iter()
+        .filter_map(|file| match (file.commits, file.code) {
+            (Some(commits), Some(code)) if commits > 0 && code > 0 => {
+                Some((commits * code as u64, file))
+            }
+            _ => None,
+        })
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.path.cmp(&b.path)));
+    Section {
+        title: "Hotspots",
+        empty:
+            "No files with both git history and lines of code - hotspots need git details and loc",
+        headings: vec!["File", "Commits", "Lines of code", "Score"],
+        rows: scored
+            .into_iter()
+            .take(top)
+            .map(|(score, file)| {
+                vec![
+                    file.path.clone(),
+                    file.commits.unwrap_or(0).to_string(),
+                    file.code.unwrap_or(0).to_string(),
+                    score.to_string(),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn biggest_files(files: &[FileFacts], top: usize) -> Section {
+    let mut sized: Vec<(usize, &FileFacts)> = files
+        .iter()
+        .filter_map(|file| file.code.filter(|code| *code > 0).map(|code| (code, file)))
+        .collect();
+    sized.sort_by(|(a_code, a), (b_code, b)| b_code.cmp(a_code).then(a.path.cmp(&b.path)));
+    Section {
+        title: "Biggest files",
+        empty: "No lines of code were counted",
+        headings: vec!["File", "Lines of code"],
+        rows: sized
+            .into_iter()
+            .take(top)
+            .map(|(code, file)| vec![file.path.clone(), code.to_string()])
+            .collect(),
+    }
+}
+
+fn coupled_pairs(data: &mut PolyglotData, top: usize) -> Section {
+    // the strongest ratio for each pair, in any bucket
+    let mut pairs: BTreeMap<(String, String), (f64, u64)> = BTreeMap::new();
+    if let Some(coupling) = &data.metadata().coupling {
+        for bucket in &coupling.summary {
+            for edge in &bucket.strongest_edges {
+                let key = (
+                    edge.source.to_path_buf().to_slash_lossy().into_owned(),
+                    edge.target.to_path_buf().to_slash_lossy().into_owned(),
+                );
+                let best = pairs.entry(key).or_insert((edge.ratio, edge.count));
+                if edge.ratio > best.0 {
+                    *best = (edge.ratio, edge.count);
+                }
+            }
+        }
+    }
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_by(|(a_key, (a_ratio, _)), (b_key, (b_ratio, _))| {
+        b_ratio.partial_cmp(a_ratio).unwrap().then(a_key.cmp(b_key))
+    });
+    Section {
+        title: "Most coupled files",
+        empty: "No coupling data - scan with coupling enabled to see coupled files",
+        headings: vec!["File", "Changes with", "Ratio", "Times"],
+        rows: pairs
+            .into_iter()
+            .take(top)
+            .map(|((source, target), (ratio, count))| {
+                vec![source, target, format!("{:.2}", ratio), count.to_string()]
+            })
+            .collect(),
+    }
+}
+
+fn single_owners(files: &[FileFacts], users: &[User], config: &ReportConfig) -> Section {
+    let mut owned: Vec<(f64, u64, usize, &FileFacts)> = files
+        .iter()
+        .filter_map(|file| match (file.commits, file.owner) {
+            (Some(commits), Some((user, owned))) if commits >= config.min_owner_commits => {
+                Some((owned as f64 / commits as f64, commits, user, file))
+            }
+            _ => None,
+        })
+        .collect();
+    owned.sort_by(|(a_share, a_commits, _, a), (b_share, b_commits, _, b)| {
+        b_share
+            .partial_cmp(a_share)
+            .unwrap()
+            .then(b_commits.cmp(a_commits))
+            .then(a.path.cmp(&b.path))
+    });
+    Section {
+        title: "Files mostly changed by one person",
+        empty: "No files with enough commits - single owners need git details",
+        headings: vec!["File", "Person", "Share of commits", "Commits"],
+        rows: owned
+            .into_iter()
+            .take(config.top)
+            .map(|(share, commits, user, file)| {
+                vec![
+                    file.path.clone(),
+                    user_name(users, user),
+                    format!("{:.0}%", share * 100.0),
+                    commits.to_string(),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn sections(data: &mut PolyglotData, config: &ReportConfig) -> Vec<Section> {
+    let mut files = Vec::new();
+    gather_files(data.tree(), Path::new(""), &mut files);
+    let users: Vec<User> = data
+        .metadata()
+        .git
+        .as_ref()
+        .map(|git| git.users.users().to_vec())
+        .unwrap_or_default();
+    vec![
+        hotspots(&files, config.top),
+        biggest_files(&files, config.top),
+        coupled_pairs(data, config.top),
+        single_owners(&files, &users, config),
+    ]
+}
+
+/// table cells can't contain pipes or newlines
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn write_markdown<W: io::Write>(name: &str, sections: &[Section], out: &mut W) -> io::Result<()> {
+    writeln!(out, "# {}", markdown_cell(name))?;
+    for section in sections {
+        writeln!(out)?;
+        writeln!(out, "## {}", section.title)?;
+        writeln!(out)?;
+        if section.rows.is_empty() {
+            writeln!(out, "{}", section.empty)?;
+            continue;
+        }
+        writeln!(out, "| {} |", section.headings.join(" | "))?;
+        let rule: Vec<&str> = section.headings.iter().map(|_| "---").collect();
+        writeln!(out, "| {} |", rule.join(" | "))?;
+        for row in &section.rows {
+            let cells: Vec<String> = row.iter().map(|cell| markdown_cell(cell)).collect();
+            writeln!(out, "| {} |", cells.join(" | "))?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_html<W: io::Write>(name: &str, sections: &[Section], out: &mut W) -> io::Result<()> {
+    let name = html_escape(name);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>{}</title>",
+        name
+    )?;
+    writeln!(
+        out,
+        "<style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} \
+         th, td {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}</style>"
+    )?;
+    writeln!(out, "</head><body>")?;
+    writeln!(out, "<h1>{}</h1>", name)?;
+    for section in sections {
+        writeln!(out, "<h2>{}</h2>", section.title)?;
+        if section.rows.is_empty() {
+            writeln!(out, "<p>{}</p>", section.empty)?;
+            continue;
+        }
+        write!(out, "<table><tr>")?;
+        for heading in &section.headings {
+            write!(out, "<th>{}</th>", heading)?;
+        }
+        writeln!(out, "</tr>")?;
+        for row in &section.rows {
+            write!(out, "<tr>")?;
+            for cell in row {
+                write!(out, "<td>{}</td>", html_escape(cell))?;
+            }
+            writeln!(out, "</tr>")?;
+        }
+        writeln!(out, "</table>")?;
+    }
+    writeln!(out, "</body></html>")?;
+    Ok(())
+}
+
+/// writes a report of the top files in the data
+pub fn write_report<W: io::Write>(
+    data: &mut PolyglotData,
+    config: &ReportConfig,
+    mut out: W,
+) -> Result<(), Error> {
+    let sections = sections(data, config);
+    let name = data.name().to_owned();
+    match config.format {
+        ReportFormat::Markdown => write_markdown(&name, &sections, &mut out)?,
+        ReportFormat::Html => write_html(&name, &sections, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::loc::LanguageLocData;
+    use pretty_assertions::assert_eq;
+
+    fn file(path: &str, code: Option<usize>, commits: Option<u64>) -> FileFacts {
+        FileFacts {
+            path: path.to_owned(),
+            code,
+            commits,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn hotspots_are_big_files_changed_often() {
+        let files = vec![
+            file("big_but_stable.rs", Some(1000), Some(1)),
+            file("busy.rs", Some(100), Some(20)),
+            file("no_git.rs", Some(5000), None),
+            file("tiny.rs", Some(1), Some(50)),
+        ];
+        let section = hotspots(&files, 2);
+        assert_eq!(
+            section.rows,
+            vec![
+                vec!["busy.rs", "20", "100", "2000"],
+                vec!["big_but_stable.rs", "1", "1000", "1000"]
+            ]
+        );
+    }
+
+    #[test]
+    fn single_owners_need_enough_commits() {
+        let users = vec![User::new(Some("Jane"), None)];
+        let mut owned = file("owned.rs", None, Some(10));
+        owned.owner = Some((0, 9));
+        let mut new = file("new.rs", None, Some(1));
+        new.owner = Some((0, 1));
+        let section = single_owners(&[owned, new], &users, &ReportConfig::default());
+        assert_eq!(section.rows, vec![vec!["owned.rs", "Jane", "90%", "10"]]);
+    }
+
+    #[test]
+    fn reports_can_be_markdown() {
+        let mut root = FlareTreeNode::dir("<root>");
+        let mut big = FlareTreeNode::file("big|file.rs");
+        big.indicators_mut().loc = Some(LanguageLocData {
+            language: "Rust".to_owned(),
+            binary: false,
+            blanks: 0,
+            code: 10,
+            comments: 0,
+            lines: 10,
+            bytes: 100,
+        });
+        root.append_child(big);
+        let mut data = PolyglotData::new("test", None, root, crate::FeatureFlags::default());
+        let mut out = Vec::new();
+        write_report(&mut data, &ReportConfig::default(), &mut out).unwrap();
+        let markdown = String::from_utf8(out).unwrap();
+
+        assert!(markdown.starts_with("# test\n"));
+        assert!(markdown.contains("| big\\|file.rs | 10 |"));
+        assert!(markdown.contains("No coupling data"));
+    }
+}
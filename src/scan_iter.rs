@@ -0,0 +1,187 @@
+#![warn(clippy::all)]
+//! Scanning one file at a time, for applications embedding the scanner that want to consume
+//! results as they are found - e.g. to write them straight to a database - without holding the
+//! whole tree in memory.
+//!
+//! Coupling, decay and path prefixes need the whole tree, so they aren't available here.
+//! Git user ids refer to the user dictionary in `ScanIter::metadata`, which is only complete
+//! once every file has been scanned.
+
+use crate::cancellation::{is_cancellation, CancellationToken};
+use crate::diagnostics::{Diagnostic, DiagnosticCategory};
+use crate::file_walker::{apply_calculators_to_node, cancelled_walk, LinkGuard};
+use crate::flare::{FlareTreeNode, IndicatorData, ROOT_NAME};
+use crate::git::GitNodeData;
+use crate::polyglot_data::IndicatorMetadata;
+use crate::timings::Profiler;
+use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+use crate::vendored::VendoredConfig;
+use anyhow::{Context, Error};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// a single scanned file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScannedFile {
+    /// relative to the scan root
+    pub path: PathBuf,
+    pub indicators: IndicatorData,
+}
+
+/// iterates over scanned files in tree order - directories are visited, so calculators see
+/// the same nodes as a full scan, but not returned.
+/// After an error, including cancellation, the iterator returns nothing more.
+pub struct ScanIter {
+    root: PathBuf,
+    paths: Box<dyn Iterator<Item = Result<PathBuf, Diagnostic>>>,
+    link_guard: Option<Arc<LinkGuard>>,
+    tics: Vec<Box<dyn ToxicityIndicatorCalculator>>,
+    git_details: bool,
+    keep_git_activity: bool,
+    vendored: Option<VendoredConfig>,
+    profiler: Profiler,
+    cancellation: CancellationToken,
+    diagnostics: Vec<Diagnostic>,
+    root_visited: bool,
+    finished: bool,
+}
+
+impl ScanIter {
+    pub(crate) fn new(
+        root: &Path,
+        paths: Box<dyn Iterator<Item = Result<PathBuf, Diagnostic>>>,
+        link_guard: Option<Arc<LinkGuard>>,
+        tics: Vec<Box<dyn ToxicityIndicatorCalculator>>,
+        git_details: bool,
+        keep_git_activity: bool,
+        vendored: Option<VendoredConfig>,
+        cancellation: CancellationToken,
+    ) -> Self {
+        ScanIter {
+            root: root.to_owned(),
+            paths,
+            link_guard,
+            tics,
+            git_details,
+            keep_git_activity,
+            vendored,
+            profiler: Profiler::new(),
+            cancellation,
+            diagnostics: Vec::new(),
+            root_visited: false,
+            finished: false,
+        }
+    }
+
+    /// metadata for the files scanned so far, such as the git user dictionary
+    pub fn metadata(&self) -> Result<IndicatorMetadata, Error> {
+        let mut metadata = IndicatorMetadata::default();
+        for tic in &self.tics {
+            tic.apply_metadata(&mut metadata)
+                .with_context(|| format!("applying metadata for {}", tic.name()))?;
+        }
+        Ok(metadata)
+    }
+
+    /// problems found so far, from walking and from calculators - paths are relative to the root
+    #[must_use]
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnostics.clone();
+        if let Some(guard) = &self.link_guard {
+            diagnostics.extend(guard.diagnostics());
+        }
+        for tic in &self.tics {
+            diagnostics.extend(tic.diagnostics());
+        }
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.relative_to(&self.root))
+            .collect()
+    }
+
+    fn visit(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+        let result = self.cancellation.check().and_then(|_| {
+            apply_calculators_to_node(node, path, &mut self.tics, &mut self.profiler)
+        });
+        match result {
+            Err(e) if is_cancellation(&e) => Err(cancelled_walk(
+                &e,
+                self.diagnostics.clone(),
+                &self.tics,
+                &self.root,
+            )),
+            other => other,
+        }
+    }
+
+    fn remove_details(&self, indicators: &mut IndicatorData) {
+        if let Some(GitNodeData::File { data }) = &mut indicators.git {
+            if !self.git_details {
+                data.details = Vec::new();
+            }
+            if !self.keep_git_activity {
+                data.activity = Vec::new();
+            }
+        }
+    }
+
+    fn next_file(&mut self) -> Result<Option<ScannedFile>, Error> {
+        if !self.root_visited {
+            // the git calculator expects the root to be visited first
+            self.root_visited = true;
+            let root = self.root.clone();
+            self.visit(&mut FlareTreeNode::new(ROOT_NAME, false), &root)?;
+        }
+        while let Some(result) = self.paths.next() {
+            let path = match result {
+                Ok(path) => path,
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+            if !path.is_dir() && !path.is_file() {
+                self.diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticCategory::SkippedPath,
+                        Some(&path),
+                        "Not a file or directory - skipping",
+                    )
+                    .relative_to(&self.root),
+                );
+                continue;
+            }
+            let mut node = FlareTreeNode::new(path.file_name().unwrap(), path.is_file());
+            self.visit(&mut node, &path)?;
+            if path.is_file() {
+                let relative = path.strip_prefix(&self.root)?.to_path_buf();
+                let mut indicators = node.indicators().clone();
+                self.remove_details(&mut indicators);
+                if let Some(vendored) = &self.vendored {
+                    indicators.vendored = vendored.is_vendored(&self.root, &relative);
+                }
+                return Ok(Some(ScannedFile {
+                    path: relative,
+                    indicators,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for ScanIter {
+    type Item = Result<ScannedFile, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.next_file().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.finished = true;
+        }
+        result
+    }
+}
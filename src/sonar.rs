@@ -0,0 +1,231 @@
+#![warn(clippy::all)]
+//! SonarQube generic issue export - files over indicator thresholds become issues in Sonar's
+//! [generic issue import format](https://docs.sonarqube.org/latest/analyzing-source-code/importing-external-issues/generic-issue-import-format/),
+//! so teams already on Sonar can see polyglot findings in the same UI.
+//!
+//! File paths are relative to the scanned root, including any path prefix - they need to match
+//! paths relative to the Sonar project's base directory. Vendored files are skipped.
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use path_slash::PathExt;
+use serde::Serialize;
+use std::path::Path;
+
+const ENGINE_ID: &str = "polyglot";
+
+/// files over any of these get an issue - None disables a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SonarThresholds {
+    pub max_code_lines: Option<usize>,
+    /// the 90th percentile of indentation, in spaces - more robust than the deepest line
+    pub max_indentation_p90: Option<u64>,
+    /// commits changing the file in the scanned history
+    pub max_commits: Option<u64>,
+}
+
+impl Default for SonarThresholds {
+    fn default() -> Self {
+        SonarThresholds {
+            max_code_lines: Some(1000),
+            max_indentation_p90: Some(24),
+            max_commits: Some(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SonarLocation {
+    pub message: String,
+    pub file_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SonarIssue {
+    pub engine_id: String,
+    pub rule_id: String,
+    pub severity: String,
+    #[serde(rename = "type")]
+    pub issue_type: String,
+    pub primary_location: SonarLocation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SonarIssues {
+    pub issues: Vec<SonarIssue>,
+}
+
+fn issue(rule_id: &str, file_path: &str, message: String) -> SonarIssue {
+    SonarIssue {
+        engine_id: ENGINE_ID.to_owned(),
+        rule_id: rule_id.to_owned(),
+        severity: "MAJOR".to_owned(),
+        issue_type: "CODE_SMELL".to_owned(),
+        primary_location: SonarLocation {
+            message,
+            file_path: file_path.to_owned(),
+        },
+    }
+}
+
+fn file_issues(
+    node: &FlareTreeNode,
+    file_path: &str,
+    thresholds: &SonarThresholds,
+) -> Vec<SonarIssue> {
+    let indicators = node.indicators();
+    let mut issues = Vec::new();
+    if let (Some(loc), Some(max)) = (&indicators.loc, thresholds.max_code_lines) {
+        if loc.code > max {
+            issues.push(issue(
+                "huge-file",
+                file_path,
+                format!("File has {} lines of code - more than {}", loc.code, max),
+            ));
+        }
+    }
+    // p90 is missing if it wasn't one of the configured percentiles
+    if let (Some(p90), Some(max)) = (
+        indicators.indentation.as_ref().and_then(|i| i.p90),
+        thresholds.max_indentation_p90,
+    ) {
+        if p90 > max {
+            issues.push(issue(
+                "deep-indentation",
+                file_path,
+                format!(
+                    "10% of lines are indented {} spaces or more - more than {}",
+                    p90, max
+                ),
+            ));
+        }
+    }
+    if let (Some(GitNodeData::File { data }), Some(max)) = (&indicators.git, thresholds.max_commits)
+    {
+        let commits: u64 = data.details.iter().map(|details| details.commits).sum();
+        if commits > max {
+            issues.push(issue(
+                "high-churn",
+                file_path,
+                format!(
+                    "File was changed in {} commits - more than {}",
+                    commits, max
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+fn gather_issues(
+    node: &FlareTreeNode,
+    path: &Path,
+    thresholds: &SonarThresholds,
+    issues: &mut Vec<SonarIssue>,
+) {
+    for child in node.get_children() {
+        let child_path = path.join(child.name());
+        if child.indicators().vendored {
+            continue;
+        }
+        if child.is_file() {
+            // sonar wants forward slashes whatever the platform
+            let file_path = child_path.to_slash_lossy();
+            issues.extend(file_issues(child, &file_path, thresholds));
+        } else {
+            gather_issues(child, &child_path, thresholds, issues);
+        }
+    }
+}
+
+/// issues for every file in the tree over the thresholds
+#[must_use]
+pub fn sonar_issues(tree: &FlareTreeNode, thresholds: &SonarThresholds) -> SonarIssues {
+    let mut issues = Vec::new();
+    gather_issues(tree, Path::new(""), thresholds, &mut issues);
+    SonarIssues { issues }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::indentation::IndentationData;
+    use crate::loc::LanguageLocData;
+    use pretty_assertions::assert_eq;
+
+    fn code_file(name: &str, code: usize, p90: u64) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: "Rust".to_owned(),
+            binary: false,
+            blanks: 0,
+            code,
+            comments: 0,
+            lines: code,
+            bytes: 0,
+        });
+        file.indicators_mut().indentation = Some(IndentationData {
+            lines: code as u64,
+            minimum: 0,
+            maximum: p90,
+            median: Some(0),
+            stddev: 0.0,
+            p75: Some(0),
+            p90: Some(p90),
+            p99: Some(p90),
+            percentiles: std::collections::BTreeMap::new(),
+            sum: 0,
+            truncated: false,
+            histogram: None,
+        });
+        file
+    }
+
+    #[test]
+    fn files_over_thresholds_are_issues() {
+        let mut root = FlareTreeNode::dir("<root>");
+        let mut src = FlareTreeNode::dir("src");
+        src.append_child(code_file("big.rs", 2000, 4));
+        src.append_child(code_file("small.rs", 20, 4));
+        root.append_child(src);
+        let mut vendor = FlareTreeNode::dir("vendor");
+        vendor.indicators_mut().vendored = true;
+        vendor.append_child(code_file("deep.rs", 10, 40));
+        root.append_child(vendor);
+        root.append_child(code_file("deep.rs", 10, 40));
+
+        let issues = sonar_issues(&root, &SonarThresholds::default()).issues;
+        let found: Vec<(&str, &str)> = issues
+            .iter()
+            .map(|issue| {
+                (
+                    issue.rule_id.as_str(),
+                    issue.primary_location.file_path.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![("huge-file", "src/big.rs"), ("deep-indentation", "deep.rs")]
+        );
+    }
+
+    #[test]
+    fn issues_serialize_in_sonar_format() {
+        let issues = SonarIssues {
+            issues: vec![issue("huge-file", "src/big.rs", "too big".to_owned())],
+        };
+        assert_eq!(
+            serde_json::to_value(&issues).unwrap(),
+            serde_json::json!({"issues": [{
+                "engineId": "polyglot",
+                "ruleId": "huge-file",
+                "severity": "MAJOR",
+                "type": "CODE_SMELL",
+                "primaryLocation": {"message": "too big", "filePath": "src/big.rs"}
+            }]})
+        );
+    }
+}
@@ -0,0 +1,295 @@
+#![warn(clippy::all)]
+//! Flat summaries of a scan, for tools that don't want a tree - a CSV file with a row of
+//! summary values per file, for spreadsheets, and Prometheus metrics with totals per language,
+//! for monitoring how a codebase changes over time.
+//!
+//! As with reports, vendored files and `<other>` nodes standing in for aggregated files are left
+//! out. Values that weren't scanned are left empty in CSV rows, and left out of metrics.
+
+use anyhow::Error;
+use path_slash::PathExt;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use crate::loc_report::{summarise, LanguageSummary};
+use crate::polyglot_data::PolyglotData;
+
+const CSV_HEADINGS: [&str; 11] = [
+    "path",
+    "language",
+    "code",
+    "comments",
+    "blanks",
+    "indentation_p99",
+    "indentation_sum",
+    "commits",
+    "users",
+    "last_update",
+    "age_in_days",
+];
+
+/// summary values for a file - all but the path are optional, as they depend on what was scanned
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct FileRow {
+    path: String,
+    language: Option<String>,
+    code: Option<usize>,
+    comments: Option<usize>,
+    blanks: Option<usize>,
+    indentation_p99: Option<u64>,
+    indentation_sum: Option<u64>,
+    commits: Option<u64>,
+    users: Option<usize>,
+    last_update: Option<u64>,
+    age_in_days: Option<u64>,
+}
+
+impl FileRow {
+    fn values(&self) -> Vec<String> {
+        fn cell<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map(ToString::to_string).unwrap_or_default()
+        }
+        vec![
+            self.path.clone(),
+            cell(&self.language),
+            cell(&self.code),
+            cell(&self.comments),
+            cell(&self.blanks),
+            cell(&self.indentation_p99),
+            cell(&self.indentation_sum),
+            cell(&self.commits),
+            cell(&self.users),
+            cell(&self.last_update),
+            cell(&self.age_in_days),
+        ]
+    }
+}
+
+fn gather_rows(node: &FlareTreeNode, path: &Path, rows: &mut Vec<FileRow>) {
+    for child in node.get_children() {
+        let indicators = child.indicators();
+        if indicators.vendored || indicators.aggregated.is_some() {
+            continue;
+        }
+        let child_path = path.join(child.name());
+        if !child.is_file() {
+            gather_rows(child, &child_path, rows);
+            continue;
+        }
+        let mut row = FileRow {
+            path: child_path.to_slash_lossy().into_owned(),
+            ..FileRow::default()
+        };
+        if let Some(loc) = &indicators.loc {
+            row.language = Some(loc.language.clone());
+            row.code = Some(loc.code);
+            row.comments = Some(loc.comments);
+            row.blanks = Some(loc.blanks);
+        }
+        if let Some(indentation) = &indicators.indentation {
+            row.indentation_p99 = indentation.p99;
+            row.indentation_sum = Some(indentation.sum);
+        }
+        if let Some(GitNodeData::File { data }) = &indicators.git {
+            if !data.details.is_empty() {
+                row.commits = Some(data.details.iter().map(|details| details.commits).sum());
+            }
+            row.users = Some(data.user_count);
+            row.last_update = Some(data.last_update);
+            row.age_in_days = Some(data.age_in_days);
+        }
+        rows.push(row);
+    }
+}
+
+/// quotes a CSV value if it needs it - paths can contain anything
+fn csv_escape(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn write_csv_line<W: io::Write>(out: &mut W, values: &[String]) -> Result<(), Error> {
+    let line: Vec<String> = values.iter().map(|value| csv_escape(value)).collect();
+    writeln!(out, "{}", line.join(","))?;
+    Ok(())
+}
+
+/// writes a heading row, then a row per file, in tree order
+pub fn write_csv<W: io::Write>(tree: &FlareTreeNode, out: W) -> Result<(), Error> {
+    let mut rows = Vec::new();
+    gather_rows(tree, Path::new(""), &mut rows);
+    let mut out = BufWriter::new(out);
+    let headings: Vec<String> = CSV_HEADINGS.iter().map(|h| (*h).to_owned()).collect();
+    write_csv_line(&mut out, &headings)?;
+    for row in &rows {
+        write_csv_line(&mut out, &row.values())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// escapes a Prometheus label value
+fn label_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// writes a gauge with one sample per set of labels
+fn write_gauge<W: io::Write>(
+    out: &mut W,
+    name: &str,
+    help: &str,
+    samples: &[(Vec<(&str, &str)>, u64)],
+) -> Result<(), Error> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} gauge", name)?;
+    for (labels, value) in samples {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, label_escape(value)))
+            .collect();
+        writeln!(out, "{}{{{}}} {}", name, labels.join(","), value)?;
+    }
+    Ok(())
+}
+
+/// writes totals for the project in the Prometheus text format, labelled with the project name
+pub fn write_prometheus<W: io::Write>(data: &PolyglotData, out: W) -> Result<(), Error> {
+    let project = data.name();
+    let languages = summarise(data.tree());
+    let mut rows = Vec::new();
+    gather_rows(data.tree(), Path::new(""), &mut rows);
+    let mut out = BufWriter::new(out);
+
+    let by_language = |value: fn(&LanguageSummary) -> u64| {
+        languages
+            .iter()
+            .map(|(language, summary)| {
+                (
+                    vec![("project", project), ("language", language.as_str())],
+                    value(summary),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    write_gauge(
+        &mut out,
+        "polyglot_files",
+        "Files with a known language, by language",
+        &by_language(|summary| summary.files),
+    )?;
+    write_gauge(
+        &mut out,
+        "polyglot_code_lines",
+        "Lines of code, by language",
+        &by_language(|summary| summary.code),
+    )?;
+    write_gauge(
+        &mut out,
+        "polyglot_comment_lines",
+        "Comment lines, by language",
+        &by_language(|summary| summary.comments),
+    )?;
+    write_gauge(
+        &mut out,
+        "polyglot_blank_lines",
+        "Blank lines, by language",
+        &by_language(|summary| summary.blanks),
+    )?;
+    let commits: Vec<u64> = rows.iter().filter_map(|row| row.commits).collect();
+    if !commits.is_empty() {
+        write_gauge(
+            &mut out,
+            "polyglot_file_commits",
+            "Commits to each file, summed over files - a commit changing several files counts for each",
+            &[(vec![("project", project)], commits.iter().sum())],
+        )?;
+    }
+    write_gauge(
+        &mut out,
+        "polyglot_diagnostics",
+        "Problems found while scanning",
+        &[(vec![("project", project)], data.diagnostics().len() as u64)],
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::loc::LanguageLocData;
+    use crate::FeatureFlags;
+    use pretty_assertions::assert_eq;
+
+    fn file(name: &str, language: &str, code: usize) -> FlareTreeNode {
+        let mut file = FlareTreeNode::file(name);
+        file.indicators_mut().loc = Some(LanguageLocData {
+            language: language.to_owned(),
+            binary: false,
+            blanks: 1,
+            code,
+            comments: 2,
+            lines: code + 3,
+            bytes: 100,
+        });
+        file
+    }
+
+    fn sample_tree() -> FlareTreeNode {
+        let mut root = FlareTreeNode::dir("<root>");
+        let mut src = FlareTreeNode::dir("src");
+        src.append_child(file("main.rs", "Rust", 10));
+        src.append_child(file("odd, \"name\".rs", "Rust", 5));
+        root.append_child(src);
+        root.append_child(file("build.sh", "Shell", 3));
+        root.append_child(FlareTreeNode::file("LICENSE"));
+        root
+    }
+
+    #[test]
+    fn csv_has_a_row_per_file() {
+        let mut out = Vec::new();
+        write_csv(&sample_tree(), &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "path,language,code,comments,blanks,indentation_p99,indentation_sum,commits,users,last_update,age_in_days",
+                "src/main.rs,Rust,10,2,1,,,,,,",
+                "\"src/odd, \"\"name\"\".rs\",Rust,5,2,1,,,,,,",
+                "build.sh,Shell,3,2,1,,,,,,",
+                "LICENSE,,,,,,,,,,",
+            ]
+        );
+    }
+
+    #[test]
+    fn metrics_are_totals_by_language() {
+        let data = PolyglotData::new(
+            "my \"project\"",
+            None,
+            sample_tree(),
+            FeatureFlags::default(),
+        );
+        let mut out = Vec::new();
+        write_prometheus(&data, &mut out).unwrap();
+        let metrics = String::from_utf8(out).unwrap();
+        assert!(metrics.contains("# TYPE polyglot_code_lines gauge\n"));
+        assert!(metrics.contains(
+            "polyglot_code_lines{project=\"my \\\"project\\\"\",language=\"Rust\"} 15\n"
+        ));
+        assert!(metrics
+            .contains("polyglot_files{project=\"my \\\"project\\\"\",language=\"Shell\"} 1\n"));
+        assert!(metrics.contains("polyglot_diagnostics{project=\"my \\\"project\\\"\"} 0\n"));
+        // no git details were scanned
+        assert!(!metrics.contains("polyglot_file_commits"));
+    }
+}
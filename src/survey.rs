@@ -0,0 +1,607 @@
+#![warn(clippy::all)]
+//! A quick look at a directory before scanning it - how many files and languages, which git
+//! repositories and how much history - and recommended scan settings based on that, for
+//! `polyglot_code_scanner init`.
+//!
+//! Time estimates are a rough guide only, from the number of commits and a sample of how many
+//! files each commit changes - real times vary a lot with hardware and the shape of the history.
+//! Output sizes are similarly rough, from typical JSON sizes per file, commit and coupled file.
+
+use anyhow::Error;
+use git2::Repository;
+use ignore::WalkBuilder;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokei::{Config, LanguageType};
+
+use crate::FeatureFlags;
+
+const SECONDS_PER_YEAR: u64 = 60 * 60 * 24 * 365;
+
+/// stop counting commits after this many - the history is big enough to need care anyway
+const MAX_SURVEY_COMMITS: u64 = 100_000;
+
+/// how many recent commits are diffed, to estimate files changed per commit
+const SAMPLE_COMMITS: usize = 100;
+
+/// rough git log cost for each file changed by a commit
+const GIT_SECONDS_PER_FILE_CHANGE: f64 = 0.000_5;
+
+/// rough coupling cost for each pair of files changed by a commit
+const COUPLING_SECONDS_PER_FILE_PAIR: f64 = 0.000_01;
+
+/// rough cost of each file calculator, such as loc or indentation, for each file
+const FILE_SECONDS_PER_CALCULATOR: f64 = 0.000_2;
+
+/// rough cost of reading file contents, per byte
+const FILE_SECONDS_PER_BYTE: f64 = 0.000_000_01;
+
+/// typical JSON for a file or directory node, without indicators
+const NODE_BYTES: u64 = 100;
+
+/// typical JSON for each file calculator's indicators
+const CALCULATOR_BYTES: u64 = 80;
+
+/// typical JSON for the git summary of a file
+const GIT_BYTES_PER_FILE: u64 = 400;
+
+/// typical JSON for each git details entry - a day of changes to a file
+const DETAILS_BYTES: u64 = 90;
+
+/// typical JSON for each git activity entry - a commit changing a file
+const ACTIVITY_BYTES: u64 = 70;
+
+/// typical JSON for the coupling data of a file
+const COUPLING_BYTES_PER_FILE: u64 = 600;
+
+/// enough years to mean "all of history"
+pub const ALL_HISTORY_YEARS: u64 = 100;
+
+/// scans estimated to take longer than this get a warning
+const SLOW_SCAN: Duration = Duration::from_secs(30 * 60);
+
+/// more files than this make big output files without detailed git data removed
+const MANY_FILES: u64 = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepositorySurvey {
+    pub workdir: PathBuf,
+    /// commits in each of the last few years, most recent first
+    pub commits_per_year: Vec<u64>,
+    /// true if there were too many commits to count them all
+    pub truncated: bool,
+    /// mean files changed per commit, from a sample of recent commits
+    pub files_per_commit: f64,
+}
+
+impl RepositorySurvey {
+    #[must_use]
+    pub fn commits(&self, years: u64) -> u64 {
+        self.commits_per_year.iter().take(years as usize).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectSurvey {
+    pub files: u64,
+    pub bytes: u64,
+    /// files per language
+    pub languages: BTreeMap<String, u64>,
+    pub repositories: Vec<RepositorySurvey>,
+}
+
+impl ProjectSurvey {
+    fn estimate(&self, years: u64, cost: impl Fn(f64) -> f64) -> Duration {
+        let seconds: f64 = self
+            .repositories
+            .iter()
+            .map(|repository| repository.commits(years) as f64 * cost(repository.files_per_commit))
+            .sum();
+        Duration::from_secs_f64(seconds)
+    }
+
+    /// roughly how long reading git history will take
+    #[must_use]
+    pub fn git_estimate(&self, years: u64) -> Duration {
+        self.estimate(years, |files_per_commit| {
+            files_per_commit * GIT_SECONDS_PER_FILE_CHANGE
+        })
+    }
+
+    /// roughly how long calculating coupling will take
+    #[must_use]
+    pub fn coupling_estimate(&self, years: u64) -> Duration {
+        self.estimate(years, |files_per_commit| {
+            files_per_commit * files_per_commit * COUPLING_SECONDS_PER_FILE_PAIR
+        })
+    }
+
+    /// roughly how long walking files and running file calculators will take
+    #[must_use]
+    pub fn files_estimate(&self, file_calculators: usize) -> Duration {
+        Duration::from_secs_f64(
+            self.files as f64 * file_calculators as f64 * FILE_SECONDS_PER_CALCULATOR
+                + self.bytes as f64 * FILE_SECONDS_PER_BYTE,
+        )
+    }
+
+    /// how many times files were changed by commits, roughly
+    fn file_changes(&self, years: u64) -> u64 {
+        self.repositories
+            .iter()
+            .map(|repository| {
+                (repository.commits(years) as f64 * repository.files_per_commit).round() as u64
+            })
+            .sum()
+    }
+
+    /// roughly how long each enabled feature will take, and how much it adds to the output
+    #[must_use]
+    pub fn estimate_scan(
+        &self,
+        years: u64,
+        file_calculators: usize,
+        features: &FeatureFlags,
+        keep_git_activity: bool,
+    ) -> ScanEstimate {
+        let mut estimates = vec![FeatureEstimate {
+            feature: format!("files ({} calculators)", file_calculators),
+            duration: self.files_estimate(file_calculators),
+            output_bytes: self.files * (NODE_BYTES + file_calculators as u64 * CALCULATOR_BYTES),
+        }];
+        if features.git {
+            estimates.push(FeatureEstimate {
+                feature: "git".to_owned(),
+                duration: self.git_estimate(years),
+                output_bytes: self.files * GIT_BYTES_PER_FILE,
+            });
+            // details are gathered along with the git log, so only cost output
+            if features.git_details {
+                estimates.push(FeatureEstimate {
+                    feature: "git details".to_owned(),
+                    duration: Duration::ZERO,
+                    output_bytes: self.file_changes(years) * DETAILS_BYTES,
+                });
+            }
+            if keep_git_activity {
+                estimates.push(FeatureEstimate {
+                    feature: "git activity".to_owned(),
+                    duration: Duration::ZERO,
+                    output_bytes: self.file_changes(years) * ACTIVITY_BYTES,
+                });
+            }
+            if features.coupling {
+                estimates.push(FeatureEstimate {
+                    feature: "coupling".to_owned(),
+                    duration: self.coupling_estimate(years),
+                    output_bytes: self.files * COUPLING_BYTES_PER_FILE,
+                });
+            }
+        }
+        ScanEstimate {
+            features: estimates,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeatureEstimate {
+    pub feature: String,
+    pub duration: Duration,
+    pub output_bytes: u64,
+}
+
+/// a rough breakdown of scan time and output size per feature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanEstimate {
+    pub features: Vec<FeatureEstimate>,
+}
+
+/// a duration rounded to something readable
+fn human_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 1 {
+        "<1s".to_owned()
+    } else if seconds < 120 {
+        format!("{}s", seconds)
+    } else if seconds < 2 * 60 * 60 {
+        format!("{}m", minutes(duration))
+    } else {
+        format!("{:.1}h", seconds as f64 / 3600.0)
+    }
+}
+
+/// bytes rounded to something readable
+fn human_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KB", "MB"] {
+        if value < 1024.0 {
+            return format!("{:.0}{}", value, unit);
+        }
+        value /= 1024.0;
+    }
+    format!("{:.1}GB", value)
+}
+
+impl ScanEstimate {
+    #[must_use]
+    pub fn total_duration(&self) -> Duration {
+        self.features.iter().map(|feature| feature.duration).sum()
+    }
+
+    #[must_use]
+    pub fn total_output_bytes(&self) -> u64 {
+        self.features
+            .iter()
+            .map(|feature| feature.output_bytes)
+            .sum()
+    }
+
+    /// writes a table of features, with a total
+    pub fn write_report<W: io::Write>(&self, mut out: W) -> Result<(), Error> {
+        let width = self
+            .features
+            .iter()
+            .map(|feature| feature.feature.len())
+            .chain(std::iter::once("Feature".len()))
+            .max()
+            .unwrap_or_default();
+        let rule = "-".repeat(width + 22);
+        writeln!(out, "{}", rule)?;
+        writeln!(
+            out,
+            "{:<width$} {:>10} {:>10}",
+            "Feature",
+            "Time",
+            "Output",
+            width = width
+        )?;
+        writeln!(out, "{}", rule)?;
+        for feature in &self.features {
+            writeln!(
+                out,
+                "{:<width$} {:>10} {:>10}",
+                feature.feature,
+                human_duration(feature.duration),
+                human_bytes(feature.output_bytes),
+                width = width
+            )?;
+        }
+        writeln!(out, "{}", rule)?;
+        writeln!(
+            out,
+            "{:<width$} {:>10} {:>10}",
+            "Total",
+            human_duration(self.total_duration()),
+            human_bytes(self.total_output_bytes()),
+            width = width
+        )?;
+        writeln!(out, "{}", rule)?;
+        writeln!(
+            out,
+            "Estimates are rough - other features such as blame and line age aren't included"
+        )?;
+        Ok(())
+    }
+}
+
+fn is_repository_root(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+fn survey_repository(workdir: &Path, years: u64) -> Result<RepositorySurvey, Error> {
+    let repository = Repository::open(workdir)?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let mut commits_per_year = vec![0; years as usize];
+    let mut truncated = false;
+    let mut sampled_files = 0;
+    let mut sampled_commits = 0;
+    let mut revwalk = repository.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    if revwalk.push_head().is_err() {
+        // no commits yet
+        return Ok(RepositorySurvey {
+            workdir: workdir.to_path_buf(),
+            commits_per_year,
+            truncated,
+            files_per_commit: 0.0,
+        });
+    }
+    let mut counted = 0;
+    for oid in revwalk {
+        let commit = repository.find_commit(oid?)?;
+        let age_years = now.saturating_sub(commit.time().seconds() as u64) / SECONDS_PER_YEAR;
+        if age_years >= years {
+            break;
+        }
+        commits_per_year[age_years as usize] += 1;
+        if sampled_commits < SAMPLE_COMMITS {
+            let parent_tree = match commit.parents().next() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+            let diff =
+                repository.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+            sampled_files += diff.deltas().len();
+            sampled_commits += 1;
+        }
+        counted += 1;
+        if counted >= MAX_SURVEY_COMMITS {
+            truncated = true;
+            break;
+        }
+    }
+    Ok(RepositorySurvey {
+        workdir: workdir.to_path_buf(),
+        commits_per_year,
+        truncated,
+        files_per_commit: if sampled_commits == 0 {
+            0.0
+        } else {
+            sampled_files as f64 / sampled_commits as f64
+        },
+    })
+}
+
+/// looks at files and git repositories under `root`, with up to `years` of history
+pub fn survey(root: &Path, years: u64) -> Result<ProjectSurvey, Error> {
+    let config = Config::default();
+    let mut files = 0;
+    let mut bytes = 0;
+    let mut languages: BTreeMap<String, u64> = BTreeMap::new();
+    let mut workdirs: BTreeSet<PathBuf> = BTreeSet::new();
+    if let Ok(repository) = Repository::discover(root) {
+        if let Some(workdir) = repository.workdir() {
+            workdirs.insert(workdir.canonicalize()?);
+        }
+    }
+    let walker = WalkBuilder::new(root)
+        .add_custom_ignore_filename(".polyglot_code_scanner_ignore")
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        let path = entry.path();
+        if entry
+            .file_type()
+            .map_or(false, |file_type| file_type.is_dir())
+        {
+            if is_repository_root(path) {
+                workdirs.insert(path.canonicalize()?);
+            }
+            continue;
+        }
+        files += 1;
+        bytes += entry.metadata().map_or(0, |metadata| metadata.len());
+        let language = LanguageType::from_path(path, &config).map_or_else(
+            || "(other)".to_owned(),
+            |language| language.name().to_owned(),
+        );
+        *languages.entry(language).or_insert(0) += 1;
+    }
+    let repositories = workdirs
+        .iter()
+        .map(|workdir| survey_repository(workdir, years))
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(ProjectSurvey {
+        files,
+        bytes,
+        languages,
+        repositories,
+    })
+}
+
+/// suggested settings for a first scan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanRecommendation {
+    pub git_years: u64,
+    pub coupling: bool,
+    pub detailed_git: bool,
+    pub warnings: Vec<String>,
+}
+
+fn minutes(duration: Duration) -> u64 {
+    (duration.as_secs() + 59) / 60
+}
+
+/// recommends settings - the survey should cover at least `max_years` of history
+#[must_use]
+pub fn recommend(survey: &ProjectSurvey, max_years: u64) -> ScanRecommendation {
+    let mut warnings = Vec::new();
+    let mut git_years = max_years;
+    while git_years > 1 && survey.git_estimate(git_years) > SLOW_SCAN {
+        git_years -= 1;
+    }
+    if git_years < max_years {
+        warnings.push(format!(
+            "Reading {} years of git history would take ~{} minutes, so only {} are scanned",
+            max_years,
+            minutes(survey.git_estimate(max_years)),
+            git_years
+        ));
+    }
+    if survey.git_estimate(git_years) > SLOW_SCAN {
+        warnings.push(format!(
+            "Reading {} years of git history will take ~{} minutes",
+            git_years,
+            minutes(survey.git_estimate(git_years))
+        ));
+    }
+    if survey
+        .repositories
+        .iter()
+        .any(|repository| repository.truncated)
+    {
+        warnings.push(format!(
+            "A repository has more than {} recent commits - estimates are low",
+            MAX_SURVEY_COMMITS
+        ));
+    }
+    let coupling_estimate = survey.coupling_estimate(git_years);
+    let coupling = !survey.repositories.is_empty() && coupling_estimate <= SLOW_SCAN;
+    if !survey.repositories.is_empty() && !coupling {
+        warnings.push(format!(
+            "Coupling will take ~{} minutes with these settings, so is off - add `-c` to enable it",
+            minutes(coupling_estimate)
+        ));
+    }
+    let detailed_git = survey.files <= MANY_FILES;
+    if !detailed_git {
+        warnings.push(format!(
+            "There are {} files, so detailed git data is off to keep the output small",
+            survey.files
+        ));
+    }
+    if survey.repositories.is_empty() {
+        warnings.push("No git repositories found - git indicators will be empty".to_owned());
+    }
+    ScanRecommendation {
+        git_years,
+        coupling,
+        detailed_git,
+        warnings,
+    }
+}
+
+/// quotes a string for a posix shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+impl ScanRecommendation {
+    /// a shell script to run the recommended scan
+    #[must_use]
+    pub fn script(&self, name: &str, root: &Path, output: &Path) -> String {
+        let mut script = String::from("#!/bin/sh\n");
+        script.push_str("# Generated by `polyglot_code_scanner init` - edit to taste\n");
+        for warning in &self.warnings {
+            writeln!(script, "# {}", warning).unwrap();
+        }
+        let mut args = vec![
+            "polyglot_code_scanner".to_owned(),
+            "--name".to_owned(),
+            shell_quote(name),
+            "--years".to_owned(),
+            self.git_years.to_string(),
+        ];
+        if self.coupling {
+            args.push("--coupling".to_owned());
+        }
+        if !self.detailed_git {
+            args.push("--no-detailed-git".to_owned());
+        }
+        args.push("--output".to_owned());
+        args.push(shell_quote(&output.to_string_lossy()));
+        args.push(shell_quote(&root.to_string_lossy()));
+        writeln!(script, "{} \"$@\"", args.join(" ")).unwrap();
+        script
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn survey_of(files: u64, commits_per_year: Vec<u64>, files_per_commit: f64) -> ProjectSurvey {
+        ProjectSurvey {
+            files,
+            bytes: 0,
+            languages: BTreeMap::new(),
+            repositories: vec![RepositorySurvey {
+                workdir: PathBuf::from("/src/project"),
+                commits_per_year,
+                truncated: false,
+                files_per_commit,
+            }],
+        }
+    }
+
+    #[test]
+    fn small_projects_get_everything() {
+        let recommendation = recommend(&survey_of(100, vec![500, 500, 500], 5.0), 3);
+        assert_eq!(
+            recommendation,
+            ScanRecommendation {
+                git_years: 3,
+                coupling: true,
+                detailed_git: true,
+                warnings: Vec::new()
+            }
+        );
+        assert_eq!(
+            recommendation.script("it's", Path::new("."), Path::new("out.json")),
+            "#!/bin/sh\n# Generated by `polyglot_code_scanner init` - edit to taste\n\
+             polyglot_code_scanner --name 'it'\\''s' --years 3 --coupling --output 'out.json' '.' \"$@\"\n"
+        );
+    }
+
+    #[test]
+    fn big_histories_are_cut_down_with_warnings() {
+        // ~42 minutes of git log per year, and as long again for coupling
+        let recommendation = recommend(&survey_of(200_000, vec![100_000; 3], 50.0), 3);
+        assert_eq!(recommendation.git_years, 1);
+        assert!(!recommendation.coupling);
+        assert!(!recommendation.detailed_git);
+        assert_eq!(
+            recommendation.warnings,
+            vec![
+                "Reading 3 years of git history would take ~125 minutes, so only 1 are scanned",
+                "Reading 1 years of git history will take ~42 minutes",
+                "Coupling will take ~42 minutes with these settings, so is off - add `-c` to enable it",
+                "There are 200000 files, so detailed git data is off to keep the output small",
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_are_estimated_per_feature() {
+        let features = FeatureFlags {
+            git: true,
+            coupling: true,
+            git_details: true,
+            file_stats: true,
+        };
+        let estimate =
+            survey_of(1000, vec![1000, 1000, 1000], 4.0).estimate_scan(2, 3, &features, false);
+
+        let names: Vec<&str> = estimate
+            .features
+            .iter()
+            .map(|feature| feature.feature.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["files (3 calculators)", "git", "git details", "coupling"]
+        );
+        // 1000 files * 3 calculators * 0.2ms, 2000 commits * 4 files * 0.5ms
+        assert!((estimate.features[0].duration.as_secs_f64() - 0.6).abs() < 0.001);
+        assert!((estimate.features[1].duration.as_secs_f64() - 4.0).abs() < 0.001);
+        assert_eq!(estimate.features[2].output_bytes, 8000 * DETAILS_BYTES);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        estimate.write_report(&mut buffer).unwrap();
+        let report = String::from_utf8(buffer).unwrap();
+        assert!(report.contains("git details                  <1s      703KB"));
+    }
+
+    #[test]
+    fn directories_without_git_are_surveyed() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n")?;
+        std::fs::write(dir.path().join("lib.rs"), "\n")?;
+        std::fs::write(dir.path().join("notes.xyz"), "hello\n")?;
+
+        let survey = survey(dir.path(), 3)?;
+
+        assert_eq!(survey.files, 3);
+        assert_eq!(survey.languages.get("Rust"), Some(&2));
+        assert_eq!(survey.languages.get("(other)"), Some(&1));
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+#![warn(clippy::all)]
+//! Syntax-aware counts - functions, classes, the most parameters to any function, and TODO
+//! comments - from tree-sitter parse trees.
+//!
+//! Parsing needs a grammar per language, which makes builds much bigger and slower, so the
+//! calculator is only available with the `tree-sitter` cargo feature. The data types are always
+//! available, so data files from either kind of build can be read.
+//!
+//! Classes are anything class-like in the language - structs, traits, interfaces, modules - and
+//! parse errors are tolerated, as tree-sitter parses what it can.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyntaxData {
+    /// the grammar used to parse the file
+    pub language: String,
+    /// function, method and lambda definitions
+    pub functions: u64,
+    pub classes: u64,
+    /// the most parameters declared by any one function
+    pub max_parameters: u64,
+    /// comments containing TODO or FIXME
+    pub todos: u64,
+}
+
+#[cfg(feature = "tree-sitter")]
+pub use calculator::SyntaxCalculator;
+
+#[cfg(feature = "tree-sitter")]
+mod calculator {
+    use super::SyntaxData;
+    use crate::diagnostics::Diagnostic;
+    use crate::flare::FlareTreeNode;
+    use crate::polyglot_data::IndicatorMetadata;
+    use crate::toxicity_indicator_calculator::ToxicityIndicatorCalculator;
+    use anyhow::{anyhow, Context, Error};
+    use std::fs;
+    use std::path::Path;
+    use tree_sitter::{Language, Node, Parser};
+
+    /// bigger files are skipped - they are nearly always generated, and slow to parse
+    const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+    const TODO_MARKERS: &[&str] = &["TODO", "FIXME"];
+
+    struct Grammar {
+        name: &'static str,
+        language: fn() -> Language,
+        functions: &'static [&'static str],
+        classes: &'static [&'static str],
+        /// only count class nodes with a body, e.g. not C's `struct foo *x;`
+        classes_need_body: bool,
+    }
+
+    const JS_FUNCTIONS: &[&str] = &[
+        "function_declaration",
+        "generator_function_declaration",
+        "function",
+        "generator_function",
+        "arrow_function",
+        "method_definition",
+    ];
+
+    const JS_CLASSES: &[&str] = &["class_declaration", "abstract_class_declaration", "class"];
+
+    fn grammar_for(extension: &str) -> Option<Grammar> {
+        let grammar = match extension {
+            "rs" => Grammar {
+                name: "rust",
+                language: tree_sitter_rust::language,
+                functions: &["function_item", "closure_expression"],
+                classes: &["struct_item", "enum_item", "trait_item"],
+                classes_need_body: false,
+            },
+            "js" | "jsx" | "mjs" | "cjs" => Grammar {
+                name: "javascript",
+                language: tree_sitter_javascript::language,
+                functions: JS_FUNCTIONS,
+                classes: JS_CLASSES,
+                classes_need_body: false,
+            },
+            "ts" | "mts" | "cts" => Grammar {
+                name: "typescript",
+                language: tree_sitter_typescript::language_typescript,
+                functions: JS_FUNCTIONS,
+                classes: JS_CLASSES,
+                classes_need_body: false,
+            },
+            "tsx" => Grammar {
+                name: "tsx",
+                language: tree_sitter_typescript::language_tsx,
+                functions: JS_FUNCTIONS,
+                classes: JS_CLASSES,
+                classes_need_body: false,
+            },
+            "py" => Grammar {
+                name: "python",
+                language: tree_sitter_python::language,
+                functions: &["function_definition", "lambda"],
+                classes: &["class_definition"],
+                classes_need_body: false,
+            },
+            "java" => Grammar {
+                name: "java",
+                language: tree_sitter_java::language,
+                functions: &[
+                    "method_declaration",
+                    "constructor_declaration",
+                    "lambda_expression",
+                ],
+                classes: &[
+                    "class_declaration",
+                    "interface_declaration",
+                    "enum_declaration",
+                    "record_declaration",
+                ],
+                classes_need_body: false,
+            },
+            "go" => Grammar {
+                name: "go",
+                language: tree_sitter_go::language,
+                functions: &["function_declaration", "method_declaration", "func_literal"],
+                classes: &["struct_type", "interface_type"],
+                classes_need_body: false,
+            },
+            "c" | "h" => Grammar {
+                name: "c",
+                language: tree_sitter_c::language,
+                functions: &["function_definition"],
+                classes: &["struct_specifier", "union_specifier"],
+                classes_need_body: true,
+            },
+            "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => Grammar {
+                name: "cpp",
+                language: tree_sitter_cpp::language,
+                functions: &["function_definition", "lambda_expression"],
+                classes: &["class_specifier", "struct_specifier", "union_specifier"],
+                classes_need_body: true,
+            },
+            "cs" => Grammar {
+                name: "c_sharp",
+                language: tree_sitter_c_sharp::language,
+                functions: &[
+                    "method_declaration",
+                    "constructor_declaration",
+                    "local_function_statement",
+                    "lambda_expression",
+                ],
+                classes: &[
+                    "class_declaration",
+                    "struct_declaration",
+                    "interface_declaration",
+                    "record_declaration",
+                ],
+                classes_need_body: false,
+            },
+            "rb" => Grammar {
+                name: "ruby",
+                language: tree_sitter_ruby::language,
+                functions: &["method", "singleton_method", "lambda"],
+                classes: &["class", "module"],
+                classes_need_body: false,
+            },
+            _ => return None,
+        };
+        Some(grammar)
+    }
+
+    /// the parameter list of a function node - C and C++ nest it inside the declarator
+    fn parameter_list(node: Node) -> Option<Node> {
+        node.child_by_field_name("parameters").or_else(|| {
+            node.child_by_field_name("declarator")
+                .and_then(parameter_list)
+        })
+    }
+
+    fn parameter_count(node: Node) -> u64 {
+        if let Some(parameters) = parameter_list(node) {
+            let mut cursor = parameters.walk();
+            return parameters
+                .named_children(&mut cursor)
+                .filter(|child| !child.kind().ends_with("comment"))
+                .count() as u64;
+        }
+        // e.g. javascript's `x => x + 1`
+        u64::from(node.child_by_field_name("parameter").is_some())
+    }
+
+    fn count_syntax(grammar: &Grammar, source: &[u8]) -> Result<SyntaxData, Error> {
+        let mut parser = Parser::new();
+        parser
+            .set_language((grammar.language)())
+            .with_context(|| format!("loading {} grammar", grammar.name))?;
+        let tree = parser
+            .parse(source, None)
+            .ok_or_else(|| anyhow!("{} parser gave no tree", grammar.name))?;
+        let mut data = SyntaxData {
+            language: grammar.name.to_owned(),
+            functions: 0,
+            classes: 0,
+            max_parameters: 0,
+            todos: 0,
+        };
+        let mut cursor = tree.walk();
+        // depth-first walk, without recursing - deeply nested code shouldn't overflow the stack
+        'walk: loop {
+            let node = cursor.node();
+            let kind = node.kind();
+            if grammar.functions.contains(&kind) {
+                data.functions += 1;
+                data.max_parameters = data.max_parameters.max(parameter_count(node));
+            } else if grammar.classes.contains(&kind)
+                && (!grammar.classes_need_body || node.child_by_field_name("body").is_some())
+            {
+                data.classes += 1;
+            } else if kind.ends_with("comment") {
+                let text = node.utf8_text(source).unwrap_or_default();
+                if TODO_MARKERS.iter().any(|marker| text.contains(marker)) {
+                    data.todos += 1;
+                }
+            }
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    #[derive(Debug, Default)]
+    pub struct SyntaxCalculator {
+        diagnostics: Vec<Diagnostic>,
+    }
+
+    impl SyntaxCalculator {
+        fn parse_file(file: &Path) -> Result<Option<SyntaxData>, Error> {
+            let grammar = match file
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(grammar_for)
+            {
+                Some(grammar) => grammar,
+                None => return Ok(None),
+            };
+            if fs::metadata(file)?.len() > MAX_FILE_BYTES {
+                debug!("Not parsing large file {:?}", file);
+                return Ok(None);
+            }
+            let source = fs::read(file)?;
+            Ok(Some(count_syntax(&grammar, &source)?))
+        }
+    }
+
+    impl ToxicityIndicatorCalculator for SyntaxCalculator {
+        fn name(&self) -> String {
+            "syntax".to_string()
+        }
+
+        fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error> {
+            if path.is_file() {
+                match SyntaxCalculator::parse_file(path)
+                    .with_context(|| format!("parsing {:?}", path))
+                {
+                    Ok(syntax) => node.indicators_mut().syntax = syntax,
+                    Err(e) => self
+                        .diagnostics
+                        .push(Diagnostic::file_error(path, &self.name(), &e)),
+                }
+            }
+            Ok(())
+        }
+
+        fn apply_metadata(&self, _metadata: &mut IndicatorMetadata) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn diagnostics(&self) -> Vec<Diagnostic> {
+            self.diagnostics.clone()
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        fn syntax(extension: &str, source: &str) -> SyntaxData {
+            count_syntax(&grammar_for(extension).unwrap(), source.as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn rust_functions_and_types_are_counted() {
+            let source = "// TODO: tidy this\n\
+                          struct Point { x: i32, y: i32 }\n\
+                          trait Shape {}\n\
+                          fn add(a: i32, b: i32, c: i32) -> i32 { a + b + c }\n\
+                          fn main() { let f = |x| x; }\n";
+            assert_eq!(
+                syntax("rs", source),
+                SyntaxData {
+                    language: "rust".to_owned(),
+                    functions: 3,
+                    classes: 2,
+                    max_parameters: 3,
+                    todos: 1
+                }
+            );
+        }
+
+        #[test]
+        fn c_parameters_are_found_in_declarators() {
+            let source = "struct point;\n\
+                          struct point { int x; int y; };\n\
+                          int add(int a, int b) { return a + b; }\n";
+            let data = syntax("c", source);
+            assert_eq!(
+                (data.functions, data.classes, data.max_parameters),
+                (1, 1, 2)
+            );
+        }
+
+        #[test]
+        fn unknown_languages_are_not_parsed() {
+            assert!(grammar_for("txt").is_none());
+        }
+    }
+}
@@ -0,0 +1,277 @@
+#![warn(clippy::all)]
+//! Teams - groups of git users declared in a JSON file, so ownership can be viewed by team
+//! rather than by individual.
+//!
+//! The file format is:
+//! ```json
+//! {
+//!   "teams": [
+//!     { "name": "payments", "emails": ["jo@example.com"], "domains": ["payments.example.com"] },
+//!     { "name": "contractors", "patterns": ["@contractor\\.(com|net)$"] }
+//!   ]
+//! }
+//! ```
+//! Users are matched by email - emails and domains ignore case, patterns are regular
+//! expressions matched against the whole email. A user belongs to the first team that matches,
+//! and users that match no team aren't counted.
+
+use anyhow::{Context, Error};
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::git_logger::User;
+use crate::git_user_dictionary::GitUserDictionary;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeamConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeamsConfig {
+    pub teams: Vec<TeamConfig>,
+}
+
+impl TeamsConfig {
+    pub fn from_file(file: &Path) -> Result<Self, Error> {
+        let reader = BufReader::new(
+            File::open(file).with_context(|| format!("opening teams file {:?}", file))?,
+        );
+        serde_json::from_reader(reader).with_context(|| format!("parsing teams file {:?}", file))
+    }
+}
+
+/// A team and its users, as dictionary ids
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeamMembers {
+    pub name: String,
+    pub users: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct TeamMatcher {
+    config: TeamConfig,
+    patterns: Vec<Regex>,
+}
+
+impl TeamMatcher {
+    fn matches(&self, email: &str) -> bool {
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+        self.config
+            .emails
+            .iter()
+            .any(|team_email| team_email.eq_ignore_ascii_case(email))
+            || domain.map_or(false, |domain| {
+                self.config
+                    .domains
+                    .iter()
+                    .any(|team_domain| team_domain.eq_ignore_ascii_case(domain))
+            })
+            || self.patterns.iter().any(|pattern| pattern.is_match(email))
+    }
+}
+
+/// finds users' teams, caching teams by user id
+#[derive(Debug, Clone)]
+pub struct Teams {
+    matchers: Vec<TeamMatcher>,
+    team_by_user: HashMap<usize, Option<usize>>,
+}
+
+impl Teams {
+    pub fn new(config: TeamsConfig) -> Result<Self, Error> {
+        let matchers = config
+            .teams
+            .into_iter()
+            .map(|team| {
+                let patterns = team
+                    .patterns
+                    .iter()
+                    .map(|pattern| {
+                        Regex::new(pattern)
+                            .with_context(|| format!("invalid pattern for team {}", team.name))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Ok(TeamMatcher {
+                    config: team,
+                    patterns,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Teams {
+            matchers,
+            team_by_user: HashMap::new(),
+        })
+    }
+
+    /// the teams as configured
+    #[must_use]
+    pub fn config(&self) -> Vec<TeamConfig> {
+        self.matchers
+            .iter()
+            .map(|matcher| matcher.config.clone())
+            .collect()
+    }
+
+    fn team_index(&self, user: &User) -> Option<usize> {
+        let email = user.email()?;
+        self.matchers
+            .iter()
+            .position(|matcher| matcher.matches(email))
+    }
+
+    fn team_of(&mut self, user_id: usize, dictionary: &GitUserDictionary) -> Option<usize> {
+        if let Some(team) = self.team_by_user.get(&user_id) {
+            return *team;
+        }
+        let team = dictionary
+            .users()
+            .get(user_id)
+            .and_then(|user| self.team_index(user));
+        self.team_by_user.insert(user_id, team);
+        team
+    }
+
+    /// commits per team - a commit counts once for each team with a user in it
+    pub fn commits_by_team<'a>(
+        &mut self,
+        commit_users: impl Iterator<Item = &'a BTreeSet<usize>>,
+        dictionary: &GitUserDictionary,
+    ) -> BTreeMap<String, u64> {
+        let mut commits: BTreeMap<String, u64> = BTreeMap::new();
+        for users in commit_users {
+            let teams: BTreeSet<usize> = users
+                .iter()
+                .filter_map(|user| self.team_of(*user, dictionary))
+                .collect();
+            for team in teams {
+                *commits
+                    .entry(self.matchers[team].config.name.clone())
+                    .or_insert(0) += 1;
+            }
+        }
+        commits
+    }
+
+    /// every team with its users, in config order - teams with no users are included
+    #[must_use]
+    pub fn members(&self, dictionary: &GitUserDictionary) -> Vec<TeamMembers> {
+        let mut members: Vec<TeamMembers> = self
+            .matchers
+            .iter()
+            .map(|matcher| TeamMembers {
+                name: matcher.config.name.clone(),
+                users: Vec::new(),
+            })
+            .collect();
+        for (user_id, user) in dictionary.users().iter().enumerate() {
+            if let Some(team) = self.team_index(user) {
+                members[team].users.push(user_id);
+            }
+        }
+        members
+    }
+}
+
+impl Serialize for Teams {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.config().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn teams() -> Teams {
+        Teams::new(TeamsConfig {
+            teams: vec![
+                TeamConfig {
+                    name: "payments".to_owned(),
+                    emails: vec!["Jo@Example.com".to_owned()],
+                    domains: vec!["payments.example.com".to_owned()],
+                    patterns: Vec::new(),
+                },
+                TeamConfig {
+                    name: "contractors".to_owned(),
+                    emails: Vec::new(),
+                    domains: Vec::new(),
+                    patterns: vec![r"@contractor\.(com|net)$".to_owned()],
+                },
+            ],
+        })
+        .unwrap()
+    }
+
+    fn dictionary(emails: &[&str]) -> GitUserDictionary {
+        let mut dictionary = GitUserDictionary::default();
+        for email in emails {
+            dictionary.register(&User::new(None, Some(email)));
+        }
+        dictionary
+    }
+
+    #[test]
+    fn users_are_matched_to_the_first_matching_team() {
+        let dictionary = dictionary(&[
+            "jo@example.com",
+            "sam@PAYMENTS.example.com",
+            "x@contractor.net",
+            "nobody@example.com",
+        ]);
+        assert_eq!(
+            teams().members(&dictionary),
+            vec![
+                TeamMembers {
+                    name: "payments".to_owned(),
+                    users: vec![0, 1]
+                },
+                TeamMembers {
+                    name: "contractors".to_owned(),
+                    users: vec![2]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn commits_count_once_per_team() {
+        let dictionary = dictionary(&["jo@example.com", "sam@payments.example.com", "x@y.com"]);
+        let commits = vec![
+            BTreeSet::from([0, 1]),
+            BTreeSet::from([1, 2]),
+            BTreeSet::from([2]),
+        ];
+        assert_eq!(
+            teams().commits_by_team(commits.iter(), &dictionary),
+            BTreeMap::from([("payments".to_owned(), 2)])
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let config = TeamsConfig {
+            teams: vec![TeamConfig {
+                name: "broken".to_owned(),
+                emails: Vec::new(),
+                domains: Vec::new(),
+                patterns: vec!["(".to_owned()],
+            }],
+        };
+        assert!(Teams::new(config).is_err());
+    }
+}
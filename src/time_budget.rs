@@ -0,0 +1,307 @@
+#![warn(clippy::all)]
+//! Keeping scans inside a wall-clock budget, for nightly CI runs with hard time limits.
+//!
+//! Before scanning, the project is surveyed - see `survey` - and the scan is planned to fit
+//! within most of the budget, leaving room for estimates being wrong. If it doesn't fit,
+//! features are degraded in order of least value for time: coupling is skipped, then fewer
+//! years of git history are read, then only a sample of files is scanned. While scanning,
+//! coupling is also skipped if the scan is running late. Every degradation is recorded in
+//! metadata, so nobody mistakes a degraded scan for a full one.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::survey::{ProjectSurvey, ALL_HISTORY_YEARS};
+use crate::FeatureFlags;
+
+/// plan to use this much of the budget - estimates are rough, and saving takes time too
+const PLANNED_SHARE: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeBudgetConfig {
+    pub seconds: u64,
+}
+
+impl TimeBudgetConfig {
+    fn budget(self) -> Duration {
+        Duration::from_secs(self.seconds)
+    }
+}
+
+/// something left out of a scan to keep to the time budget
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Degradation {
+    /// coupling wasn't calculated - either planned, or because the scan was running late
+    SkippedCoupling { while_scanning: bool },
+    /// only `to` years of git history were read
+    FewerGitYears { from: Option<u64>, to: u64 },
+    /// only one in every `one_in` files was scanned, chosen by a hash of its path
+    SampledFiles { one_in: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeBudgetMetadata {
+    pub budget_seconds: u64,
+    /// the estimated scan time, after any planned degradations
+    pub estimated_seconds: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degradations: Vec<Degradation>,
+}
+
+/// how to scan to fit the budget
+#[derive(Debug)]
+pub struct BudgetPlan {
+    pub git_years: Option<u64>,
+    pub coupling: bool,
+    pub sample_one_in: Option<u64>,
+    /// estimated time for coupling, to check against the time left when coupling is due
+    coupling_estimate: Duration,
+    deadline: Instant,
+    metadata: TimeBudgetMetadata,
+}
+
+/// plans a scan of a surveyed project, which started at `started` - `features.coupling` should
+/// only be set if coupling is configured
+#[must_use]
+pub fn plan(
+    survey: &ProjectSurvey,
+    config: TimeBudgetConfig,
+    git_years: Option<u64>,
+    file_calculators: usize,
+    features: &FeatureFlags,
+    keep_git_activity: bool,
+    started: Instant,
+) -> BudgetPlan {
+    let target = config.budget().mul_f64(PLANNED_SHARE);
+    let mut features = features.clone();
+    // more years than any repository has make no difference to the estimate
+    let history_years = survey
+        .repositories
+        .iter()
+        .map(|repository| repository.commits_per_year.len() as u64)
+        .max()
+        .unwrap_or(0);
+    let mut years = git_years
+        .unwrap_or(ALL_HISTORY_YEARS)
+        .min(history_years)
+        .max(1);
+    let estimate = |years: u64, features: &FeatureFlags| {
+        survey
+            .estimate_scan(years, file_calculators, features, keep_git_activity)
+            .total_duration()
+    };
+    let mut degradations = Vec::new();
+
+    let coupling_estimate = survey.coupling_estimate(years);
+    if features.coupling && estimate(years, &features) > target {
+        features.coupling = false;
+        degradations.push(Degradation::SkippedCoupling {
+            while_scanning: false,
+        });
+    }
+    if features.git && estimate(years, &features) > target {
+        let from = years;
+        while years > 1 && estimate(years, &features) > target {
+            years -= 1;
+        }
+        if years < from {
+            degradations.push(Degradation::FewerGitYears {
+                from: git_years,
+                to: years,
+            });
+        }
+    }
+    let mut estimated = estimate(years, &features);
+    let mut sample_one_in = None;
+    if estimated > target {
+        let files = survey.files_estimate(file_calculators);
+        let others = estimated.saturating_sub(files);
+        let one_in = match target.checked_sub(others) {
+            Some(left) if !left.is_zero() => {
+                (files.as_secs_f64() / left.as_secs_f64()).ceil() as u64
+            }
+            // nothing else fits either - sampling can't help much, but can help a bit
+            _ => survey.files.max(1),
+        };
+        if one_in > 1 {
+            sample_one_in = Some(one_in);
+            degradations.push(Degradation::SampledFiles { one_in });
+            estimated = others + files.div_f64(one_in as f64);
+        }
+    }
+    for degradation in &degradations {
+        warn!("To keep to the time budget: {:?}", degradation);
+    }
+
+    BudgetPlan {
+        git_years: if degradations
+            .iter()
+            .any(|d| matches!(d, Degradation::FewerGitYears { .. }))
+        {
+            Some(years)
+        } else {
+            git_years
+        },
+        coupling: features.coupling,
+        sample_one_in,
+        coupling_estimate,
+        deadline: started + config.budget(),
+        metadata: TimeBudgetMetadata {
+            budget_seconds: config.seconds,
+            estimated_seconds: estimated.as_secs(),
+            degradations,
+        },
+    }
+}
+
+impl BudgetPlan {
+    /// true if coupling was planned, and should still finish in time - otherwise records that
+    /// it was skipped
+    pub fn coupling_fits(&mut self) -> bool {
+        if !self.coupling {
+            return false;
+        }
+        if Instant::now() + self.coupling_estimate <= self.deadline {
+            return true;
+        }
+        warn!("Skipping coupling, as the scan is running late for the time budget");
+        self.coupling = false;
+        self.metadata
+            .degradations
+            .push(Degradation::SkippedCoupling {
+                while_scanning: true,
+            });
+        false
+    }
+
+    #[must_use]
+    pub fn metadata(&self) -> TimeBudgetMetadata {
+        self.metadata.clone()
+    }
+}
+
+/// whether a file is in the sample - by a hash of its path, so the same files are chosen each scan
+#[must_use]
+pub fn is_sampled(path: &Path, one_in: u64) -> bool {
+    // FNV-1a - stable across runs and platforms, unlike the std hasher
+    let hash = path
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    // the high bits are better mixed
+    (hash >> 32) % one_in.max(1) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::survey::RepositorySurvey;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    fn survey_of(files: u64, commits_per_year: Vec<u64>, files_per_commit: f64) -> ProjectSurvey {
+        ProjectSurvey {
+            files,
+            bytes: 0,
+            languages: std::collections::BTreeMap::new(),
+            repositories: vec![RepositorySurvey {
+                workdir: PathBuf::from("/repo"),
+                commits_per_year,
+                truncated: false,
+                files_per_commit,
+            }],
+        }
+    }
+
+    fn all_features() -> FeatureFlags {
+        FeatureFlags {
+            git: true,
+            coupling: true,
+            git_details: true,
+            file_stats: false,
+        }
+    }
+
+    #[test]
+    fn scans_that_fit_are_not_degraded() {
+        let survey = survey_of(100, vec![100, 100], 5.0);
+        let plan = plan(
+            &survey,
+            TimeBudgetConfig { seconds: 3600 },
+            Some(3),
+            2,
+            &all_features(),
+            false,
+            Instant::now(),
+        );
+        assert!(plan.coupling);
+        assert_eq!(plan.git_years, Some(3));
+        assert_eq!(plan.sample_one_in, None);
+        assert_eq!(plan.metadata().degradations, Vec::new());
+    }
+
+    #[test]
+    fn coupling_is_skipped_before_history_is_reduced() {
+        // 50s of git and 100s of coupling per year
+        let survey = survey_of(100, vec![1000; 3], 100.0);
+        let plan = plan(
+            &survey,
+            TimeBudgetConfig { seconds: 130 },
+            None,
+            1,
+            &all_features(),
+            false,
+            Instant::now(),
+        );
+        assert!(!plan.coupling);
+        assert_eq!(
+            plan.metadata().degradations,
+            vec![
+                Degradation::SkippedCoupling {
+                    while_scanning: false
+                },
+                Degradation::FewerGitYears { from: None, to: 2 }
+            ]
+        );
+        assert_eq!(plan.git_years, Some(2));
+    }
+
+    #[test]
+    fn files_are_sampled_if_nothing_else_fits() {
+        // 1,000,000 files at 0.0002s per calculator is 200s
+        let survey = survey_of(1_000_000, Vec::new(), 0.0);
+        let plan = plan(
+            &survey,
+            TimeBudgetConfig { seconds: 100 },
+            None,
+            1,
+            &FeatureFlags::default(),
+            false,
+            Instant::now(),
+        );
+        // 80s planned, so 1 in 3 files
+        assert_eq!(plan.sample_one_in, Some(3));
+        assert_eq!(
+            plan.metadata().degradations,
+            vec![Degradation::SampledFiles { one_in: 3 }]
+        );
+    }
+
+    #[test]
+    fn sampling_is_stable() {
+        let paths: Vec<PathBuf> = (0..1000)
+            .map(|i| PathBuf::from(format!("src/file_{}.rs", i)))
+            .collect();
+        let sampled = paths.iter().filter(|path| is_sampled(path, 4)).count();
+        assert!(sampled > 150 && sampled < 350, "sampled {}", sampled);
+        assert!(paths.iter().all(|path| is_sampled(path, 1)));
+        assert_eq!(
+            paths.iter().filter(|path| is_sampled(path, 4)).count(),
+            sampled
+        );
+    }
+}
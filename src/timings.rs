@@ -0,0 +1,172 @@
+#![warn(clippy::all)]
+//! Simple timing collection - so we can answer "why is my scan slow?"
+//!
+//! Timings are collected per scan phase, per calculator, and for the slowest individual nodes.
+//! Phases can overlap - e.g. git history loading happens lazily during the walk.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// how many of the slowest nodes to keep
+const SLOWEST_NODE_COUNT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub millis: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CalculatorTiming {
+    /// how many nodes the calculator visited
+    pub nodes: u64,
+    pub millis: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeTiming {
+    pub path: PathBuf,
+    pub millis: u64,
+}
+
+/// Serializable summary of timings, for metadata or a separate file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanTimings {
+    pub total_millis: u64,
+    pub phases: Vec<PhaseTiming>,
+    pub calculators: BTreeMap<String, CalculatorTiming>,
+    pub slowest_nodes: Vec<NodeTiming>,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    started: Instant,
+    phases: Vec<(String, Duration)>,
+    calculators: BTreeMap<String, (u64, Duration)>,
+    /// kept sorted, slowest first
+    slowest_nodes: Vec<(Duration, PathBuf)>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            started: Instant::now(),
+            phases: Vec::new(),
+            calculators: BTreeMap::new(),
+            slowest_nodes: Vec::new(),
+        }
+    }
+
+    pub fn record_phase(&mut self, name: &str, elapsed: Duration) {
+        debug!("Phase {} took {:?}", name, elapsed);
+        self.phases.push((name.to_owned(), elapsed));
+    }
+
+    pub fn record_calculator(&mut self, name: &str, elapsed: Duration) {
+        let entry = self
+            .calculators
+            .entry(name.to_owned())
+            .or_insert((0, Duration::ZERO));
+        entry.0 += 1;
+        entry.1 += elapsed;
+    }
+
+    pub fn record_node(&mut self, path: &Path, elapsed: Duration) {
+        if self.slowest_nodes.len() >= SLOWEST_NODE_COUNT {
+            if let Some((fastest, _)) = self.slowest_nodes.last() {
+                if *fastest >= elapsed {
+                    return;
+                }
+            }
+        }
+        self.slowest_nodes.push((elapsed, path.to_owned()));
+        self.slowest_nodes.sort_by(|a, b| b.0.cmp(&a.0));
+        self.slowest_nodes.truncate(SLOWEST_NODE_COUNT);
+    }
+
+    pub fn summary(&self) -> ScanTimings {
+        ScanTimings {
+            total_millis: millis(self.started.elapsed()),
+            phases: self
+                .phases
+                .iter()
+                .map(|(name, elapsed)| PhaseTiming {
+                    name: name.clone(),
+                    millis: millis(*elapsed),
+                })
+                .collect(),
+            calculators: self
+                .calculators
+                .iter()
+                .map(|(name, (nodes, elapsed))| {
+                    (
+                        name.clone(),
+                        CalculatorTiming {
+                            nodes: *nodes,
+                            millis: millis(*elapsed),
+                        },
+                    )
+                })
+                .collect(),
+            slowest_nodes: self
+                .slowest_nodes
+                .iter()
+                .map(|(elapsed, path)| NodeTiming {
+                    path: path.clone(),
+                    millis: millis(*elapsed),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn calculator_timings_are_accumulated() {
+        let mut profiler = Profiler::new();
+        profiler.record_calculator("loc", Duration::from_millis(10));
+        profiler.record_calculator("loc", Duration::from_millis(15));
+        profiler.record_calculator("git", Duration::from_millis(1));
+        let summary = profiler.summary();
+        assert_eq!(
+            summary.calculators.get("loc"),
+            Some(&CalculatorTiming {
+                nodes: 2,
+                millis: 25
+            })
+        );
+        assert_eq!(summary.calculators.get("git").unwrap().nodes, 1);
+    }
+
+    #[test]
+    fn only_slowest_nodes_are_kept() {
+        let mut profiler = Profiler::new();
+        for ix in 0..20 {
+            profiler.record_node(
+                &PathBuf::from(format!("file_{}", ix)),
+                Duration::from_millis(ix),
+            );
+        }
+        let summary = profiler.summary();
+        assert_eq!(summary.slowest_nodes.len(), SLOWEST_NODE_COUNT);
+        assert_eq!(summary.slowest_nodes[0].path, PathBuf::from("file_19"));
+        assert_eq!(summary.slowest_nodes[0].millis, 19);
+        assert_eq!(summary.slowest_nodes[9].millis, 10);
+    }
+}
@@ -2,8 +2,9 @@
 
 use anyhow::Error;
 use std::path::Path;
+use std::time::Duration;
 
-use crate::{flare::FlareTreeNode, polyglot_data::IndicatorMetadata};
+use crate::{diagnostics::Diagnostic, flare::FlareTreeNode, polyglot_data::IndicatorMetadata};
 
 /// Wrapper for the logic that calculates toxicity indicators
 pub trait ToxicityIndicatorCalculator: std::fmt::Debug {
@@ -11,4 +12,12 @@ pub trait ToxicityIndicatorCalculator: std::fmt::Debug {
     fn visit_node(&mut self, node: &mut FlareTreeNode, path: &Path) -> Result<(), Error>;
     /// root-level metadata - output after all files added
     fn apply_metadata(&self, metadata: &mut IndicatorMetadata) -> Result<(), Error>;
+    /// extra timings worth reporting, such as slow one-off setup - most calculators have none
+    fn timings(&self) -> Vec<(String, Duration)> {
+        Vec::new()
+    }
+    /// non-fatal problems found while visiting nodes
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        Vec::new()
+    }
 }
@@ -0,0 +1,190 @@
+#![warn(clippy::all)]
+//! Sending data files to a web server - so CI jobs can publish scans without a wrapper script.
+//!
+//! Files are POSTed as JSON, streamed from disk so big scans aren't held in memory, with an
+//! optional bearer token. Connection failures, rate limits and server errors are retried with an
+//! increasing delay; other errors such as bad tokens fail straight away. Most scans don't need
+//! an http client, so uploads are only available with the `upload` cargo feature.
+
+use anyhow::Error;
+use std::path::Path;
+use std::time::Duration;
+
+pub const DEFAULT_UPLOAD_RETRIES: u32 = 3;
+
+/// uploads can be big, so this is generous
+#[cfg(feature = "upload")]
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub url: String,
+    /// sent as an `Authorization: Bearer` header
+    pub token: Option<String>,
+    /// how many times to retry after the first attempt
+    pub retries: u32,
+    /// the delay before the first retry - doubled for each retry after that
+    pub retry_delay: Duration,
+}
+
+impl UploadConfig {
+    #[must_use]
+    pub fn new(url: &str, token: Option<String>) -> Self {
+        UploadConfig {
+            url: url.to_owned(),
+            token,
+            retries: DEFAULT_UPLOAD_RETRIES,
+            retry_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+#[cfg(feature = "upload")]
+fn is_retryable_status(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// POSTs a JSON file to the configured url
+#[cfg(feature = "upload")]
+pub fn upload_file(file: &Path, config: &UploadConfig) -> Result<(), Error> {
+    use anyhow::Context;
+    use std::fs::File;
+    use std::thread;
+
+    let agent = ureq::AgentBuilder::new().timeout(UPLOAD_TIMEOUT).build();
+    let mut delay = config.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let body = File::open(file).with_context(|| format!("opening {:?} to upload", file))?;
+        let length = body.metadata()?.len();
+        let mut request = agent
+            .post(&config.url)
+            .set("Content-Type", "application/json")
+            .set("Content-Length", &length.to_string());
+        if let Some(token) = &config.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        let error = match request.send(body) {
+            Ok(response) => {
+                info!("uploaded to {} - status {}", config.url, response.status());
+                return Ok(());
+            }
+            Err(ureq::Error::Status(status, response)) if !is_retryable_status(status) => {
+                bail!(
+                    "upload to {} failed with status {} {}",
+                    config.url,
+                    status,
+                    response.status_text()
+                );
+            }
+            Err(e) => e,
+        };
+        if attempt >= config.retries {
+            return Err(Error::new(error).context(format!(
+                "uploading to {} after {} attempts",
+                config.url,
+                attempt + 1
+            )));
+        }
+        attempt += 1;
+        warn!(
+            "upload to {} failed, retrying in {:?}: {}",
+            config.url, delay, error
+        );
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+/// POSTs a JSON file to the configured url
+#[cfg(not(feature = "upload"))]
+pub fn upload_file(_file: &Path, config: &UploadConfig) -> Result<(), Error> {
+    bail!(
+        "Can't upload to {} - uploads need a build with the upload feature",
+        config.url
+    )
+}
+
+#[cfg(all(test, feature = "upload"))]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// replies to each request with the next status, returning the requests received
+    fn serve(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/scans", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:")
+                    {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                requests.push(request);
+                write!(
+                    &stream,
+                    "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn test_config(url: &str, retries: u32) -> UploadConfig {
+        UploadConfig {
+            retries,
+            retry_delay: Duration::from_millis(1),
+            ..UploadConfig::new(url, Some("secret-token".to_owned()))
+        }
+    }
+
+    #[test]
+    fn server_errors_are_retried() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("scan.json");
+        std::fs::write(&file, "{\"version\":\"1.1.0\"}")?;
+        let (url, server) = serve(vec![503, 201]);
+
+        upload_file(&file, &test_config(&url, 2))?;
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /api/scans"));
+        assert!(requests[1].contains("Authorization: Bearer secret-token"));
+        assert!(requests[1].ends_with("{\"version\":\"1.1.0\"}"));
+        Ok(())
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("scan.json");
+        std::fs::write(&file, "{}")?;
+        let (url, server) = serve(vec![401]);
+
+        assert!(upload_file(&file, &test_config(&url, 2)).is_err());
+        assert_eq!(server.join().unwrap().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+#![warn(clippy::all)]
+//! Checking an existing data file - useful after hand-merging or editing data files, which
+//! explorers otherwise fail on in confusing ways.
+//!
+//! Files must have a compatible version, and match the data file format. Then the contents are
+//! checked for consistency:
+//! - git user ids on files must be in the user list in metadata
+//! - git repository indexes must be in the repository list
+//! - coupled files must be in the tree
+//! - files can't have children, and no directory can have two children with the same name
+
+use anyhow::{Context, Error};
+use path_slash::PathExt;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::coupling::SerializableCouplingData;
+use crate::flare::FlareTreeNode;
+use crate::git::GitNodeData;
+use crate::polyglot_data::{IndicatorMetadata, PolyglotData, DATA_FILE_VERSION};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationProblem {
+    /// the node with the problem, relative to the tree root - None for problems with the whole file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl ValidationProblem {
+    fn new(path: Option<&Path>, message: impl Into<String>) -> Self {
+        ValidationProblem {
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.to_slash_lossy(), self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn major_version(version: &str) -> Option<&str> {
+    version.split('.').next()
+}
+
+/// problems with a data file - an error if it can't be read as JSON at all
+pub fn validate_file(file: &Path) -> Result<Vec<ValidationProblem>, Error> {
+    let reader =
+        BufReader::new(File::open(file).with_context(|| format!("opening data file {:?}", file))?);
+    let json: Value =
+        serde_json::from_reader(reader).with_context(|| format!("parsing data file {:?}", file))?;
+    match json.get("version").and_then(Value::as_str) {
+        None => {
+            return Ok(vec![ValidationProblem::new(
+                None,
+                "No version - this isn't a polyglot data file, or is a classic flare file",
+            )])
+        }
+        Some(version) if major_version(version) != major_version(DATA_FILE_VERSION) => {
+            return Ok(vec![ValidationProblem::new(
+                None,
+                format!(
+                    "Version {} is incompatible with version {}",
+                    version, DATA_FILE_VERSION
+                ),
+            )])
+        }
+        Some(_) => {}
+    }
+    match serde_json::from_value::<PolyglotData>(json) {
+        Ok(data) => Ok(validate(data)),
+        Err(e) => Ok(vec![ValidationProblem::new(
+            None,
+            format!("Doesn't match the data file format: {}", e),
+        )]),
+    }
+}
+
+/// problems with the contents of a data file
+#[must_use]
+pub fn validate(data: PolyglotData) -> Vec<ValidationProblem> {
+    let (tree, metadata) = data.into_parts();
+    let mut problems = Vec::new();
+    check_node(&tree, &tree, Path::new(""), &metadata, &mut problems);
+    problems
+}
+
+fn check_node(
+    node: &FlareTreeNode,
+    root: &FlareTreeNode,
+    path: &Path,
+    metadata: &IndicatorMetadata,
+    problems: &mut Vec<ValidationProblem>,
+) {
+    if node.is_file() && !node.get_children().is_empty() {
+        problems.push(ValidationProblem::new(Some(path), "File has children"));
+    }
+    let mut names = HashSet::new();
+    for child in node.get_children() {
+        if !names.insert(child.name()) {
+            problems.push(ValidationProblem::new(
+                Some(&path.join(child.name())),
+                "Another node in the same directory has this name",
+            ));
+        }
+    }
+
+    let indicators = node.indicators();
+    if let Some(GitNodeData::File { data }) = &indicators.git {
+        let user_count = metadata
+            .git
+            .as_ref()
+            .map_or(0, |git| git.users.users().len());
+        let repository_count = metadata
+            .git
+            .as_ref()
+            .map_or(0, |git| git.repositories.len());
+        let mut users: Vec<usize> = data.users.clone();
+        users.extend(data.last_author);
+        users.extend(
+            data.top_contributors
+                .iter()
+                .map(|contributor| contributor.user),
+        );
+        users.extend(data.details.iter().flat_map(|details| details.users.iter()));
+        if let Some(user) = users.iter().find(|user| **user >= user_count) {
+            problems.push(ValidationProblem::new(
+                Some(path),
+                format!(
+                    "Git data refers to user {}, but there are only {} users",
+                    user, user_count
+                ),
+            ));
+        }
+        match data.repository {
+            Some(repository) if repository >= repository_count => {
+                problems.push(ValidationProblem::new(
+                    Some(path),
+                    format!(
+                        "Git data refers to repository {}, but there are only {} repositories",
+                        repository, repository_count
+                    ),
+                ));
+            }
+            _ => {}
+        }
+    }
+    let coupling = indicators
+        .coupling
+        .iter()
+        .chain(indicators.named_coupling.values());
+    for coupling in coupling {
+        check_coupling(coupling, root, path, problems);
+    }
+
+    for child in node.get_children() {
+        check_node(child, root, &path.join(child.name()), metadata, problems);
+    }
+}
+
+fn check_coupling(
+    coupling: &SerializableCouplingData,
+    root: &FlareTreeNode,
+    path: &Path,
+    problems: &mut Vec<ValidationProblem>,
+) {
+    let mut missing = HashSet::new();
+    for bucket in &coupling.buckets {
+        for (coupled, _count) in &bucket.coupled_files {
+            let coupled = coupled.to_path_buf();
+            if root.get_in(&mut coupled.components()).is_none() && missing.insert(coupled.clone()) {
+                problems.push(ValidationProblem::new(
+                    Some(path),
+                    format!(
+                        "Coupled to {}, which isn't in the tree",
+                        coupled.to_slash_lossy()
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::GitData;
+    use crate::FeatureFlags;
+    use pretty_assertions::assert_eq;
+
+    fn messages(problems: &[ValidationProblem]) -> Vec<String> {
+        problems.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn consistent_data_has_no_problems() -> Result<(), Error> {
+        assert_eq!(
+            validate_file(Path::new("./tests/expected/simple_files.json"))?,
+            Vec::new()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inconsistencies_are_problems() {
+        let mut root = FlareTreeNode::dir("<root>");
+        let mut src = FlareTreeNode::dir("src");
+        let mut main = FlareTreeNode::file("main.rs");
+        let mut git = GitData::fake_with_activity(Vec::new());
+        git.users = vec![0];
+        git.repository = Some(0);
+        main.indicators_mut().git = Some(GitNodeData::File { data: git });
+        src.append_child(main);
+        src.append_child(FlareTreeNode::file("lib.rs"));
+        src.append_child(FlareTreeNode::file("lib.rs"));
+        root.append_child(src);
+        let data = PolyglotData::new("test", None, root, FeatureFlags::default());
+
+        assert_eq!(
+            messages(&validate(data)),
+            vec![
+                "src/lib.rs: Another node in the same directory has this name",
+                "src/main.rs: Git data refers to user 0, but there are only 0 users",
+                "src/main.rs: Git data refers to repository 0, but there are only 0 repositories",
+            ]
+        );
+    }
+
+    #[test]
+    fn incompatible_files_are_problems() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("data.json");
+        std::fs::write(&file, r#"{"version": "0.9.0"}"#)?;
+        assert_eq!(
+            messages(&validate_file(&file)?),
+            vec![format!(
+                "Version 0.9.0 is incompatible with version {}",
+                DATA_FILE_VERSION
+            )]
+        );
+        std::fs::write(&file, format!(r#"{{"version": "{}"}}"#, DATA_FILE_VERSION))?;
+        assert!(messages(&validate_file(&file)?)[0].starts_with("Doesn't match"));
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+#![warn(clippy::all)]
+//! Vendored dependencies - directories of third-party code such as `node_modules`, which swamp
+//! the real code in most scans. They can be skipped while walking, or kept and tagged so clients
+//! can filter them.
+//!
+//! Directories are vendored if they have a well-known name, or contain a `pyvenv.cfg` (a Python
+//! virtualenv under any name). Everything inside a vendored directory is vendored too.
+
+use crate::flare::FlareTreeNode;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+pub static DEFAULT_VENDORED_NAMES: &[&str] =
+    &["node_modules", "vendor", "third_party", "Pods", "venv"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VendoredMode {
+    /// keep vendored files, with a `vendored` flag
+    Tag,
+    /// don't scan vendored directories at all
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VendoredConfig {
+    pub mode: VendoredMode,
+    /// directory names that are vendored wherever they are - case sensitive
+    pub names: Vec<String>,
+    /// directories that are never vendored, relative to the root - for projects with their own
+    /// code in e.g. a `vendor` directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_vendored: Vec<PathBuf>,
+}
+
+impl VendoredConfig {
+    /// the default names, plus any extra names
+    #[must_use]
+    pub fn new(mode: VendoredMode, extra_names: &[String], not_vendored: &[PathBuf]) -> Self {
+        VendoredConfig {
+            mode,
+            names: DEFAULT_VENDORED_NAMES
+                .iter()
+                .map(|name| (*name).to_owned())
+                .chain(extra_names.iter().cloned())
+                .collect(),
+            not_vendored: not_vendored.to_vec(),
+        }
+    }
+
+    /// is this directory vendored itself, ignoring its parents? `relative` is relative to `root`
+    #[must_use]
+    pub fn is_vendored_dir(&self, root: &Path, relative: &Path) -> bool {
+        if self.not_vendored.iter().any(|dir| dir == relative) {
+            return false;
+        }
+        let named = relative.file_name().map_or(false, |name| {
+            self.names.iter().any(|n| OsStr::new(n) == name)
+        });
+        let dir = root.join(relative);
+        dir.is_dir() && (named || dir.join("pyvenv.cfg").is_file())
+    }
+
+    /// is this file or directory in a vendored directory, or vendored itself?
+    #[must_use]
+    pub fn is_vendored(&self, root: &Path, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| self.is_vendored_dir(root, ancestor))
+    }
+}
+
+/// flags vendored nodes in a tree - `relative` is the path of `node` relative to `root`
+pub fn tag_vendored(
+    node: &mut FlareTreeNode,
+    root: &Path,
+    relative: &Path,
+    parent_vendored: bool,
+    config: &VendoredConfig,
+) {
+    let vendored = parent_vendored
+        || (!relative.as_os_str().is_empty() && config.is_vendored_dir(root, relative));
+    if vendored {
+        node.indicators_mut().vendored = true;
+    }
+    for child in node.get_children_mut() {
+        let child_path = relative.join(child.name());
+        tag_vendored(child, root, &child_path, vendored, config);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn vendored_dirs_are_found_by_name_or_virtualenv() -> Result<(), anyhow::Error> {
+        let root = tempdir()?;
+        let root = root.path();
+        fs::create_dir_all(root.join("web/node_modules/lib"))?;
+        fs::create_dir_all(root.join("src/vendor"))?;
+        fs::create_dir_all(root.join("env"))?;
+        fs::write(root.join("env/pyvenv.cfg"), "home = /usr/bin")?;
+        fs::write(root.join("vendor"), "a file, not a directory")?;
+        let config = VendoredConfig::new(VendoredMode::Tag, &[], &[PathBuf::from("src/vendor")]);
+
+        assert!(config.is_vendored(root, Path::new("web/node_modules/lib")));
+        assert!(config.is_vendored(root, Path::new("env")));
+        assert!(!config.is_vendored(root, Path::new("web")));
+        assert!(!config.is_vendored(root, Path::new("src/vendor")));
+        assert!(!config.is_vendored(root, Path::new("vendor")));
+        Ok(())
+    }
+}
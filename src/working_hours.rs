@@ -0,0 +1,239 @@
+#![warn(clippy::all)]
+//! When work happens - how commits are spread across hours of the day and days of the week,
+//! for each file and each contributor, for sustainability and burnout analyses.
+//!
+//! Times are in each commit's author timezone, so a commit at 11pm in Sydney is late night even
+//! though it was lunchtime in London. Contributors' commits are only counted once, however many
+//! files they changed - but only commits to scanned files are counted.
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::iter::once;
+
+use crate::git_file_history::FileHistoryEntry;
+use crate::git_user_dictionary::GitUserDictionary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkingHoursConfig {
+    /// commits from this hour of the day count as late night
+    pub late_night_from: u8,
+    /// commits before this hour of the day count as late night
+    pub late_night_until: u8,
+}
+
+impl Default for WorkingHoursConfig {
+    fn default() -> Self {
+        WorkingHoursConfig {
+            late_night_from: 22,
+            late_night_until: 6,
+        }
+    }
+}
+
+impl WorkingHoursConfig {
+    fn is_late_night(&self, hour: u32) -> bool {
+        let from = u32::from(self.late_night_from);
+        let until = u32::from(self.late_night_until);
+        if from > until {
+            // past midnight
+            hour >= from || hour < until
+        } else {
+            hour >= from && hour < until
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkingHours {
+    pub commits: u64,
+    /// commits by hour of the day, from midnight
+    pub by_hour: Vec<u64>,
+    /// commits by day of the week, from Monday
+    pub by_weekday: Vec<u64>,
+    /// commits on Saturday or Sunday
+    pub weekend: u64,
+    pub late_night: u64,
+}
+
+impl Default for WorkingHours {
+    fn default() -> Self {
+        WorkingHours {
+            commits: 0,
+            by_hour: vec![0; 24],
+            by_weekday: vec![0; 7],
+            weekend: 0,
+            late_night: 0,
+        }
+    }
+}
+
+impl WorkingHours {
+    /// adds a commit at the author's local time
+    fn add(&mut self, entry: &FileHistoryEntry, config: &WorkingHoursConfig) {
+        let local_secs = entry.author_time as i64 + i64::from(entry.author_offset_minutes) * 60;
+        let local_time = NaiveDateTime::from_timestamp(local_secs, 0);
+        let hour = local_time.hour();
+        let weekday = local_time.weekday().num_days_from_monday();
+        self.commits += 1;
+        self.by_hour[hour as usize] += 1;
+        self.by_weekday[weekday as usize] += 1;
+        if weekday >= 5 {
+            self.weekend += 1;
+        }
+        if config.is_late_night(hour) {
+            self.late_night += 1;
+        }
+    }
+}
+
+/// when a file's commits were made
+pub fn from_history(
+    history: &[FileHistoryEntry],
+    config: &WorkingHoursConfig,
+) -> Option<WorkingHours> {
+    if history.is_empty() {
+        return None;
+    }
+    let mut hours = WorkingHours::default();
+    for entry in history {
+        hours.add(entry, config);
+    }
+    Some(hours)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContributorWorkingHours {
+    pub user: usize,
+    #[serde(flatten)]
+    pub hours: WorkingHours,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkingHoursMetadata {
+    pub config: WorkingHoursConfig,
+    /// by user id - authors and co-authors
+    pub contributors: Vec<ContributorWorkingHours>,
+}
+
+/// working hours for each contributor, built up from file histories as files are scanned
+#[derive(Debug, Default)]
+pub struct ContributorHours {
+    /// commits already counted, as most commits change several files
+    seen_commits: HashSet<String>,
+    by_user: BTreeMap<usize, WorkingHours>,
+}
+
+impl ContributorHours {
+    pub fn add_history(
+        &mut self,
+        history: &[FileHistoryEntry],
+        dictionary: &mut GitUserDictionary,
+        config: &WorkingHoursConfig,
+    ) {
+        for entry in history {
+            if !self.seen_commits.insert(entry.id.clone()) {
+                continue;
+            }
+            let users: BTreeSet<usize> = once(&entry.author)
+                .chain(&entry.co_authors)
+                .map(|user| dictionary.register(user))
+                .collect();
+            for user in users {
+                self.by_user.entry(user).or_default().add(entry, config);
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn metadata(&self, config: WorkingHoursConfig) -> WorkingHoursMetadata {
+        WorkingHoursMetadata {
+            config,
+            contributors: self
+                .by_user
+                .iter()
+                .map(|(user, hours)| ContributorWorkingHours {
+                    user: *user,
+                    hours: hours.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git_file_history::FileHistoryEntryBuilder;
+    use crate::git_logger::User;
+    use pretty_assertions::assert_eq;
+
+    // Monday 5th January 1970
+    const MONDAY: u64 = 4 * 24 * 60 * 60;
+    const HOUR: u64 = 60 * 60;
+
+    fn commit(id: &str, email: &str, utc_time: u64, offset_minutes: i32) -> FileHistoryEntry {
+        FileHistoryEntryBuilder::test_default()
+            .emails(email)
+            .times(utc_time)
+            .author_offset_minutes(offset_minutes)
+            .id(id)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn commits_are_counted_in_the_author_timezone() {
+        let history = vec![
+            // 10am Monday UTC
+            commit("1", "jo@smith.com", MONDAY + 10 * HOUR, 0),
+            // 11pm Monday UTC, 9am Tuesday in Sydney
+            commit("2", "jo@smith.com", MONDAY + 23 * HOUR, 10 * 60),
+            // 11pm Friday UTC, midnight Saturday in London summer time
+            commit("3", "jo@smith.com", MONDAY + 4 * 24 * HOUR + 23 * HOUR, 60),
+        ];
+        let hours = from_history(&history, &WorkingHoursConfig::default()).unwrap();
+
+        assert_eq!(hours.commits, 3);
+        assert_eq!(hours.by_hour[10], 1);
+        assert_eq!(hours.by_hour[9], 1);
+        assert_eq!(hours.by_hour[0], 1);
+        assert_eq!(hours.by_weekday, vec![1, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(hours.weekend, 1);
+        assert_eq!(hours.late_night, 1);
+        assert_eq!(from_history(&[], &WorkingHoursConfig::default()), None);
+    }
+
+    #[test]
+    fn late_night_can_be_within_a_day() {
+        let config = WorkingHoursConfig {
+            late_night_from: 1,
+            late_night_until: 5,
+        };
+        assert!(!config.is_late_night(0));
+        assert!(config.is_late_night(1));
+        assert!(config.is_late_night(4));
+        assert!(!config.is_late_night(5));
+    }
+
+    #[test]
+    fn contributors_commits_are_only_counted_once() {
+        let config = WorkingHoursConfig::default();
+        let mut dictionary = GitUserDictionary::default();
+        let mut contributors = ContributorHours::default();
+        let mut paired = commit("2", "jo@smith.com", MONDAY + 3 * HOUR, 0);
+        paired.co_authors = vec![User::new(None, Some("x@smith.com"))];
+        let first_file = vec![commit("1", "jo@smith.com", MONDAY + 10 * HOUR, 0), paired];
+        let second_file = vec![commit("1", "jo@smith.com", MONDAY + 10 * HOUR, 0)];
+        contributors.add_history(&first_file, &mut dictionary, &config);
+        contributors.add_history(&second_file, &mut dictionary, &config);
+
+        let metadata = contributors.metadata(config);
+        let commits: Vec<(usize, u64, u64)> = metadata
+            .contributors
+            .iter()
+            .map(|c| (c.user, c.hours.commits, c.hours.late_night))
+            .collect();
+        assert_eq!(commits, vec![(0, 2, 1), (1, 1, 1)]);
+    }
+}
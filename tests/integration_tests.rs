@@ -1,15 +1,31 @@
 use anyhow::Error;
-use polyglot_code_scanner::ScannerConfig;
+use polyglot_code_scanner::coupling::CouplingConfig;
+use polyglot_code_scanner::git_file_history::GitFileHistory;
+use polyglot_code_scanner::git_logger::{GitLog, GitLogConfig};
+use polyglot_code_scanner::{ScanCancelled, ScannerConfig};
 use serde_json::Value;
+use std::fs::File;
 use std::io::Cursor;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 use test_shared::*;
 
+/// the scanner version differs on every release, so check it is there and remove it - and check
+/// no local paths are recorded unless asked for
+fn without_scanner_version(mut result: Value) -> Value {
+    let metadata = result["metadata"].as_object_mut().unwrap();
+    assert_eq!(metadata.get("paths"), None);
+    let scanner = metadata.remove("scanner").expect("no scanner metadata");
+    assert_eq!(scanner["version"], env!("CARGO_PKG_VERSION"));
+    result
+}
+
 fn test_scanner_config(with_git: bool) -> ScannerConfig {
     let mut config = ScannerConfig::default("test");
     config.data_id = Some("test-id".to_string());
     config.features.git = with_git;
+    // git ages are measured to the scan time, so it must be fixed for the expected output
+    config.scan_time = 1_560_000_000;
     config
 }
 
@@ -25,7 +41,7 @@ fn it_calculates_lines_of_code() -> Result<(), Error> {
 
     assert!(result.is_ok());
 
-    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let parsed_result = without_scanner_version(serde_json::from_reader(buffer.as_slice())?);
 
     assert_eq_json_file(
         &parsed_result,
@@ -35,6 +51,89 @@ fn it_calculates_lines_of_code() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn scanner_config_is_saved_in_metadata() -> Result<(), Error> {
+    let root = PathBuf::from("./tests/data/simple/");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    let ignore_dir = tempdir()?;
+    let ignore_file = ignore_dir.path().join("extra_ignore");
+    std::fs::write(&ignore_file, "*.xyz\n")?;
+    let mut config = test_scanner_config(false);
+    config.max_depth = Some(3);
+    config.ignore_files = vec![ignore_file];
+
+    polyglot_code_scanner::run(&root, &config, None, &["loc", "indentation"], out)?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let scanner = &parsed_result["metadata"]["scanner"];
+    assert_eq!(
+        scanner["calculators"],
+        serde_json::json!(["loc", "indentation"])
+    );
+    assert_eq!(scanner["config"]["max_depth"], 3);
+    assert_eq!(scanner["config"]["git_subtree_only"], false);
+    assert_eq!(
+        scanner["config"]["co_authors"]["trailers"],
+        serde_json::json!(["Co-authored-by"])
+    );
+    // ignore files can be anywhere, so only their names are saved
+    assert_eq!(
+        scanner["config"]["ignore_files"],
+        serde_json::json!(["extra_ignore"])
+    );
+    assert!(scanner["config"]["secrets"].is_array());
+    assert!(scanner["config"]["categories"].is_array());
+    assert_eq!(scanner["config"]["report"]["top"], 10);
+    assert_eq!(scanner["config"].get("name"), None);
+    assert_eq!(scanner["config"].get("cancellation"), None);
+
+    Ok(())
+}
+
+#[test]
+fn the_absolute_root_is_only_saved_if_asked_for() -> Result<(), Error> {
+    let root = PathBuf::from("./tests/data/simple/");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    let mut config = test_scanner_config(false);
+    config.local_paths = true;
+
+    polyglot_code_scanner::run(&root, &config, None, &["loc"], out)?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let scanned_root = PathBuf::from(
+        parsed_result["metadata"]["paths"]["root"]
+            .as_str()
+            .expect("no root in metadata"),
+    );
+    assert!(scanned_root.is_absolute());
+    assert_eq!(scanned_root, root.canonicalize()?);
+
+    Ok(())
+}
+
+#[test]
+fn cancelled_scans_fail_without_output() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    let config = test_scanner_config(true);
+    config.cancellation.cancel();
+
+    let result = polyglot_code_scanner::run(&git_root, &config, None, &["loc", "git"], out);
+
+    let error = result.expect_err("cancelled scan should fail");
+    assert!(error.downcast_ref::<ScanCancelled>().is_some());
+    assert!(buffer.is_empty());
+
+    Ok(())
+}
+
 #[test]
 fn it_calculates_git_stats() -> Result<(), Error> {
     let gitdir = tempdir()?;
@@ -48,7 +147,7 @@ fn it_calculates_git_stats() -> Result<(), Error> {
 
     assert!(result.is_ok());
 
-    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let parsed_result = without_scanner_version(serde_json::from_reader(buffer.as_slice())?);
 
     assert_eq_json_file(
         &parsed_result,
@@ -58,6 +157,29 @@ fn it_calculates_git_stats() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn git_history_can_be_read_without_scanning() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+    let mut git_log = GitLog::new(&git_root, GitLogConfig::default())?;
+    let commits = git_log.iterator()?.collect::<Result<Vec<_>, Error>>()?;
+    assert!(commits
+        .iter()
+        .any(|commit| commit.id() == "0dbd54d4c524ecc776f381e660cce9b2dd92162c"));
+
+    let history = GitFileHistory::new(&mut git_log)?;
+    let parent = history
+        .history_for_relative(Path::new("simple/parent.clj"))
+        .unwrap();
+    assert_eq!(parent.len(), 3);
+    assert!(history
+        .files()
+        .any(|(file, _)| file == Path::new("simple/parent.clj")));
+
+    Ok(())
+}
+
 #[test]
 fn it_calculates_detailed_git_stats() -> Result<(), Error> {
     let gitdir = tempdir()?;
@@ -73,7 +195,7 @@ fn it_calculates_detailed_git_stats() -> Result<(), Error> {
 
     assert!(result.is_ok());
 
-    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let parsed_result = without_scanner_version(serde_json::from_reader(buffer.as_slice())?);
 
     assert_eq_json_file(
         &parsed_result,
@@ -83,4 +205,231 @@ fn it_calculates_detailed_git_stats() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn reprocessing_can_strip_kept_git_activity() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+    let scanned = gitdir.path().join("scanned.json");
+
+    let mut config = test_scanner_config(true);
+    config.keep_git_activity = true;
+    polyglot_code_scanner::run(&git_root, &config, None, &["git"], File::create(&scanned)?)?;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    polyglot_code_scanner::reprocess(&scanned, &test_scanner_config(true), None, out)?;
+
+    let parsed_result = without_scanner_version(serde_json::from_reader(buffer.as_slice())?);
+
+    assert_eq_json_file(
+        &parsed_result,
+        "./tests/expected/integration_tests/git_flare_test.json",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn reprocessing_recalculates_coupling_from_kept_git_activity() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+    let scanned = gitdir.path().join("scanned.json");
+
+    let mut config = test_scanner_config(true);
+    config.keep_git_activity = true;
+    polyglot_code_scanner::run(
+        &git_root,
+        &config,
+        None,
+        &["loc", "git"],
+        File::create(&scanned)?,
+    )?;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    // one minute gaps, so each commit is its own activity burst
+    let coupling_config = CouplingConfig::new(91, 1, 0.001, 60, 60, 0, None);
+    polyglot_code_scanner::reprocess(
+        &scanned,
+        &test_scanner_config(true),
+        Some(coupling_config),
+        out,
+    )?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let simple = &parsed_result["tree"]["children"][0];
+    assert_eq!(simple["name"], "simple");
+    let parent = simple["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|child| child["name"] == "parent.clj")
+        .unwrap();
+    // parent.clj and a.txt were changed together in the first commits, and the merge
+    let coupled_files = parent["data"]["coupling"]["buckets"][0]["coupled_files"]
+        .as_array()
+        .unwrap();
+    assert_eq!(coupled_files.len(), 1);
+    assert_eq!(coupled_files[0][0], "simple/child/a_renamed.txt");
+    // activity is only kept for reprocessing, so it is stripped again
+    assert_eq!(parent["data"]["git"]["activity"], serde_json::json!([]));
+
+    Ok(())
+}
+
+#[test]
+fn files_outside_git_are_reported_in_diagnostics() -> Result<(), Error> {
+    let root = tempdir()?;
+    std::fs::write(root.path().join("file.txt"), "not in git\n")?;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    polyglot_code_scanner::run(
+        root.path(),
+        &test_scanner_config(true),
+        None,
+        &["loc", "git"],
+        out,
+    )?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let diagnostics = parsed_result["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["category"], "missing_git");
+
+    Ok(())
+}
+
 // TODO: add a coupling e2e test!  Needs a lot of setup
+
+#[test]
+fn files_can_be_scanned_one_at_a_time() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+    let config = test_scanner_config(true);
+    let mut files = polyglot_code_scanner::scan_iter(&git_root, &config, &["git"])?;
+    let scanned = files.by_ref().collect::<Result<Vec<_>, Error>>()?;
+
+    let paths: Vec<PathBuf> = scanned.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("simple/child/a_renamed.txt"),
+            PathBuf::from("simple/parent.clj")
+        ]
+    );
+    assert!(scanned.iter().all(|file| file.indicators.git.is_some()));
+    assert_eq!(
+        scanned[1].indicators.stable_id.as_deref(),
+        Some("a6bc90fc8d15b8f5")
+    );
+    assert!(files.metadata()?.git.is_some());
+    assert!(files.diagnostics().is_empty());
+    Ok(())
+}
+
+#[test]
+fn a_single_file_can_be_scanned_without_git() -> Result<(), Error> {
+    let config = test_scanner_config(false);
+    let file = PathBuf::from("./tests/data/simple/parent.clj");
+    let scanned = polyglot_code_scanner::scan_file(&file, &config, &["loc", "indentation"])?;
+
+    assert_eq!(scanned.path, file);
+    assert!(scanned.indicators.loc.is_some());
+    assert!(scanned.indicators.indentation.is_some());
+    assert!(scanned.indicators.git.is_none());
+    assert!(polyglot_code_scanner::scan_file(&file, &config, &["git"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn changed_files_can_be_scanned_incrementally() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+
+    let mut config = test_scanner_config(true);
+    config.changed_since = Some("HEAD~1".to_string());
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    polyglot_code_scanner::run(&git_root, &config, None, &["loc", "git"], out)?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let simple = &parsed_result["tree"]["children"];
+    assert_eq!(simple.as_array().unwrap().len(), 1);
+    let files = simple[0]["children"][0]["children"].as_array().unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0]["name"], "a_renamed.txt");
+    assert_eq!(
+        parsed_result["metadata"]["incremental"],
+        serde_json::json!({"changed_since": "HEAD~1", "changed_files": 1})
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ignored_changed_files_are_not_scanned() -> Result<(), Error> {
+    let gitdir = tempdir()?;
+    let git_root = unzip_test_sample("git_sample", gitdir.path())?;
+    // untracked files count as changed - the ignore file is hidden, so is skipped too
+    std::fs::write(git_root.join("simple/ignored.txt"), "ignore me\n")?;
+    std::fs::write(
+        git_root.join(".polyglot_code_scanner_ignore"),
+        "ignored.txt\n",
+    )?;
+
+    let mut config = test_scanner_config(true);
+    config.changed_since = Some("HEAD~1".to_string());
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    polyglot_code_scanner::run(&git_root, &config, None, &["loc", "git"], out)?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let simple = &parsed_result["tree"]["children"][0]["children"];
+    let names: Vec<&str> = simple
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|child| child["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["child"]);
+    assert_eq!(
+        parsed_result["metadata"]["incremental"],
+        serde_json::json!({"changed_since": "HEAD~1", "changed_files": 1})
+    );
+
+    Ok(())
+}
+
+#[test]
+fn no_content_scans_record_sizes_without_reading_files() -> Result<(), Error> {
+    let root = PathBuf::from("./tests/data/simple/");
+    let mut config = test_scanner_config(false);
+    config.no_content = true;
+    config.features.file_stats = true;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let out = Cursor::new(&mut buffer);
+    polyglot_code_scanner::run(&root, &config, None, &["file_stats"], out)?;
+
+    let parsed_result: Value = serde_json::from_reader(buffer.as_slice())?;
+    let files = parsed_result["tree"]["children"].as_array().unwrap();
+    let parent = files
+        .iter()
+        .find(|file| file["name"] == "parent.clj")
+        .unwrap();
+    assert!(parent["data"]["loc"].is_null());
+    assert_eq!(
+        parent["data"]["file_stats"]["bytes"],
+        std::fs::metadata(root.join("parent.clj"))?.len()
+    );
+    assert_eq!(
+        parsed_result["metadata"]["scanner"]["config"]["no_content"],
+        true
+    );
+
+    let out = Cursor::new(Vec::new());
+    assert!(polyglot_code_scanner::run(&root, &config, None, &["loc"], out).is_err());
+    Ok(())
+}